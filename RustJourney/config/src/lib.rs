@@ -0,0 +1,95 @@
+//! Settings shared by the hyper microservice, the CRUD app and the Postgres
+//! and Redis examples. Values come from the environment, optionally seeded
+//! from a `.env` file in the working directory.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+
+use dotenv::dotenv;
+
+const DEFAULT_DATABASE_URL: &str = "postgres://postgres@localhost/postgres";
+const DEFAULT_REDIS_URL: &str = "redis://localhost:6379";
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8000";
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    database_url: String,
+    redis_url: Option<String>,
+    bind_address: SocketAddr,
+    api_key: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Invalid { key: &'static str, value: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Invalid { key, value } => write!(f, "invalid value for {}: {:?}", key, value),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Settings {
+    /// Reads `DATABASE_URL`, `REDIS_URL` and `BIND_ADDRESS`, falling back to
+    /// local defaults for anything unset, and the optional `API_KEY`.
+    pub fn load() -> Result<Settings, ConfigError> {
+        dotenv().ok();
+        let bind_address = var_or("BIND_ADDRESS", DEFAULT_BIND_ADDRESS);
+        let bind_address = bind_address.parse().map_err(|_| ConfigError::Invalid {
+            key: "BIND_ADDRESS",
+            value: bind_address.clone(),
+        })?;
+        Ok(Settings {
+            database_url: var_or("DATABASE_URL", DEFAULT_DATABASE_URL),
+            redis_url: env::var("REDIS_URL").ok().filter(|url| !url.is_empty()),
+            bind_address,
+            api_key: env::var("API_KEY").ok().filter(|key| !key.is_empty()),
+        })
+    }
+
+    /// Settings given outright rather than read from the environment, for
+    /// running an app against a database of the caller's choosing, as
+    /// tests do. The bind address is the default.
+    pub fn new(database_url: &str, redis_url: Option<&str>, api_key: Option<&str>) -> Settings {
+        Settings {
+            database_url: database_url.to_string(),
+            redis_url: redis_url.map(str::to_string),
+            bind_address: DEFAULT_BIND_ADDRESS.parse().expect("the default bind address is valid"),
+            api_key: api_key.map(str::to_string),
+        }
+    }
+
+    pub fn database_url(&self) -> &str {
+        &self.database_url
+    }
+
+    pub fn redis_url(&self) -> &str {
+        self.redis_url.as_deref().unwrap_or(DEFAULT_REDIS_URL)
+    }
+
+    /// The Redis URL only if `REDIS_URL` was set, for callers that treat
+    /// Redis as optional rather than assuming a local instance.
+    pub fn configured_redis_url(&self) -> Option<&str> {
+        self.redis_url.as_deref()
+    }
+
+    pub fn bind_address(&self) -> SocketAddr {
+        self.bind_address
+    }
+
+    /// The key clients must present to change data, if one is configured.
+    pub fn api_key(&self) -> Option<&str> {
+        self.api_key.as_deref()
+    }
+}
+
+fn var_or(key: &str, default: &str) -> String {
+    env::var(key).unwrap_or_else(|_| default.to_string())
+}
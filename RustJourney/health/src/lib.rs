@@ -0,0 +1,157 @@
+//! A health report shared by the hyper microservice and the CRUD app. Each
+//! backend a service depends on is probed in turn, with a round trip such
+//! as `SELECT 1` or `PING`, and reported up or down along with how long the
+//! probe took.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+type Probe = Box<dyn Fn() -> Result<(), String> + Send + Sync>;
+
+struct Backend {
+    name: &'static str,
+    required: bool,
+    probe: Probe,
+}
+
+/// The backends of a service and how to probe each. A probe returns the
+/// error that made the backend unusable; it should give up on its own
+/// after a short timeout, so a hung backend shows up as down rather than
+/// holding up the whole report.
+#[derive(Default)]
+pub struct HealthReport {
+    backends: Vec<Backend>,
+}
+
+impl HealthReport {
+    pub fn new() -> HealthReport {
+        HealthReport::default()
+    }
+
+    /// Adds a backend the service cannot work without.
+    pub fn required<F>(self, name: &'static str, probe: F) -> HealthReport
+    where
+        F: Fn() -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.with(name, true, Box::new(probe))
+    }
+
+    /// Adds a backend the service keeps working without, only worse, such
+    /// as a cache.
+    pub fn optional<F>(self, name: &'static str, probe: F) -> HealthReport
+    where
+        F: Fn() -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.with(name, false, Box::new(probe))
+    }
+
+    fn with(mut self, name: &'static str, required: bool, probe: Probe) -> HealthReport {
+        self.backends.push(Backend { name, required, probe });
+        self
+    }
+
+    /// Probes every backend, one after another in the order they were
+    /// added.
+    pub fn check(&self) -> HealthStatus {
+        let backends = self.backends
+            .iter()
+            .map(|backend| {
+                let started = Instant::now();
+                let result = (backend.probe)();
+                BackendStatus {
+                    name: backend.name,
+                    required: backend.required,
+                    latency: started.elapsed(),
+                    error: result.err(),
+                }
+            })
+            .collect();
+        HealthStatus { backends }
+    }
+}
+
+/// How the service as a whole is doing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overall {
+    /// Every backend is up.
+    Healthy,
+    /// An optional backend is down.
+    Degraded,
+    /// A required backend is down.
+    Unhealthy,
+}
+
+impl fmt::Display for Overall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Overall::Healthy => "healthy",
+            Overall::Degraded => "degraded",
+            Overall::Unhealthy => "unhealthy",
+        })
+    }
+}
+
+/// How probing one backend went.
+#[derive(Debug, Clone)]
+pub struct BackendStatus {
+    pub name: &'static str,
+    pub required: bool,
+    pub latency: Duration,
+    /// Why the backend is down, or `None` when it is up.
+    pub error: Option<String>,
+}
+
+impl BackendStatus {
+    pub fn is_up(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// What `HealthReport::check` found.
+#[derive(Debug, Clone)]
+pub struct HealthStatus {
+    pub backends: Vec<BackendStatus>,
+}
+
+impl HealthStatus {
+    pub fn overall(&self) -> Overall {
+        let down = |required| self.backends.iter().any(|backend| backend.required == required && !backend.is_up());
+        if down(true) {
+            Overall::Unhealthy
+        } else if down(false) {
+            Overall::Degraded
+        } else {
+            Overall::Healthy
+        }
+    }
+
+    /// The status as `/health/detailed` answers with it:
+    /// `{"status": "degraded", "backends": [{"name": "redis", "required":
+    /// false, "status": "down", "latency_ms": 0.4, "error": "..."}]}`, with
+    /// `error` only on backends that are down.
+    pub fn to_json(&self) -> Value {
+        let backends: Vec<Value> = self.backends
+            .iter()
+            .map(|backend| {
+                let mut entry = json!({
+                    "name": backend.name,
+                    "required": backend.required,
+                    "status": if backend.is_up() { "up" } else { "down" },
+                    "latency_ms": millis(backend.latency),
+                });
+                if let Some(error) = &backend.error {
+                    entry["error"] = json!(error);
+                }
+                entry
+            })
+            .collect();
+        json!({ "status": self.overall().to_string(), "backends": backends })
+    }
+}
+
+/// `duration` in milliseconds, to the microsecond.
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
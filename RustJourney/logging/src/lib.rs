@@ -0,0 +1,68 @@
+//! The log format shared by the hyper microservice, the CRUD app and the
+//! Postgres and Redis examples: one JSON object per line on stderr, with
+//! `timestamp`, `level`, `target` and `message`, and `fields` for what an
+//! `event` carries besides. What gets logged follows `RUST_LOG` as
+//! env_logger reads it, `info` and up when it is unset.
+
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+use env_logger::{Builder, Env};
+use log::Level;
+use serde_derive::Serialize;
+use serde_json::Value;
+
+const DEFAULT_FILTER: &str = "info";
+
+/// One line of the log, its fields in the order they are written.
+#[derive(Serialize)]
+struct Line<'a> {
+    /// RFC 3339, in UTC, to the millisecond.
+    timestamp: String,
+    level: String,
+    target: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<&'a Value>,
+}
+
+impl<'a> Line<'a> {
+    fn new(level: Level, target: &'a str, message: &'a str, fields: Option<&'a Value>) -> Line<'a> {
+        Line {
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            level: level.to_string(),
+            target,
+            message,
+            fields,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a log line always serializes")
+    }
+}
+
+/// Installs the JSON logger, for every `main` to call before anything
+/// logs. When a logger is already installed this leaves it in place.
+pub fn init() {
+    // Only a logger set before this one makes it fail, and that one keeps
+    // logging.
+    let _ = Builder::from_env(Env::default().default_filter_or(DEFAULT_FILTER))
+        .format(|buf, record| {
+            let message = record.args().to_string();
+            writeln!(buf, "{}", Line::new(record.level(), record.target(), &message, None).to_json())
+        })
+        .try_init();
+}
+
+/// Logs `message` at `level` under `target`, with `fields`, an object
+/// such as `json!({"status": 200})`, beside it, when `RUST_LOG` lets
+/// `target` log at `level`. The `log` macros carry only a message, so
+/// structured entries such as a service's one per request go through here.
+pub fn event(level: Level, target: &str, message: &str, fields: Value) {
+    if !log::log_enabled!(target: target, level) {
+        return;
+    }
+    let line = Line::new(level, target, message, Some(&fields)).to_json();
+    let _ = writeln!(io::stderr().lock(), "{}", line);
+}
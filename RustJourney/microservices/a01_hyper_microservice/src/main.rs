@@ -1,15 +1,739 @@
-use hyper::{Body, Response, Server};
-use hyper::rt::Future;
-use hyper::service::service_fn_ok;
+use std::env;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use bb8::{Pool, RunError};
+use bb8_postgres::PostgresConnectionManager;
+use config::Settings;
+use futures::future::Either;
+use futures::{future, Future, Stream};
+use health::{HealthReport, Overall};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hyper::header::{HeaderMap, ACCEPT_ENCODING, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, RETRY_AFTER, VARY};
+use hyper::service::service_fn;
+use log::{error, Level};
+use percent_encoding::percent_decode;
+use serde_json::json;
+use tokio::fs::File;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::timer::{Delay, Timeout};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{NoTls, Row};
+use tokio_threadpool::blocking;
 
-fn main() {
-    let addr = ([127, 0, 0, 1], 8000).into();
-    let builder = Server::bind(&addr);
-    let server = builder.serve(|| {
-        service_fn_ok(|_| {
-            Response::new(Body::from("Rust Microservice"))
+#[cfg(test)]
+mod tests;
+
+static INDEX: &[u8] = b"Rust Microservice";
+static DEFAULT_PUBLIC_DIR: &str = "./public";
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 30_000;
+/// Seconds between TCP keep-alive probes; `TCP_KEEPALIVE_SECS=0` turns them off.
+const DEFAULT_TCP_KEEPALIVE_SECS: u64 = 60;
+/// Open connections past this are answered 503 and closed.
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+/// Kept well under the request timeout, so a database that is down shows
+/// up as a 503 rather than a 504.
+const DB_CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `/health/detailed` waits on Postgres before calling it down.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+/// Bodies smaller than this are sent as they are; gzip would barely save
+/// anything on them.
+const GZIP_MIN_BYTES: usize = 1024;
+/// Jobs waiting on the worker past this are turned away with 503.
+const DEFAULT_JOB_QUEUE_CAPACITY: usize = 64;
+/// Job bodies over this are refused with 413 rather than queued.
+const MAX_JOB_BYTES: usize = 64 * 1024;
+/// More distinct ids than this in one `/heroes?ids=` request are refused
+/// with 400.
+const DEFAULT_MAX_BATCH_IDS: usize = 100;
+static HEROES_QUERY: &str = "SELECT id, name, identity, hometown, age, version \
+    FROM heroes WHERE deleted_at IS NULL ORDER BY id";
+static HEROES_BY_ID_QUERY: &str = "SELECT id, name, identity, hometown, age, version \
+    FROM heroes WHERE deleted_at IS NULL AND id = ANY($1) ORDER BY id";
+static CREATE_JOBS_TABLE: &str = "CREATE TABLE IF NOT EXISTS jobs (\
+    id BIGSERIAL PRIMARY KEY, \
+    payload TEXT NOT NULL, \
+    accepted_at TIMESTAMPTZ NOT NULL, \
+    done_at TIMESTAMPTZ NOT NULL DEFAULT now())";
+static INSERT_JOB: &str = "INSERT INTO jobs (payload, accepted_at) VALUES ($1, $2)";
+
+type ResponseFuture = Box<dyn Future<Item=Response<Body>, Error=Error> + Send>;
+type PgPool = Pool<PostgresConnectionManager<NoTls>>;
+
+struct Config {
+    public: PathBuf,
+    request_timeout: Duration,
+    tcp_keepalive: Option<Duration>,
+    /// Whether a connection is kept open for further HTTP/1 requests
+    /// (`HTTP_KEEPALIVE`, on by default).
+    http_keepalive: bool,
+    max_connections: usize,
+    job_queue_capacity: usize,
+    max_batch_ids: usize,
+}
+
+impl Config {
+    fn from_env() -> Config {
+        let public = env::var("PUBLIC_DIR").unwrap_or_else(|_| DEFAULT_PUBLIC_DIR.to_string());
+        let timeout_ms = env_parse("REQUEST_TIMEOUT_MS").unwrap_or(DEFAULT_REQUEST_TIMEOUT_MS);
+        let keepalive_secs = env_parse("TCP_KEEPALIVE_SECS").unwrap_or(DEFAULT_TCP_KEEPALIVE_SECS);
+        Config {
+            public: PathBuf::from(public),
+            request_timeout: Duration::from_millis(timeout_ms),
+            tcp_keepalive: if keepalive_secs == 0 { None } else { Some(Duration::from_secs(keepalive_secs)) },
+            http_keepalive: env_parse("HTTP_KEEPALIVE").unwrap_or(true),
+            max_connections: env_parse("MAX_CONNECTIONS").unwrap_or(DEFAULT_MAX_CONNECTIONS),
+            job_queue_capacity: env_parse("JOB_QUEUE_CAPACITY").unwrap_or(DEFAULT_JOB_QUEUE_CAPACITY).max(1),
+            max_batch_ids: env_parse("MAX_BATCH_IDS").unwrap_or(DEFAULT_MAX_BATCH_IDS),
+        }
+    }
+}
+
+/// Reads and parses an environment variable, treating unparsable values as unset.
+fn env_parse<T: FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|value| value.parse().ok())
+}
+
+/// Counts open connections against `max_connections`.
+struct ConnectionLimit {
+    open: AtomicUsize,
+    max: usize,
+}
+
+/// One open connection's place under the limit, given back on drop.
+struct ConnectionPermit(Arc<ConnectionLimit>);
+
+impl ConnectionLimit {
+    fn new(max: usize) -> Arc<ConnectionLimit> {
+        Arc::new(ConnectionLimit { open: AtomicUsize::new(0), max })
+    }
+
+    fn acquire(limit: &Arc<ConnectionLimit>) -> Option<ConnectionPermit> {
+        let mut open = limit.open.load(Ordering::SeqCst);
+        while open < limit.max {
+            match limit.open.compare_exchange(open, open + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return Some(ConnectionPermit(limit.clone())),
+                Err(current) => open = current,
+            }
+        }
+        None
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A job taken by `POST /jobs`, waiting for the worker.
+struct Job {
+    payload: String,
+    accepted_at: SystemTime,
+}
+
+/// Counts kept for `/metrics`, shared by the handlers and the worker.
+#[derive(Default)]
+struct JobMetrics {
+    /// Jobs accepted that the worker has not picked up yet.
+    depth: AtomicUsize,
+    accepted: AtomicUsize,
+    /// Jobs turned away because the queue was full.
+    rejected: AtomicUsize,
+    processed: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+/// The bounded queue between the handlers and the one worker that drains
+/// it. Handlers never wait on it: a job either fits or is refused, so a
+/// worker that falls behind pushes back on clients instead of piling jobs
+/// up in memory.
+#[derive(Clone)]
+struct JobQueue {
+    sender: mpsc::Sender<Job>,
+    capacity: usize,
+    metrics: Arc<JobMetrics>,
+}
+
+impl JobQueue {
+    /// Spawns the worker on the current runtime and returns the queue that
+    /// feeds it.
+    fn spawn(capacity: usize, pool: Arc<PgPool>) -> JobQueue {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let metrics = Arc::new(JobMetrics::default());
+        tokio::spawn(job_worker(receiver, pool, metrics.clone()));
+        JobQueue { sender, capacity, metrics }
+    }
+
+    /// Queues `job` unless `capacity` jobs are already waiting, returning
+    /// how many are waiting with it.
+    fn try_enqueue(&self, job: Job) -> Result<usize, TrySendError<Job>> {
+        // Counted before sending, so the worker can never take the job and
+        // count it off first.
+        let depth = self.metrics.depth.fetch_add(1, Ordering::SeqCst) + 1;
+        match self.sender.clone().try_send(job) {
+            Ok(()) => {
+                self.metrics.accepted.fetch_add(1, Ordering::SeqCst);
+                Ok(depth)
+            },
+            Err(err) => {
+                self.metrics.depth.fetch_sub(1, Ordering::SeqCst);
+                self.metrics.rejected.fetch_add(1, Ordering::SeqCst);
+                Err(err)
+            },
+        }
+    }
+}
+
+fn microservice_handler(req: Request<Body>, config: &Config, pool: &PgPool, report: &Arc<HealthReport>, jobs: &JobQueue) -> ResponseFuture {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/") => {
+            Box::new(future::ok(Response::new(INDEX.into())))
+        },
+        (&Method::GET, "/metrics") => {
+            metrics(jobs)
+        },
+        (&Method::POST, "/jobs") => {
+            enqueue_job(req, jobs)
+        },
+        (&Method::GET, "/health/detailed") => {
+            health_detailed(report)
+        },
+        (&Method::GET, "/heroes") => match req.uri().query().and_then(ids_param) {
+            Some(ids) => batch_heroes(pool, parse_ids(ids), config.max_batch_ids),
+            None => list_heroes(pool),
+        },
+        (&Method::GET, "/slow") if cfg!(debug_assertions) => {
+            // Debug-only route that always outlives the request timeout.
+            let wake_at = Instant::now() + config.request_timeout * 2;
+            let body = Delay::new(wake_at)
+                .map(|_| Response::new(INDEX.into()))
+                .map_err(other);
+            Box::new(body)
+        },
+        (&Method::GET, path) => {
+            serve_file(&config.public, path)
+        },
+        _ => {
+            response_with_error(StatusCode::NOT_FOUND, "not found")
+        },
+    }
+}
+
+/// Answers `req` within the request timeout, gzipped when the client takes
+/// that, and logs it.
+fn respond(req: Request<Body>, config: &Config, pool: &PgPool, report: &Arc<HealthReport>, jobs: &JobQueue) -> ResponseFuture {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let gzip = accepts_gzip(req.headers());
+    let response = microservice_handler(req, config, pool, report, jobs);
+    let response = with_compression(with_timeout(response, config.request_timeout), gzip);
+    with_request_log(response, method, path)
+}
+
+/// Answers with 504 Gateway Timeout when the handler does not finish in time.
+fn with_timeout(response: ResponseFuture, timeout: Duration) -> ResponseFuture {
+    let response = Timeout::new(response, timeout).or_else(|err| {
+        if err.is_elapsed() {
+            return Ok(json_error(StatusCode::GATEWAY_TIMEOUT, "request timed out"));
+        }
+        match err.into_inner() {
+            Some(err) => Err(err),
+            None => Ok(json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")),
+        }
+    });
+    Box::new(response)
+}
+
+/// Reads the live heroes from the CRUD app's table. Any database failure,
+/// including no connection within `DB_CONNECTION_TIMEOUT`, answers 503.
+fn list_heroes(pool: &PgPool) -> ResponseFuture {
+    query_heroes(pool, HEROES_QUERY, None)
+}
+
+/// The value of the `ids` parameter of a query string.
+fn ids_param(query: &str) -> Option<&str> {
+    query.split('&').find_map(|param| param.strip_prefix("ids="))
+}
+
+/// The distinct ids of a percent-encoded, comma-separated list, in
+/// ascending order, skipping any that are not positive integers.
+fn parse_ids(ids: &str) -> Vec<i32> {
+    let mut ids: Vec<i32> = percent_decode(ids.as_bytes())
+        .decode_utf8_lossy()
+        .split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .filter(|id| *id > 0)
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+/// Reads the live heroes with the given ids in one query, in id order; ids
+/// with no live hero are left out. More than `max` ids answer 400, and no
+/// ids an empty array without touching the database.
+fn batch_heroes(pool: &PgPool, ids: Vec<i32>, max: usize) -> ResponseFuture {
+    if ids.len() > max {
+        return response_with_error(StatusCode::BAD_REQUEST, &format!("at most {} ids per request", max));
+    }
+    if ids.is_empty() {
+        let resp = Response::builder().header(CONTENT_TYPE, "application/json").body("[]".into()).unwrap();
+        return Box::new(future::ok(resp));
+    }
+    query_heroes(pool, HEROES_BY_ID_QUERY, Some(ids))
+}
+
+/// Runs `query`, with `ids` as its one parameter when given, and answers
+/// with the heroes it returns as a JSON array, or 503 when the database
+/// fails.
+fn query_heroes(pool: &PgPool, query: &'static str, ids: Option<Vec<i32>>) -> ResponseFuture {
+    let rows = pool.run(move |mut client| {
+        client.prepare(query).then(move |statement| match statement {
+            Ok(statement) => {
+                let params: Vec<&dyn ToSql> = ids.iter().map(|ids| ids as &dyn ToSql).collect();
+                let rows = client.query(&statement, &params).collect().then(move |rows| match rows {
+                    Ok(rows) => Ok((rows, client)),
+                    Err(err) => Err((err, client)),
+                });
+                Either::A(rows)
+            },
+            Err(err) => Either::B(future::err((err, client))),
         })
     });
-    let server = server.map_err(drop);
-    hyper::rt::run(server);
+    let body = rows.then(|rows| {
+        let heroes = rows
+            .map_err(drop)
+            .and_then(|rows| rows.iter().map(hero_json).collect::<Result<Vec<_>, _>>().map_err(drop));
+        match heroes {
+            Ok(heroes) => {
+                let resp = Response::builder()
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(serde_json::Value::from(heroes).to_string().into())
+                    .unwrap();
+                Ok(resp)
+            },
+            Err(_) => Ok(json_error(StatusCode::SERVICE_UNAVAILABLE, "database unavailable")),
+        }
+    });
+    Box::new(body)
+}
+
+/// Queues the request body as a job and answers 202 Accepted at once, or
+/// 503 with `Retry-After` when the queue is full, the worker busy with the
+/// jobs before it. The body must be UTF-8 and at most `MAX_JOB_BYTES`.
+fn enqueue_job(req: Request<Body>, jobs: &JobQueue) -> ResponseFuture {
+    let jobs = jobs.clone();
+    // Once the body runs over the limit the rest is read and dropped, and
+    // the request answered 413.
+    let payload = req.into_body().map_err(other).fold(Some(Vec::new()), |payload, chunk| {
+        let payload = payload
+            .filter(|payload| payload.len() + chunk.len() <= MAX_JOB_BYTES)
+            .map(|mut payload| {
+                payload.extend_from_slice(&chunk);
+                payload
+            });
+        Ok::<_, Error>(payload)
+    });
+    let body = payload.map(move |payload| {
+        let payload = match payload.map(String::from_utf8) {
+            Some(Ok(payload)) => payload,
+            Some(Err(_)) => return json_error(StatusCode::BAD_REQUEST, "job payload must be UTF-8"),
+            None => return json_error(StatusCode::PAYLOAD_TOO_LARGE, "job payload too large"),
+        };
+        match jobs.try_enqueue(Job { payload, accepted_at: SystemTime::now() }) {
+            Ok(depth) => {
+                let body = json!({
+                    "status": "accepted",
+                    "queue_depth": depth,
+                });
+                Response::builder()
+                    .status(StatusCode::ACCEPTED)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(body.to_string().into())
+                    .unwrap()
+            },
+            Err(ref err) if err.is_full() => {
+                let mut resp = json_error(StatusCode::SERVICE_UNAVAILABLE, "job queue is full");
+                resp.headers_mut().insert(RETRY_AFTER, "1".parse().unwrap());
+                resp
+            },
+            Err(_) => json_error(StatusCode::SERVICE_UNAVAILABLE, "job worker unavailable"),
+        }
+    });
+    Box::new(body)
+}
+
+/// Takes jobs off the queue one at a time and stores them in the `jobs`
+/// table. A job that fails is logged and counted, and the worker moves on
+/// to the next.
+fn job_worker(receiver: mpsc::Receiver<Job>, pool: Arc<PgPool>, metrics: Arc<JobMetrics>) -> impl Future<Item=(), Error=()> {
+    let table_ready = Arc::new(AtomicBool::new(false));
+    receiver.map_err(drop).for_each(move |job| {
+        metrics.depth.fetch_sub(1, Ordering::SeqCst);
+        let metrics = metrics.clone();
+        store_job(&pool, job, &table_ready).then(move |stored| {
+            match stored {
+                Ok(()) => metrics.processed.fetch_add(1, Ordering::SeqCst),
+                Err(err) => {
+                    error!("Job failed: {}", err);
+                    metrics.failed.fetch_add(1, Ordering::SeqCst)
+                },
+            };
+            Ok(())
+        })
+    })
+}
+
+/// Inserts `job`, creating the `jobs` table first until that has worked
+/// once, so a worker started while Postgres was down still gets one.
+fn store_job(pool: &PgPool, job: Job, table_ready: &Arc<AtomicBool>) -> impl Future<Item=(), Error=RunError<tokio_postgres::Error>> {
+    let table_ready = table_ready.clone();
+    pool.run(move |mut client| {
+        let created = if table_ready.load(Ordering::SeqCst) {
+            Either::A(future::ok(()))
+        } else {
+            Either::B(client.simple_query(CREATE_JOBS_TABLE).collect().map(drop))
+        };
+        created.then(move |created| match created {
+            Ok(()) => {
+                table_ready.store(true, Ordering::SeqCst);
+                let inserted = client.prepare(INSERT_JOB).then(move |statement| match statement {
+                    Ok(statement) => {
+                        let inserted = client.execute(&statement, &[&job.payload, &job.accepted_at]).then(move |inserted| match inserted {
+                            Ok(_) => Ok(((), client)),
+                            Err(err) => Err((err, client)),
+                        });
+                        Either::A(inserted)
+                    },
+                    Err(err) => Either::B(future::err((err, client))),
+                });
+                Either::A(inserted)
+            },
+            Err(err) => Either::B(future::err((err, client))),
+        })
+    })
+}
+
+/// The job queue's depth and counts, in the Prometheus text format.
+fn metrics(jobs: &JobQueue) -> ResponseFuture {
+    let counts = &jobs.metrics;
+    let load = |count: &AtomicUsize| count.load(Ordering::SeqCst);
+    let series = [
+        ("job_queue_depth", "gauge", "Jobs accepted and waiting for the worker.", load(&counts.depth)),
+        ("job_queue_capacity", "gauge", "Jobs that can wait before new ones are refused.", jobs.capacity),
+        ("jobs_accepted_total", "counter", "Jobs queued.", load(&counts.accepted)),
+        ("jobs_rejected_total", "counter", "Jobs refused because the queue was full.", load(&counts.rejected)),
+        ("jobs_processed_total", "counter", "Jobs the worker stored.", load(&counts.processed)),
+        ("jobs_failed_total", "counter", "Jobs the worker could not store.", load(&counts.failed)),
+    ];
+    let body: String = series.iter()
+        .map(|(name, kind, help, value)| format!("# HELP {0} {2}\n# TYPE {0} {1}\n{0} {3}\n", name, kind, help, value))
+        .collect();
+    let resp = Response::builder()
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(body.into())
+        .unwrap();
+    Box::new(future::ok(resp))
+}
+
+/// Probes every backend and answers with how each is doing, 503 when a
+/// required one is down. The probes block, so they run where the thread
+/// pool can hand this worker's other tasks to a spare thread meanwhile.
+fn health_detailed(report: &Arc<HealthReport>) -> ResponseFuture {
+    let report = report.clone();
+    let body = future::poll_fn(move || blocking(|| report.check())).then(|checked| match checked {
+        Ok(checked) => {
+            let status = if checked.overall() == Overall::Unhealthy {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            };
+            let resp = Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, "application/json")
+                .body(checked.to_json().to_string().into())
+                .unwrap();
+            Ok(resp)
+        },
+        Err(_) => Ok(json_error(StatusCode::INTERNAL_SERVER_ERROR, "could not run the health checks")),
+    });
+    Box::new(body)
+}
+
+/// Runs `SELECT 1` on a pooled connection, waiting on it from the calling
+/// thread for at most `PROBE_TIMEOUT`.
+fn ping_postgres(pool: &PgPool) -> Result<(), String> {
+    let select = pool.run(|mut client| {
+        client.simple_query("SELECT 1").collect().then(move |rows| match rows {
+            Ok(_) => Ok(((), client)),
+            Err(err) => Err((err, client)),
+        })
+    });
+    Timeout::new(select, PROBE_TIMEOUT).wait().map_err(|err| {
+        if err.is_elapsed() {
+            format!("no answer within {}ms", PROBE_TIMEOUT.as_millis())
+        } else {
+            err.into_inner().map_or_else(|| "the timer failed".to_string(), |err| err.to_string())
+        }
+    })
+}
+
+fn hero_json(row: &Row) -> Result<serde_json::Value, tokio_postgres::Error> {
+    Ok(json!({
+        "id": row.try_get::<_, i32>(0)?,
+        "name": row.try_get::<_, String>(1)?,
+        "identity": row.try_get::<_, String>(2)?,
+        "hometown": row.try_get::<_, String>(3)?,
+        "age": row.try_get::<_, i32>(4)?,
+        "version": row.try_get::<_, i32>(5)?,
+    }))
+}
+
+/// Logs one event per request once its response is ready, with its method,
+/// path, status and how long it took: at info, or at error for a 5xx or a
+/// handler that failed without one.
+fn with_request_log(response: ResponseFuture, method: Method, path: String) -> ResponseFuture {
+    let started = Instant::now();
+    let response = response.then(move |result| {
+        let duration_ms = started.elapsed().as_micros() as f64 / 1000.0;
+        let mut fields = json!({
+            "method": method.as_str(),
+            "path": path,
+            "duration_ms": duration_ms,
+        });
+        let (level, outcome) = match result {
+            Ok(ref resp) => {
+                fields["status"] = resp.status().as_u16().into();
+                let level = if resp.status().is_server_error() { Level::Error } else { Level::Info };
+                (level, resp.status().as_u16().to_string())
+            },
+            Err(ref err) => {
+                fields["error"] = err.to_string().into();
+                (Level::Error, "failed".to_string())
+            },
+        };
+        logging::event(level, module_path!(), &format!("{} {} -> {}", method, path, outcome), fields);
+        result
+    });
+    Box::new(response)
+}
+
+/// Gzips the response body when the client accepts it and the body is big
+/// enough to be worth it. Either way the response varies with
+/// `Accept-Encoding`, so a cache must not hand one client's copy to another.
+fn with_compression(response: ResponseFuture, accepts_gzip: bool) -> ResponseFuture {
+    let response = response.and_then(move |resp| {
+        if resp.headers().contains_key(CONTENT_ENCODING) {
+            return Either::A(future::ok(resp));
+        }
+        let (mut parts, body) = resp.into_parts();
+        parts.headers.insert(VARY, "Accept-Encoding".parse().unwrap());
+        if !accepts_gzip {
+            return Either::A(future::ok(Response::from_parts(parts, body)));
+        }
+        let body = body.concat2().map_err(other).and_then(move |content| {
+            if content.len() < GZIP_MIN_BYTES {
+                return Ok(Response::from_parts(parts, content.into()));
+            }
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&content)?;
+            let compressed = encoder.finish()?;
+            parts.headers.insert(CONTENT_ENCODING, "gzip".parse().unwrap());
+            parts.headers.remove(CONTENT_LENGTH);
+            Ok(Response::from_parts(parts, compressed.into()))
+        });
+        Either::B(body)
+    });
+    Box::new(response)
+}
+
+/// Whether `Accept-Encoding` accepts gzip. A gzip entry decides when there
+/// is one, and `*` otherwise; either is ruled out by a q of 0, however it
+/// is written. An entry whose q is not a number from 0 to 1 is skipped.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    let mut gzip = None;
+    let mut any = None;
+    let codings = headers.get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    for coding in codings {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or("");
+        let q = match quality(params) {
+            Some(q) => q,
+            None => continue,
+        };
+        if name.eq_ignore_ascii_case("gzip") {
+            gzip = Some(q);
+        } else if name == "*" {
+            any = Some(q);
+        }
+    }
+    gzip.or(any).is_some_and(|q| q > 0.0)
+}
+
+/// The q among an `Accept-Encoding` entry's parameters, 1 when it has none.
+fn quality<'a>(params: impl Iterator<Item = &'a str>) -> Option<f32> {
+    for param in params {
+        let mut pair = param.splitn(2, '=');
+        if pair.next().unwrap_or("").trim().eq_ignore_ascii_case("q") {
+            return pair.next()?.trim().parse().ok().filter(|q| (0.0..=1.0).contains(q));
+        }
+    }
+    Some(1.0)
+}
+
+/// Answers with the file `path` names below `public`. A directory named
+/// without its trailing slash is redirected to the path with one, which
+/// serves its `index.html`.
+fn serve_file(public: &Path, path: &str) -> ResponseFuture {
+    let filepath = match resolve_path(public, path) {
+        Some(filepath) => filepath,
+        None => return response_with_error(StatusCode::BAD_REQUEST, "invalid path"),
+    };
+    let content_type = content_type(&filepath);
+    let location = format!("{}/", path);
+    let read_file = File::open(filepath)
+        .and_then(|file| file.metadata())
+        .and_then(|(file, metadata)| {
+            if metadata.is_dir() {
+                return Either::A(future::ok(None));
+            }
+            Either::B(tokio::io::read_to_end(file, Vec::new()).map(|(_, content)| Some(content)))
+        });
+    let body = read_file.then(move |result| match result {
+        Ok(Some(content)) => {
+            let resp = Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .body(content.into())
+                .unwrap();
+            Ok(resp)
+        },
+        Ok(None) => {
+            let resp = Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header(LOCATION, location)
+                .body(Body::empty())
+                .unwrap();
+            Ok(resp)
+        },
+        Err(ref err) if err.kind() == ErrorKind::NotFound => {
+            Ok(json_error(StatusCode::NOT_FOUND, "file not found"))
+        },
+        Err(_) => {
+            Ok(json_error(StatusCode::INTERNAL_SERVER_ERROR, "could not read file"))
+        },
+    });
+    Box::new(body)
+}
+
+/// Maps a request path onto a file below `public`. Returns `None` when the
+/// path tries to climb out of the directory with `..`.
+fn resolve_path(public: &Path, path: &str) -> Option<PathBuf> {
+    let mut filepath = public.to_path_buf();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => filepath.push(part),
+            Component::CurDir => {},
+            _ => return None,
+        }
+    }
+    if path.ends_with('/') {
+        filepath.push("index.html");
+    }
+    Some(filepath)
+}
+
+fn content_type(filepath: &Path) -> &'static str {
+    match filepath.extension().and_then(|ext| ext.to_str()) {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "application/javascript",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+fn other<E>(err: E) -> Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>, {
+    Error::other(err)
+}
+
+/// Builds the `{"error": ..., "status": ...}` body every error path answers with.
+fn json_error(status: StatusCode, message: &str) -> Response<Body> {
+    let body = json!({
+        "error": message,
+        "status": status.as_u16(),
+    });
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string().into())
+        .unwrap()
+}
+
+fn response_with_error(status: StatusCode, message: &str) -> ResponseFuture {
+    Box::new(future::ok(json_error(status, message)))
+}
+
+/// What a connection over the limit gets for each request: a 503 that also
+/// tells hyper to close the connection once it is written.
+fn too_many_connections() -> ResponseFuture {
+    let mut resp = json_error(StatusCode::SERVICE_UNAVAILABLE, "too many connections");
+    resp.headers_mut().insert(CONNECTION, "close".parse().unwrap());
+    Box::new(future::ok(resp))
+}
+
+fn main() {
+    logging::init();
+    let settings = Settings::load().expect("Can't load settings");
+    let config = Arc::new(Config::from_env());
+    let addr = settings.bind_address();
+    let manager = PostgresConnectionManager::new(settings.database_url(), NoTls);
+    // The pool spawns its connection tasks, so it has to be built on the runtime.
+    hyper::rt::run(future::lazy(move || {
+        let pool = Arc::new(Pool::builder()
+            .connection_timeout(DB_CONNECTION_TIMEOUT)
+            .build_unchecked(manager));
+        let probed = pool.clone();
+        let report = Arc::new(HealthReport::new().required("postgres", move || ping_postgres(&probed)));
+        let jobs = JobQueue::spawn(config.job_queue_capacity, pool.clone());
+        let limit = ConnectionLimit::new(config.max_connections);
+        let builder = Server::bind(&addr)
+            .tcp_keepalive(config.tcp_keepalive)
+            .http1_keepalive(config.http_keepalive);
+        let server = builder.serve(move || {
+            let config = config.clone();
+            let pool = pool.clone();
+            let report = report.clone();
+            let jobs = jobs.clone();
+            // Held by the service, so the place frees up when hyper drops
+            // the connection.
+            let permit = ConnectionLimit::acquire(&limit);
+            service_fn(move |req| {
+                if permit.is_none() {
+                    let method = req.method().clone();
+                    let path = req.uri().path().to_string();
+                    return with_request_log(too_many_connections(), method, path);
+                }
+                respond(req, &config, &pool, &report, &jobs)
+            })
+        });
+        server.map_err(drop)
+    }));
 }
@@ -0,0 +1,241 @@
+//! Requests answered through `respond`, as the server answers them, each on
+//! a runtime of its own. The pool points at a Postgres that is never there,
+//! so only routes that leave the database alone are asked.
+
+use std::fs;
+use std::io::Read;
+use std::process;
+
+use flate2::read::GzDecoder;
+use hyper::http::response::Parts;
+use tokio::runtime::Runtime;
+
+use super::*;
+
+static NO_DATABASE: &str = "postgres://nobody@127.0.0.1:1/none";
+
+/// The defaults, but for a request timeout short enough to wait out.
+fn config() -> Config {
+    Config {
+        public: PathBuf::from(DEFAULT_PUBLIC_DIR),
+        request_timeout: Duration::from_millis(100),
+        tcp_keepalive: None,
+        http_keepalive: true,
+        max_connections: DEFAULT_MAX_CONNECTIONS,
+        job_queue_capacity: DEFAULT_JOB_QUEUE_CAPACITY,
+        max_batch_ids: DEFAULT_MAX_BATCH_IDS,
+    }
+}
+
+/// Answers `req` under `config`, returning the response's head and body.
+fn send(config: Config, req: Request<Body>) -> (Parts, Vec<u8>) {
+    let mut runtime = Runtime::new().unwrap();
+    let response = future::lazy(move || {
+        let manager = PostgresConnectionManager::new(NO_DATABASE, NoTls);
+        let pool = Pool::builder().connection_timeout(DB_CONNECTION_TIMEOUT).build_unchecked(manager);
+        let report = Arc::new(HealthReport::new());
+        let jobs = JobQueue::spawn(config.job_queue_capacity, Arc::new(pool.clone()));
+        respond(req, &config, &pool, &report, &jobs)
+    });
+    runtime.block_on(response.and_then(|resp| {
+        let (parts, body) = resp.into_parts();
+        body.concat2().map_err(other).map(move |body| (parts, body.to_vec()))
+    })).unwrap()
+}
+
+fn get(path: &str) -> Request<Body> {
+    Request::get(path).body(Body::empty()).unwrap()
+}
+
+/// A public directory of a test's own, removed when the test ends.
+struct Public(PathBuf);
+
+impl Public {
+    fn new(name: &str, files: &[(&str, &[u8])]) -> Public {
+        let dir = env::temp_dir().join(format!("a01_test_{}_{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, content) in files {
+            fs::write(dir.join(file), content).unwrap();
+        }
+        Public(dir)
+    }
+
+    fn config(&self) -> Config {
+        Config { public: self.0.clone(), ..config() }
+    }
+}
+
+impl Drop for Public {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A JSON body well over `GZIP_MIN_BYTES`.
+fn large_json() -> Vec<u8> {
+    let heroes: Vec<_> = (0..100).map(|id| json!({ "id": id, "name": format!("Hero {}", id) })).collect();
+    let json = serde_json::Value::from(heroes).to_string().into_bytes();
+    assert!(json.len() > GZIP_MIN_BYTES);
+    json
+}
+
+fn gunzip(body: &[u8]) -> Vec<u8> {
+    let mut content = Vec::new();
+    GzDecoder::new(body).read_to_end(&mut content).unwrap();
+    content
+}
+
+#[test]
+fn a_handler_that_outlives_the_timeout_is_answered_504() {
+    let config = config();
+    let timeout = config.request_timeout;
+    let started = Instant::now();
+    let (parts, body) = send(config, get("/slow"));
+    let elapsed = started.elapsed();
+
+    assert_eq!(parts.status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(parts.headers[CONTENT_TYPE], "application/json");
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error, json!({ "error": "request timed out", "status": 504 }));
+    assert!(elapsed >= timeout && elapsed < timeout * 2, "answered after {:?}", elapsed);
+}
+
+#[test]
+fn a_handler_that_finishes_in_time_is_answered_as_usual() {
+    let (parts, body) = send(config(), get("/"));
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(body, INDEX);
+}
+
+#[test]
+fn a_handler_that_fails_in_time_keeps_its_error() {
+    let failing: ResponseFuture = Box::new(future::err(other("the handler failed")));
+    let err = with_timeout(failing, Duration::from_secs(1)).wait().unwrap_err();
+    assert_eq!(err.to_string(), "the handler failed");
+}
+
+#[test]
+fn a_large_body_is_gzipped_for_a_client_that_accepts_it() {
+    let json = large_json();
+    let public = Public::new("gzip_accepted", &[("heroes.json", &json)]);
+    let req = Request::get("/heroes.json").header(ACCEPT_ENCODING, "deflate, gzip;q=0.8").body(Body::empty()).unwrap();
+    let (parts, body) = send(public.config(), req);
+
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(parts.headers[CONTENT_ENCODING], "gzip");
+    assert_eq!(parts.headers[VARY], "Accept-Encoding");
+    assert_eq!(parts.headers[CONTENT_TYPE], "application/json");
+    assert!(body.len() < json.len());
+    assert_eq!(gunzip(&body), json);
+}
+
+#[test]
+fn a_large_body_is_sent_as_it_is_without_accept_encoding() {
+    let json = large_json();
+    let public = Public::new("gzip_not_accepted", &[("heroes.json", &json)]);
+    let (parts, body) = send(public.config(), get("/heroes.json"));
+
+    assert_eq!(parts.status, StatusCode::OK);
+    assert!(!parts.headers.contains_key(CONTENT_ENCODING));
+    assert_eq!(parts.headers[VARY], "Accept-Encoding");
+    assert_eq!(body, json);
+}
+
+#[test]
+fn a_small_body_is_not_gzipped_even_when_accepted() {
+    let req = Request::get("/").header(ACCEPT_ENCODING, "gzip").body(Body::empty()).unwrap();
+    let (parts, body) = send(config(), req);
+
+    assert!(!parts.headers.contains_key(CONTENT_ENCODING));
+    assert_eq!(parts.headers[VARY], "Accept-Encoding");
+    assert_eq!(body, INDEX);
+}
+
+#[test]
+fn a_directory_without_its_trailing_slash_is_redirected_to_its_index() {
+    let public = Public::new("directory", &[]);
+    fs::create_dir(public.0.join("docs")).unwrap();
+    fs::write(public.0.join("docs").join("index.html"), "<h1>Docs</h1>").unwrap();
+
+    let (parts, body) = send(public.config(), get("/docs"));
+    assert_eq!(parts.status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(parts.headers[LOCATION], "/docs/");
+    assert!(body.is_empty());
+
+    let (parts, body) = send(public.config(), get("/docs/"));
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(body, b"<h1>Docs</h1>");
+}
+
+#[test]
+fn gzip_is_accepted_unless_it_is_ruled_out() {
+    let accepts = |value: &str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, value.parse().unwrap());
+        accepts_gzip(&headers)
+    };
+    assert!(accepts("gzip"));
+    assert!(accepts("br, GZIP"));
+    assert!(accepts("*"));
+    assert!(accepts("gzip;q=0.5"));
+    assert!(!accepts("gzip;q=0"));
+    assert!(!accepts("gzip; q = 0"));
+    assert!(!accepts("gzip;q=0.0"));
+    assert!(!accepts("gzip;Q=0.000"));
+    assert!(!accepts("*;q=0"));
+    assert!(accepts("*;q=0, gzip"));
+    assert!(!accepts("gzip;q=0, *"));
+    assert!(!accepts("*, gzip;q=0.000"));
+    assert!(accepts("gzip;q=nonsense, *"));
+    assert!(!accepts("deflate, br"));
+    assert!(!accepts_gzip(&HeaderMap::new()));
+}
+
+#[test]
+fn ids_are_decoded_deduplicated_and_sorted_dropping_the_invalid_ones() {
+    assert_eq!(parse_ids("3,1,3,2"), vec![1, 2, 3]);
+    assert_eq!(parse_ids("2%2C1%2c2"), vec![1, 2]);
+    assert_eq!(parse_ids("%31%32,%20%37"), vec![7, 12]);
+    assert_eq!(parse_ids("4,hero,-1,0,2.5,,99999999999,%ZZ,%FF"), vec![4]);
+    assert!(parse_ids("").is_empty());
+}
+
+#[test]
+fn more_ids_than_the_cap_are_answered_400() {
+    let config = Config { max_batch_ids: 2, ..config() };
+    let (parts, body) = send(config, get("/heroes?ids=1,2,3"));
+
+    assert_eq!(parts.status, StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error, json!({ "error": "at most 2 ids per request", "status": 400 }));
+}
+
+#[test]
+fn ids_that_are_all_invalid_are_answered_with_no_heroes() {
+    let (parts, body) = send(config(), get("/heroes?ids=hero,-1,0"));
+
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(parts.headers[CONTENT_TYPE], "application/json");
+    assert_eq!(body, b"[]");
+}
+
+#[test]
+fn connections_past_the_cap_get_no_permit_until_one_closes() {
+    let limit = ConnectionLimit::new(2);
+    let first = ConnectionLimit::acquire(&limit).expect("the first connection fits");
+    let second = ConnectionLimit::acquire(&limit).expect("the second connection fits");
+    assert!(ConnectionLimit::acquire(&limit).is_none());
+
+    drop(first);
+    let third = ConnectionLimit::acquire(&limit).expect("a closed connection frees its place");
+    assert!(ConnectionLimit::acquire(&limit).is_none());
+    drop((second, third));
+    assert_eq!(limit.open.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn a_connection_over_the_cap_is_answered_503_and_closed() {
+    let resp = too_many_connections().wait().unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(resp.headers()[CONNECTION], "close");
+}
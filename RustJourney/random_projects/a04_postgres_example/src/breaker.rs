@@ -0,0 +1,100 @@
+//! Retries of Postgres operations, behind a circuit breaker. An error a
+//! second try may well not hit, such as a dropped connection, is retried
+//! with a growing pause between tries; once an operation has failed every
+//! try, the circuit opens, and until its cooldown is over every call fails
+//! at once without touching the network. A database that stays down so
+//! costs one round of retries, not one per call.
+
+use std::cell::Cell;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use postgres::error::{ADMIN_SHUTDOWN, CANNOT_CONNECT_NOW, CRASH_SHUTDOWN, T_R_DEADLOCK_DETECTED, T_R_SERIALIZATION_FAILURE};
+use postgres::{Error, Result};
+
+/// Whether trying again may succeed: the connection failed, the server was
+/// shutting down or not yet accepting connections, or the transaction lost
+/// to a concurrent one. What is retried after such an error must be safe
+/// to run twice.
+pub fn is_transient(err: &Error) -> bool {
+    if err.as_io().is_some() {
+        return true;
+    }
+    match err.code() {
+        // Class 08 is every connection exception.
+        Some(code) => {
+            code.code().starts_with("08")
+                || [ADMIN_SHUTDOWN, CRASH_SHUTDOWN, CANNOT_CONNECT_NOW, T_R_SERIALIZATION_FAILURE, T_R_DEADLOCK_DETECTED]
+                    .contains(code)
+        },
+        None => false,
+    }
+}
+
+pub struct CircuitBreaker {
+    retries: u32,
+    backoff: Duration,
+    cooldown: Duration,
+    /// When an open circuit lets calls through again, `None` while closed.
+    open_until: Cell<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+    /// A closed breaker that retries a transient error up to `retries`
+    /// times, waiting `backoff` before the first retry and twice as long as
+    /// the last before each after that, and opens for `cooldown` once an
+    /// operation has used all its retries.
+    pub fn new(retries: u32, backoff: Duration, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker { retries, backoff, cooldown, open_until: Cell::new(None) }
+    }
+
+    /// Runs `op` until it succeeds, fails with an error that is not
+    /// transient, or has failed `retries` times more, returning its last
+    /// result. While the circuit is open this fails without running `op`.
+    ///
+    /// The first call after the cooldown gets its retries again: should they
+    /// all fail, the circuit opens anew, and one that reaches the database,
+    /// succeeding or not, closes it.
+    pub fn call<T, F>(&self, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        if let Some(until) = self.open_until.get() {
+            let now = Instant::now();
+            if now < until {
+                let message = format!(
+                    "not trying Postgres for another {:.1}s after it failed {} retries",
+                    (until - now).as_secs_f64(),
+                    self.retries,
+                );
+                return Err(io::Error::other(message).into());
+            }
+        }
+        let mut pause = self.backoff;
+        let mut retried = 0;
+        loop {
+            let err = match op() {
+                Ok(value) => {
+                    self.open_until.set(None);
+                    return Ok(value);
+                },
+                Err(err) => err,
+            };
+            if !is_transient(&err) {
+                self.open_until.set(None);
+                return Err(err);
+            }
+            if retried == self.retries {
+                warn!("Giving up on Postgres for {:?} after {} retries: {}", self.cooldown, retried, err);
+                self.open_until.set(Some(Instant::now() + self.cooldown));
+                return Err(err);
+            }
+            retried += 1;
+            warn!("Retrying in {:?} ({} of {}): {}", pause, retried, self.retries, err);
+            thread::sleep(pause);
+            pause *= 2;
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! The per-category report, cached aside in Redis. Redis only ever speeds
+//! the report up: when it is down or answers with an error, the report is
+//! read from Postgres as if there were no cache, and a warning logged.
+
+use std::time::Duration;
+
+use log::warn;
+use postgres::{Connection, Result};
+use redis::{Client, Commands, RedisResult};
+
+use crate::{sales_by_category, SalesSummary};
+
+/// Where an uncached report is read from: Postgres, or a stand-in over it.
+pub trait SalesRepository {
+    fn sales_by_category(&self) -> Result<Vec<SalesSummary>>;
+}
+
+impl SalesRepository for Connection {
+    fn sales_by_category(&self) -> Result<Vec<SalesSummary>> {
+        sales_by_category(self)
+    }
+}
+
+/// Where the report is cached, as the JSON of its rows.
+static REPORT_KEY: &str = "sales:report";
+/// What `invalidate` sends, as `--dry-run` prints it.
+pub static INVALIDATE: &str = "DEL sales:report";
+
+/// A connection to the Redis the report is cached in.
+pub struct ReportCache {
+    conn: redis::Connection,
+}
+
+impl ReportCache {
+    /// Connects to Redis at `url`, or warns and returns `None` when it
+    /// cannot be reached, for the caller to go without the cache.
+    pub fn connect(url: &str) -> Option<ReportCache> {
+        let connected = Client::open(url).and_then(|client| client.get_connection());
+        match connected {
+            Ok(conn) => Some(ReportCache { conn }),
+            Err(err) => {
+                warn!("Going without the report cache, Redis at {} is unavailable: {}", url, err);
+                None
+            },
+        }
+    }
+
+    /// The cached report, or `None` on a miss. A cached value that no longer
+    /// parses, say from an older version, is treated as a miss.
+    fn get(&self) -> RedisResult<Option<Vec<SalesSummary>>> {
+        let cached: Option<String> = self.conn.get(REPORT_KEY)?;
+        Ok(cached.and_then(|json| serde_json::from_str(&json).ok()))
+    }
+
+    fn store(&self, report: &[SalesSummary], ttl: Duration) -> RedisResult<()> {
+        let json = serde_json::to_string(report).expect("summaries always serialize");
+        self.conn.set_ex(REPORT_KEY, json, ttl.as_secs().max(1) as usize)
+    }
+
+    /// Drops the cached report, for writes that change the totals to call, so
+    /// the next report reads them fresh rather than waiting out the TTL.
+    pub fn invalidate(&self) {
+        if let Err(err) = self.conn.del::<_, ()>(REPORT_KEY) {
+            warn!("Could not drop the cached report, it may be stale for a while: {}", err);
+        }
+    }
+}
+
+/// The per-category totals, from `cache` when it holds them, otherwise from
+/// Postgres, then cached for `ttl`. Without a cache, or when Redis fails,
+/// this is `sales_by_category`.
+pub fn cached_report_sales<R: SalesRepository + ?Sized>(
+    db: &R,
+    cache: Option<&ReportCache>,
+    ttl: Duration,
+) -> Result<Vec<SalesSummary>> {
+    let cache = match cache {
+        Some(cache) => cache,
+        None => return db.sales_by_category(),
+    };
+    match cache.get() {
+        Ok(Some(report)) => Ok(report),
+        Ok(None) => {
+            let report = db.sales_by_category()?;
+            if let Err(err) = cache.store(&report, ttl) {
+                warn!("Could not cache the report: {}", err);
+            }
+            Ok(report)
+        },
+        Err(err) => {
+            warn!("Reading the report from Postgres, the cache failed: {}", err);
+            db.sales_by_category()
+        },
+    }
+}
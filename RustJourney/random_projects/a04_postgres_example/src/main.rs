@@ -1,5 +1,34 @@
-use postgres::types::ToSql;
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+use chrono::{DateTime, NaiveDate, NaiveTime, ParseResult, Utc};
+use fallible_iterator::FallibleIterator;
+use log::{error, info, warn};
+use clap::{crate_authors, crate_name, crate_version, App, AppSettings, Arg, SubCommand};
+use postgres::params::{ConnectParams, Host, IntoConnectParams};
+use postgres::rows::Row;
+use postgres::stmt::Statement;
+use postgres::types::{FromSql, ToSql};
 use postgres::{Connection, Result, TlsMode};
+use config::Settings;
+use serde_derive::{Deserialize, Serialize};
+use breaker::CircuitBreaker;
+use cache::ReportCache;
+use tls::{NativeTls, SslMode};
+
+mod breaker;
+mod cache;
+mod tls;
+
+#[cfg(test)]
+mod tests;
+
+static UNKNOWN: &str = "unknown";
 
 #[derive(Debug)]
 struct SaleWithProduct {
@@ -7,96 +36,692 @@ struct SaleWithProduct {
     name: String,
     quantity: f64,
     unit: String,
-    date: i64,
-}
-
-fn create_db() -> Result<Connection> {
-    let username: String = "postgres".to_string();
-    let password: String = "postgres".to_string();
-    let host: String = "52.170.169.113".to_string();
-    let port: String = "5432".to_string();
-    let database: String = "testdb".to_string();
-    let conn: Connection = Connection::connect(
-        format!(
-            "postgres://{}{}{}@{}{}{}{}{}",
-            username,
-            if password.is_empty(){ "" } else { ":" },
-            password,
+    date: DateTime<Utc>,
+}
+
+/// A sale to insert. `Sales.sale_date` holds whole seconds since the Unix
+/// epoch, UTC; `date` is converted to that on insert and back on read, so
+/// callers never handle the raw number.
+#[derive(Debug)]
+struct NewSale {
+    id: String,
+    product_id: i32,
+    date: DateTime<Utc>,
+    quantity: f64,
+    unit: String,
+}
+
+/// Where and as whom to connect, kept apart from the connection itself so
+/// errors can name the server that could not be reached. A `host` starting
+/// with `/` is the directory holding the server's Unix socket.
+#[derive(Debug)]
+struct ConnectionConfig {
+    host: String,
+    port: u16,
+    user: String,
+    password: Option<String>,
+    database: Option<String>,
+    ssl_mode: SslMode,
+}
+
+impl ConnectionConfig {
+    fn from_url(url: &str) -> std::result::Result<ConnectionConfig, Box<dyn Error + Sync + Send>> {
+        let params = url.into_connect_params()?;
+        let host = match params.host() {
+            Host::Tcp(host) => host.clone(),
+            Host::Unix(path) => path.to_string_lossy().into_owned(),
+        };
+        let user = params.user().ok_or("DATABASE_URL has no user")?;
+        Ok(ConnectionConfig {
             host,
-            if port.is_empty() { "" } else { ":" },
-            port,
-            if database.is_empty() { "" } else { "/" },
-            database
-        ),
-        TlsMode::None,
-    )?;
-    let _ = conn.execute("DROP TABLE Sales", &[]);
-    let _ = conn.execute("DROP TABLE Products", &[]);
-    conn.execute(
-        "CREATE TABLE Products ( \
-                    id INTEGER PRIMARY KEY, \
+            port: params.port(),
+            user: user.name().to_string(),
+            password: user.password().map(str::to_string),
+            database: params.database().map(str::to_string),
+            ssl_mode: SslMode::Disable,
+        })
+    }
+
+    /// Like `from_url`, but `PGHOST` and `PGPORT`, when set, replace the
+    /// URL's host and port, so `PGHOST=/var/run/postgresql` reaches the
+    /// server over its socket whatever host the URL names. `PGSSLMODE`
+    /// picks the TLS mode, `disable` when unset.
+    fn from_env(url: &str) -> std::result::Result<ConnectionConfig, Box<dyn Error + Sync + Send>> {
+        let mut config = ConnectionConfig::from_url(url)?;
+        if let Some(host) = env::var("PGHOST").ok().filter(|host| !host.is_empty()) {
+            config.host = host;
+        }
+        if let Some(port) = env::var("PGPORT").ok().filter(|port| !port.is_empty()) {
+            config.port = port.parse().map_err(|_| format!("PGPORT {:?} is not a port number", port))?;
+        }
+        if let Some(mode) = env::var("PGSSLMODE").ok().filter(|mode| !mode.is_empty()) {
+            config.ssl_mode = mode.parse()?;
+        }
+        Ok(config)
+    }
+
+    fn is_socket(&self) -> bool {
+        self.host.starts_with('/')
+    }
+
+    /// The server as named in messages: `host:port`, or the socket's path.
+    fn address(&self) -> String {
+        if self.is_socket() {
+            format!("{}/.s.PGSQL.{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// For a socket, the port only picks the socket file's name, as in libpq.
+    fn params(&self) -> ConnectParams {
+        let mut builder = ConnectParams::builder();
+        builder.port(self.port).user(&self.user, self.password.as_deref());
+        if let Some(database) = &self.database {
+            builder.database(database);
+        }
+        if self.is_socket() {
+            builder.build(Host::Unix(PathBuf::from(&self.host)))
+        } else {
+            builder.build(Host::Tcp(self.host.clone()))
+        }
+    }
+}
+
+/// Connects with TLS over TCP unless the mode is `disable`. Unix sockets
+/// always connect without it, as libpq does: a socket never leaves the
+/// machine, so there is nothing for TLS to protect there.
+fn connect(config: &ConnectionConfig) -> Result<Connection> {
+    if config.ssl_mode == SslMode::Disable || config.is_socket() {
+        return Connection::connect(config.params(), TlsMode::None);
+    }
+    let tls = NativeTls::new(config.ssl_mode).map_err(io::Error::other)?;
+    Connection::connect(config.params(), TlsMode::Require(&tls))
+}
+
+/// How often `seed` and `report` retry a transient failure, the pause
+/// before the first retry, and how long Postgres is left alone once the
+/// retries are used up.
+const DB_RETRIES: u32 = 3;
+const DB_BACKOFF: Duration = Duration::from_millis(200);
+const DB_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Runs `op` through `breaker`, first on `conn`, then on a new connection
+/// for each retry: one that dropped stays dropped.
+fn with_retries<T, F>(breaker: &CircuitBreaker, config: &ConnectionConfig, conn: Connection, mut op: F) -> Result<T>
+where
+    F: FnMut(&Connection) -> Result<T>,
+{
+    let mut conn = Some(conn);
+    breaker.call(|| {
+        let conn = match conn.take() {
+            Some(conn) => conn,
+            None => connect(config)?,
+        };
+        op(&conn)
+    })
+}
+
+/// Schema changes in the order they apply, each under a version number that
+/// never changes once released. New changes go at the end.
+static MIGRATIONS: &[(i32, &str)] = &[
+    // IF NOT EXISTS adopts databases created before versioning existed.
+    (1, "CREATE TABLE IF NOT EXISTS Products ( \
+                    id SERIAL PRIMARY KEY, \
                     category TEXT NOT NULL, \
                     name TEXT NOT NULL UNIQUE\
-                    )", &[],
-    )?;
-    conn.execute(
-        "CREATE TABLE Sales (\
+                    ); \
+        CREATE TABLE IF NOT EXISTS Sales (\
                     id TEXT PRIMARY KEY,\
                     product_id INTEGER NOT NULL REFERENCES Products,\
                     sale_date BIGINT NOT NULL,\
                     quantity DOUBLE PRECISION NOT NULL,\
-                    unit TEXT NOT NULL)",
-        &[],
-    )?;
-    Ok(conn)
-}
-
-fn populate_db(conn: &Connection) -> Result<()> {
-    conn.execute(
-        "INSERT INTO Products (\
-                    id, category, name) \
-                    VALUES ($1, $2, $3)",
-        &[&1 as &dyn ToSql, &"fruit", &"pears"],
-    )?;
-    conn.execute(
-        "INSERT INTO Sales (\
+                    unit TEXT NOT NULL)"),
+];
+
+static CREATE_SCHEMA_MIGRATIONS: &str = "CREATE TABLE IF NOT EXISTS schema_migrations (\
+                    version INTEGER PRIMARY KEY,\
+                    applied_at TIMESTAMPTZ NOT NULL DEFAULT now())";
+static LOCK_SCHEMA_MIGRATIONS: &str = "LOCK TABLE schema_migrations IN EXCLUSIVE MODE";
+static SELECT_APPLIED_VERSIONS: &str = "SELECT version FROM schema_migrations";
+static RECORD_MIGRATION: &str = "INSERT INTO schema_migrations (version) VALUES ($1)";
+
+/// Runs the migrations not yet recorded in `schema_migrations`, in order and
+/// in one transaction, and returns how many ran. Running it again applies
+/// nothing, so existing data is kept.
+fn apply_migrations(conn: &Connection) -> Result<usize> {
+    conn.batch_execute(CREATE_SCHEMA_MIGRATIONS)?;
+    let transaction = conn.transaction()?;
+    // Keeps two concurrent runs from both applying the same version.
+    transaction.batch_execute(LOCK_SCHEMA_MIGRATIONS)?;
+    let applied: Vec<i32> = transaction
+        .query(SELECT_APPLIED_VERSIONS, &[])?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    let mut count = 0;
+    for (version, sql) in MIGRATIONS.iter().filter(|(version, _)| !applied.contains(version)) {
+        transaction.batch_execute(sql)?;
+        transaction.execute(RECORD_MIGRATION, &[version])?;
+        count += 1;
+    }
+    transaction.commit()?;
+    Ok(count)
+}
+
+fn migrate(conn: &Connection) -> Result<()> {
+    match apply_migrations(conn)? {
+        0 => println!("Schema is up to date"),
+        count => println!("Applied {} migration(s)", count),
+    }
+    Ok(())
+}
+
+static UPSERT_PRODUCT: &str = "INSERT INTO Products (category, name) \
+                    VALUES ($1, $2) \
+                    ON CONFLICT (name) DO UPDATE SET category = EXCLUDED.category \
+                    RETURNING id";
+static INSERT_SALE: &str = "INSERT INTO Sales (\
                     id, product_id, sale_date, quantity, unit) \
-                    VALUES ($1, $2, $3, $4, $5)",
-        &[&"2020-183" as &dyn ToSql,  &1, &1_234_567_890_i64, &7.34 ,&"Kg"],
-    )?;
+                    VALUES ($1, $2, $3, $4, $5) \
+                    ON CONFLICT (id) DO NOTHING";
+
+/// Inserts the product, or updates the category of the existing product
+/// with that name, and returns its id either way.
+fn upsert_product(conn: &Connection, category: &str, name: &str) -> Result<i32> {
+    let rows = conn.query(UPSERT_PRODUCT, &[&category, &name])?;
+    Ok(rows.get(0).get(0))
+}
+
+/// Inserts sales through one prepared statement, so the SQL is parsed once
+/// however many sales go through it. Each sale written drops the cached
+/// report, whose totals it changes.
+struct SaleInserter<'conn> {
+    statement: Statement<'conn>,
+    cache: Option<&'conn ReportCache>,
+}
+
+impl<'conn> SaleInserter<'conn> {
+    fn new(conn: &'conn Connection, cache: Option<&'conn ReportCache>) -> Result<SaleInserter<'conn>> {
+        let statement = conn.prepare(INSERT_SALE)?;
+        Ok(SaleInserter { statement, cache })
+    }
+
+    /// Inserts the sale, returning the number of rows written: 0 when a sale
+    /// with that id already exists, which leaves the cached report alone.
+    fn insert(&mut self, sale: &NewSale) -> Result<u64> {
+        let written = self.statement.execute(
+            &[&sale.id as &dyn ToSql, &sale.product_id, &sale.date.timestamp(), &sale.quantity, &sale.unit],
+        )?;
+        if let (Some(cache), true) = (self.cache, written > 0) {
+            cache.invalidate();
+        }
+        Ok(written)
+    }
+}
+
+fn insert_sales(conn: &Connection, sales: &[NewSale], cache: Option<&ReportCache>) -> Result<u64> {
+    let mut inserter = SaleInserter::new(conn, cache)?;
+    let mut inserted = 0;
+    for sale in sales {
+        inserted += inserter.insert(sale)?;
+    }
+    Ok(inserted)
+}
+
+/// The sample product `seed` inserts, as `(category, name)`.
+static SEED_PRODUCT: (&str, &str) = ("fruit", "pears");
+
+/// The sample sales `seed` inserts, all of `SEED_PRODUCT`.
+fn seed_sales(product_id: i32) -> Vec<NewSale> {
+    vec![NewSale {
+        id: "2020-183".to_string(),
+        product_id,
+        date: parse_sale_date("2009-02-13T23:31:30Z").expect("the seed date is valid RFC 3339"),
+        quantity: 7.34,
+        unit: "Kg".to_string(),
+    }]
+}
+
+/// Seeds the sample data, then drops the cached report it may have changed:
+/// once, rather than per sale, and even when every sale was there already,
+/// as the upsert may have changed the product's category.
+fn populate_db(conn: &Connection, cache: Option<&ReportCache>) -> Result<()> {
+    let (category, name) = SEED_PRODUCT;
+    let product_id = upsert_product(conn, category, name)?;
+    insert_sales(conn, &seed_sales(product_id), None)?;
+    if let Some(cache) = cache {
+        cache.invalidate();
+    }
     Ok(())
 }
 
+/// Reads a sale date as RFC 3339, such as `2020-04-17T08:48:53+02:00`, or
+/// as a bare `YYYY-MM-DD`, taken as midnight UTC, as CSV exports often give.
+fn parse_sale_date(value: &str) -> ParseResult<DateTime<Utc>> {
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(date) => Ok(date.with_timezone(&Utc)),
+        Err(_) => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map(|date| date.and_time(NaiveTime::MIN).and_utc()),
+    }
+}
+
+/// Reads column `idx`, treating NULLs and unconvertible values alike as absent.
+fn column<T: FromSql>(row: &Row, idx: usize) -> Option<T> {
+    row.get_opt::<_, Option<T>>(idx).and_then(|value| value.ok()).and_then(|value| value)
+}
+
+/// Maps a row of the report query without panicking on bad data: a sale
+/// whose product is gone is reported as an unknown product, and a sale
+/// missing its own columns is skipped.
+fn sale_from_row(row: &Row) -> Option<SaleWithProduct> {
+    Some(SaleWithProduct {
+        category: column(row, 4).unwrap_or_else(|| UNKNOWN.to_string()),
+        name: column(row, 0).unwrap_or_else(|| UNKNOWN.to_string()),
+        quantity: column(row, 2)?,
+        unit: column(row, 1)?,
+        date: DateTime::from_timestamp(column(row, 3)?, 0)?,
+    })
+}
+
+static SELECT_SALES: &str = "SELECT p.name, s.unit, s.quantity, s.sale_date, p.category \
+        FROM Sales s \
+        LEFT JOIN Products p \
+        ON p.id = s.product_id \
+        ORDER BY s.sale_date";
+
 fn print_db(conn: &Connection) -> Result<()> {
-    for row in &conn.query(
-        "SELECT p.name, s.unit, s.quantity, s.sale_date\
-        FROM Sales s\
-        LEFT JOIN Products p\
-        ON p.id = s.product_id\
-        ORDER BY s.sale_date",
-        &[],
-    )? {
-        let sale_with_product = SaleWithProduct {
-            category: "".to_string(),
-            name: row.get(0),
-            quantity: row.get(2),
-            unit: row.get(1),
-            date: row.get(3),
+    for row in &conn.query(SELECT_SALES, &[])? {
+        let sale_with_product = match sale_from_row(&row) {
+            Some(sale_with_product) => sale_with_product,
+            None => {
+                warn!("Skipping a sale with missing or malformed columns");
+                continue;
+            },
         };
         println!(
-            "At instant {}, {} {} of {} were sold.",
-            sale_with_product.date,
+            "On {}, {} {} of {} ({}) were sold.",
+            sale_with_product.date.to_rfc3339(),
             sale_with_product.quantity,
             sale_with_product.unit,
-            sale_with_product.name
+            sale_with_product.name,
+            sale_with_product.category
         );
     }
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let conn: Connection = create_db()?;
-    populate_db(&conn)?;
-    print_db(&conn)?;
+static SELECT_CATEGORY_TOTALS: &str = "SELECT COALESCE(p.category, $1), SUM(s.quantity) \
+        FROM Sales s \
+        LEFT JOIN Products p \
+        ON p.id = s.product_id \
+        GROUP BY 1 \
+        ORDER BY 1";
+
+/// One row of the per-category report.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SalesSummary {
+    category: String,
+    /// Total quantity sold, whatever the unit.
+    quantity: f64,
+}
+
+/// Total quantity sold per category, whatever the unit, in category order.
+fn sales_by_category(conn: &Connection) -> Result<Vec<SalesSummary>> {
+    let rows = conn.query(SELECT_CATEGORY_TOTALS, &[&UNKNOWN])?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| Some(SalesSummary { category: column(&row, 0)?, quantity: column(&row, 1)? }))
+        .collect())
+}
+
+/// How long a cached report is served before it is read from Postgres again,
+/// unless a write drops it first.
+const REPORT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// How `report --format` renders the per-category totals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReportFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<ReportFormat, String> {
+        match value {
+            "table" => Ok(ReportFormat::Table),
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!("unknown report format {:?}", value)),
+        }
+    }
+}
+
+impl ReportFormat {
+    const NAMES: [&'static str; 3] = ["table", "json", "csv"];
+
+    /// Renders the report; the JSON form is an array of `[category,
+    /// quantity]` pairs, so it reads back as the same rows.
+    fn render(&self, rows: &[SalesSummary]) -> String {
+        let mut out = String::new();
+        match self {
+            ReportFormat::Table => {
+                let width = rows.iter().map(|row| row.category.len()).max().unwrap_or(0).max(8);
+                writeln!(out, "{:<width$}  {:>10}", "category", "quantity", width = width).unwrap();
+                for row in rows {
+                    writeln!(out, "{:<width$}  {:>10.2}", row.category, row.quantity, width = width).unwrap();
+                }
+            },
+            ReportFormat::Json => {
+                let pairs: Vec<(&str, f64)> = rows.iter().map(|row| (row.category.as_str(), row.quantity)).collect();
+                out = serde_json::to_string_pretty(&pairs).expect("pairs of strings and numbers serialize");
+                out.push('\n');
+            },
+            ReportFormat::Csv => {
+                out.push_str("category,quantity\n");
+                for row in rows {
+                    writeln!(out, "{},{}", csv_field(&row.category), row.quantity).unwrap();
+                }
+            },
+        }
+        out
+    }
+}
+
+/// Quotes a CSV field when it holds a comma, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn print_report(conn: &Connection, cache: Option<&ReportCache>, format: ReportFormat) -> Result<()> {
+    print!("{}", format.render(&cache::cached_report_sales(conn, cache, REPORT_CACHE_TTL)?));
     Ok(())
-}
\ No newline at end of file
+}
+
+/// One export line for a row of the export query, or `None` when the sale
+/// is missing its own columns; a missing product is exported as unknown.
+fn sale_csv_line(row: &Row) -> Option<String> {
+    let date = DateTime::from_timestamp(column(row, 5)?, 0)?;
+    Some(format!(
+        "{},{},{},{},{},{}",
+        csv_field(&column::<String>(row, 0)?),
+        csv_field(&column(row, 1).unwrap_or_else(|| UNKNOWN.to_string())),
+        csv_field(&column(row, 2).unwrap_or_else(|| UNKNOWN.to_string())),
+        column::<f64>(row, 3)?,
+        csv_field(&column::<String>(row, 4)?),
+        date.to_rfc3339(),
+    ))
+}
+
+/// Rows fetched per round trip while exporting.
+const EXPORT_BATCH: i32 = 1000;
+
+static SELECT_EXPORT: &str = "SELECT s.id, p.category, p.name, s.quantity, s.unit, s.sale_date \
+        FROM Sales s \
+        LEFT JOIN Products p \
+        ON p.id = s.product_id \
+        ORDER BY s.id";
+
+/// Writes every sale to `out` as CSV, in id order, and returns how many were
+/// written. Rows come through a cursor `EXPORT_BATCH` at a time and go
+/// straight to `out`, so memory stays flat however many sales there are.
+fn export_sales_csv<W: io::Write>(conn: &Connection, mut out: W) -> Result<u64> {
+    // A cursor only lives as long as the transaction around it.
+    let transaction = conn.transaction()?;
+    let statement = transaction.prepare(SELECT_EXPORT)?;
+    let mut rows = statement.lazy_query(&transaction, &[], EXPORT_BATCH)?;
+    writeln!(out, "id,category,product,quantity,unit,sale_date")?;
+    let mut written = 0;
+    while let Some(row) = rows.next()? {
+        match sale_csv_line(&row) {
+            Some(line) => writeln!(out, "{}", line)?,
+            None => {
+                warn!("Skipping a sale with missing or malformed columns");
+                continue;
+            },
+        }
+        written += 1;
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+/// Which server a planned statement goes to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Server {
+    Postgres,
+    /// The report cache, left alone with `--no-cache` or when unreachable.
+    Redis,
+}
+
+/// A statement a subcommand would run, with its parameters rendered as SQL
+/// literals, for `--dry-run` to print in place of running it. `sql` is a
+/// Redis command for the statements sent to the report cache.
+#[derive(Debug)]
+struct PlannedStatement {
+    server: Server,
+    sql: &'static str,
+    params: Vec<String>,
+}
+
+impl PlannedStatement {
+    fn new(sql: &'static str) -> PlannedStatement {
+        PlannedStatement { server: Server::Postgres, sql, params: Vec::new() }
+    }
+
+    fn with_params(sql: &'static str, params: Vec<String>) -> PlannedStatement {
+        PlannedStatement { server: Server::Postgres, sql, params }
+    }
+
+    fn redis(command: &'static str) -> PlannedStatement {
+        PlannedStatement { server: Server::Redis, sql: command, params: Vec::new() }
+    }
+}
+
+impl fmt::Display for PlannedStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.server == Server::Redis {
+            writeln!(f, "-- On Redis, unless --no-cache")?;
+            return write!(f, "{}", self.sql);
+        }
+        if !self.params.is_empty() {
+            let params: Vec<String> = self.params
+                .iter()
+                .enumerate()
+                .map(|(index, param)| format!("${} = {}", index + 1, param))
+                .collect();
+            writeln!(f, "-- {}", params.join(", "))?;
+        }
+        write!(f, "{};", self.sql)
+    }
+}
+
+fn sql_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// What `init` runs against a fresh database. Against one already migrated,
+/// the migrations recorded in `schema_migrations` are skipped.
+fn init_plan() -> Vec<PlannedStatement> {
+    let mut plan = vec![
+        PlannedStatement::new(CREATE_SCHEMA_MIGRATIONS),
+        PlannedStatement::new(LOCK_SCHEMA_MIGRATIONS),
+        PlannedStatement::new(SELECT_APPLIED_VERSIONS),
+    ];
+    for (version, sql) in MIGRATIONS {
+        plan.push(PlannedStatement::new(sql));
+        plan.push(PlannedStatement::with_params(RECORD_MIGRATION, vec![version.to_string()]));
+    }
+    plan
+}
+
+/// What `seed` runs. The product id is only known once the upsert returns
+/// it, so the sales show a placeholder for it.
+fn seed_plan() -> Vec<PlannedStatement> {
+    let (category, name) = SEED_PRODUCT;
+    let mut plan = vec![PlannedStatement::with_params(UPSERT_PRODUCT, vec![sql_literal(category), sql_literal(name)])];
+    for sale in seed_sales(0) {
+        plan.push(PlannedStatement::with_params(INSERT_SALE, vec![
+            sql_literal(&sale.id),
+            format!("<id of {}>", name),
+            sale.date.timestamp().to_string(),
+            sale.quantity.to_string(),
+            sql_literal(&sale.unit),
+        ]));
+    }
+    plan.push(PlannedStatement::redis(cache::INVALIDATE));
+    plan
+}
+
+/// One thing a subcommand does. Running a subcommand and `--dry-run` walk
+/// the same steps, the one running each and the other printing its plan,
+/// so what a dry run lists is what would run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Migrate,
+    Seed,
+    PrintSales,
+    PrintReport(ReportFormat),
+    Export,
+}
+
+impl Step {
+    /// The statements the step runs, in order.
+    fn plan(&self) -> Vec<PlannedStatement> {
+        match self {
+            Step::Migrate => init_plan(),
+            Step::Seed => seed_plan(),
+            Step::PrintSales => vec![PlannedStatement::new(SELECT_SALES)],
+            Step::PrintReport(_) => vec![PlannedStatement::with_params(SELECT_CATEGORY_TOTALS, vec![sql_literal(UNKNOWN)])],
+            Step::Export => vec![PlannedStatement::new(SELECT_EXPORT)],
+        }
+    }
+
+    /// Only the per-category report is cached, and only seeding changes it.
+    fn uses_cache(&self) -> bool {
+        matches!(self, Step::Seed | Step::PrintReport(_))
+    }
+
+    /// Seeding only upserts and skips sales already there, and reports only
+    /// read, so these are safe to retry.
+    fn is_retryable(&self) -> bool {
+        matches!(self, Step::Seed | Step::PrintSales | Step::PrintReport(_))
+    }
+
+    fn run(&self, conn: &Connection, cache: Option<&ReportCache>) -> Result<()> {
+        match *self {
+            Step::Migrate => migrate(conn),
+            Step::Seed => populate_db(conn, cache),
+            Step::PrintSales => print_db(conn),
+            Step::PrintReport(format) => print_report(conn, cache, format),
+            Step::Export => {
+                let written = export_sales_csv(conn, io::BufWriter::new(io::stdout().lock()))?;
+                info!("Exported {} sale(s)", written);
+                Ok(())
+            },
+        }
+    }
+}
+
+/// The steps of `subcommand`, in order.
+fn steps(subcommand: &str, format: Option<ReportFormat>) -> Vec<Step> {
+    match subcommand {
+        "init" => vec![Step::Migrate],
+        "seed" => vec![Step::Seed],
+        "report" => vec![format.map_or(Step::PrintSales, Step::PrintReport)],
+        "export" => vec![Step::Export],
+        "reset" => vec![Step::Migrate, Step::Seed, Step::PrintSales],
+        _ => unreachable!("clap requires a known subcommand"),
+    }
+}
+
+/// Runs `steps` in turn, stopping at the first that fails.
+fn run_steps(conn: &Connection, cache: Option<&ReportCache>, steps: &[Step]) -> Result<()> {
+    for step in steps {
+        step.run(conn, cache)?;
+    }
+    Ok(())
+}
+
+/// Writes what `steps` would run to `out`, for `--dry-run`, without
+/// connecting anywhere.
+fn dry_run<W: io::Write>(mut out: W, steps: &[Step]) -> io::Result<()> {
+    if steps.contains(&Step::Migrate) {
+        writeln!(out, "-- Migrations already recorded in schema_migrations are skipped when run for real.")?;
+    }
+    for statement in steps.iter().flat_map(Step::plan) {
+        writeln!(out, "{}\n", statement)?;
+    }
+    out.flush()
+}
+
+fn main() -> Result<()> {
+    logging::init();
+    let matches = App::new(crate_name!())
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Stores and reports product sales in Postgres")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("init").about("Applies pending schema migrations"))
+        .subcommand(SubCommand::with_name("seed").about("Inserts the sample product and sale"))
+        .subcommand(SubCommand::with_name("report")
+            .about("Prints every sale, or with --format the totals per category")
+            .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&ReportFormat::NAMES)
+                .help("Prints quantity sold per category as a table, JSON or CSV")))
+        .subcommand(SubCommand::with_name("export").about("Writes every sale to stdout as CSV"))
+        .subcommand(SubCommand::with_name("reset").about("Runs init, seed and report in turn"))
+        .arg(Arg::with_name("dry-run")
+            .long("dry-run")
+            .global(true)
+            .help("Prints the SQL the subcommand would run instead of connecting"))
+        .arg(Arg::with_name("no-cache")
+            .long("no-cache")
+            .global(true)
+            .help("Leaves Redis alone: reports straight from Postgres, and seeding keeps any cached report"))
+        .get_matches();
+    let subcommand = matches.subcommand_name().expect("clap requires a subcommand");
+    let format = matches
+        .subcommand_matches("report")
+        .and_then(|report| report.value_of("format"))
+        .map(|format| format.parse().expect("clap checks the format"));
+    let steps = steps(subcommand, format);
+    if matches.is_present("dry-run") {
+        dry_run(io::stdout().lock(), &steps)?;
+        return Ok(());
+    }
+    let settings = Settings::load().expect("Can't load settings");
+    let config = ConnectionConfig::from_env(settings.database_url()).unwrap_or_else(|err| {
+        error!("Invalid connection settings: {}", err);
+        process::exit(1);
+    });
+    let conn: Connection = match connect(&config) {
+        Ok(conn) => conn,
+        Err(ref err) if err.as_io().is_some() => {
+            error!("Could not connect to Postgres at {} — is it running?", config.address());
+            process::exit(1);
+        },
+        Err(err) => return Err(err),
+    };
+    let cache = if steps.iter().any(Step::uses_cache) && !matches.is_present("no-cache") {
+        ReportCache::connect(settings.redis_url())
+    } else {
+        None
+    };
+    let cache = cache.as_ref();
+    if steps.iter().all(Step::is_retryable) {
+        let breaker = CircuitBreaker::new(DB_RETRIES, DB_BACKOFF, DB_COOLDOWN);
+        with_retries(&breaker, &config, conn, |conn| run_steps(conn, cache, &steps))
+    } else {
+        run_steps(&conn, cache, &steps)
+    }
+}
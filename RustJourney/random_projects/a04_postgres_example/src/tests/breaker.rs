@@ -0,0 +1,119 @@
+//! The circuit breaker retries transient failures, and once they persist
+//! fails at once until its cooldown is over.
+
+use std::cell::Cell;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use postgres::{Connection, Error, TlsMode};
+
+use super::on_database;
+use crate::breaker::{is_transient, CircuitBreaker};
+
+const COOLDOWN: Duration = Duration::from_millis(100);
+
+fn breaker() -> CircuitBreaker {
+    CircuitBreaker::new(3, Duration::from_millis(1), COOLDOWN)
+}
+
+/// What a dropped connection looks like.
+fn dropped() -> Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "connection reset by peer").into()
+}
+
+/// An error that no retry fixes, reached without touching the network.
+fn bad_params() -> Error {
+    Connection::connect("not a url", TlsMode::None).expect_err("the URL is refused")
+}
+
+#[test]
+fn a_transient_failure_is_retried_until_it_passes() {
+    let breaker = breaker();
+    let calls = Cell::new(0);
+    let result = breaker.call(|| {
+        calls.set(calls.get() + 1);
+        if calls.get() < 3 {
+            Err(dropped())
+        } else {
+            Ok("sales")
+        }
+    });
+    assert_eq!(result.unwrap(), "sales");
+    assert_eq!(calls.get(), 3);
+
+    // Having recovered, the circuit stays closed.
+    assert_eq!(breaker.call(|| Ok(1)).unwrap(), 1);
+}
+
+#[test]
+fn a_persistent_failure_opens_the_circuit_for_the_cooldown() {
+    let breaker = breaker();
+    let calls = Cell::new(0);
+    let result: Result<(), Error> = breaker.call(|| {
+        calls.set(calls.get() + 1);
+        Err(dropped())
+    });
+    assert_eq!(result.unwrap_err().as_io().unwrap().kind(), io::ErrorKind::ConnectionReset);
+    assert_eq!(calls.get(), 4, "the first try and three retries");
+
+    let started = Instant::now();
+    let err = breaker.call(|| {
+        calls.set(calls.get() + 1);
+        Ok(())
+    }).unwrap_err();
+    assert!(started.elapsed() < COOLDOWN);
+    assert!(err.to_string().contains("not trying Postgres"), "{}", err);
+    assert_eq!(calls.get(), 4, "an open circuit does not run the operation");
+
+    thread::sleep(COOLDOWN);
+    assert!(breaker.call(|| {
+        calls.set(calls.get() + 1);
+        Ok(())
+    }).is_ok());
+    assert_eq!(calls.get(), 5);
+}
+
+#[test]
+fn a_failure_after_the_cooldown_opens_the_circuit_anew() {
+    let breaker = breaker();
+    let _ = breaker.call::<(), _>(|| Err(dropped()));
+    thread::sleep(COOLDOWN);
+
+    let calls = Cell::new(0);
+    let _ = breaker.call::<(), _>(|| {
+        calls.set(calls.get() + 1);
+        Err(dropped())
+    });
+    assert_eq!(calls.get(), 4, "the cooldown over, the retries are had again");
+    assert!(breaker.call(|| Ok(())).is_err());
+}
+
+#[test]
+fn an_error_that_is_not_transient_is_returned_at_once() {
+    assert!(!is_transient(&bad_params()));
+    let breaker = breaker();
+    let calls = Cell::new(0);
+    let result: Result<(), Error> = breaker.call(|| {
+        calls.set(calls.get() + 1);
+        Err(bad_params())
+    });
+    assert!(result.is_err());
+    assert_eq!(calls.get(), 1);
+    assert!(breaker.call(|| Ok(())).is_ok(), "only transient failures open the circuit");
+}
+
+#[test]
+fn a_constraint_violation_is_not_retried() {
+    on_database("breaker_constraint", |conn| {
+        let breaker = breaker();
+        let calls = Cell::new(0);
+        let err = breaker.call(|| {
+            calls.set(calls.get() + 1);
+            conn.execute("INSERT INTO Products (category, name) VALUES ('fruit', 'pears'), ('nuts', 'pears')", &[])
+        }).unwrap_err();
+        assert!(err.as_db().is_some(), "{}", err);
+        assert!(!is_transient(&err));
+        assert_eq!(calls.get(), 1);
+    });
+}
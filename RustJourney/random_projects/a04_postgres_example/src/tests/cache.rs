@@ -0,0 +1,156 @@
+//! The per-category report is read aside through Redis: Postgres is only
+//! asked on a miss, after a write drops the cached report, or when Redis
+//! cannot be reached.
+
+use std::cell::Cell;
+use std::time::Duration;
+
+use postgres::{Connection, Result};
+use redis::Commands;
+
+use super::{on_database, on_redis, sales};
+use crate::cache::{cached_report_sales, ReportCache, SalesRepository};
+use crate::{insert_sales, populate_db, upsert_product, SaleInserter, SalesSummary};
+
+const TTL: Duration = Duration::from_secs(60);
+
+/// Postgres, counting how often the report is read from it.
+struct Counting<'conn> {
+    conn: &'conn Connection,
+    reads: Cell<usize>,
+}
+
+impl<'conn> Counting<'conn> {
+    fn new(conn: &'conn Connection) -> Counting<'conn> {
+        Counting { conn, reads: Cell::new(0) }
+    }
+}
+
+impl<'conn> SalesRepository for Counting<'conn> {
+    fn sales_by_category(&self) -> Result<Vec<SalesSummary>> {
+        self.reads.set(self.reads.get() + 1);
+        self.conn.sales_by_category()
+    }
+}
+
+fn pairs(report: &[SalesSummary]) -> Vec<(&str, f64)> {
+    report.iter().map(|row| (row.category.as_str(), row.quantity)).collect()
+}
+
+#[test]
+fn a_second_report_within_the_ttl_is_not_read_from_postgres() {
+    on_database("cache_second_report", |conn| {
+        on_redis("cache_second_report", |cache, redis| {
+            populate_db(conn, None).unwrap();
+            let db = Counting::new(conn);
+
+            let first = cached_report_sales(&db, Some(cache), TTL).unwrap();
+            assert_eq!(pairs(&first), vec![("fruit", 7.34)]);
+            assert_eq!(db.reads.get(), 1);
+            let ttl: i64 = redis.ttl("sales:report").unwrap();
+            assert!(ttl > 0 && ttl <= 60, "the report is cached for {}s", ttl);
+
+            let second = cached_report_sales(&db, Some(cache), TTL).unwrap();
+            assert_eq!(pairs(&second), pairs(&first));
+            assert_eq!(db.reads.get(), 1);
+        });
+    });
+}
+
+#[test]
+fn seeding_drops_the_cached_report() {
+    on_database("cache_seed_invalidates", |conn| {
+        on_redis("cache_seed_invalidates", |cache, redis| {
+            let product_id = upsert_product(conn, "vegetable", "leeks").unwrap();
+            insert_sales(conn, &sales(product_id, 3), None).unwrap();
+            let db = Counting::new(conn);
+            let before = cached_report_sales(&db, Some(cache), TTL).unwrap();
+            assert_eq!(pairs(&before), vec![("vegetable", 0.75)]);
+
+            populate_db(conn, Some(cache)).unwrap();
+            let exists: bool = redis.exists("sales:report").unwrap();
+            assert!(!exists);
+            let after = cached_report_sales(&db, Some(cache), TTL).unwrap();
+            assert_eq!(pairs(&after), vec![("fruit", 7.34), ("vegetable", 0.75)]);
+            assert_eq!(db.reads.get(), 2);
+        });
+    });
+}
+
+#[test]
+fn inserting_a_sale_makes_the_next_report_miss() {
+    on_database("cache_insert_invalidates", |conn| {
+        on_redis("cache_insert_invalidates", |cache, _| {
+            let product_id = upsert_product(conn, "vegetable", "leeks").unwrap();
+            let db = Counting::new(conn);
+            assert_eq!(pairs(&cached_report_sales(&db, Some(cache), TTL).unwrap()), vec![]);
+
+            let mut sales = sales(product_id, 3).into_iter();
+            insert_sales(conn, &[sales.next().unwrap()], Some(cache)).unwrap();
+            assert_eq!(pairs(&cached_report_sales(&db, Some(cache), TTL).unwrap()), vec![("vegetable", 0.0)]);
+            assert_eq!(db.reads.get(), 2);
+
+            SaleInserter::new(conn, Some(cache)).unwrap().insert(&sales.next().unwrap()).unwrap();
+            assert_eq!(pairs(&cached_report_sales(&db, Some(cache), TTL).unwrap()), vec![("vegetable", 0.25)]);
+            assert_eq!(db.reads.get(), 3);
+        });
+    });
+}
+
+#[test]
+fn a_sale_already_there_leaves_the_cached_report() {
+    on_database("cache_skipped_insert", |conn| {
+        on_redis("cache_skipped_insert", |cache, _| {
+            let product_id = upsert_product(conn, "vegetable", "leeks").unwrap();
+            insert_sales(conn, &sales(product_id, 2), None).unwrap();
+            let db = Counting::new(conn);
+            cached_report_sales(&db, Some(cache), TTL).unwrap();
+
+            assert_eq!(insert_sales(conn, &sales(product_id, 2), Some(cache)).unwrap(), 0);
+            cached_report_sales(&db, Some(cache), TTL).unwrap();
+            assert_eq!(db.reads.get(), 1);
+        });
+    });
+}
+
+#[test]
+fn a_cached_report_that_no_longer_parses_is_a_miss() {
+    on_database("cache_unparsable", |conn| {
+        on_redis("cache_unparsable", |cache, redis| {
+            populate_db(conn, None).unwrap();
+            redis.set::<_, _, ()>("sales:report", "{\"from\": \"an older version\"}").unwrap();
+            let db = Counting::new(conn);
+
+            let report = cached_report_sales(&db, Some(cache), TTL).unwrap();
+            assert_eq!(pairs(&report), vec![("fruit", 7.34)]);
+            assert_eq!(db.reads.get(), 1);
+            let cached: String = redis.get("sales:report").unwrap();
+            assert_eq!(cached, serde_json::to_string(&report).unwrap());
+        });
+    });
+}
+
+#[test]
+fn without_a_cache_every_report_is_read_from_postgres() {
+    on_database("cache_none", |conn| {
+        populate_db(conn, None).unwrap();
+        let db = Counting::new(conn);
+        for _ in 0..2 {
+            assert_eq!(pairs(&cached_report_sales(&db, None, TTL).unwrap()), vec![("fruit", 7.34)]);
+        }
+        assert_eq!(db.reads.get(), 2);
+    });
+}
+
+#[test]
+fn a_redis_that_is_down_leaves_the_report_to_postgres() {
+    on_database("cache_redis_down", |conn| {
+        populate_db(conn, None).unwrap();
+        let cache = ReportCache::connect("redis://127.0.0.1:1");
+        assert!(cache.is_none());
+        let db = Counting::new(conn);
+        let report = cached_report_sales(&db, cache.as_ref(), TTL).unwrap();
+        assert_eq!(pairs(&report), vec![("fruit", 7.34)]);
+        assert_eq!(db.reads.get(), 1);
+    });
+}
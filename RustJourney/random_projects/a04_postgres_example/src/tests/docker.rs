@@ -0,0 +1,40 @@
+//! A throwaway Postgres in Docker, for the `docker-tests` feature.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use postgres::{Connection, TlsMode};
+use testcontainers::core::WaitFor;
+use testcontainers::{clients, GenericImage};
+
+const IMAGE: &str = "postgres";
+const TAG: &str = "16-alpine";
+/// How long the server may take to accept connections once its container
+/// says it is ready.
+const STARTUP: Duration = Duration::from_secs(30);
+
+/// Runs `test` with the URL of a Postgres in a container of its own, which
+/// is removed when `test` returns or panics.
+pub fn on_container<F: FnOnce(&str)>(test: F) {
+    let docker = clients::Cli::default();
+    let image = GenericImage::new(IMAGE, TAG)
+        .with_env_var("POSTGRES_HOST_AUTH_METHOD", "trust")
+        .with_wait_for(WaitFor::message_on_stderr("database system is ready to accept connections"));
+    let container = docker.run(image);
+    let url = format!("postgres://postgres@127.0.0.1:{}/postgres", container.get_host_port_ipv4(5432));
+    wait_until_accepting(&url);
+    test(&url);
+}
+
+/// The image's entrypoint first runs a server only it can reach, to
+/// initialise the database, and logs the same readiness line for it; the
+/// real one starts once that has stopped.
+fn wait_until_accepting(url: &str) {
+    let started = Instant::now();
+    while let Err(err) = Connection::connect(url, TlsMode::None) {
+        if started.elapsed() > STARTUP {
+            panic!("the Postgres container did not accept connections within {:?}: {}", STARTUP, err);
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,69 @@
+//! `--dry-run` lists what a subcommand would run and runs none of it.
+
+use super::{count, on_database};
+use crate::cache::INVALIDATE;
+use crate::{
+    dry_run, init_plan, run_steps, steps, ReportFormat, Step, CREATE_SCHEMA_MIGRATIONS, INSERT_SALE, MIGRATIONS,
+    RECORD_MIGRATION, SELECT_CATEGORY_TOTALS, SELECT_EXPORT, SELECT_SALES, UPSERT_PRODUCT,
+};
+
+fn sql(subcommand: &str, format: Option<ReportFormat>) -> Vec<&'static str> {
+    steps(subcommand, format).iter().flat_map(Step::plan).map(|statement| statement.sql).collect()
+}
+
+fn printed(subcommand: &str, format: Option<ReportFormat>) -> String {
+    let mut out = Vec::new();
+    dry_run(&mut out, &steps(subcommand, format)).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn seeding_plans_the_upsert_then_each_sale_then_dropping_the_cached_report() {
+    assert_eq!(
+        printed("seed", None),
+        format!(
+            "-- $1 = 'fruit', $2 = 'pears'\n{};\n\n\
+             -- $1 = '2020-183', $2 = <id of pears>, $3 = 1234567890, $4 = 7.34, $5 = 'Kg'\n{};\n\n\
+             -- On Redis, unless --no-cache\n{}\n\n",
+            UPSERT_PRODUCT, INSERT_SALE, INVALIDATE,
+        ),
+    );
+}
+
+#[test]
+fn init_plans_every_migration_and_records_it() {
+    let plan = init_plan();
+    assert_eq!(plan[0].sql, CREATE_SCHEMA_MIGRATIONS);
+    for (version, migration) in MIGRATIONS {
+        let at = plan.iter().position(|statement| statement.sql == *migration).unwrap();
+        assert_eq!(plan[at + 1].sql, RECORD_MIGRATION);
+        assert_eq!(plan[at + 1].params, vec![version.to_string()]);
+    }
+    assert!(printed("init", None).starts_with("-- Migrations already recorded"));
+}
+
+#[test]
+fn reset_plans_init_seed_and_report_in_turn() {
+    let mut expected = sql("init", None);
+    expected.extend(sql("seed", None));
+    expected.push(SELECT_SALES);
+    assert_eq!(sql("reset", None), expected);
+    assert_eq!(sql("report", Some(ReportFormat::Csv)), vec![SELECT_CATEGORY_TOTALS]);
+    assert_eq!(sql("export", None), vec![SELECT_EXPORT]);
+}
+
+#[test]
+fn a_dry_run_of_reset_inserts_nothing_where_running_its_steps_does() {
+    on_database("dry_run_inserts_nothing", |conn| {
+        let reset = steps("reset", None);
+        let mut out = Vec::new();
+        dry_run(&mut out, &reset).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains(INSERT_SALE));
+        assert_eq!(count(conn, "Products"), 0);
+        assert_eq!(count(conn, "Sales"), 0);
+
+        run_steps(conn, None, &reset).unwrap();
+        assert_eq!(count(conn, "Products"), 1);
+        assert_eq!(count(conn, "Sales"), 1);
+    });
+}
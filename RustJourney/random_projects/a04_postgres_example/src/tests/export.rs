@@ -0,0 +1,57 @@
+//! The export streams every sale through a cursor, in batches.
+
+use std::collections::HashSet;
+
+use super::{count, on_database};
+use crate::{export_sales_csv, upsert_product};
+
+/// Far more rows than one `EXPORT_BATCH`, so the cursor has to be walked.
+const SALES: i64 = 10_000;
+
+#[test]
+fn ten_thousand_sales_are_all_exported() {
+    on_database("export_ten_thousand", |conn| {
+        let product_id = upsert_product(conn, "fruit", "pears").unwrap();
+        conn.execute(
+            "INSERT INTO Sales (id, product_id, sale_date, quantity, unit) \
+                SELECT 'sale-' || n, $1, 1587110400 + n * 60, n / 4.0::DOUBLE PRECISION, 'Kg' \
+                FROM generate_series(0, $2::BIGINT - 1) AS n",
+            &[&product_id, &SALES],
+        ).unwrap();
+        assert_eq!(count(conn, "Sales"), SALES);
+
+        let mut out = Vec::new();
+        assert_eq!(export_sales_csv(conn, &mut out).unwrap(), SALES as u64);
+        let csv = String::from_utf8(out).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("id,category,product,quantity,unit,sale_date"));
+        let ids: Vec<&str> = lines.map(|line| line.split(',').next().unwrap()).collect();
+        assert_eq!(ids.len(), SALES as usize);
+        let unique: HashSet<&str> = ids.iter().cloned().collect();
+        assert_eq!(unique.len(), ids.len());
+        for n in 0..SALES {
+            assert!(unique.contains(format!("sale-{}", n).as_str()), "sale-{} is missing", n);
+        }
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(ids, sorted);
+    });
+}
+
+#[test]
+fn a_sale_is_exported_with_its_product() {
+    on_database("export_line", |conn| {
+        let product_id = upsert_product(conn, "fruit", "pears").unwrap();
+        conn.execute(
+            "INSERT INTO Sales (id, product_id, sale_date, quantity, unit) VALUES ('2020-183', $1, 1587110400, 7.439, 'Kg')",
+            &[&product_id],
+        ).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(export_sales_csv(conn, &mut out).unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "id,category,product,quantity,unit,sale_date\n2020-183,fruit,pears,7.439,Kg,2020-04-17T08:00:00+00:00\n",
+        );
+    });
+}
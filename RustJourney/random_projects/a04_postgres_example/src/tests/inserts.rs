@@ -0,0 +1,29 @@
+//! Sales go in through one prepared statement.
+
+use super::{count, on_database, sales};
+use crate::{insert_sales, upsert_product, SaleInserter};
+
+#[test]
+fn a_thousand_sales_go_through_one_prepared_statement() {
+    on_database("prepared_thousand", |conn| {
+        let product_id = upsert_product(conn, "fruit", "pears").unwrap();
+        let sales = sales(product_id, 1000);
+        let mut inserter = SaleInserter::new(conn, None).unwrap();
+        let inserted: u64 = sales.iter().map(|sale| inserter.insert(sale).unwrap()).sum();
+        assert_eq!(inserted, 1000);
+        assert_eq!(count(conn, "Sales"), 1000);
+
+        let total: f64 = conn.query("SELECT SUM(quantity) FROM Sales", &[]).unwrap().get(0).get(0);
+        assert_eq!(total, (0..1000).map(|index| index as f64 / 4.0).sum::<f64>());
+    });
+}
+
+#[test]
+fn a_sale_already_there_is_skipped() {
+    on_database("sale_skipped", |conn| {
+        let product_id = upsert_product(conn, "fruit", "pears").unwrap();
+        assert_eq!(insert_sales(conn, &sales(product_id, 3), None).unwrap(), 3);
+        assert_eq!(insert_sales(conn, &sales(product_id, 5), None).unwrap(), 2);
+        assert_eq!(count(conn, "Sales"), 5);
+    });
+}
@@ -0,0 +1,32 @@
+//! Migrations are recorded as they apply, so applying them again changes
+//! nothing.
+
+use super::{count, on_database, sales};
+use crate::{apply_migrations, insert_sales, upsert_product, MIGRATIONS};
+
+#[test]
+fn every_migration_is_recorded_once_applied() {
+    on_database("migrations_recorded", |conn| {
+        let versions: Vec<i32> = conn
+            .query("SELECT version FROM schema_migrations ORDER BY version", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        let expected: Vec<i32> = MIGRATIONS.iter().map(|(version, _)| *version).collect();
+        assert_eq!(versions, expected);
+    });
+}
+
+#[test]
+fn applying_the_migrations_twice_is_a_no_op() {
+    on_database("migrations_twice", |conn| {
+        let product_id = upsert_product(conn, "fruit", "pears").unwrap();
+        insert_sales(conn, &sales(product_id, 3), None).unwrap();
+
+        assert_eq!(apply_migrations(conn).unwrap(), 0);
+        assert_eq!(count(conn, "schema_migrations"), MIGRATIONS.len() as i64);
+        assert_eq!(count(conn, "Products"), 1);
+        assert_eq!(count(conn, "Sales"), 3);
+    });
+}
@@ -0,0 +1,119 @@
+//! Tests of the queries, run against a real Postgres when
+//! `TEST_DATABASE_URL` names a database for them, or with the
+//! `docker-tests` feature against one started in Docker for each test.
+//! Each test works in a schema of its own there, migrated fresh and dropped
+//! when the test ends, so tests run side by side without seeing each
+//! other's rows. The tests of the report cache also need the Redis named
+//! by `TEST_REDIS_URL`, which they take turns at.
+
+mod breaker;
+mod cache;
+#[cfg(feature = "docker-tests")]
+mod docker;
+mod dry_run;
+mod export;
+mod inserts;
+mod migrations;
+mod products;
+mod report;
+mod rows;
+mod schema;
+
+use std::env;
+use std::process;
+use std::sync::Mutex;
+
+use chrono::{Duration, TimeZone, Utc};
+use postgres::Connection;
+
+use crate::cache::ReportCache;
+use crate::{apply_migrations, connect, ConnectionConfig, NewSale};
+
+/// Held by whichever test is using Redis, as the report has one key there.
+static REDIS_TAKEN: Mutex<()> = Mutex::new(());
+
+/// A test's schema, dropped with everything in it when the test ends, a
+/// failing one's included.
+struct Schema<'conn> {
+    conn: &'conn Connection,
+    name: String,
+}
+
+impl<'conn> Drop for Schema<'conn> {
+    fn drop(&mut self) {
+        if let Err(err) = self.conn.batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", self.name)) {
+            eprintln!("Could not drop the test schema {}: {}", self.name, err);
+        }
+    }
+}
+
+/// Runs `test` on a connection to the test database whose `search_path` is
+/// a freshly migrated schema named after `name`. Without a test database it
+/// is skipped, unless `docker-tests` brings one up.
+pub fn on_database<F: FnOnce(&Connection)>(name: &str, test: F) {
+    match env::var("TEST_DATABASE_URL").ok().filter(|url| !url.is_empty()) {
+        Some(url) => on_database_at(&url, name, test),
+        None => without_test_database(name, test),
+    }
+}
+
+#[cfg(not(feature = "docker-tests"))]
+fn without_test_database<F: FnOnce(&Connection)>(name: &str, _test: F) {
+    eprintln!("TEST_DATABASE_URL is not set, skipping {}", name);
+}
+
+#[cfg(feature = "docker-tests")]
+fn without_test_database<F: FnOnce(&Connection)>(name: &str, test: F) {
+    docker::on_container(|url| on_database_at(url, name, test));
+}
+
+fn on_database_at<F: FnOnce(&Connection)>(url: &str, name: &str, test: F) {
+    let config = ConnectionConfig::from_url(url).expect("the test database URL is a connection URL");
+    let conn = connect(&config).expect("the test database is reachable");
+    let schema = Schema { conn: &conn, name: format!("a04_test_{}_{}", name, process::id()) };
+    conn.batch_execute(&format!(
+        "DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0}; SET search_path TO {0}",
+        schema.name,
+    )).unwrap();
+    apply_migrations(&conn).expect("the migrations apply");
+    test(&conn);
+    drop(schema);
+}
+
+/// Runs `test` with the report cache at `TEST_REDIS_URL` and a connection
+/// of its own there, the cached report dropped first. Without a test Redis
+/// it is skipped.
+pub fn on_redis<F: FnOnce(&ReportCache, &redis::Connection)>(name: &str, test: F) {
+    let url = match env::var("TEST_REDIS_URL").ok().filter(|url| !url.is_empty()) {
+        Some(url) => url,
+        None => {
+            eprintln!("TEST_REDIS_URL is not set, skipping {}", name);
+            return;
+        },
+    };
+    // A failed test only poisons the lock, it leaves nothing half done.
+    let _turn = REDIS_TAKEN.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let cache = ReportCache::connect(&url).expect("the test Redis is reachable");
+    cache.invalidate();
+    let redis = redis::Client::open(url.as_str()).and_then(|client| client.get_connection()).unwrap();
+    test(&cache, &redis);
+}
+
+/// How many rows `table` holds.
+pub fn count(conn: &Connection, table: &str) -> i64 {
+    conn.query(&format!("SELECT COUNT(*) FROM {}", table), &[]).unwrap().get(0).get(0)
+}
+
+/// `count` sales of product `product_id`, a minute apart, ids `sale-0` on.
+pub fn sales(product_id: i32, count: usize) -> Vec<NewSale> {
+    let start = Utc.with_ymd_and_hms(2020, 4, 17, 8, 0, 0).unwrap();
+    (0..count)
+        .map(|index| NewSale {
+            id: format!("sale-{}", index),
+            product_id,
+            date: start + Duration::minutes(index as i64),
+            quantity: index as f64 / 4.0,
+            unit: "Kg".to_string(),
+        })
+        .collect()
+}
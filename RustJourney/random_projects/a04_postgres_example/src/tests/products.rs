@@ -0,0 +1,37 @@
+//! Products are upserted by name, so seeding twice changes nothing.
+
+use super::{count, on_database};
+use crate::{populate_db, upsert_product};
+
+#[test]
+fn upserting_a_name_twice_returns_the_same_id() {
+    on_database("upsert_same_id", |conn| {
+        let first = upsert_product(conn, "fruit", "pears").unwrap();
+        let second = upsert_product(conn, "fruit", "pears").unwrap();
+        assert_eq!(first, second);
+        assert_eq!(count(conn, "Products"), 1);
+    });
+}
+
+#[test]
+fn upserting_an_existing_name_updates_its_category() {
+    on_database("upsert_category", |conn| {
+        let id = upsert_product(conn, "fruit", "tomatoes").unwrap();
+        assert_eq!(upsert_product(conn, "vegetable", "tomatoes").unwrap(), id);
+        let category: String = conn.query("SELECT category FROM Products WHERE id = $1", &[&id]).unwrap().get(0).get(0);
+        assert_eq!(category, "vegetable");
+
+        assert_ne!(upsert_product(conn, "fruit", "pears").unwrap(), id);
+        assert_eq!(count(conn, "Products"), 2);
+    });
+}
+
+#[test]
+fn seeding_twice_leaves_one_product_and_one_sale() {
+    on_database("seed_twice", |conn| {
+        populate_db(conn, None).unwrap();
+        populate_db(conn, None).unwrap();
+        assert_eq!(count(conn, "Products"), 1);
+        assert_eq!(count(conn, "Sales"), 1);
+    });
+}
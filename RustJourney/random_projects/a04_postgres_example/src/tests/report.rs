@@ -0,0 +1,50 @@
+//! Each report format renders the same rows, and JSON reads back into them.
+
+use crate::{csv_field, ReportFormat, SalesSummary};
+
+fn rows() -> Vec<SalesSummary> {
+    vec![
+        SalesSummary { category: "fruit".to_string(), quantity: 7.34 },
+        SalesSummary { category: "nuts, dried".to_string(), quantity: 0.5 },
+        SalesSummary { category: "unknown".to_string(), quantity: 12.0 },
+    ]
+}
+
+#[test]
+fn the_json_report_parses_back_into_the_same_pairs() {
+    let json = ReportFormat::Json.render(&rows());
+    let pairs: Vec<(String, f64)> = serde_json::from_str(&json).unwrap();
+    let expected: Vec<(String, f64)> = rows().into_iter().map(|row| (row.category, row.quantity)).collect();
+    assert_eq!(pairs, expected);
+    assert_eq!(serde_json::from_str::<Vec<(String, f64)>>(&ReportFormat::Json.render(&[])).unwrap(), vec![]);
+}
+
+#[test]
+fn the_csv_report_quotes_what_needs_it() {
+    assert_eq!(
+        ReportFormat::Csv.render(&rows()),
+        "category,quantity\nfruit,7.34\n\"nuts, dried\",0.5\nunknown,12\n",
+    );
+    assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+}
+
+#[test]
+fn the_table_report_lines_up_its_columns() {
+    assert_eq!(
+        ReportFormat::Table.render(&rows()),
+        concat!(
+            "category       quantity\n",
+            "fruit              7.34\n",
+            "nuts, dried        0.50\n",
+            "unknown           12.00\n",
+        ),
+    );
+}
+
+#[test]
+fn every_format_name_parses() {
+    for name in &ReportFormat::NAMES {
+        assert!(name.parse::<ReportFormat>().is_ok(), "{}", name);
+    }
+    assert_eq!("xml".parse::<ReportFormat>(), Err("unknown report format \"xml\"".to_string()));
+}
@@ -0,0 +1,62 @@
+//! Report rows are mapped without panicking on NULL or malformed columns.
+
+use postgres::Connection;
+
+use super::{on_database, sales};
+use crate::{insert_sales, sale_from_row, sales_by_category, upsert_product, SaleWithProduct, SalesSummary, SELECT_SALES};
+
+/// `sale_from_row` of the one row `select` returns, its columns in the
+/// order of `SELECT_SALES`: name, unit, quantity, sale_date, category.
+fn map(conn: &Connection, select: &str) -> Option<SaleWithProduct> {
+    let rows = conn.query(select, &[]).unwrap();
+    sale_from_row(&rows.get(0))
+}
+
+#[test]
+fn a_sale_of_a_known_product_maps_in_full() {
+    on_database("row_known_product", |conn| {
+        let product_id = upsert_product(conn, "fruit", "pears").unwrap();
+        insert_sales(conn, &sales(product_id, 1), None).unwrap();
+        let rows = conn.query(SELECT_SALES, &[]).unwrap();
+        let sale = sale_from_row(&rows.get(0)).expect("a complete row maps");
+        assert_eq!(sale.name, "pears");
+        assert_eq!(sale.category, "fruit");
+        assert_eq!(sale.quantity, 0.0);
+        assert_eq!(sale.unit, "Kg");
+        assert_eq!(sale.date.to_rfc3339(), "2020-04-17T08:00:00+00:00");
+    });
+}
+
+#[test]
+fn a_sale_whose_product_is_gone_is_of_an_unknown_product() {
+    on_database("row_product_gone", |conn| {
+        let product_id = upsert_product(conn, "fruit", "pears").unwrap();
+        insert_sales(conn, &sales(product_id, 2), None).unwrap();
+        conn.batch_execute("ALTER TABLE Sales DROP CONSTRAINT sales_product_id_fkey; DELETE FROM Products").unwrap();
+
+        let rows = conn.query(SELECT_SALES, &[]).unwrap();
+        let sale = sale_from_row(&rows.get(0)).expect("a sale without its product still maps");
+        assert_eq!((sale.name.as_str(), sale.category.as_str()), ("unknown", "unknown"));
+        assert_eq!(sales_by_category(conn).unwrap(), vec![SalesSummary { category: "unknown".to_string(), quantity: 0.25 }]);
+    });
+}
+
+#[test]
+fn a_sale_missing_its_own_columns_is_skipped() {
+    on_database("row_missing_columns", |conn| {
+        assert!(map(conn, "SELECT 'pears', 'Kg', NULL::float8, 1234567890::int8, 'fruit'").is_none());
+        assert!(map(conn, "SELECT 'pears', NULL::text, 7.34::float8, 1234567890::int8, 'fruit'").is_none());
+        assert!(map(conn, "SELECT 'pears', 'Kg', 7.34::float8, NULL::int8, 'fruit'").is_none());
+        let sale = map(conn, "SELECT 'pears', 'Kg', 7.34::float8, 1234567890::int8, 'fruit'").unwrap();
+        assert_eq!(sale.quantity, 7.34);
+    });
+}
+
+#[test]
+fn a_column_of_the_wrong_type_is_as_good_as_missing() {
+    on_database("row_wrong_type", |conn| {
+        assert!(map(conn, "SELECT 'pears', 'Kg', '7.34'::text, 1234567890::int8, 'fruit'").is_none());
+        let sale = map(conn, "SELECT 42, 'Kg', 7.34::float8, 1234567890::int8, 'fruit'").unwrap();
+        assert_eq!(sale.name, "unknown");
+    });
+}
@@ -0,0 +1,24 @@
+//! Every test starts from the tables `init` creates, empty and in a schema
+//! no other test sees.
+
+use super::{count, on_database};
+use crate::populate_db;
+
+#[test]
+fn a_test_starts_from_empty_tables() {
+    on_database("empty_tables", |conn| {
+        assert_eq!(count(conn, "Products"), 0);
+        assert_eq!(count(conn, "Sales"), 0);
+    });
+}
+
+#[test]
+fn a_test_sees_only_its_own_rows() {
+    on_database("own_rows", |conn| {
+        populate_db(conn, None).unwrap();
+        on_database("own_rows_other", |other| {
+            assert_eq!(count(other, "Products"), 0);
+        });
+        assert_eq!(count(conn, "Products"), 1);
+    });
+}
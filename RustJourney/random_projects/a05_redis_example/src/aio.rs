@@ -0,0 +1,168 @@
+use futures::future;
+use redis::aio::{Connection, MultiplexedConnection};
+use redis::{AsyncCommands, Client, Commands, RedisResult};
+use tokio::join;
+
+use crate::key::KeyBuilder;
+
+/// The last segments of the keys the async demo writes, under `async`, and
+/// their values.
+const DEMO_PAIRS: [(&str, &str); 3] = [("one", "1"), ("two", "2"), ("three", "3")];
+const STRESS_KEY: &str = "counter";
+/// INCRs the stress run has in flight at once.
+pub const STRESS_INCREMENTS: i64 = 100;
+
+// Each command borrows its connection mutably, so every future in flight
+// gets a clone of the multiplexed one; the clones share the one socket.
+
+async fn set(mut conn: MultiplexedConnection, key: &str, value: &str) -> RedisResult<()> {
+    conn.set(key, value).await
+}
+
+async fn get(mut conn: MultiplexedConnection, key: &str) -> RedisResult<Option<String>> {
+    conn.get(key).await
+}
+
+async fn incr(mut conn: MultiplexedConnection, key: &str) -> RedisResult<i64> {
+    conn.incr(key, 1).await
+}
+
+/// The demo key ending in `name`, built through `keys`.
+fn key(keys: &KeyBuilder, name: &str) -> RedisResult<String> {
+    Ok(keys.raw(&["async", name])?)
+}
+
+/// The three demo keys with their values.
+fn demo_pairs(keys: &KeyBuilder) -> RedisResult<[(String, &'static str); 3]> {
+    let [(one, one_value), (two, two_value), (three, three_value)] = DEMO_PAIRS;
+    Ok([(key(keys, one)?, one_value), (key(keys, two)?, two_value), (key(keys, three)?, three_value)])
+}
+
+/// Sets the three demo keys at once on the multiplexed connection, then
+/// reads them back at once, and returns each key with the value read.
+pub async fn demo(conn: &MultiplexedConnection, keys: &KeyBuilder) -> RedisResult<Vec<(String, Option<String>)>> {
+    let [(one, one_value), (two, two_value), (three, three_value)] = demo_pairs(keys)?;
+    let (first, second, third) = join!(
+        set(conn.clone(), &one, one_value),
+        set(conn.clone(), &two, two_value),
+        set(conn.clone(), &three, three_value)
+    );
+    first?;
+    second?;
+    third?;
+    let (first, second, third) = join!(get(conn.clone(), &one), get(conn.clone(), &two), get(conn.clone(), &three));
+    Ok(vec![(one, first?), (two, second?), (three, third?)])
+}
+
+/// Sets the first two demo keys and reads each back, the first over a
+/// plain async connection and the second over a multiplexed one, under the
+/// keys `KeyBuilder::from_env` builds: the async counterpart of
+/// `set_get_demo_sync`, with each command awaited where the sync one
+/// blocks.
+pub async fn set_get_demo(client: &Client) -> RedisResult<Vec<(String, Option<String>)>> {
+    set_get_demo_under(client, &KeyBuilder::from_env()?).await
+}
+
+/// `set_get_demo` under `keys`. A plain connection is borrowed by the
+/// command it is sending, so its GET can only be sent once its SET has
+/// finished; a multiplexed one can be cloned and shared, though here too
+/// each command is awaited before the next, as in the sync version.
+async fn set_get_demo_under(client: &Client, keys: &KeyBuilder) -> RedisResult<Vec<(String, Option<String>)>> {
+    let [(plain_key, plain_value), (shared_key, shared_value), _] = demo_pairs(keys)?;
+    let mut plain: Connection = client.get_async_connection().await?;
+    plain.set::<_, _, ()>(&plain_key, plain_value).await?;
+    let plain_read = plain.get(&plain_key).await?;
+    let mut shared = client.get_multiplexed_async_connection().await?;
+    shared.set::<_, _, ()>(&shared_key, shared_value).await?;
+    let shared_read = shared.get(&shared_key).await?;
+    Ok(vec![(plain_key, plain_read), (shared_key, shared_read)])
+}
+
+/// `set_get_demo` over a blocking connection, for `--single --sync`.
+pub fn set_get_demo_sync(conn: &mut redis::Connection, keys: &KeyBuilder) -> RedisResult<Vec<(String, Option<String>)>> {
+    let [(first_key, first_value), (second_key, second_value), _] = demo_pairs(keys)?;
+    conn.set::<_, _, ()>(&first_key, first_value)?;
+    let first_read = conn.get(&first_key)?;
+    conn.set::<_, _, ()>(&second_key, second_value)?;
+    let second_read = conn.get(&second_key)?;
+    Ok(vec![(first_key, first_read), (second_key, second_read)])
+}
+
+/// Resets a counter, fires `STRESS_INCREMENTS` INCRs at it all at once over
+/// the multiplexed connection, and returns what it ends at. Every INCR must
+/// land, however the replies interleave, so anything but
+/// `STRESS_INCREMENTS` means a lost command.
+pub async fn stress(conn: &MultiplexedConnection, keys: &KeyBuilder) -> RedisResult<i64> {
+    let key = key(keys, STRESS_KEY)?;
+    conn.clone().del::<_, ()>(&key).await?;
+    future::try_join_all((0..STRESS_INCREMENTS).map(|_| incr(conn.clone(), &key))).await?;
+    conn.clone().get(&key).await
+}
+
+/// `demo` over a blocking connection, for `--sync`: each SET and GET waits
+/// for its reply before the next is sent.
+pub fn demo_sync(conn: &mut redis::Connection, keys: &KeyBuilder) -> RedisResult<Vec<(String, Option<String>)>> {
+    let pairs = demo_pairs(keys)?;
+    for (key, value) in &pairs {
+        conn.set::<_, _, ()>(key, *value)?;
+    }
+    pairs.iter().map(|(key, _)| Ok((key.clone(), conn.get(key)?))).collect()
+}
+
+/// `stress` over a blocking connection, for `--sync`: the INCRs go one
+/// round trip at a time.
+pub fn stress_sync(conn: &mut redis::Connection, keys: &KeyBuilder) -> RedisResult<i64> {
+    let key = key(keys, STRESS_KEY)?;
+    conn.del::<_, ()>(&key)?;
+    for _ in 0..STRESS_INCREMENTS {
+        conn.incr::<_, _, ()>(&key, 1)?;
+    }
+    conn.get(&key)
+}
+
+// The async demos only run against one server, as `async` does.
+#[cfg(all(test, not(feature = "cluster")))]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestKeys};
+
+    #[tokio::test]
+    async fn concurrent_increments_all_land() {
+        let target = match testing::target() {
+            Some(target) => target,
+            None => return,
+        };
+        let test = TestKeys::new(&target, "async_stress");
+        let conn = Client::open(target.as_str()).unwrap().get_multiplexed_async_connection().await.unwrap();
+        assert_eq!(stress(&conn, &test.keys).await.unwrap(), STRESS_INCREMENTS);
+    }
+
+    #[tokio::test]
+    async fn the_demo_reads_back_what_it_set_like_the_sync_one() {
+        let target = match testing::target() {
+            Some(target) => target,
+            None => return,
+        };
+        let test = TestKeys::new(&target, "async_demo");
+        let client = Client::open(target.as_str()).unwrap();
+        let conn = client.get_multiplexed_async_connection().await.unwrap();
+        let read = demo(&conn, &test.keys).await.unwrap();
+        let expected: Vec<_> = DEMO_PAIRS.iter().map(|(name, value)| (key(&test.keys, name).unwrap(), Some(value.to_string()))).collect();
+        assert_eq!(read, expected);
+        assert_eq!(demo_sync(&mut client.get_connection().unwrap(), &test.keys).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn set_get_reads_back_over_both_async_connections_like_the_sync_one() {
+        let target = match testing::target() {
+            Some(target) => target,
+            None => return,
+        };
+        let test = TestKeys::new(&target, "async_set_get");
+        let client = Client::open(target.as_str()).unwrap();
+        let read = set_get_demo_under(&client, &test.keys).await.unwrap();
+        let expected: Vec<_> = DEMO_PAIRS[..2].iter().map(|(name, value)| (key(&test.keys, name).unwrap(), Some(value.to_string()))).collect();
+        assert_eq!(read, expected);
+        assert_eq!(set_get_demo_sync(&mut client.get_connection().unwrap(), &test.keys).unwrap(), expected);
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+use redis::RedisResult;
+
+use crate::stats::Summary;
+use crate::store::{self, StoreConnection};
+
+/// Keys each DEL removes when a run cleans up after itself.
+const CLEANUP_BATCH: usize = 1000;
+
+/// What `run` sends: `ops` SETs of `value_size`-byte values to the keys
+/// `<prefix>:0` onwards, then `ops` GETs of them, either one command per
+/// round trip or `pipeline` commands at a time.
+pub struct BenchOptions {
+    pub ops: usize,
+    pub prefix: String,
+    pub value_size: usize,
+    pub pipeline: Option<usize>,
+}
+
+/// Times the SETs and then the GETs, returning a summary of each, and
+/// deletes the keys whether or not a command failed along the way.
+///
+/// Pipelined, the commands of a batch share one round trip, so each is
+/// counted as taking as long as its whole batch did: the time the caller
+/// waited for its reply.
+pub fn run(conn: &mut StoreConnection, options: &BenchOptions) -> RedisResult<(Summary, Summary)> {
+    let keys: Vec<String> = (0..options.ops).map(|index| format!("{}:{}", options.prefix, index)).collect();
+    let value = vec![b'x'; options.value_size];
+    let result = time(&keys, options.pipeline, |batch| {
+        let mut pipe = store::pipe();
+        for key in batch {
+            pipe.set(key, value.as_slice()).ignore();
+        }
+        pipe.query(conn)
+    })
+    .and_then(|sets| {
+        let gets = time(&keys, options.pipeline, |batch| {
+            let mut pipe = store::pipe();
+            for key in batch {
+                pipe.get(key).ignore();
+            }
+            pipe.query(conn)
+        })?;
+        Ok((sets, gets))
+    });
+    let cleaned = cleanup(conn, &keys);
+    let summaries = result?;
+    cleaned?;
+    Ok(summaries)
+}
+
+/// Sends `keys` through `send` in batches of `pipeline`, or one at a time,
+/// recording one latency per key. A one-command pipeline goes out as that
+/// bare command, so both modes share the code.
+fn time<F>(keys: &[String], pipeline: Option<usize>, mut send: F) -> RedisResult<Summary>
+where
+    F: FnMut(&[String]) -> RedisResult<()>,
+{
+    let mut latencies: Vec<Duration> = Vec::with_capacity(keys.len());
+    let started = Instant::now();
+    for batch in keys.chunks(pipeline.unwrap_or(1)) {
+        let sent = Instant::now();
+        send(batch)?;
+        let took = sent.elapsed();
+        latencies.extend(batch.iter().map(|_| took));
+    }
+    Ok(Summary::new(latencies, started.elapsed()))
+}
+
+#[cfg(not(feature = "cluster"))]
+fn cleanup(conn: &mut StoreConnection, keys: &[String]) -> RedisResult<()> {
+    for batch in keys.chunks(CLEANUP_BATCH) {
+        redis::cmd("DEL").arg(batch).query::<()>(conn)?;
+    }
+    Ok(())
+}
+
+/// In cluster mode one DEL per key, pipelined: the keys of one DEL must
+/// share a slot.
+#[cfg(feature = "cluster")]
+fn cleanup(conn: &mut StoreConnection, keys: &[String]) -> RedisResult<()> {
+    for batch in keys.chunks(CLEANUP_BATCH) {
+        let mut pipe = store::pipe();
+        for key in batch {
+            pipe.del(key).ignore();
+        }
+        pipe.query::<()>(conn)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use r2d2::{Pool, PooledConnection};
+#[cfg(feature = "cluster")]
+use redis::ConnectionInfo;
+use redis::{Commands, RedisError, RedisResult, Script};
+
+use crate::scan::KeyScan;
+use crate::store::{self, StoreConnection};
+
+/// What the pool opens its connections with: to one server, or with the
+/// `cluster` feature, to the whole cluster.
+#[cfg(not(feature = "cluster"))]
+pub type Manager = redis::Client;
+#[cfg(feature = "cluster")]
+pub type Manager = redis::cluster::ClusterClient;
+
+const DEFAULT_POOL_SIZE: u32 = 8;
+const DEFAULT_POOL_TIMEOUT_SECS: u64 = 5;
+
+/// Adds ARGV[1] to the integer at KEYS[1] unless that would take it past
+/// ARGV[2], answering `{1, new value}` or `{0, current value}`. A missing
+/// key counts as 0. Redis runs a script without interleaving any other
+/// command, so no two callers can both see room for the last unit.
+const BOUNDED_INCR_SCRIPT: &str = r"
+local value = redis.call('GET', KEYS[1])
+local current = 0
+if value then
+    current = tonumber(value)
+    if not current then
+        return redis.error_reply('ERR value is not an integer')
+    end
+end
+local delta = tonumber(ARGV[1])
+if current + delta > tonumber(ARGV[2]) then
+    return {0, current}
+end
+return {1, redis.call('INCRBY', KEYS[1], delta)}
+";
+
+/// How big the pool grows and how long a caller waits for a connection,
+/// from `REDIS_POOL_SIZE` and `REDIS_POOL_TIMEOUT_SECS`.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolOptions {
+    pub size: u32,
+    pub timeout: Duration,
+}
+
+impl PoolOptions {
+    pub fn from_env() -> Result<PoolOptions, String> {
+        let size = match env::var("REDIS_POOL_SIZE").ok().filter(|size| !size.is_empty()) {
+            Some(size) => size
+                .parse()
+                .ok()
+                .filter(|size| *size > 0)
+                .ok_or_else(|| format!("REDIS_POOL_SIZE {:?} is not a positive number", size))?,
+            None => DEFAULT_POOL_SIZE,
+        };
+        let timeout_secs = match env::var("REDIS_POOL_TIMEOUT_SECS").ok().filter(|secs| !secs.is_empty()) {
+            Some(secs) => secs
+                .parse()
+                .map_err(|_| format!("REDIS_POOL_TIMEOUT_SECS {:?} is not a number of seconds", secs))?,
+            None => DEFAULT_POOL_TIMEOUT_SECS,
+        };
+        Ok(PoolOptions { size, timeout: Duration::from_secs(timeout_secs) })
+    }
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    /// Every connection was checked out for the whole timeout.
+    Exhausted(Duration),
+    /// No connection could be opened to Redis.
+    Connection(String),
+    /// A command reached Redis and failed there.
+    Redis(RedisError),
+    /// The value at `key` could not be decoded as the type asked for.
+    Corrupt { key: String, reason: String },
+    /// The value for `key` could not be encoded.
+    Unencodable { key: String, reason: String },
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::Exhausted(timeout) => {
+                write!(f, "every pooled connection stayed busy for {}s", timeout.as_secs())
+            },
+            CacheError::Connection(err) => write!(f, "could not connect: {}", err),
+            CacheError::Redis(err) => err.fmt(f),
+            CacheError::Corrupt { key, reason } => write!(f, "{} holds an unreadable value: {}", key, reason),
+            CacheError::Unencodable { key, reason } => write!(f, "could not encode the value for {}: {}", key, reason),
+        }
+    }
+}
+
+impl Error for CacheError {}
+
+impl From<RedisError> for CacheError {
+    fn from(err: RedisError) -> CacheError {
+        CacheError::Redis(err)
+    }
+}
+
+/// What `Cache::bounded_incr` did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoundedIncrResult {
+    /// The increment went through and the key now holds this.
+    Incremented(i64),
+    /// The increment would have passed the cap, so the key was left at
+    /// `current`.
+    Capped { current: i64 },
+}
+
+/// What `Cache::compare_and_set` did.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CasOutcome {
+    /// The key held the expected value and now holds the new one, after
+    /// `retries` tries that another client's write to the key aborted.
+    Swapped { retries: usize },
+    /// The key held `current`, not the expected value, and was left alone;
+    /// `None` when there was no such key.
+    Mismatch { current: Option<Vec<u8>> },
+    /// Another client wrote the key during every try, the first and all
+    /// `retries` retries, so the key was never set.
+    Exhausted { retries: usize },
+}
+
+/// Values kept as raw bytes in Redis, safe to share between threads: each
+/// call checks a connection out of the pool and returns it when done.
+#[derive(Clone)]
+pub struct Cache {
+    pool: Pool<Manager>,
+    options: PoolOptions,
+    bounded_incr: Arc<Script>,
+    #[cfg(feature = "cluster")]
+    seed: ConnectionInfo,
+}
+
+impl Cache {
+    /// Opens the pool, failing with `Connection` when Redis can't be reached
+    /// within the timeout. With the `cluster` feature `url` is the list of
+    /// node URLs `redis_url::nodes_from_env` returns.
+    pub fn connect(url: &str, options: PoolOptions) -> Result<Cache, CacheError> {
+        #[cfg(not(feature = "cluster"))]
+        let manager = Manager::open(url)?;
+        #[cfg(feature = "cluster")]
+        let manager = store::cluster_client(url)?;
+        let pool = Pool::builder()
+            .max_size(options.size)
+            .connection_timeout(options.timeout)
+            .build(manager)
+            .map_err(|err| CacheError::Connection(err.to_string()))?;
+        Ok(Cache {
+            pool,
+            options,
+            bounded_incr: Arc::new(Script::new(BOUNDED_INCR_SCRIPT)),
+            #[cfg(feature = "cluster")]
+            seed: store::cluster_seed(url)?,
+        })
+    }
+
+    pub fn set(&self, key: &str, value: &[u8]) -> Result<(), CacheError> {
+        Ok(self.connection()?.set(key, value)?)
+    }
+
+    pub fn set_with_ttl(&self, key: &str, value: &[u8], ttl_secs: u64) -> Result<(), CacheError> {
+        Ok(redis::cmd("SET").arg(key).arg(value).arg("EX").arg(ttl_secs).query(&mut *self.connection()?)?)
+    }
+
+    /// The value at `key`, or `None` when it does not exist.
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        Ok(self.connection()?.get(key)?)
+    }
+
+    /// Sets every pair in one pipeline, so however many there are they cost
+    /// a single round trip.
+    pub fn set_many(&self, pairs: &[(String, Vec<u8>)]) -> Result<(), CacheError> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = store::pipe();
+        for (key, value) in pairs {
+            pipe.set(key, value.as_slice()).ignore();
+        }
+        Ok(pipe.query(&mut *self.connection()?)?)
+    }
+
+    /// The values of `keys` in the same order, with `None` for each key that
+    /// does not exist, fetched with one MGET.
+    #[cfg(not(feature = "cluster"))]
+    pub fn get_many(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>, CacheError> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(redis::cmd("MGET").arg(keys).query(&mut *self.connection()?)?)
+    }
+
+    /// In cluster mode the values are fetched with one GET per key instead,
+    /// pipelined: the keys of one MGET must share a slot. The cluster
+    /// connection splits the pipeline by node, so it costs one round trip
+    /// per node rather than one per key.
+    #[cfg(feature = "cluster")]
+    pub fn get_many(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>, CacheError> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut pipe = store::pipe();
+        for key in keys {
+            pipe.get(key);
+        }
+        Ok(pipe.query(&mut *self.connection()?)?)
+    }
+
+    /// Adds `delta` to the counter at `key` unless it would end up above
+    /// `max`, checked and applied in one server-side script so concurrent
+    /// callers can never push it past the cap between them. The script is
+    /// sent by its SHA1; only when Redis answers NOSCRIPT is its source
+    /// loaded, once per server, and the call retried.
+    pub fn bounded_incr(&self, key: &str, delta: i64, max: i64) -> Result<BoundedIncrResult, CacheError> {
+        let (applied, value): (i64, i64) =
+            self.bounded_incr.key(key).arg(delta).arg(max).invoke(&mut *self.connection()?)?;
+        Ok(if applied == 1 { BoundedIncrResult::Incremented(value) } else { BoundedIncrResult::Capped { current: value } })
+    }
+
+    /// Sets `key` to `new` if it holds `expected`, as an optimistic
+    /// transaction: WATCH the key, GET it, and only if it matches send the
+    /// SET in a MULTI/EXEC. Should another client write the key between the
+    /// WATCH and the EXEC, EXEC answers nil and drops the SET, and the whole
+    /// check is tried again, up to `max_retries` more times, against the
+    /// value the other client left.
+    pub fn compare_and_set(&self, key: &str, expected: &[u8], new: &[u8], max_retries: usize) -> Result<CasOutcome, CacheError> {
+        // A WATCH holds for the connection that sent it, so every try keeps
+        // the one checked out.
+        let conn = &mut *self.connection()?;
+        let outcome = compare_and_set_on(conn, key, expected, new, max_retries);
+        if outcome.is_err() {
+            // EXEC and UNWATCH end a WATCH; a connection going back to the
+            // pool still watching the key would abort its next transaction.
+            let _ = redis::cmd("UNWATCH").query::<()>(conn);
+        }
+        Ok(outcome?)
+    }
+
+    /// Writes every field of `fields` to the hash at `key` in one HSET,
+    /// leaving any other fields it has alone.
+    pub fn store_fields(&self, key: &str, fields: &HashMap<String, String>) -> Result<(), CacheError> {
+        if fields.is_empty() {
+            return Ok(());
+        }
+        let mut command = redis::cmd("HSET");
+        command.arg(key);
+        for (field, value) in fields {
+            command.arg(field).arg(value);
+        }
+        Ok(command.query(&mut *self.connection()?)?)
+    }
+
+    /// The keys matching the glob `pattern`, as `RedisStore::scan` finds
+    /// them, over a connection the scan keeps checked out until dropped.
+    pub fn scan(
+        &self,
+        pattern: &str,
+        count: Option<usize>,
+    ) -> Result<KeyScan<PooledConnection<Manager>>, CacheError> {
+        #[cfg(not(feature = "cluster"))]
+        let scan = KeyScan::new(self.connection()?, pattern, count);
+        #[cfg(feature = "cluster")]
+        let scan = KeyScan::new(self.connection()?, self.seed.clone(), pattern, count);
+        Ok(scan)
+    }
+
+    /// r2d2 reports both failures as a timeout, so they are told apart by
+    /// the pool: if it is full, every connection was busy; otherwise it
+    /// could not open a new one.
+    fn connection(&self) -> Result<PooledConnection<Manager>, CacheError> {
+        self.pool.get().map_err(|err| {
+            let state = self.pool.state();
+            if state.connections == self.options.size && state.idle_connections == 0 {
+                CacheError::Exhausted(self.options.timeout)
+            } else {
+                CacheError::Connection(err.to_string())
+            }
+        })
+    }
+}
+
+/// The tries of `Cache::compare_and_set`, all on `conn`.
+fn compare_and_set_on(conn: &mut StoreConnection, key: &str, expected: &[u8], new: &[u8], max_retries: usize) -> RedisResult<CasOutcome> {
+    for retries in 0..=max_retries {
+        redis::cmd("WATCH").arg(key).query::<()>(conn)?;
+        let current: Option<Vec<u8>> = conn.get(key)?;
+        if current.as_deref() != Some(expected) {
+            redis::cmd("UNWATCH").query::<()>(conn)?;
+            return Ok(CasOutcome::Mismatch { current });
+        }
+        // Nil, rather than the SET's reply, when the WATCH aborted it.
+        let executed: Option<()> = store::transaction(redis::pipe().atomic().set(key, new), conn)?;
+        if executed.is_some() {
+            return Ok(CasOutcome::Swapped { retries });
+        }
+    }
+    Ok(CasOutcome::Exhausted { retries: max_retries })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn get_many_keeps_key_order_and_marks_missing_keys() {
+        let (cache, test) = match testing::cache("get_many") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = |name| test.keys.raw(&["item", name]).unwrap();
+        let pairs: Vec<(String, Vec<u8>)> =
+            ["c", "a", "b"].iter().map(|name| (key(name), name.as_bytes().to_vec())).collect();
+        cache.set_many(&pairs).unwrap();
+
+        let asked = vec![key("b"), key("missing"), key("c"), key("a"), key("gone")];
+        let values = cache.get_many(&asked).unwrap();
+        assert_eq!(values, vec![Some(b"b".to_vec()), None, Some(b"c".to_vec()), Some(b"a".to_vec()), None]);
+        assert_eq!(cache.get_many(&[]).unwrap(), Vec::<Option<Vec<u8>>>::new());
+    }
+
+    #[test]
+    fn store_fields_adds_to_a_hash_without_dropping_other_fields() {
+        let (cache, test) = match testing::cache("store_fields") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = &test.keys.raw(&["user", "42"]).unwrap();
+        let fields = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs.iter().map(|(field, value)| (field.to_string(), value.to_string())).collect()
+        };
+        cache.store_fields(key, &fields(&[("name", "Ada"), ("role", "admin")])).unwrap();
+        cache.store_fields(key, &fields(&[("role", "owner"), ("email", "ada@example.com")])).unwrap();
+        cache.store_fields(key, &HashMap::new()).unwrap();
+
+        let stored: HashMap<String, String> = cache.connection().unwrap().hgetall(key).unwrap();
+        assert_eq!(stored, fields(&[("name", "Ada"), ("role", "owner"), ("email", "ada@example.com")]));
+    }
+
+    #[test]
+    fn scan_finds_exactly_the_matching_keys_in_any_batch_size() {
+        let (cache, test) = match testing::cache("scan") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let pairs: Vec<(String, Vec<u8>)> = (0..1000)
+            .map(|i| {
+                let kind = if i % 4 == 0 { "order" } else { "user" };
+                (test.keys.raw(&[kind, &i.to_string()]).unwrap(), Vec::new())
+            })
+            .collect();
+        cache.set_many(&pairs).unwrap();
+        let expected: HashSet<String> = pairs.into_iter().map(|(key, _)| key).filter(|key| key.contains(":order:")).collect();
+        assert_eq!(expected.len(), 250);
+
+        for count in &[None, Some(1), Some(7), Some(100), Some(5000)] {
+            let found: Vec<String> = cache.scan(&test.keys.pattern("order:*"), *count).unwrap().map(Result::unwrap).collect();
+            assert_eq!(found.len(), expected.len(), "with COUNT {:?}", count);
+            assert_eq!(found.into_iter().collect::<HashSet<_>>(), expected, "with COUNT {:?}", count);
+        }
+    }
+
+    #[test]
+    fn bounded_incr_from_four_threads_stops_at_the_cap() {
+        let (cache, test) = match testing::cache("bounded_incr") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = test.keys.raw(&["quota"]).unwrap();
+        let max = 150;
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let (cache, key) = (cache.clone(), key.clone());
+                thread::spawn(move || {
+                    let mut applied = 0;
+                    for _ in 0..50 {
+                        match cache.bounded_incr(&key, 2, max).unwrap() {
+                            BoundedIncrResult::Incremented(value) => {
+                                assert!(value <= max, "incremented to {}", value);
+                                applied += 1;
+                            },
+                            BoundedIncrResult::Capped { current } => assert!(current + 2 > max && current <= max),
+                        }
+                    }
+                    applied
+                })
+            })
+            .collect();
+        let applied: i64 = workers.into_iter().map(|worker| worker.join().unwrap()).sum();
+
+        assert_eq!(applied, max / 2);
+        assert_eq!(cache.get(&key).unwrap(), Some(max.to_string().into_bytes()));
+        assert_eq!(cache.bounded_incr(&key, 1, max).unwrap(), BoundedIncrResult::Capped { current: max });
+    }
+
+    #[test]
+    fn compare_and_set_retries_when_a_writer_touches_the_key() {
+        let (cache, test) = match testing::cache("compare_and_set") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = test.keys.raw(&["version"]).unwrap();
+        cache.set(&key, b"0").unwrap();
+
+        // APPENDing nothing leaves the value alone but still counts as a
+        // write, aborting any transaction watching the key.
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (cache, key, stop) = (cache.clone(), key.clone(), stop.clone());
+            thread::spawn(move || {
+                let mut conn = cache.connection().unwrap();
+                while !stop.load(Ordering::SeqCst) {
+                    conn.append::<_, _, ()>(&key, "").unwrap();
+                    thread::sleep(Duration::from_millis(2));
+                }
+            })
+        };
+        let rounds = 100;
+        let mut aborted = 0;
+        for round in 0..rounds {
+            let (expected, new) = (round.to_string(), (round + 1).to_string());
+            match cache.compare_and_set(&key, expected.as_bytes(), new.as_bytes(), 50).unwrap() {
+                CasOutcome::Swapped { retries } => aborted += retries,
+                outcome => panic!("round {} ended {:?}", round, outcome),
+            }
+        }
+        stop.store(true, Ordering::SeqCst);
+        writer.join().unwrap();
+
+        assert!(aborted > 0, "the writer never aborted a transaction");
+        assert_eq!(cache.get(&key).unwrap(), Some(rounds.to_string().into_bytes()));
+        assert_eq!(
+            cache.compare_and_set(&key, b"0", b"1", 50).unwrap(),
+            CasOutcome::Mismatch { current: Some(rounds.to_string().into_bytes()) }
+        );
+    }
+}
+
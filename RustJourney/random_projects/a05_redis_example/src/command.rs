@@ -0,0 +1,384 @@
+use std::io::{self, IsTerminal, Write};
+
+use clap::{ArgMatches, Error, ErrorKind};
+use redis::RedisResult;
+
+use crate::key::KeyBuilder;
+use crate::store::{RedisStore, TtlStatus};
+use crate::{EXIT_MISSING, EXIT_REDIS};
+
+pub const REPL_USAGE: &str = "commands: set [--ttl <seconds>] <key> <value>, get <key>, del <key>, \
+                              keys [pattern], ttl <key>, expire <key> <seconds>, \
+                              mset <key> <value> [<key> <value>...], mget <key>..., publish <channel> <message>, \
+                              incr [--atomic] <key> [times], health [field...], \
+                              hset <key> <field> <value>, hget <key> <field>, hgetall <key>, \
+                              hdel <key> <field>..., quit";
+
+/// One key-value command, from the command line or a REPL line.
+#[derive(Debug)]
+pub enum Command {
+    Set { key: String, value: String, ttl_secs: Option<u64> },
+    Get { key: String },
+    Del { key: String },
+    Keys { pattern: String, count: Option<usize>, limit: Option<usize> },
+    Ttl { key: String },
+    Expire { key: String, ttl_secs: u64 },
+    MSet { pairs: Vec<(String, String)> },
+    MGet { keys: Vec<String> },
+    Publish { channel: String, message: String },
+    Incr { key: String, times: usize, atomic: bool },
+    Health { fields: Vec<String> },
+    HSet { key: String, field: String, value: String },
+    HGet { key: String, field: String },
+    HGetAll { key: String },
+    HDel { key: String, fields: Vec<String> },
+}
+
+/// The INFO field `health` prints when given none.
+pub const DEFAULT_HEALTH_FIELD: &str = "redis_version";
+
+/// `key value key value ...` as pairs, or `None` if a key has no value.
+fn pairs(words: &[&str]) -> Option<Vec<(String, String)>> {
+    if !words.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(words.chunks(2).map(|pair| (pair[0].to_string(), pair[1].to_string())).collect())
+}
+
+/// A positive number, as `keys --count` and `--limit` take.
+pub fn parse_count(value: &str) -> Result<usize, String> {
+    value.parse().ok().filter(|count| *count > 0).ok_or_else(|| format!("{:?} is not a positive number", value))
+}
+
+/// A positive number of seconds, as `--ttl` and `expire` take.
+pub fn parse_ttl(value: &str) -> Result<u64, String> {
+    value.parse().ok().filter(|secs| *secs > 0).ok_or_else(|| format!("{:?} is not a positive number of seconds", value))
+}
+
+impl Command {
+    /// The command for a matched key-value subcommand, with its arguments
+    /// already checked by clap. Exits with clap's usage error when `mset`
+    /// is given a key without a value.
+    pub fn from_matches(matches: &ArgMatches) -> Option<Command> {
+        let arg = |args: &ArgMatches, name| args.value_of(name).expect("clap requires it").to_string();
+        let ttl = |args: &ArgMatches, name| args.value_of(name).map(|secs| parse_ttl(secs).expect("clap checks it"));
+        match matches.subcommand() {
+            ("set", Some(args)) => Some(Command::Set {
+                key: arg(args, "key"),
+                value: arg(args, "value"),
+                ttl_secs: ttl(args, "ttl"),
+            }),
+            ("get", Some(args)) => Some(Command::Get { key: arg(args, "key") }),
+            ("del", Some(args)) => Some(Command::Del { key: arg(args, "key") }),
+            ("keys", Some(args)) => Some(Command::Keys {
+                pattern: arg(args, "pattern"),
+                count: args.value_of("count").map(|count| parse_count(count).expect("clap checks it")),
+                limit: args.value_of("limit").map(|limit| parse_count(limit).expect("clap checks it")),
+            }),
+            ("ttl", Some(args)) => Some(Command::Ttl { key: arg(args, "key") }),
+            ("expire", Some(args)) => Some(Command::Expire {
+                key: arg(args, "key"),
+                ttl_secs: ttl(args, "seconds").expect("clap requires it"),
+            }),
+            ("mset", Some(args)) => {
+                let words: Vec<&str> = args.values_of("pairs").expect("clap requires it").collect();
+                match pairs(&words) {
+                    Some(pairs) => Some(Command::MSet { pairs }),
+                    None => Error::with_description("mset needs a value for every key", ErrorKind::WrongNumberOfValues)
+                        .exit(),
+                }
+            },
+            ("mget", Some(args)) => Some(Command::MGet {
+                keys: args.values_of("keys").expect("clap requires it").map(str::to_string).collect(),
+            }),
+            ("incr", Some(args)) => Some(Command::Incr {
+                key: arg(args, "key"),
+                times: args.value_of("times").expect("it has a default").parse().expect("clap checks it"),
+                atomic: args.is_present("atomic"),
+            }),
+            ("hset", Some(args)) => Some(Command::HSet {
+                key: arg(args, "key"),
+                field: arg(args, "field"),
+                value: arg(args, "value"),
+            }),
+            ("hget", Some(args)) => Some(Command::HGet { key: arg(args, "key"), field: arg(args, "field") }),
+            ("hgetall", Some(args)) => Some(Command::HGetAll { key: arg(args, "key") }),
+            ("hdel", Some(args)) => Some(Command::HDel {
+                key: arg(args, "key"),
+                fields: args.values_of("fields").expect("clap requires them").map(str::to_string).collect(),
+            }),
+            ("health", Some(args)) => Some(Command::Health {
+                fields: args.values_of("fields").expect("it has a default").map(str::to_string).collect(),
+            }),
+            ("publish", Some(args)) => Some(Command::Publish {
+                channel: arg(args, "channel"),
+                message: arg(args, "message"),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Parses a REPL line. Words are split on whitespace, except that the
+    /// values of `set` and `hset` and the message of `publish` are the rest
+    /// of the line, spaces and all. `None` means
+    /// the line is not a command.
+    pub fn parse(line: &str) -> Option<Command> {
+        let line = line.trim();
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mut rest = rest.trim_start();
+        let mut ttl_secs = None;
+        let atomic = name == "incr" && rest.starts_with("--atomic");
+        if atomic {
+            rest = rest["--atomic".len()..].trim_start();
+        }
+        if name == "set" && rest.starts_with("--ttl") {
+            let (_, after) = rest.split_once(char::is_whitespace)?;
+            let (secs, after) = after.trim_start().split_once(char::is_whitespace)?;
+            ttl_secs = Some(parse_ttl(secs).ok()?);
+            rest = after.trim_start();
+        }
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        match (name, words.as_slice()) {
+            ("set", [key, _, ..]) => {
+                let value = rest[key.len()..].trim_start();
+                Some(Command::Set { key: key.to_string(), value: value.to_string(), ttl_secs })
+            },
+            ("get", [key]) => Some(Command::Get { key: key.to_string() }),
+            ("del", [key]) => Some(Command::Del { key: key.to_string() }),
+            ("keys", []) => Some(Command::Keys { pattern: "*".to_string(), count: None, limit: None }),
+            ("keys", [pattern]) => Some(Command::Keys { pattern: pattern.to_string(), count: None, limit: None }),
+            ("ttl", [key]) => Some(Command::Ttl { key: key.to_string() }),
+            ("expire", [key, secs]) => Some(Command::Expire { key: key.to_string(), ttl_secs: parse_ttl(secs).ok()? }),
+            ("publish", [channel, _, ..]) => {
+                let message = rest[channel.len()..].trim_start();
+                Some(Command::Publish { channel: channel.to_string(), message: message.to_string() })
+            },
+            ("incr", [key]) => Some(Command::Incr { key: key.to_string(), times: 1, atomic }),
+            ("incr", [key, times]) => Some(Command::Incr { key: key.to_string(), times: times.parse().ok()?, atomic }),
+            ("hset", [key, field, _, ..]) => {
+                let value = rest[key.len()..].trim_start()[field.len()..].trim_start();
+                Some(Command::HSet { key: key.to_string(), field: field.to_string(), value: value.to_string() })
+            },
+            ("hget", [key, field]) => Some(Command::HGet { key: key.to_string(), field: field.to_string() }),
+            ("hgetall", [key]) => Some(Command::HGetAll { key: key.to_string() }),
+            ("hdel", [key, fields @ ..]) if !fields.is_empty() => Some(Command::HDel {
+                key: key.to_string(),
+                fields: fields.iter().map(|field| field.to_string()).collect(),
+            }),
+            ("health", []) => Some(Command::Health { fields: vec![DEFAULT_HEALTH_FIELD.to_string()] }),
+            ("health", fields) => Some(Command::Health { fields: fields.iter().map(|field| field.to_string()).collect() }),
+            ("mset", words) if !words.is_empty() => Some(Command::MSet { pairs: pairs(words)? }),
+            ("mget", keys) if !keys.is_empty() => Some(Command::MGet { keys: keys.iter().map(|key| key.to_string()).collect() }),
+            _ => None,
+        }
+    }
+
+    /// Runs the command and prints its result, returning the exit status it
+    /// calls for. Keys are built through `keys`, so `get user:42` reads
+    /// `<namespace>:<environment>:user:42`, and printed without the prefix;
+    /// a key `keys` refuses fails the command. `get` writes the raw value, so binary values survive a
+    /// pipe, ending it with a newline only on a terminal or when
+    /// `interactive`; there a missing key prints `(nil)`.
+    pub fn execute(&self, store: &mut RedisStore, keys: &KeyBuilder, interactive: bool) -> RedisResult<i32> {
+        match self {
+            Command::Set { key, value, ttl_secs } => {
+                let key = &keys.path(key)?;
+                match ttl_secs {
+                    Some(ttl_secs) => store.set_with_ttl(key, value.as_bytes(), *ttl_secs)?,
+                    None => store.set(key, value.as_bytes())?,
+                }
+                if interactive {
+                    println!("OK");
+                }
+                Ok(0)
+            },
+            Command::Get { key } => match store.get(&keys.path(key)?)? {
+                Some(value) => {
+                    let mut stdout = io::stdout();
+                    stdout.write_all(&value)?;
+                    if interactive || stdout.is_terminal() {
+                        stdout.write_all(b"\n")?;
+                    }
+                    Ok(0)
+                },
+                None => {
+                    if interactive {
+                        println!("(nil)");
+                    }
+                    Ok(EXIT_MISSING)
+                },
+            },
+            Command::Del { key } => {
+                let removed = store.del(&keys.path(key)?)?;
+                println!("{}", removed);
+                Ok(if removed == 0 { EXIT_MISSING } else { 0 })
+            },
+            Command::Keys { pattern, count, limit } => {
+                // Each key is printed as its batch arrives rather than once
+                // the whole scan is done.
+                for key in store.scan(&keys.pattern(pattern), *count).take(limit.unwrap_or(usize::MAX)) {
+                    let key = key?;
+                    println!("{}", keys.strip(&key).unwrap_or(&key));
+                }
+                Ok(0)
+            },
+            Command::Ttl { key } => match store.ttl(&keys.path(key)?)? {
+                TtlStatus::Missing => {
+                    println!("no such key");
+                    Ok(EXIT_MISSING)
+                },
+                TtlStatus::Persistent => {
+                    println!("no expiry");
+                    Ok(0)
+                },
+                TtlStatus::Expiring(left) => {
+                    println!("{}", left.as_secs());
+                    Ok(0)
+                },
+            },
+            Command::MSet { pairs } => {
+                let pairs = pairs.iter().map(|(key, value)| keys.path(key).map(|key| (key, value.as_str()))).collect::<Result<Vec<_>, _>>()?;
+                let pairs: Vec<(&str, &str)> = pairs.iter().map(|(key, value)| (key.as_str(), *value)).collect();
+                store.mset(&pairs)?;
+                if interactive {
+                    println!("OK");
+                }
+                Ok(0)
+            },
+            Command::MGet { keys: names } => {
+                let built = names.iter().map(|name| keys.path(name)).collect::<Result<Vec<_>, _>>()?;
+                let built: Vec<&str> = built.iter().map(String::as_str).collect();
+                let values = store.mget(&built)?;
+                for (key, value) in names.iter().zip(&values) {
+                    match value {
+                        Some(value) => println!("{}: {}", key, value),
+                        None => println!("{}: (nil)", key),
+                    }
+                }
+                Ok(if values.iter().any(Option::is_none) { EXIT_MISSING } else { 0 })
+            },
+            Command::Incr { key, times, atomic } => {
+                for value in store.pipelined_increments(&keys.path(key)?, *times, *atomic)? {
+                    println!("{}", value);
+                }
+                Ok(0)
+            },
+            Command::HSet { key, field, value } => {
+                println!("{}", store.hset(&keys.path(key)?, field, value)? as u8);
+                Ok(0)
+            },
+            Command::HGet { key, field } => match store.hget(&keys.path(key)?, field)? {
+                Some(value) => {
+                    println!("{}", value);
+                    Ok(0)
+                },
+                None => {
+                    if interactive {
+                        println!("(nil)");
+                    }
+                    Ok(EXIT_MISSING)
+                },
+            },
+            Command::HGetAll { key } => {
+                let mut fields: Vec<(String, String)> = store.hgetall(&keys.path(key)?)?.into_iter().collect();
+                fields.sort();
+                let width = fields.iter().map(|(field, _)| field.len()).max().unwrap_or(0);
+                for (field, value) in fields {
+                    println!("{:<width$} = {}", field, value, width = width);
+                }
+                Ok(0)
+            },
+            Command::HDel { key, fields } => {
+                let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+                let removed = store.hdel(&keys.path(key)?, &fields)?;
+                println!("{}", removed);
+                Ok(if removed == 0 { EXIT_MISSING } else { 0 })
+            },
+            Command::Health { fields } => {
+                if !store.ping()? {
+                    println!("PING was not answered with PONG");
+                    return Ok(EXIT_REDIS);
+                }
+                let info = store.server_info()?;
+                let mut missing = false;
+                for field in fields {
+                    match info.get(field) {
+                        Some(value) => println!("{}: {}", field, value),
+                        None => {
+                            println!("{}: (not reported)", field);
+                            missing = true;
+                        },
+                    }
+                }
+                Ok(if missing { EXIT_MISSING } else { 0 })
+            },
+            Command::Publish { channel, message } => {
+                println!("{}", store.publish(channel, message)?);
+                Ok(0)
+            },
+            Command::Expire { key, ttl_secs } => {
+                if store.expire(&keys.path(key)?, *ttl_secs)? {
+                    if interactive {
+                        println!("OK");
+                    }
+                    Ok(0)
+                } else {
+                    println!("no such key");
+                    Ok(EXIT_MISSING)
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::ErrorKind;
+
+    use super::*;
+    use crate::testing;
+
+    fn command(words: &str) -> Command {
+        Command::parse(words).expect("a valid command")
+    }
+
+    #[test]
+    fn subcommands_round_trip_through_prefixed_keys() {
+        let (mut store, test) = match testing::redis("kv_cli") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let keys = &test.keys;
+        let run = |store: &mut RedisStore, words| command(words).execute(store, keys, false).unwrap();
+
+        assert_eq!(run(&mut store, "set user:42 Ada Lovelace"), 0);
+        assert_eq!(run(&mut store, "set user:43 Grace"), 0);
+        assert_eq!(run(&mut store, "set session:1 x"), 0);
+        let stored = keys.path("user:42").unwrap();
+        assert!(stored.starts_with("a05_test:kv_cli-"), "{}", stored);
+        assert_eq!(store.get(&stored).unwrap(), Some(b"Ada Lovelace".to_vec()));
+        // A found value goes to stdout unbuffered, past the test harness.
+        assert_eq!(run(&mut store, "get user:44"), EXIT_MISSING);
+
+        let mut users: Vec<String> = store.scan(&keys.pattern("user:*"), None).map(Result::unwrap).collect();
+        users.sort();
+        assert_eq!(users, vec![stored.clone(), keys.path("user:43").unwrap()]);
+        assert_eq!(run(&mut store, "keys user:*"), 0);
+
+        assert_eq!(run(&mut store, "del user:42"), 0);
+        assert_eq!(run(&mut store, "del user:42"), EXIT_MISSING);
+        assert_eq!(store.get(&stored).unwrap(), None);
+    }
+
+    #[test]
+    fn a_key_the_builder_refuses_writes_nothing() {
+        let (mut store, test) = match testing::redis("kv_cli_refused") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let set = Command::Set { key: "user 42".to_string(), value: "x".to_string(), ttl_secs: None };
+        let err = set.execute(&mut store, &test.keys, false).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidClientConfig);
+        assert_eq!(store.scan(&test.keys.pattern("*"), None).count(), 0);
+    }
+}
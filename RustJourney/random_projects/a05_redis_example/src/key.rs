@@ -0,0 +1,225 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+
+use redis::{Commands, ErrorKind, FromRedisValue, RedisError, RedisResult, ToRedisArgs};
+
+use crate::store::StoreConnection;
+
+/// The namespace and environment keys are built under when
+/// `REDIS_KEY_NAMESPACE` and `REDIS_KEY_ENV` are unset.
+const DEFAULT_NAMESPACE: &str = "a05_redis_example";
+const DEFAULT_ENVIRONMENT: &str = "dev";
+
+/// Builds every key the example writes as `<namespace>:<environment>:`
+/// followed by segments joined with `:`, so it neither tramples another
+/// tool's keys in a shared Redis nor its own from another environment. A
+/// segment must not be empty or hold a `:` or whitespace:
+///
+/// ```ignore
+/// let keys = KeyBuilder::new("myapp", "dev")?;
+/// assert_eq!(keys.sale("2020-183")?, "myapp:dev:sale:2020-183");
+/// assert_eq!(keys.raw(&["demo", "1", "7"])?, "myapp:dev:demo:1:7");
+/// assert!(keys.raw(&["user:42"]).is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct KeyBuilder {
+    prefix: String,
+}
+
+impl KeyBuilder {
+    pub fn new(namespace: &str, environment: &str) -> Result<KeyBuilder, KeyError> {
+        check(namespace)?;
+        check(environment)?;
+        Ok(KeyBuilder { prefix: format!("{}:{}:", namespace, environment) })
+    }
+
+    /// The builder for `REDIS_KEY_NAMESPACE` and `REDIS_KEY_ENV`, or for
+    /// `DEFAULT_NAMESPACE` and `DEFAULT_ENVIRONMENT` where they are unset.
+    pub fn from_env() -> Result<KeyBuilder, KeyError> {
+        let var = |name, default: &str| env::var(name).ok().filter(|value| !value.is_empty()).unwrap_or_else(|| default.to_string());
+        KeyBuilder::new(&var("REDIS_KEY_NAMESPACE", DEFAULT_NAMESPACE), &var("REDIS_KEY_ENV", DEFAULT_ENVIRONMENT))
+    }
+
+    /// The key made of `parts`, at least one, after the prefix.
+    pub fn raw(&self, parts: &[&str]) -> Result<String, KeyError> {
+        if parts.is_empty() {
+            return Err(KeyError::Empty);
+        }
+        let mut key = self.prefix.clone();
+        for (index, part) in parts.iter().enumerate() {
+            if index > 0 {
+                key.push(':');
+            }
+            key.push_str(check(part)?);
+        }
+        Ok(key)
+    }
+
+    /// A cached sale record.
+    pub fn sale(&self, id: &str) -> Result<String, KeyError> {
+        self.raw(&["sale", id])
+    }
+
+    pub fn counter(&self, name: &str) -> Result<String, KeyError> {
+        self.raw(&["counter", name])
+    }
+
+    /// A key given whole, as the command line takes them, its segments
+    /// separated by `:`: `user:42` is the key of `user` and `42`.
+    pub fn path(&self, key: &str) -> Result<String, KeyError> {
+        self.raw(&key.split(':').collect::<Vec<_>>())
+    }
+
+    /// `glob` under the prefix, for SCAN and keyspace notifications to
+    /// match only this builder's keys. The glob is not checked: it is no key.
+    pub fn pattern(&self, glob: &str) -> String {
+        format!("{}{}", self.prefix, glob)
+    }
+
+    /// `key` without the prefix, as the command line shows keys, or `None`
+    /// for a key this builder did not make.
+    pub fn strip<'k>(&self, key: &'k str) -> Option<&'k str> {
+        key.strip_prefix(self.prefix.as_str())
+    }
+}
+
+/// `segment`, if it can be one segment of a key.
+fn check(segment: &str) -> Result<&str, KeyError> {
+    if segment.is_empty() {
+        Err(KeyError::Empty)
+    } else if segment.contains(|c: char| c == ':' || c.is_whitespace()) {
+        Err(KeyError::Invalid(segment.to_string()))
+    } else {
+        Ok(segment)
+    }
+}
+
+/// A segment `KeyBuilder` refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyError {
+    Empty,
+    /// The segment holds a `:` or whitespace.
+    Invalid(String),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyError::Empty => write!(f, "a key segment is empty"),
+            KeyError::Invalid(segment) => write!(f, "key segment {:?} holds a ':' or whitespace", segment),
+        }
+    }
+}
+
+impl Error for KeyError {}
+
+impl From<KeyError> for RedisError {
+    fn from(err: KeyError) -> RedisError {
+        RedisError::from((ErrorKind::InvalidClientConfig, "invalid key", err.to_string()))
+    }
+}
+
+/// A key bound to the type of value it holds, so a key written as an
+/// `i64` can only be read back as one:
+///
+/// ```ignore
+/// let counter = RedisKey::<i64>::new(&keys.counter("visits")?);
+/// counter.set(conn, 45)?;
+/// let value: Option<i64> = counter.get(conn)?;
+/// ```
+pub struct RedisKey<T> {
+    name: String,
+    // fn() -> T keeps the key Send and Sync whatever T is; it never holds one.
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> RedisKey<T> {
+    pub fn new(name: &str) -> RedisKey<T> {
+        RedisKey { name: name.to_string(), value: PhantomData }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<T: ToRedisArgs + FromRedisValue> RedisKey<T> {
+    pub fn set(&self, conn: &mut StoreConnection, value: T) -> RedisResult<()> {
+        conn.set(&self.name, value)
+    }
+
+    /// The value, or `None` when the key does not exist. A value Redis can't
+    /// convert to `T`, because something else wrote the key, is an error.
+    pub fn get(&self, conn: &mut StoreConnection) -> RedisResult<Option<T>> {
+        conn.get(&self.name)
+    }
+}
+
+impl<T> fmt::Debug for RedisKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RedisKey").field(&self.name).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> KeyBuilder {
+        KeyBuilder::new("myapp", "dev").unwrap()
+    }
+
+    #[test]
+    fn builds_keys_under_the_namespace_and_environment() {
+        let keys = keys();
+        assert_eq!(keys.sale("2020-183").unwrap(), "myapp:dev:sale:2020-183");
+        assert_eq!(keys.counter("visits").unwrap(), "myapp:dev:counter:visits");
+        assert_eq!(keys.raw(&["demo", "1", "7"]).unwrap(), "myapp:dev:demo:1:7");
+        assert_eq!(keys.path("user:42").unwrap(), "myapp:dev:user:42");
+    }
+
+    #[test]
+    fn refuses_segments_that_would_blur_the_key() {
+        let keys = keys();
+        assert_eq!(keys.raw(&["user:42"]), Err(KeyError::Invalid("user:42".to_string())));
+        assert_eq!(keys.raw(&["user 42"]), Err(KeyError::Invalid("user 42".to_string())));
+        assert_eq!(keys.raw(&["tab\there"]), Err(KeyError::Invalid("tab\there".to_string())));
+        assert_eq!(keys.raw(&["user", ""]), Err(KeyError::Empty));
+        assert_eq!(keys.raw(&[]), Err(KeyError::Empty));
+        assert_eq!(keys.path("user::42"), Err(KeyError::Empty));
+        assert_eq!(keys.path(":user"), Err(KeyError::Empty));
+    }
+
+    #[test]
+    fn refuses_a_bad_namespace_or_environment() {
+        assert_eq!(KeyBuilder::new("my:app", "dev").unwrap_err(), KeyError::Invalid("my:app".to_string()));
+        assert_eq!(KeyBuilder::new("myapp", "").unwrap_err(), KeyError::Empty);
+        assert_eq!(KeyBuilder::new("myapp", "dev env").unwrap_err(), KeyError::Invalid("dev env".to_string()));
+    }
+
+    #[test]
+    fn patterns_and_strip_stay_inside_the_prefix() {
+        let keys = keys();
+        assert_eq!(keys.pattern("demo:*"), "myapp:dev:demo:*");
+        assert_eq!(keys.strip("myapp:dev:user:42"), Some("user:42"));
+        assert_eq!(keys.strip("myapp:prod:user:42"), None);
+        assert_eq!(keys.strip("user:42"), None);
+    }
+
+    #[test]
+    fn environments_never_share_keys() {
+        let dev = KeyBuilder::new("myapp", "dev").unwrap();
+        let prod = KeyBuilder::new("myapp", "prod").unwrap();
+        assert_ne!(dev.path("user:42").unwrap(), prod.path("user:42").unwrap());
+        assert_eq!(dev.strip(&prod.path("user:42").unwrap()), None);
+    }
+
+    #[test]
+    fn a_refused_segment_is_a_client_config_error() {
+        let err = RedisError::from(KeyError::Invalid("a:b".to_string()));
+        assert_eq!(err.kind(), ErrorKind::InvalidClientConfig);
+        assert!(err.to_string().contains("\"a:b\""), "{}", err);
+    }
+}
@@ -0,0 +1,290 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use log::{info, warn};
+use redis::{Client, Connection, RedisResult};
+
+use crate::pubsub::stopping;
+
+/// Every key event in every database: `__keyevent@<db>__:<event>`, with the
+/// key as the payload.
+const KEYEVENT_PATTERN: &str = "__keyevent@*__:*";
+/// How long the watcher waits for an event before checking whether it has
+/// been told to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the watcher waits to reconnect after losing its connection at
+/// first; each later wait doubles it, up to `MAX_RECONNECT_BACKOFF`.
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(200);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5);
+/// The classes `A` stands for in `notify-keyspace-events`.
+const ALL_CLASSES: &str = "g$lshzxe";
+
+/// The events `--events` can narrow the watch to.
+pub const EVENT_NAMES: [&str; 3] = ["set", "del", "expired"];
+
+/// What `watch` listens for.
+pub struct WatchOptions {
+    /// A glob, as KEYS and SCAN take, that an event's key must match.
+    pub pattern: String,
+    /// The event types to report, all of them when `None`.
+    pub events: Option<Vec<String>>,
+    /// Whether to turn on the notifications the watch needs with CONFIG
+    /// SET. Servers that forbid CONFIG need them set up beforehand.
+    pub configure: bool,
+}
+
+/// One key event as the server published it.
+pub struct KeyEvent {
+    pub event: String,
+    pub key: String,
+    /// When the watcher received it; Redis sends no timestamp of its own.
+    pub received: SystemTime,
+}
+
+/// Calls `on_event` for each key event matching `options` until `stop` is
+/// set. When the connection drops, it reconnects, backing off while the
+/// server stays unreachable, and carries on; events published while it
+/// was disconnected are lost, as Redis does not keep them.
+///
+/// Only a failure to set up the watch is returned: the first connection,
+/// or CONFIG being refused. A connection lost later is retried for as long
+/// as it takes.
+pub fn watch<F>(url: &str, options: &WatchOptions, stop: &AtomicBool, mut on_event: F) -> RedisResult<()>
+where
+    F: FnMut(&KeyEvent),
+{
+    let client = Client::open(url)?;
+    let mut conn = subscribe(&client, options)?;
+    let mut backoff = RECONNECT_BACKOFF;
+    loop {
+        let err = match listen(&mut conn, options, stop, &mut on_event) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        warn!("Lost the keyspace subscription: {}", err);
+        conn = loop {
+            if stop.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            info!("Reconnecting in {}ms", backoff.as_millis());
+            thread::sleep(backoff);
+            match subscribe(&client, options) {
+                Ok(conn) => break conn,
+                Err(err) => {
+                    warn!("Could not resubscribe: {}", err);
+                    backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                },
+            }
+        };
+        info!("Resubscribed");
+        backoff = RECONNECT_BACKOFF;
+    }
+}
+
+/// Opens a connection, turning notifications on first when asked to: a
+/// restarted server has forgotten a CONFIG SET, so this runs on every
+/// reconnect.
+fn subscribe(client: &Client, options: &WatchOptions) -> RedisResult<Connection> {
+    let mut conn = client.get_connection()?;
+    if options.configure {
+        enable_notifications(&mut conn, &required_flags(options.events.as_deref()))?;
+    }
+    Ok(conn)
+}
+
+/// Reads events until `stop` is set or the connection fails. Pubsub mode
+/// ties up a connection, so the watcher keeps this one to itself.
+fn listen<F>(conn: &mut Connection, options: &WatchOptions, stop: &AtomicBool, on_event: &mut F) -> RedisResult<()>
+where
+    F: FnMut(&KeyEvent),
+{
+    let mut pubsub = conn.as_pubsub();
+    pubsub.psubscribe(KEYEVENT_PATTERN)?;
+    pubsub.set_read_timeout(Some(POLL_INTERVAL))?;
+    while !stop.load(Ordering::SeqCst) {
+        let message = match pubsub.get_message() {
+            Ok(message) => message,
+            Err(ref err) if err.is_timeout() => continue,
+            Err(_) if stopping(stop) => break,
+            Err(err) => return Err(err),
+        };
+        let event = match message.get_channel_name().rsplit_once("__:") {
+            Some((_, event)) => event,
+            None => continue,
+        };
+        if options.events.as_ref().is_some_and(|events| !events.iter().any(|wanted| wanted == event)) {
+            continue;
+        }
+        let key = String::from_utf8_lossy(message.get_payload_bytes());
+        if glob_match(options.pattern.as_bytes(), key.as_bytes()) {
+            on_event(&KeyEvent { event: event.to_string(), key: key.into_owned(), received: SystemTime::now() });
+        }
+    }
+    Ok(())
+}
+
+/// The `notify-keyspace-events` flags the watch needs: `E` for key events,
+/// and the class of each event asked for, or `A` for all of them.
+fn required_flags(events: Option<&[String]>) -> String {
+    let classes: String = match events {
+        Some(events) => events.iter().filter_map(|event| class(event)).collect(),
+        None => "A".to_string(),
+    };
+    format!("E{}", classes)
+}
+
+/// The class of `notify-keyspace-events` `event` is published under.
+fn class(event: &str) -> Option<char> {
+    match event {
+        "set" => Some('$'),
+        "del" => Some('g'),
+        "expired" => Some('x'),
+        _ => None,
+    }
+}
+
+/// Adds whatever of `needed` the server's `notify-keyspace-events` lacks,
+/// leaving the flags it already has, such as another client's `K`, in
+/// place. Returns whether anything had to change.
+fn enable_notifications(conn: &mut Connection, needed: &str) -> RedisResult<bool> {
+    let (_, current): (String, String) = redis::cmd("CONFIG").arg("GET").arg("notify-keyspace-events").query(conn)?;
+    let have = expand(&current);
+    let missing: String = expand(needed).chars().filter(|flag| !have.contains(*flag)).collect();
+    if missing.is_empty() {
+        return Ok(false);
+    }
+    let flags = format!("{}{}", current, missing);
+    redis::cmd("CONFIG").arg("SET").arg("notify-keyspace-events").arg(flags).query::<()>(conn)?;
+    Ok(true)
+}
+
+/// `flags` with `A` spelled out as the classes it stands for.
+fn expand(flags: &str) -> String {
+    flags.replace('A', ALL_CLASSES)
+}
+
+/// Whether `text` matches the glob `pattern` as Redis matches them: `*`
+/// for any run of bytes, `?` for any one, `[abc]`, `[a-z]` and `[^abc]`
+/// for one of a set, and `\` to take the next byte literally.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') => (0..=text.len()).any(|skip| glob_match(&pattern[1..], &text[skip..])),
+        Some(b'?') => !text.is_empty() && glob_match(&pattern[1..], &text[1..]),
+        Some(b'[') => {
+            let (matched, rest) = match text.first() {
+                Some(&byte) => class_match(&pattern[1..], byte),
+                None => return false,
+            };
+            matched && glob_match(rest, &text[1..])
+        },
+        Some(b'\\') if pattern.len() > 1 => text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..]),
+        Some(&literal) => text.first() == Some(&literal) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Whether `byte` is in the set `class` opens, just past its `[`, and the
+/// pattern after the closing `]`. An unclosed set runs to the end.
+fn class_match(class: &[u8], byte: u8) -> (bool, &[u8]) {
+    let (negated, mut at) = if class.first() == Some(&b'^') { (true, 1) } else { (false, 0) };
+    let mut matched = false;
+    while at < class.len() && class[at] != b']' {
+        if class[at] == b'\\' && at + 1 < class.len() {
+            matched |= class[at + 1] == byte;
+            at += 2;
+        } else if at + 2 < class.len() && class[at + 1] == b'-' && class[at + 2] != b']' {
+            let (low, high) = (class[at].min(class[at + 2]), class[at].max(class[at + 2]));
+            matched |= (low..=high).contains(&byte);
+            at += 3;
+        } else {
+            matched |= class[at] == byte;
+            at += 1;
+        }
+    }
+    (matched != negated, &class[(at + 1).min(class.len())..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_match_as_redis_matches_them() {
+        let matches = |pattern: &str, text: &str| glob_match(pattern.as_bytes(), text.as_bytes());
+        assert!(matches("user:*", "user:42"));
+        assert!(matches("user:*", "user:"));
+        assert!(!matches("user:*", "order:42"));
+        assert!(matches("h?llo", "hello") && !matches("h?llo", "hllo"));
+        assert!(matches("h[ae]llo", "hallo") && !matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo") && !matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-c]llo", "hbllo") && !matches("h[a-c]llo", "hdllo"));
+        assert!(matches(r"star\*", "star*") && !matches(r"star\*", "stars"));
+    }
+
+    #[test]
+    fn only_the_classes_of_the_events_asked_for_are_required() {
+        assert_eq!(required_flags(None), "EA");
+        let events = ["set".to_string(), "expired".to_string()];
+        assert_eq!(required_flags(Some(&events)), "E$x");
+        assert_eq!(expand("KEA"), "KEg$lshzxe");
+    }
+
+    /// Turns key events on with CONFIG SET, so `TEST_REDIS_URL` has to name
+    /// a server that allows it.
+    #[cfg(not(feature = "cluster"))]
+    #[test]
+    fn a_watcher_sees_keys_set_and_deleted_elsewhere() {
+        use std::sync::mpsc;
+        use std::sync::Arc;
+        use std::time::Instant;
+
+        use crate::testing;
+
+        let (mut store, test) = match testing::redis("keyspace") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let url = testing::target().expect("testing::redis found it");
+        let key = test.keys.raw(&["watched"]).unwrap();
+        let options = WatchOptions {
+            pattern: test.keys.pattern("*"),
+            events: Some(vec!["set".to_string(), "del".to_string()]),
+            configure: true,
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (seen, events) = mpsc::channel();
+        let watcher = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                watch(&url, &options, &stop, |event| {
+                    let _ = seen.send((event.event.clone(), event.key.clone()));
+                })
+            })
+        };
+
+        // A key set before the watcher has subscribed goes unseen, so keep
+        // setting it until an event comes back.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let first = loop {
+            store.set(&key, b"value").unwrap();
+            if let Ok(event) = events.recv_timeout(Duration::from_millis(100)) {
+                break event;
+            }
+            assert!(Instant::now() < deadline, "no event for {}", key);
+        };
+        assert_eq!(first, ("set".to_string(), key.clone()));
+        store.del(&key).unwrap();
+        let deleted = loop {
+            let event = events.recv_timeout(Duration::from_secs(2)).expect("the delete is seen");
+            if event.0 != "set" {
+                break event;
+            }
+        };
+        assert_eq!(deleted, ("del".to_string(), key));
+
+        stop.store(true, Ordering::SeqCst);
+        watcher.join().unwrap().unwrap();
+    }
+}
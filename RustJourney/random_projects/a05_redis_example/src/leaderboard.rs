@@ -0,0 +1,36 @@
+use redis::{Commands, RedisResult};
+
+use crate::store::StoreConnection;
+
+/// Players and their scores in one sorted set, highest score first.
+pub struct Leaderboard<'conn> {
+    conn: &'conn mut StoreConnection,
+    key: String,
+}
+
+impl<'conn> Leaderboard<'conn> {
+    pub fn new(conn: &'conn mut StoreConnection, key: &str) -> Leaderboard<'conn> {
+        Leaderboard { conn, key: key.to_string() }
+    }
+
+    /// Sets `member`'s score, adding the member if it is new.
+    pub fn add(&mut self, member: &str, score: f64) -> RedisResult<()> {
+        self.conn.zadd(&self.key, member, score)
+    }
+
+    /// The `n` highest scorers with their scores, best first. Nothing for
+    /// `n` of 0 or less, where ZREVRANGE would read a negative stop as
+    /// counting back from the end.
+    pub fn top(&mut self, n: isize) -> RedisResult<Vec<(String, f64)>> {
+        if n <= 0 {
+            return Ok(Vec::new());
+        }
+        self.conn.zrevrange_withscores(&self.key, 0, n - 1)
+    }
+
+    /// `member`'s place counting from 0 for the best score, or `None` when
+    /// it is not on the board.
+    pub fn rank(&mut self, member: &str) -> RedisResult<Option<usize>> {
+        self.conn.zrevrank(&self.key, member)
+    }
+}
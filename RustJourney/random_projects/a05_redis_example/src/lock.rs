@@ -0,0 +1,161 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use redis::{RedisResult, Script};
+
+use crate::key::KeyBuilder;
+use crate::store::StoreConnection;
+
+/// Deletes KEYS[1] only while it still holds ARGV[1], our token.
+const RELEASE_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+/// Sets KEYS[1] to expire in ARGV[2] milliseconds only while it still
+/// holds ARGV[1], our token.
+const EXTEND_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+";
+
+/// A lock shared by every client of one Redis server or cluster, held as
+/// `KeyBuilder`'s key of `lock` and the name, set to a token only its
+/// holder knows. The key expires after a TTL, so a holder that dies cannot
+/// keep it forever.
+pub struct RedisLock;
+
+impl RedisLock {
+    /// Takes the lock `name` for `ttl` with one `SET ... NX PX`, returning
+    /// `None` without waiting when someone else holds it.
+    pub fn acquire<'a>(
+        conn: &'a mut StoreConnection,
+        keys: &KeyBuilder,
+        name: &str,
+        ttl: Duration,
+    ) -> RedisResult<Option<LockGuard<'a>>> {
+        let key = keys.raw(&["lock", name])?;
+        let token = token();
+        let set: Option<String> =
+            redis::cmd("SET").arg(&key).arg(&token).arg("NX").arg("PX").arg(millis(ttl)).query(conn)?;
+        Ok(set.map(move |_| LockGuard { conn, key, token, released: false }))
+    }
+}
+
+/// A held `RedisLock`, released when dropped. Only the key still holding
+/// this guard's token is ever deleted or extended: once the TTL has run out
+/// and another client has taken the lock, both leave that client's key be.
+pub struct LockGuard<'a> {
+    conn: &'a mut StoreConnection,
+    key: String,
+    token: String,
+    released: bool,
+}
+
+impl<'a> LockGuard<'a> {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Releases the lock now rather than on drop, returning whether it was
+    /// still ours to release. Drop cannot report either that or an error.
+    pub fn release(mut self) -> RedisResult<bool> {
+        self.released = true;
+        self.run(RELEASE_SCRIPT, None)
+    }
+
+    /// Makes the lock expire `ttl` from now, for work that outlasts the TTL
+    /// it was taken with. Returns `false`, changing nothing, when it has
+    /// already expired and may be someone else's.
+    pub fn extend(&mut self, ttl: Duration) -> RedisResult<bool> {
+        self.run(EXTEND_SCRIPT, Some(ttl))
+    }
+
+    fn run(&mut self, script: &str, ttl: Option<Duration>) -> RedisResult<bool> {
+        let script = Script::new(script);
+        let mut invocation = script.key(&self.key);
+        invocation.arg(&self.token);
+        if let Some(ttl) = ttl {
+            invocation.arg(millis(ttl));
+        }
+        Ok(invocation.invoke::<i64>(self.conn)? == 1)
+    }
+}
+
+impl<'a> Drop for LockGuard<'a> {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = self.run(RELEASE_SCRIPT, None);
+        }
+    }
+}
+
+/// 128 random bits as hex. Each `RandomState` is seeded from the OS per
+/// thread and advanced per call, so no two tokens share keys.
+fn token() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos());
+    let half = || {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(nanos);
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", half(), half())
+}
+
+/// `ttl` as PX takes it: whole milliseconds, and at least one.
+fn millis(ttl: Duration) -> u64 {
+    (ttl.as_millis() as u64).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use redis::Commands;
+
+    use super::*;
+    use crate::store::RedisStore;
+    use crate::testing;
+
+    #[test]
+    fn a_held_lock_cannot_be_taken_twice() {
+        let (mut store, test) = match testing::redis("lock_held") {
+            Some(redis) => redis,
+            None => return,
+        };
+        // A guard holds the connection it took the lock on, so the other
+        // attempts come from a client of their own.
+        let mut other = RedisStore::connect(&testing::target().unwrap()).unwrap();
+        let guard = RedisLock::acquire(store.connection(), &test.keys, "report", Duration::from_secs(10)).unwrap().unwrap();
+        assert!(RedisLock::acquire(other.connection(), &test.keys, "report", Duration::from_secs(10)).unwrap().is_none());
+        assert!(guard.release().unwrap());
+        assert!(RedisLock::acquire(other.connection(), &test.keys, "report", Duration::from_secs(10)).unwrap().is_some());
+    }
+
+    #[test]
+    fn an_expired_guard_leaves_the_next_holders_lock_alone() {
+        let (mut store, test) = match testing::redis("lock_expired") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let target = testing::target().unwrap();
+        let (mut next, mut reader) = (RedisStore::connect(&target).unwrap(), RedisStore::connect(&target).unwrap());
+        let conn = reader.connection();
+        let mut stale = RedisLock::acquire(store.connection(), &test.keys, "report", Duration::from_millis(200)).unwrap().unwrap();
+        thread::sleep(Duration::from_millis(400));
+        let current = RedisLock::acquire(next.connection(), &test.keys, "report", Duration::from_secs(10)).unwrap().unwrap();
+        let key = current.key().to_string();
+        let held: String = conn.get(&key).unwrap();
+
+        assert!(!stale.extend(Duration::from_secs(60)).unwrap());
+        assert!(!stale.release().unwrap());
+        assert_eq!(conn.get::<_, Option<String>>(&key).unwrap(), Some(held));
+        assert!(current.release().unwrap());
+        assert_eq!(conn.get::<_, Option<String>>(&key).unwrap(), None);
+    }
+}
@@ -1,18 +1,1178 @@
-use redis::Commands;
-
-fn main() -> redis::RedisResult<()> {
-    let conn = redis::Client::open("redis://localhost:6379")?.get_connection()?;
-    conn.set("aKey", "a string".to_string())?;
-    conn.set("anotherKey", 4567)?;
-    conn.set(45, 12345)?;
-
-    println!(
-        "{:?}, {:#?}, {:#?}, {:#?}.",
-        conn.get("aKey")?,
-        conn.get("anotherKey")?,
-        conn.get(45)?,
-        conn.exists(40)?
-    );
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
+use clap::{crate_authors, crate_name, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+#[cfg(not(feature = "cluster"))]
+use config::Settings;
+use log::{error, info, warn};
+use redis::{Client, ErrorKind, RedisResult};
+use serde_derive::{Deserialize, Serialize};
+
+use bench::BenchOptions;
+use cache::{BoundedIncrResult, Cache, CacheError, CasOutcome, PoolOptions};
+use command::{parse_count, parse_ttl, Command, DEFAULT_HEALTH_FIELD, REPL_USAGE};
+use key::{KeyBuilder, RedisKey};
+use keyspace::{WatchOptions, EVENT_NAMES};
+use leaderboard::Leaderboard;
+use lock::RedisLock;
+use rate_limit::{RateLimiter, Strategy};
+use store::RedisStore;
+use stream::ConsumerOptions;
+use typed_cache::{Encoding, TypedCache};
+
+mod aio;
+mod bench;
+mod cache;
+mod command;
+mod key;
+mod keyspace;
+mod leaderboard;
+mod lock;
+mod pubsub;
+mod queue;
+mod rate_limit;
+mod redis_url;
+mod retry;
+mod scan;
+mod stats;
+mod store;
+mod stream;
+#[cfg(test)]
+mod testing;
+mod typed_cache;
+
+/// Exit status when a command finds no such key.
+const EXIT_MISSING: i32 = 1;
+/// Exit status when Redis cannot be reached or a command fails.
+const EXIT_REDIS: i32 = 2;
+/// Exit status when `incr --max` leaves the key alone because the
+/// increment would take it past the cap.
+const EXIT_CAPPED: i32 = 3;
+/// Exit status when `cas` finds the key holding something other than the
+/// expected value.
+const EXIT_MISMATCH: i32 = 4;
+/// Exit status when `cas` gives up because other clients kept writing the
+/// key under it.
+const EXIT_CONTENDED: i32 = 5;
+
+fn main() {
+    logging::init();
+    let matches = App::new(crate_name!())
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Gets, sets and lists keys in Redis")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .arg(Arg::with_name("db")
+            .long("db")
+            .takes_value(true)
+            .value_name("n")
+            .global(true)
+            .validator(|db| redis_url::database(&db).map(|_| ()))
+            .help("Selects this database instead of the one in REDIS_URL"))
+        .arg(Arg::with_name("repl")
+            .long("repl")
+            .help("Reads commands such as get and set from stdin until EOF or quit"))
+        .subcommand(SubCommand::with_name("set")
+            .about("Sets a key to a value")
+            .arg(Arg::with_name("ttl")
+                .long("ttl")
+                .takes_value(true)
+                .value_name("seconds")
+                .validator(|secs| parse_ttl(&secs).map(|_| ()))
+                .help("Expires the key after this many seconds"))
+            .arg(Arg::with_name("key").required(true))
+            .arg(Arg::with_name("value").required(true)))
+        .subcommand(SubCommand::with_name("get")
+            .about("Prints the value of a key")
+            .arg(Arg::with_name("key").required(true)))
+        .subcommand(SubCommand::with_name("del")
+            .about("Deletes a key and prints how many were removed")
+            .arg(Arg::with_name("key").required(true)))
+        .subcommand(SubCommand::with_name("keys")
+            .about("Lists the keys matching a glob pattern as SCAN finds them")
+            .arg(Arg::with_name("count")
+                .long("count")
+                .takes_value(true)
+                .validator(|count| parse_count(&count).map(|_| ()))
+                .help("Keys the server looks at per SCAN batch, as a hint"))
+            .arg(Arg::with_name("limit")
+                .long("limit")
+                .takes_value(true)
+                .validator(|limit| parse_count(&limit).map(|_| ()))
+                .help("Stops after this many keys"))
+            .arg(Arg::with_name("pattern").default_value("*")))
+        .subcommand(SubCommand::with_name("ttl")
+            .about("Prints the seconds a key has left, or that it has no expiry")
+            .arg(Arg::with_name("key").required(true)))
+        .subcommand(SubCommand::with_name("expire")
+            .about("Makes an existing key expire after a number of seconds")
+            .arg(Arg::with_name("key").required(true))
+            .arg(Arg::with_name("seconds").required(true).validator(|secs| parse_ttl(&secs).map(|_| ()))))
+        .subcommand(SubCommand::with_name("mset")
+            .about("Sets several keys at once")
+            .arg(Arg::with_name("pairs")
+                .required(true)
+                .multiple(true)
+                .value_names(&["key", "value"])))
+        .subcommand(SubCommand::with_name("mget")
+            .about("Prints the values of several keys, fetched at once")
+            .arg(Arg::with_name("keys").required(true).multiple(true)))
+        .subcommand(SubCommand::with_name("incr")
+            .about("Increments a key, pipelining repeats, and prints each value")
+            .arg(Arg::with_name("atomic")
+                .long("atomic")
+                .help("Runs the increments as one MULTI/EXEC transaction"))
+            .arg(Arg::with_name("max")
+                .long("max")
+                .takes_value(true)
+                .value_name("m")
+                .conflicts_with("atomic")
+                .validator(|max| parse_integer(&max).map(|_| ()))
+                .help("Increments once, unless that would take the key above m"))
+            .arg(Arg::with_name("by")
+                .long("by")
+                .takes_value(true)
+                .value_name("n")
+                .requires("max")
+                .allow_hyphen_values(true)
+                .validator(|by| parse_integer(&by).map(|_| ()))
+                .help("Adds n instead of 1 with --max"))
+            .arg(Arg::with_name("key").required(true))
+            .arg(Arg::with_name("times")
+                .default_value("1")
+                .validator(|times| times.parse::<usize>().map(|_| ()).map_err(|_| format!("{:?} is not a count", times)))))
+        .subcommand(SubCommand::with_name("cas")
+            .about("Sets a key to a new value only if it holds the expected one, under WATCH")
+            .arg(Arg::with_name("retries")
+                .long("retries")
+                .takes_value(true)
+                .value_name("n")
+                .default_value("5")
+                .validator(|retries| retries.parse::<usize>().map(|_| ()).map_err(|_| format!("{:?} is not a count", retries)))
+                .help("Tries again this many times when another client writes the key midway"))
+            .arg(Arg::with_name("key").required(true))
+            .arg(Arg::with_name("expected").required(true))
+            .arg(Arg::with_name("new").required(true)))
+        .subcommand(SubCommand::with_name("hset")
+            .about("Sets a field of a hash and prints 1 if the field is new")
+            .arg(Arg::with_name("key").required(true))
+            .arg(Arg::with_name("field").required(true))
+            .arg(Arg::with_name("value").required(true)))
+        .subcommand(SubCommand::with_name("hget")
+            .about("Prints a field of a hash")
+            .arg(Arg::with_name("key").required(true))
+            .arg(Arg::with_name("field").required(true)))
+        .subcommand(SubCommand::with_name("hgetall")
+            .about("Prints every field of a hash as field = value, by field")
+            .arg(Arg::with_name("key").required(true)))
+        .subcommand(SubCommand::with_name("hdel")
+            .about("Deletes fields of a hash and prints how many were removed")
+            .arg(Arg::with_name("key").required(true))
+            .arg(Arg::with_name("fields").required(true).multiple(true)))
+        .subcommand(SubCommand::with_name("health")
+            .about("Checks that Redis answers PING, then prints fields of its INFO reply")
+            .arg(Arg::with_name("fields").multiple(true).default_value(DEFAULT_HEALTH_FIELD)))
+        .subcommand(SubCommand::with_name("publish")
+            .about("Sends a message to a channel and prints how many subscribers got it")
+            .arg(Arg::with_name("channel").required(true))
+            .arg(Arg::with_name("message").required(true)))
+        .subcommand(SubCommand::with_name("subscribe")
+            .about("Prints messages from channels, given as names or glob patterns, until Ctrl-C")
+            .arg(Arg::with_name("channels").required(true).multiple(true)))
+        .subcommand(SubCommand::with_name("watch-keys")
+            .about("Prints changes to keys matching a glob pattern, from keyspace notifications, until Ctrl-C")
+            .arg(Arg::with_name("pattern").required(true))
+            .arg(Arg::with_name("events")
+                .long("events")
+                .takes_value(true)
+                .value_name("event,...")
+                .use_delimiter(true)
+                .possible_values(&EVENT_NAMES)
+                .help("Prints only these events instead of every one"))
+            .arg(Arg::with_name("no-config")
+                .long("no-config")
+                .help("Leaves notify-keyspace-events alone, for servers that forbid CONFIG")))
+        .subcommand(SubCommand::with_name("enqueue")
+            .about("Adds a job carrying the payload to a work queue and prints its id")
+            .arg(Arg::with_name("queue").required(true))
+            .arg(Arg::with_name("payload").required(true)))
+        .subcommand(SubCommand::with_name("work")
+            .about("Prints the jobs of a work queue as they arrive until Ctrl-C, then finishes the current one")
+            .arg(Arg::with_name("fail")
+                .long("fail")
+                .takes_value(true)
+                .value_name("text")
+                .help("Fails the jobs whose payload contains this, sending them to <queue>:dead"))
+            .arg(Arg::with_name("queue").required(true)))
+        .subcommand(SubCommand::with_name("stream-add")
+            .about("Appends an entry to a stream and prints the id it was given")
+            .arg(Arg::with_name("stream").required(true))
+            .arg(Arg::with_name("fields")
+                .required(true)
+                .multiple(true)
+                .value_name("field=value")
+                .validator(|field| parse_field(&field).map(|_| ()))))
+        .subcommand(SubCommand::with_name("stream-consume")
+            .about("Prints a stream's entries as one consumer of a group until Ctrl-C, acknowledging each")
+            .arg(Arg::with_name("group")
+                .long("group")
+                .takes_value(true)
+                .value_name("g")
+                .required(true)
+                .help("Reads as part of this consumer group, creating it if needed"))
+            .arg(Arg::with_name("consumer")
+                .long("consumer")
+                .takes_value(true)
+                .value_name("c")
+                .required(true)
+                .help("Names this consumer within the group"))
+            .arg(Arg::with_name("claim-idle")
+                .long("claim-idle")
+                .takes_value(true)
+                .value_name("ms")
+                .validator(|ms| parse_count(&ms).map(|_| ()))
+                .help("Takes over entries other consumers left unacknowledged for this long"))
+            .arg(Arg::with_name("fail")
+                .long("fail")
+                .takes_value(true)
+                .value_name("text")
+                .help("Fails the entries with a value containing this, leaving them pending"))
+            .arg(Arg::with_name("stream").required(true)))
+        .subcommand(SubCommand::with_name("bench")
+            .about("Times SETs and then GETs, printing throughput and latency percentiles, and deletes the keys after")
+            .arg(Arg::with_name("ops")
+                .long("ops")
+                .takes_value(true)
+                .value_name("n")
+                .default_value("10000")
+                .validator(|ops| parse_count(&ops).map(|_| ()))
+                .help("SETs, and as many GETs, to send"))
+            .arg(Arg::with_name("prefix")
+                .long("prefix")
+                .takes_value(true)
+                .default_value("bench")
+                .help("Writes the keys <prefix>:0 onwards"))
+            .arg(Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .value_name("bytes")
+                .default_value("64")
+                .validator(|size| parse_count(&size).map(|_| ()))
+                .help("Bytes in each value"))
+            .arg(Arg::with_name("pipeline")
+                .long("pipeline")
+                .takes_value(true)
+                .value_name("batch")
+                .validator(|batch| parse_count(&batch).map(|_| ()))
+                .help("Pipelines this many commands per round trip instead of sending one at a time")))
+        .subcommand(SubCommand::with_name("typed")
+            .about("Writes and reads back a number and a string through typed keys"))
+        .subcommand(SubCommand::with_name("batch")
+            .about("Sets three keys with one MSET, then fetches them and a missing fourth with one MGET"))
+        .subcommand(SubCommand::with_name("async")
+            .about("Sets and gets three keys concurrently over one multiplexed async connection")
+            .arg(Arg::with_name("stress")
+                .long("stress")
+                .help("Fires 100 concurrent INCRs at one counter instead and checks the total"))
+            .arg(Arg::with_name("single")
+                .long("single")
+                .conflicts_with("stress")
+                .help("Sets and gets one key in turn over a plain async connection, then another over a multiplexed one"))
+            .arg(Arg::with_name("sync")
+                .long("sync")
+                .help("Runs the same commands over a blocking connection, one round trip at a time")))
+        .subcommand(SubCommand::with_name("leaderboard")
+            .about("Scores a few sample players in a sorted set and prints the top 3"))
+        .subcommand(SubCommand::with_name("lock")
+            .about("Has two threads contend for one lock, then shows a lapsed holder cannot release its successor"))
+        .subcommand(SubCommand::with_name("ratelimit-demo")
+            .about("Fires 20 requests, 100ms apart, at a limit of 5 per second and prints each decision")
+            .arg(Arg::with_name("sliding")
+                .long("sliding")
+                .help("Limits with a sliding window in a sorted set instead of a fixed-window counter")))
+        .subcommand(SubCommand::with_name("sale")
+            .about("Caches a sample sale record through the typed cache and reads it back")
+            .arg(Arg::with_name("encoding")
+                .long("encoding")
+                .possible_values(&Encoding::NAMES)
+                .default_value("json")))
+        .subcommand(SubCommand::with_name("demo")
+            .about("Writes and reads keys from several threads through a connection pool")
+            .arg(Arg::with_name("threads").long("threads").default_value("4"))
+            .arg(Arg::with_name("keys").long("keys").default_value("25").help("Keys each thread writes"))
+            .arg(Arg::with_name("bench")
+                .long("bench")
+                .help("Times writing --keys keys one command at a time and then pipelined")))
+        .get_matches();
+    let db = matches.value_of("db").map(|db| redis_url::database(db).expect("clap checks it"));
+    let url = target(db);
+    let url = url.as_str();
+    let keys = KeyBuilder::from_env().unwrap_or_else(|err| {
+        error!("Invalid REDIS_KEY_NAMESPACE or REDIS_KEY_ENV: {}", err);
+        process::exit(EXIT_REDIS);
+    });
+    if let Some(args) = matches.subcommand_matches("demo") {
+        process::exit(demo(url, args, &keys));
+    }
+    if let Some(args) = matches.subcommand_matches("sale") {
+        let encoding = args.value_of("encoding").unwrap().parse().expect("clap checks the encoding");
+        process::exit(cache_sale(url, encoding, &keys));
+    }
+    if let Some(args) = matches.subcommand_matches("async") {
+        if cfg!(feature = "cluster") {
+            error!("async runs over one server's connection, which a cluster does not have");
+            process::exit(EXIT_REDIS);
+        }
+        if args.is_present("sync") {
+            process::exit(run_sync(url, args, &keys));
+        }
+        process::exit(run_async(url, args, &keys));
+    }
+    if let Some(args) = matches.subcommand_matches("subscribe") {
+        // A cluster passes every message published on it to all its nodes,
+        // so subscribing to any one of them will do.
+        let url = if cfg!(feature = "cluster") { url.split(',').next().unwrap_or(url) } else { url };
+        process::exit(subscribe(url, args));
+    }
+    if let Some(args) = matches.subcommand_matches("watch-keys") {
+        if cfg!(feature = "cluster") {
+            error!("watch-keys follows one server's notifications; a cluster sends each node's only to its own");
+            process::exit(EXIT_REDIS);
+        }
+        process::exit(watch_keys(url, args, &keys));
+    }
+    if matches.subcommand_matches("lock").is_some() {
+        process::exit(lock_demo(url, &keys));
+    }
+    if let Some(args) = matches.subcommand_matches("incr").filter(|args| args.is_present("max")) {
+        process::exit(bounded_incr(url, args, &keys));
+    }
+    if let Some(args) = matches.subcommand_matches("cas") {
+        process::exit(compare_and_set(url, args, &keys));
+    }
+    let mut store = retry::connect_with_retry(url, retry::CONNECT_ATTEMPTS, retry::CONNECT_BACKOFF)
+        .unwrap_or_else(|err| {
+            error!("{}", retry::diagnosis(url, &err));
+            process::exit(EXIT_REDIS);
+        });
+    if matches.is_present("repl") {
+        repl(&mut store, &keys);
+        return;
+    }
+    if let Some(args) = matches.subcommand_matches("enqueue") {
+        let queue = key_arg(&keys, args.value_of("queue").expect("clap requires it"));
+        match queue::enqueue(store.connection(), &queue, args.value_of("payload").expect("clap requires it")) {
+            Ok(job) => println!("{}", job.id),
+            Err(err) => {
+                error!("Redis error: {}", err);
+                process::exit(EXIT_REDIS);
+            },
+        }
+        return;
+    }
+    if let Some(args) = matches.subcommand_matches("work") {
+        process::exit(work(&mut store, args, &keys));
+    }
+    if let Some(args) = matches.subcommand_matches("stream-add") {
+        process::exit(stream_add(&mut store, args, &keys));
+    }
+    if let Some(args) = matches.subcommand_matches("stream-consume") {
+        process::exit(stream_consume(&mut store, args, &keys));
+    }
+    if let Some(args) = matches.subcommand_matches("bench") {
+        process::exit(throughput(&mut store, args, &keys));
+    }
+    if let Some(args) = matches.subcommand_matches("ratelimit-demo") {
+        process::exit(rate_limit_demo(&mut store, args, &keys));
+    }
+    let demo: Option<fn(&mut RedisStore, &KeyBuilder) -> RedisResult<()>> = match matches.subcommand_name() {
+        Some("typed") => Some(typed),
+        Some("batch") => Some(batch),
+        Some("leaderboard") => Some(leaderboard),
+        _ => None,
+    };
+    if let Some(demo) = demo {
+        if let Err(err) = demo(&mut store, &keys) {
+            error!("Redis error: {}", err);
+            process::exit(EXIT_REDIS);
+        }
+        return;
+    }
+    let command = Command::from_matches(&matches).expect("clap requires a known subcommand");
+    match command.execute(&mut store, &keys, false) {
+        Ok(0) => {},
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            error!("Redis error: {}", err);
+            process::exit(EXIT_REDIS);
+        },
+    }
+}
+
+/// Where to connect: `REDIS_URL`, checked, with `--db` selecting the
+/// database when given. Exits when it is not a usable Redis URL.
+#[cfg(not(feature = "cluster"))]
+fn target(db: Option<i64>) -> String {
+    let settings = Settings::load().expect("Can't load settings");
+    redis_url::resolve(settings.redis_url(), db).unwrap_or_else(|err| {
+        error!("Invalid REDIS_URL: {}", err);
+        process::exit(EXIT_REDIS);
+    })
+}
+
+/// With the `cluster` feature, the cluster nodes in `REDIS_NODES` instead,
+/// comma-separated. `REDIS_URL` is not read.
+#[cfg(feature = "cluster")]
+fn target(db: Option<i64>) -> String {
+    redis_url::nodes_from_env(db).unwrap_or_else(|err| {
+        error!("Invalid REDIS_NODES: {}", err);
+        process::exit(EXIT_REDIS);
+    })
+}
+
+/// `name`, a key given on the command line, built through `keys`. Exits
+/// when it is no valid key.
+fn key_arg(keys: &KeyBuilder, name: &str) -> String {
+    keys.path(name).unwrap_or_else(|err| {
+        error!("Invalid key {:?}: {}", name, err);
+        process::exit(EXIT_REDIS);
+    })
+}
+
+/// Runs commands read from stdin, one per line, until EOF or `quit`. A
+/// failed command is reported and the next line read; a prompt is shown
+/// only on a terminal.
+fn repl(store: &mut RedisStore, keys: &KeyBuilder) {
+    let stdin = io::stdin();
+    let prompt = stdin.is_terminal();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if prompt {
+            print!("> ");
+            io::stdout().flush().ok();
+        }
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(err)) => {
+                eprintln!("Could not read stdin: {}", err);
+                return;
+            },
+            None => return,
+        };
+        match line.trim() {
+            "" => continue,
+            "quit" => return,
+            _ => {},
+        }
+        match Command::parse(&line) {
+            Some(command) => {
+                if let Err(err) = command.execute(store, keys, true) {
+                    eprintln!("Redis error: {}", err);
+                }
+            },
+            None => println!("{}", REPL_USAGE),
+        }
+    }
+}
+
+/// Sets a counter to 45 and a name to a string through keys that carry
+/// their value types, then reads both back. `counter.set(conn, "45")` or
+/// reading `name` into an `i64` would not compile.
+fn typed(store: &mut RedisStore, keys: &KeyBuilder) -> RedisResult<()> {
+    let conn = store.connection();
+    let counter = RedisKey::<i64>::new(&keys.counter("typed")?);
+    let name = RedisKey::<String>::new(&keys.raw(&["typed", "name"])?);
+    counter.set(conn, 45)?;
+    name.set(conn, "aKey".to_string())?;
+    let count: Option<i64> = counter.get(conn)?;
+    let text: Option<String> = name.get(conn)?;
+    println!("{} = {:?}", counter.name(), count);
+    println!("{} = {:?}", name.name(), text);
+    Ok(())
+}
+
+/// The key names and values the `batch` demo sets.
+const BATCH_PAIRS: [(&str, &str); 3] = [("one", "1"), ("two", "2"), ("three", "3")];
+
+/// Sets the `BATCH_PAIRS` keys in one MSET, then fetches them and a fourth
+/// that is deleted first in one MGET, printing the `None` its slot holds.
+fn batch(store: &mut RedisStore, keys: &KeyBuilder) -> RedisResult<()> {
+    let mut pairs = Vec::new();
+    for (name, value) in &BATCH_PAIRS {
+        pairs.push((keys.raw(&["batch", name])?, *value));
+    }
+    let missing = keys.raw(&["batch", "four"])?;
+    store.del(&missing)?;
+    store.mset(&pairs.iter().map(|(key, value)| (key.as_str(), *value)).collect::<Vec<_>>())?;
+    let mut wanted: Vec<&str> = pairs.iter().map(|(key, _)| key.as_str()).collect();
+    wanted.push(&missing);
+    for (key, value) in wanted.iter().zip(store.mget(&wanted)?) {
+        println!("{} = {:?}", keys.strip(key).unwrap_or(key), value);
+    }
+    Ok(())
+}
+
+/// Sample players and their scores for the `leaderboard` demo.
+const PLAYERS: [(&str, f64); 5] = [("ana", 3100.0), ("bo", 2750.5), ("cy", 3400.0), ("dee", 1200.0), ("eli", 2999.9)];
+
+/// Scores the sample players on a fresh board, prints the top 3, then the
+/// rank of one player and of a name that never played.
+fn leaderboard(store: &mut RedisStore, keys: &KeyBuilder) -> RedisResult<()> {
+    let key = keys.raw(&["leaderboard"])?;
+    store.del(&key)?;
+    let mut board = Leaderboard::new(store.connection(), &key);
+    for (player, score) in &PLAYERS {
+        board.add(player, *score)?;
+    }
+    for (place, (player, score)) in board.top(3)?.iter().enumerate() {
+        println!("{}. {:<6} {:>8.1}", place + 1, player, score);
+    }
+    for player in &["eli", "zed"] {
+        match board.rank(player)? {
+            Some(rank) => println!("{} is number {}", player, rank + 1),
+            None => println!("{} is not on the board", player),
+        }
+    }
+    Ok(())
+}
+
+/// A sale as the Postgres example reports it, the kind of record worth
+/// caching whole.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SaleWithProduct {
+    category: String,
+    name: String,
+    quantity: f64,
+    unit: String,
+    date: i64,
+}
+
+/// Bumped whenever `SaleWithProduct` changes shape, so sales cached in the
+/// old shape read as corrupt rather than as wrong values.
+const SALE_SCHEMA_VERSION: u8 = 1;
+const SALE_TTL_SECS: u64 = 60;
+
+/// Puts a sample sale in the typed cache with `encoding`, fetches it back
+/// and checks that it came back unchanged.
+fn cache_sale(url: &str, encoding: Encoding, keys: &KeyBuilder) -> i32 {
+    let cache = open_cache(url);
+    let sales = TypedCache::new(cache, SALE_SCHEMA_VERSION, encoding);
+    let key = keys.sale("2020-183").expect("the sample id is one segment");
+    let sale = SaleWithProduct {
+        category: "fruit".to_string(),
+        name: "pears".to_string(),
+        quantity: 7.34,
+        unit: "Kg".to_string(),
+        date: 1_234_567_890,
+    };
+    let fetched = sales
+        .put(&key, &sale, Some(SALE_TTL_SECS))
+        .and_then(|()| sales.fetch::<SaleWithProduct>(&key));
+    match fetched {
+        Ok(Some(ref fetched)) if *fetched == sale => {
+            println!("{:?}", fetched);
+            0
+        },
+        Ok(Some(fetched)) => {
+            error!("Read back {:?}, not what was put", fetched);
+            EXIT_MISSING
+        },
+        Ok(None) => {
+            error!("The sale was gone as soon as it was put");
+            EXIT_MISSING
+        },
+        Err(err) => {
+            error!("Cache error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// Opens the pool the cache demos share, exiting when the settings are bad
+/// or Redis cannot be reached.
+fn open_cache(url: &str) -> Cache {
+    let options = PoolOptions::from_env().unwrap_or_else(|err| {
+        error!("Invalid pool settings: {}", err);
+        process::exit(EXIT_REDIS);
+    });
+    Cache::connect(url, options).unwrap_or_else(|err| {
+        error!("Could not open a pool to Redis at {}: {}", url, err);
+        process::exit(EXIT_REDIS);
+    })
+}
+
+/// An `incr --by` or `--max` value.
+fn parse_integer(value: &str) -> Result<i64, String> {
+    value.parse().map_err(|_| format!("{:?} is not an integer", value))
+}
+
+/// Runs `incr --max`: adds `--by` (1 by default) to the key and prints the
+/// new value, or reports the value it was left at when that would pass the
+/// cap, exiting with `EXIT_CAPPED`.
+fn bounded_incr(url: &str, args: &ArgMatches, keys: &KeyBuilder) -> i32 {
+    if args.occurrences_of("times") > 0 {
+        error!("--max increments once; it takes no count");
+        return EXIT_REDIS;
+    }
+    let key = args.value_of("key").expect("clap requires it");
+    let built = key_arg(keys, key);
+    let max = parse_integer(args.value_of("max").unwrap()).expect("clap checks --max");
+    let by = args.value_of("by").map_or(Ok(1), parse_integer).expect("clap checks --by");
+    match open_cache(url).bounded_incr(&built, by, max) {
+        Ok(BoundedIncrResult::Incremented(value)) => {
+            println!("{}", value);
+            0
+        },
+        Ok(BoundedIncrResult::Capped { current }) => {
+            warn!("{} is at {}; adding {} would take it past {}", key, current, by, max);
+            EXIT_CAPPED
+        },
+        Err(err) => {
+            error!("Cache error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// Runs `cas`, exiting with `EXIT_MISMATCH` when the key holds something
+/// else and `EXIT_CONTENDED` when every try was cut short.
+fn compare_and_set(url: &str, args: &ArgMatches, keys: &KeyBuilder) -> i32 {
+    let key = args.value_of("key").expect("clap requires it");
+    let expected = args.value_of("expected").expect("clap requires it");
+    let new = args.value_of("new").expect("clap requires it");
+    let retries = args.value_of("retries").expect("it has a default").parse().expect("clap checks it");
+    match open_cache(url).compare_and_set(&key_arg(keys, key), expected.as_bytes(), new.as_bytes(), retries) {
+        Ok(CasOutcome::Swapped { retries: 0 }) => 0,
+        Ok(CasOutcome::Swapped { retries }) => {
+            info!("Set {} after {} retries, the key changed under the first tries", key, retries);
+            0
+        },
+        Ok(CasOutcome::Mismatch { current: Some(current) }) => {
+            warn!("{} holds {:?}, not {:?}; left it alone", key, String::from_utf8_lossy(&current), expected);
+            EXIT_MISMATCH
+        },
+        Ok(CasOutcome::Mismatch { current: None }) => {
+            warn!("{} does not exist, so it does not hold {:?}", key, expected);
+            EXIT_MISMATCH
+        },
+        Ok(CasOutcome::Exhausted { retries }) => {
+            warn!("Gave up on {} after {} retries, other clients kept writing it", key, retries);
+            EXIT_CONTENDED
+        },
+        Err(err) => {
+            error!("Cache error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// Runs the async demo, or with `--stress` the concurrent INCRs, on a tokio
+/// runtime over one multiplexed connection, which pipelines the commands of
+/// every future using it. `--single` runs the one-key-at-a-time demo over a
+/// plain connection and a multiplexed one instead.
+#[tokio::main]
+async fn run_async(url: &str, args: &ArgMatches, keys: &KeyBuilder) -> i32 {
+    let client = match Client::open(url) {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Invalid Redis URL {}: {}", url, err);
+            return EXIT_REDIS;
+        },
+    };
+    if args.is_present("single") {
+        return report_demo(aio::set_get_demo(&client).await, keys);
+    }
+    let conn = match client.get_multiplexed_async_connection().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("Redis error: {}", err);
+            return EXIT_REDIS;
+        },
+    };
+    if args.is_present("stress") {
+        report_stress(aio::stress(&conn, keys).await, "concurrent")
+    } else {
+        report_demo(aio::demo(&conn, keys).await, keys)
+    }
+}
+
+/// `async --sync`: the same demo, `--single` demo or stress run as
+/// `run_async`, over a blocking connection.
+fn run_sync(url: &str, args: &ArgMatches, keys: &KeyBuilder) -> i32 {
+    let mut conn = match Client::open(url).and_then(|client| client.get_connection()) {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("Redis error: {}", err);
+            return EXIT_REDIS;
+        },
+    };
+    if args.is_present("single") {
+        report_demo(aio::set_get_demo_sync(&mut conn, keys), keys)
+    } else if args.is_present("stress") {
+        report_stress(aio::stress_sync(&mut conn, keys), "sequential")
+    } else {
+        report_demo(aio::demo_sync(&mut conn, keys), keys)
+    }
+}
+
+fn report_demo(values: RedisResult<Vec<(String, Option<String>)>>, keys: &KeyBuilder) -> i32 {
+    match values {
+        Ok(values) => {
+            for (key, value) in values {
+                println!("{} = {:?}", keys.strip(&key).unwrap_or(&key), value);
+            }
+            0
+        },
+        Err(err) => {
+            error!("Redis error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// Exits with `EXIT_MISSING` when the `how` INCRs left the counter
+/// anywhere but `STRESS_INCREMENTS`.
+fn report_stress(total: RedisResult<i64>, how: &str) -> i32 {
+    match total {
+        Ok(total) if total == aio::STRESS_INCREMENTS => {
+            println!("{} {} INCRs, counter at {}", aio::STRESS_INCREMENTS, how, total);
+            0
+        },
+        Ok(total) => {
+            error!("{} {} INCRs left the counter at {}", aio::STRESS_INCREMENTS, how, total);
+            EXIT_MISSING
+        },
+        Err(err) => {
+            error!("Redis error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// Prints each message on the channels as `channel: payload` until Ctrl-C,
+/// then unsubscribes and returns the exit status.
+fn subscribe(url: &str, args: &ArgMatches) -> i32 {
+    let channels: Vec<&str> = args.values_of("channels").expect("clap requires them").collect();
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    if let Err(err) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst)) {
+        error!("Could not handle Ctrl-C: {}", err);
+        return EXIT_REDIS;
+    }
+    let result = pubsub::subscribe(url, &channels, &stop, |channel, payload| {
+        println!("{}: {}", channel, String::from_utf8_lossy(payload));
+    });
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            error!("Redis error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// Prints `<seconds since the epoch> <event> <key>` for each key event
+/// until Ctrl-C.
+fn watch_keys(url: &str, args: &ArgMatches, keys: &KeyBuilder) -> i32 {
+    let options = WatchOptions {
+        pattern: keys.pattern(args.value_of("pattern").expect("clap requires it")),
+        events: args.values_of("events").map(|events| events.map(str::to_string).collect()),
+        configure: !args.is_present("no-config"),
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    if let Err(err) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst)) {
+        error!("Could not handle Ctrl-C: {}", err);
+        return EXIT_REDIS;
+    }
+    let result = keyspace::watch(url, &options, &stop, |event| {
+        let at = event.received.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let key = keys.strip(&event.key).unwrap_or(&event.key);
+        println!("{}.{:03} {} {}", at.as_secs(), at.subsec_millis(), event.event, key);
+        // Piped into another program, each event should reach it at once.
+        let _ = io::stdout().flush();
+    });
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            error!("Redis error: {}", err);
+            if options.configure && err.kind() == ErrorKind::ResponseError {
+                info!("If the server forbids CONFIG, enable notify-keyspace-events there and pass --no-config.");
+            }
+            EXIT_REDIS
+        },
+    }
+}
+
+/// How long the `lock` demo's holders keep the lock, and the TTL they take
+/// it with.
+const LOCK_HOLD: Duration = Duration::from_millis(300);
+const LOCK_TTL: Duration = Duration::from_secs(2);
+
+/// Runs both halves of the `lock` demo, each over its own connections.
+fn lock_demo(url: &str, keys: &KeyBuilder) -> i32 {
+    let result = contend(url, keys).and_then(|()| lapsed_release(url, keys));
+    match result {
+        Ok(true) => 0,
+        Ok(false) => EXIT_MISSING,
+        Err(err) => {
+            error!("Redis error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// Two threads try for the `demo` lock until they get it, each holding it for
+/// `LOCK_HOLD` and extending it once on the way, so one always waits
+/// for the other.
+fn contend(url: &str, keys: &KeyBuilder) -> RedisResult<()> {
+    let workers: Vec<_> = (1..=2)
+        .map(|worker| {
+            let url = url.to_string();
+            let keys = keys.clone();
+            thread::spawn(move || -> RedisResult<()> {
+                let mut store = RedisStore::connect(&url)?;
+                let conn = store.connection();
+                let started = Instant::now();
+                let mut tries = 1;
+                let mut guard = loop {
+                    if let Some(guard) = RedisLock::acquire(conn, &keys, "demo", LOCK_TTL)? {
+                        break guard;
+                    }
+                    tries += 1;
+                    thread::sleep(Duration::from_millis(50));
+                };
+                let key = keys.strip(guard.key()).unwrap_or(guard.key());
+                println!("Thread {} took {} after {} tries in {:?}", worker, key, tries, started.elapsed());
+                thread::sleep(LOCK_HOLD / 2);
+                guard.extend(LOCK_TTL)?;
+                thread::sleep(LOCK_HOLD / 2);
+                guard.release()?;
+                println!("Thread {} released it", worker);
+                Ok(())
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("a lock thread panicked")?;
+    }
+    Ok(())
+}
+
+/// Takes the `lapsed` lock with a TTL shorter than the work, lets it run out and
+/// a second client take the lock, then has the first release: that must
+/// leave the second's lock in place. Returns whether it did.
+fn lapsed_release(url: &str, keys: &KeyBuilder) -> RedisResult<bool> {
+    let mut first_store = RedisStore::connect(url)?;
+    let mut second_store = RedisStore::connect(url)?;
+    let first = RedisLock::acquire(first_store.connection(), keys, "lapsed", Duration::from_millis(100))?
+        .expect("nothing else takes the lapsed lock");
+    thread::sleep(Duration::from_millis(200));
+    let second = match RedisLock::acquire(second_store.connection(), keys, "lapsed", LOCK_TTL)? {
+        Some(second) => second,
+        None => {
+            error!("The lapsed lock was still held after its TTL");
+            return Ok(false);
+        },
+    };
+    let released = first.release()?;
+    // The second store's connection is the second guard's until it is
+    // released, so the check goes over the first's.
+    let held = redis::cmd("EXISTS").arg(second.key()).query::<bool>(first_store.connection())?;
+    println!("The lapsed holder released: {}; its successor still holds the lock: {}", released, held);
+    Ok(!released && held && second.release()?)
+}
+
+const RATE_LIMIT: u64 = 5;
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+const RATE_REQUESTS: usize = 20;
+/// Spreads the requests over two windows, so the demo shows them reset.
+const RATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Sends `ratelimit-demo`'s requests through a fresh window and prints what
+/// the limiter decided for each, with when it was made.
+fn rate_limit_demo(store: &mut RedisStore, args: &ArgMatches, keys: &KeyBuilder) -> i32 {
+    let mut limiter = if args.is_present("sliding") {
+        RateLimiter::sliding_window(store.connection(), keys)
+    } else {
+        RateLimiter::fixed_window(store.connection(), keys)
+    };
+    let strategy = match limiter.strategy() {
+        Strategy::FixedWindow => "fixed",
+        Strategy::SlidingWindow => "sliding",
+    };
+    println!("{} requests at {} per {:?}, {} window", RATE_REQUESTS, RATE_LIMIT, RATE_WINDOW, strategy);
+    let result = limiter.reset("demo").and_then(|()| {
+        let started = Instant::now();
+        for request in 1..=RATE_REQUESTS {
+            let decision = limiter.check("demo", RATE_LIMIT, RATE_WINDOW)?;
+            println!(
+                "{:>2} {:>7.3}s  {:<7}  {} left, resets in {:.3}s",
+                request,
+                started.elapsed().as_secs_f64(),
+                if decision.allowed { "allowed" } else { "denied" },
+                decision.remaining,
+                decision.reset_after.as_secs_f64(),
+            );
+            thread::sleep(RATE_INTERVAL);
+        }
+        Ok(())
+    });
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            error!("Redis error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// Works through the queue until Ctrl-C, printing each job, and then how
+/// many were done and how many failed.
+fn work(store: &mut RedisStore, args: &ArgMatches, keys: &KeyBuilder) -> i32 {
+    let queue = &key_arg(keys, args.value_of("queue").expect("clap requires it"));
+    let fail = args.value_of("fail");
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    if let Err(err) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst)) {
+        error!("Could not handle Ctrl-C: {}", err);
+        return EXIT_REDIS;
+    }
+    let result = queue::work(store.connection(), queue, &stop, |job| {
+        println!("job {} (enqueued at {}): {}", job.id, job.enqueued_at, job.payload);
+        match fail {
+            Some(text) if job.payload.contains(text) => Err(format!("payload contains {:?}", text)),
+            _ => Ok(()),
+        }
+    });
+    match result {
+        Ok(summary) => {
+            println!("{} job(s) done, {} sent to {}", summary.done, summary.failed, queue::dead_letter_queue(queue));
+            0
+        },
+        Err(err) => {
+            error!("Redis error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// A `stream-add` field, `field=value`, split at its first `=`.
+fn parse_field(field: &str) -> Result<(&str, &str), String> {
+    field.split_once('=').ok_or_else(|| format!("{:?} is not field=value", field))
+}
+
+fn stream_add(store: &mut RedisStore, args: &ArgMatches, keys: &KeyBuilder) -> i32 {
+    let stream = &key_arg(keys, args.value_of("stream").expect("clap requires it"));
+    let fields: Vec<(&str, &str)> = args.values_of("fields")
+        .expect("clap requires them")
+        .map(|field| parse_field(field).expect("clap checks them"))
+        .collect();
+    match stream::add(store.connection(), stream, &fields) {
+        Ok(id) => {
+            println!("{}", id);
+            0
+        },
+        Err(err) => {
+            error!("Redis error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// Prints each entry as `<id> field=value ...` until Ctrl-C, then how many
+/// were done, left pending and claimed.
+fn stream_consume(store: &mut RedisStore, args: &ArgMatches, keys: &KeyBuilder) -> i32 {
+    let stream = &key_arg(keys, args.value_of("stream").expect("clap requires it"));
+    let options = ConsumerOptions {
+        group: args.value_of("group").expect("clap requires it").to_string(),
+        consumer: args.value_of("consumer").expect("clap requires it").to_string(),
+        claim_idle: args.value_of("claim-idle")
+            .map(|ms| Duration::from_millis(parse_count(ms).expect("clap checks it") as u64)),
+    };
+    let fail = args.value_of("fail");
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    if let Err(err) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst)) {
+        error!("Could not handle Ctrl-C: {}", err);
+        return EXIT_REDIS;
+    }
+    let result = stream::consume(store.connection(), stream, &options, &stop, |entry| {
+        let fields: Vec<String> = entry.fields.iter().map(|(field, value)| format!("{}={}", field, value)).collect();
+        println!("{} {}", entry.id, fields.join(" "));
+        let _ = io::stdout().flush();
+        match fail {
+            Some(text) if entry.fields.iter().any(|(_, value)| value.contains(text)) => {
+                Err(format!("a value contains {:?}", text))
+            },
+            _ => Ok(()),
+        }
+    });
+    match result {
+        Ok(summary) => {
+            println!("{} entr(ies) done, {} left pending, {} claimed", summary.done, summary.failed, summary.claimed);
+            0
+        },
+        Err(err) => {
+            error!("Redis error: {}", err);
+            EXIT_REDIS
+        },
+    }
+}
+
+/// Has `--threads` threads each set `--keys` keys through one shared pool
+/// in a single pipeline, reading them all back with a single MGET, then
+/// checks that one scan of `demo:*:*` finds all the keys. Each thread also
+/// records what it did in the hash `demo:<thread>`. With `--bench`, times
+/// the writes instead.
+fn demo(url: &str, args: &ArgMatches, builder: &KeyBuilder) -> i32 {
+    let threads: usize = args.value_of("threads").unwrap().parse().unwrap_or_else(|_| {
+        error!("--threads must be a number");
+        process::exit(EXIT_REDIS);
+    });
+    let keys: usize = args.value_of("keys").unwrap().parse().unwrap_or_else(|_| {
+        error!("--keys must be a number");
+        process::exit(EXIT_REDIS);
+    });
+    let cache = open_cache(url);
+    if args.is_present("bench") {
+        return match bench(&cache, keys, builder) {
+            Ok(()) => 0,
+            Err(err) => {
+                error!("Redis error: {}", err);
+                EXIT_REDIS
+            },
+        };
+    }
+    let handles: Vec<_> = (0..threads)
+        .map(|thread| {
+            let cache = cache.clone();
+            let builder = builder.clone();
+            thread::spawn(move || -> Result<(), CacheError> {
+                let value = format!("written by thread {}", thread).into_bytes();
+                let pairs: Vec<(String, Vec<u8>)> = (0..keys).map(|index| (demo_key(&builder, thread, index), value.clone())).collect();
+                cache.set_many(&pairs)?;
+                let names: Vec<String> = pairs.into_iter().map(|(key, _)| key).collect();
+                let mut mismatched = 0;
+                for (name, read) in names.iter().zip(cache.get_many(&names)?) {
+                    if read.as_ref() != Some(&value) {
+                        error!("{} did not read back as written", name);
+                        mismatched += 1;
+                    }
+                }
+                let mut summary = HashMap::new();
+                summary.insert("written".to_string(), keys.to_string());
+                summary.insert("mismatched".to_string(), mismatched.to_string());
+                let hash = builder.raw(&["demo", &thread.to_string()]).expect("numbers are valid segments");
+                cache.store_fields(&hash, &summary)
+            })
+        })
+        .collect();
+    for handle in handles {
+        if let Err(err) = handle.join().expect("demo threads do not panic") {
+            error!("Redis error: {}", err);
+            return EXIT_REDIS;
+        }
+    }
+
+    let found: Result<HashSet<String>, CacheError> = cache
+        .scan(&builder.pattern("demo:*:*"), None)
+        .and_then(|scan| scan.collect::<RedisResult<_>>().map_err(CacheError::from));
+    let found = match found {
+        Ok(found) => found,
+        Err(err) => {
+            error!("Redis error: {}", err);
+            return EXIT_REDIS;
+        },
+    };
+    let mut missing = 0;
+    for thread in 0..threads {
+        for index in 0..keys {
+            let key = demo_key(builder, thread, index);
+            if !found.contains(&key) {
+                error!("{} is missing", key);
+                missing += 1;
+            }
+        }
+    }
+    if missing > 0 {
+        return EXIT_MISSING;
+    }
+    println!("{} thread(s) wrote {} key(s) each, all present", threads, keys);
+    0
+}
+
+/// The `index`th key `thread` writes in `demo`.
+fn demo_key(builder: &KeyBuilder, thread: usize, index: usize) -> String {
+    builder.raw(&["demo", &thread.to_string(), &index.to_string()]).expect("numbers are valid segments")
+}
+
+/// Runs the `bench` subcommand and prints a row per command.
+fn throughput(store: &mut RedisStore, args: &ArgMatches, keys: &KeyBuilder) -> i32 {
+    let count = |name| args.value_of(name).map(|value| parse_count(value).expect("clap checks it"));
+    let options = BenchOptions {
+        ops: count("ops").expect("it has a default"),
+        prefix: key_arg(keys, args.value_of("prefix").expect("it has a default")),
+        value_size: count("size").expect("it has a default"),
+        pipeline: count("pipeline"),
+    };
+    let (sets, gets) = match bench::run(store.connection(), &options) {
+        Ok(summaries) => summaries,
+        Err(err) => {
+            error!("Redis error: {}", err);
+            return EXIT_REDIS;
+        },
+    };
+    let mode = options.pipeline.map_or("one at a time".to_string(), |batch| format!("pipelined {} at a time", batch));
+    println!("{} SETs and GETs of {}-byte values, {}", options.ops, options.value_size, mode);
+    println!("{:<6}{:>12}{:>10}{:>10}{:>10}", "", "ops/s", "p50", "p95", "p99");
+    for (name, summary) in &[("SET", sets), ("GET", gets)] {
+        println!(
+            "{:<6}{:>12.0}{:>10.1?}{:>10.1?}{:>10.1?}",
+            name, summary.ops_per_sec(), summary.p50, summary.p95, summary.p99,
+        );
+    }
+    0
+}
+
+/// Writes `keys` keys one SET at a time, then the same keys in one
+/// pipeline, reads them back both ways, and prints how long each took.
+fn bench(cache: &Cache, keys: usize, builder: &KeyBuilder) -> Result<(), CacheError> {
+    let pairs: Vec<(String, Vec<u8>)> = (0..keys)
+        .map(|index| {
+            let key = builder.raw(&["bench", &index.to_string()]).expect("numbers are valid segments");
+            (key, format!("value {}", index).into_bytes())
+        })
+        .collect();
+    let names: Vec<String> = pairs.iter().map(|(key, _)| key.clone()).collect();
+
+    let started = Instant::now();
+    for (key, value) in &pairs {
+        cache.set(key, value)?;
+    }
+    let unpipelined_writes = started.elapsed();
+    let started = Instant::now();
+    cache.set_many(&pairs)?;
+    let pipelined_writes = started.elapsed();
+
+    let started = Instant::now();
+    for key in &names {
+        cache.get(key)?;
+    }
+    let unpipelined_reads = started.elapsed();
+    let started = Instant::now();
+    cache.get_many(&names)?;
+    let pipelined_reads = started.elapsed();
+
+    println!("{} key(s)", keys);
+    println!("{:<8}{:>14}  {:>7}", "", "one at a time", "batched");
+    println!("writes  {:>14.1?}  {:>7.1?}", unpipelined_writes, pipelined_writes);
+    println!("reads   {:>14.1?}  {:>7.1?}", unpipelined_reads, pipelined_reads);
     Ok(())
-}
\ No newline at end of file
+}
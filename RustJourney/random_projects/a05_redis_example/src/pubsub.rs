@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use redis::{Client, RedisResult};
+
+/// How long a subscriber waits for a message before checking whether it
+/// has been told to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long unsubscribing may take before the connection is given up on.
+const UNSUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether `channel` holds glob characters, and so needs PSUBSCRIBE.
+fn is_pattern(channel: &str) -> bool {
+    channel.contains(['*', '?', '['])
+}
+
+/// Whether a loop blocked reading from Redis has been told to stop. Ctrl-C
+/// interrupts a blocked read, possibly just before the handler thread sets
+/// `stop`, so a failed read gives the handler a moment to catch up.
+pub fn stopping(stop: &AtomicBool) -> bool {
+    if stop.load(Ordering::SeqCst) {
+        return true;
+    }
+    thread::sleep(Duration::from_millis(50));
+    stop.load(Ordering::SeqCst)
+}
+
+/// Listens on `channels`, given as names or glob patterns, calling
+/// `on_message` with the channel and payload of each message until `stop`
+/// is set. It then unsubscribes from all of them and returns. Pubsub mode
+/// ties up a connection, so this opens its own.
+pub fn subscribe<F>(url: &str, channels: &[&str], stop: &AtomicBool, mut on_message: F) -> RedisResult<()>
+where
+    F: FnMut(&str, &[u8]),
+{
+    let mut conn = Client::open(url)?.get_connection()?;
+    // PubSub::subscribe and psubscribe each read one reply and drop it.
+    // Once the first subscription is live that reply may be a message
+    // rather than the confirmation, so both are sent unread instead, and
+    // get_message skips the confirmations.
+    let (patterns, names): (Vec<&str>, Vec<&str>) = channels.iter().partition(|channel| is_pattern(channel));
+    if !names.is_empty() {
+        conn.send_packed_command(&redis::cmd("SUBSCRIBE").arg(&names[..]).get_packed_command())?;
+    }
+    if !patterns.is_empty() {
+        conn.send_packed_command(&redis::cmd("PSUBSCRIBE").arg(&patterns[..]).get_packed_command())?;
+    }
+    let mut pubsub = conn.as_pubsub();
+    pubsub.set_read_timeout(Some(POLL_INTERVAL))?;
+    while !stop.load(Ordering::SeqCst) {
+        match pubsub.get_message() {
+            Ok(message) => on_message(message.get_channel_name(), message.get_payload_bytes()),
+            Err(ref err) if err.is_timeout() => {},
+            Err(_) if stopping(stop) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    // Dropping `pubsub` sends UNSUBSCRIBE and PUNSUBSCRIBE and waits for
+    // the server to confirm both.
+    pubsub.set_read_timeout(Some(UNSUBSCRIBE_TIMEOUT))
+}
+
+#[cfg(all(test, not(feature = "cluster")))]
+mod tests {
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn a_subscriber_receives_what_is_published() {
+        let (mut store, test) = match testing::redis("pubsub") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let url = testing::target().expect("testing::redis found it");
+        let named = test.keys.raw(&["events"]).unwrap();
+        let pattern = test.keys.pattern("news.*");
+        let sport = test.keys.raw(&["news.sport"]).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (received, messages) = mpsc::channel();
+        let subscriber = {
+            let (stop, channels) = (stop.clone(), [named.clone(), pattern]);
+            thread::spawn(move || {
+                let channels: Vec<&str> = channels.iter().map(String::as_str).collect();
+                subscribe(&url, &channels, &stop, |channel, payload| {
+                    let _ = received.send((channel.to_string(), payload.to_vec()));
+                })
+            })
+        };
+
+        // Publishing before the subscriber is listening reaches no one, so
+        // keep at it until the server counts a receiver.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut publish = |channel: &str, message| {
+            while store.publish(channel, message).unwrap() == 0 {
+                assert!(Instant::now() < deadline, "nothing subscribed to {}", channel);
+                thread::sleep(Duration::from_millis(20));
+            }
+        };
+        let timeout = Duration::from_secs(2);
+        publish(&named, "hello");
+        assert_eq!(messages.recv_timeout(timeout).unwrap(), (named, b"hello".to_vec()));
+        publish(&sport, "goal");
+        assert_eq!(messages.recv_timeout(timeout).unwrap(), (sport, b"goal".to_vec()));
+
+        stop.store(true, Ordering::SeqCst);
+        subscriber.join().unwrap().unwrap();
+    }
+}
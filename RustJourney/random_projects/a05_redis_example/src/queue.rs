@@ -0,0 +1,159 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use redis::{Commands, RedisResult};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::pubsub::stopping;
+use crate::store::StoreConnection;
+
+/// How long a worker blocks on an empty queue before checking whether it
+/// has been told to stop.
+const POLL_TIMEOUT_SECS: f64 = 1.0;
+
+/// The envelope a job travels in, as JSON on the queue's list.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    /// Seconds since the Unix epoch when the job was enqueued.
+    pub enqueued_at: u64,
+    pub payload: String,
+}
+
+impl Job {
+    pub fn new(id: u64, payload: &str) -> Job {
+        let enqueued_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        Job { id, enqueued_at, payload: payload.to_string() }
+    }
+}
+
+/// What a worker got through before it stopped.
+#[derive(Debug, Default)]
+pub struct WorkSummary {
+    pub done: usize,
+    pub failed: usize,
+}
+
+/// Where the jobs of `queue` that failed are kept.
+pub fn dead_letter_queue(queue: &str) -> String {
+    format!("{}:dead", queue)
+}
+
+/// Wraps `payload` in a job with the next id of `queue` and pushes it on.
+pub fn enqueue(conn: &mut StoreConnection, queue: &str, payload: &str) -> RedisResult<Job> {
+    let id = conn.incr(format!("{}:next_id", queue), 1)?;
+    let job = Job::new(id, payload);
+    let envelope = serde_json::to_string(&job).expect("a job always serializes");
+    conn.lpush::<_, _, ()>(queue, envelope)?;
+    Ok(job)
+}
+
+/// Takes jobs off `queue` oldest first and hands each to `process` until
+/// `stop` is set, which cuts short only the wait for a job, never the
+/// handling of one. A job `process` fails, or an entry that is not a job
+/// at all, goes onto the dead-letter queue with an `error` field added.
+pub fn work<F>(conn: &mut StoreConnection, queue: &str, stop: &AtomicBool, mut process: F) -> RedisResult<WorkSummary>
+where
+    F: FnMut(&Job) -> Result<(), String>,
+{
+    let dead = dead_letter_queue(queue);
+    let mut summary = WorkSummary::default();
+    while !stop.load(Ordering::SeqCst) {
+        let popped: Option<(String, String)> = match conn.brpop(queue, POLL_TIMEOUT_SECS) {
+            Ok(popped) => popped,
+            Err(_) if stopping(stop) => break,
+            Err(err) => return Err(err),
+        };
+        let envelope = match popped {
+            Some((_, envelope)) => envelope,
+            None => continue,
+        };
+        let outcome = serde_json::from_str(&envelope)
+            .map_err(|err| format!("not a job: {}", err))
+            .and_then(|job| process(&job));
+        match outcome {
+            Ok(()) => summary.done += 1,
+            Err(error) => {
+                conn.lpush::<_, _, ()>(&dead, dead_letter(&envelope, &error))?;
+                summary.failed += 1;
+            },
+        }
+    }
+    Ok(summary)
+}
+
+/// The envelope with `error` added, or for an entry that is not a JSON
+/// object, the entry as `payload` beside it.
+fn dead_letter(envelope: &str, error: &str) -> String {
+    let mut entry = match serde_json::from_str(envelope) {
+        Ok(Value::Object(entry)) => entry,
+        _ => {
+            let mut entry = serde_json::Map::new();
+            entry.insert("payload".to_string(), envelope.into());
+            entry
+        },
+    };
+    entry.insert("error".to_string(), error.into());
+    Value::Object(entry).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn a_worker_drains_the_queue_and_keeps_failed_jobs() {
+        let (mut store, test) = match testing::redis("queue") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let conn = store.connection();
+        let queue = &test.keys.raw(&["jobs"]).unwrap();
+        for payload in &["resize", "explode", "email"] {
+            enqueue(conn, queue, payload).unwrap();
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        // Should the jobs never all arrive, the worker still stops.
+        let deadline = Arc::clone(&stop);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(10));
+            deadline.store(true, Ordering::SeqCst);
+        });
+        let mut handled = Vec::new();
+        let summary = work(conn, queue, &stop, |job| {
+            handled.push(job.payload.clone());
+            if handled.len() == 3 {
+                stop.store(true, Ordering::SeqCst);
+            }
+            if job.payload == "explode" {
+                Err("boom".to_string())
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap();
+
+        assert_eq!(handled, ["resize", "explode", "email"]);
+        assert_eq!((summary.done, summary.failed), (2, 1));
+        assert_eq!(conn.llen::<_, u64>(queue).unwrap(), 0);
+        let dead: Vec<String> = conn.lrange(dead_letter_queue(queue), 0, -1).unwrap();
+        assert_eq!(dead.len(), 1);
+        let entry: Value = serde_json::from_str(&dead[0]).unwrap();
+        assert_eq!(entry["payload"], "explode");
+        assert_eq!(entry["id"], 2);
+        assert_eq!(entry["error"], "boom");
+    }
+
+    #[test]
+    fn an_entry_that_is_no_job_is_kept_whole() {
+        let entry: Value = serde_json::from_str(&dead_letter("not json", "not a job: oops")).unwrap();
+        assert_eq!(entry, serde_json::json!({"payload": "not json", "error": "not a job: oops"}));
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use redis::{Commands, RedisResult, Script};
+
+use crate::key::KeyBuilder;
+use crate::store::StoreConnection;
+
+/// Counts a request against KEYS[1], a counter that expires ARGV[2]
+/// milliseconds after its first request, and answers `{allowed, count,
+/// milliseconds until it expires}` for a limit of ARGV[1]. A counter
+/// found without an expiry, as one set by anything but this script might
+/// be, is given one rather than limiting forever.
+const FIXED_WINDOW_SCRIPT: &str = r"
+local count = redis.call('INCR', KEYS[1])
+if count == 1 then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl < 0 then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    ttl = tonumber(ARGV[2])
+end
+local allowed = 0
+if count <= tonumber(ARGV[1]) then
+    allowed = 1
+end
+return {allowed, count, ttl}
+";
+
+/// Drops the requests in the sorted set KEYS[1] older than ARGV[2]
+/// milliseconds before ARGV[3], now, and records ARGV[4] scored by now if
+/// fewer than ARGV[1] remain. Answers `{allowed, count, milliseconds until
+/// the oldest request leaves the window}`. Denied requests are not
+/// recorded, so a client that keeps retrying is let back in as soon as
+/// there is room.
+const SLIDING_WINDOW_SCRIPT: &str = r"
+local now = tonumber(ARGV[3])
+local window = tonumber(ARGV[2])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+local count = redis.call('ZCARD', KEYS[1])
+local allowed = 0
+if count < tonumber(ARGV[1]) then
+    redis.call('ZADD', KEYS[1], now, ARGV[4])
+    count = count + 1
+    allowed = 1
+end
+redis.call('PEXPIRE', KEYS[1], window)
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+local reset = 0
+if oldest[2] then
+    reset = tonumber(oldest[2]) + window - now
+end
+return {allowed, count, reset}
+";
+
+/// How a `RateLimiter` decides whether a request fits in its window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// One counter per window, starting at the window's first request. Its
+    /// one key is cheap, but a client can fit up to twice the limit around
+    /// the moment one window ends and the next begins.
+    FixedWindow,
+    /// Every allowed request in a sorted set, scored by when it came. No
+    /// window of the given length ever holds more than the limit, at the
+    /// cost of one member per request.
+    SlidingWindow,
+}
+
+/// Whether a request may go ahead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// Requests that can still be made before the limit is hit.
+    pub remaining: u64,
+    /// How long until the window frees up: the fixed window ends, or the
+    /// oldest request leaves the sliding one.
+    pub reset_after: Duration,
+}
+
+/// Limits how often each of many keys, such as a client or user id, may
+/// make requests, sharing its counts between every process using one
+/// Redis. Each check runs as a single script, so concurrent checks can
+/// never both take the last request of a window.
+pub struct RateLimiter<'conn> {
+    conn: &'conn mut StoreConnection,
+    keys: KeyBuilder,
+    strategy: Strategy,
+    script: Script,
+    /// Tells apart sliding-window requests made in the same nanosecond.
+    sequence: u64,
+}
+
+impl<'conn> RateLimiter<'conn> {
+    pub fn fixed_window(conn: &'conn mut StoreConnection, keys: &KeyBuilder) -> RateLimiter<'conn> {
+        RateLimiter::new(conn, keys, Strategy::FixedWindow)
+    }
+
+    pub fn sliding_window(conn: &'conn mut StoreConnection, keys: &KeyBuilder) -> RateLimiter<'conn> {
+        RateLimiter::new(conn, keys, Strategy::SlidingWindow)
+    }
+
+    fn new(conn: &'conn mut StoreConnection, keys: &KeyBuilder, strategy: Strategy) -> RateLimiter<'conn> {
+        let script = match strategy {
+            Strategy::FixedWindow => FIXED_WINDOW_SCRIPT,
+            Strategy::SlidingWindow => SLIDING_WINDOW_SCRIPT,
+        };
+        RateLimiter { conn, keys: keys.clone(), strategy, script: Script::new(script), sequence: 0 }
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// Counts a request by `key` against `limit` requests per `window`.
+    /// Windows are timed in whole milliseconds, and at least one.
+    pub fn check(&mut self, key: &str, limit: u64, window: Duration) -> RedisResult<Decision> {
+        let window = (window.as_millis() as u64).max(1);
+        let mut invocation = self.script.key(self.key(key)?);
+        invocation.arg(limit).arg(window);
+        if self.strategy == Strategy::SlidingWindow {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            let sequence = self.sequence;
+            self.sequence = sequence.wrapping_add(1);
+            invocation.arg(now.as_millis() as u64).arg(format!("{}-{}", now.as_nanos(), sequence));
+        }
+        let (allowed, count, reset): (i64, u64, i64) = invocation.invoke(self.conn)?;
+        Ok(Decision {
+            allowed: allowed == 1,
+            remaining: limit.saturating_sub(count),
+            reset_after: Duration::from_millis(reset.max(0) as u64),
+        })
+    }
+
+    /// Forgets the requests counted for `key`, opening a fresh window.
+    pub fn reset(&mut self, key: &str) -> RedisResult<()> {
+        self.conn.del(self.key(key)?)
+    }
+
+    /// The key of `ratelimit`, the strategy and `key`, whose segments are
+    /// separated by `:` as on the command line: the two strategies keep
+    /// different types under their keys, so they cannot share one.
+    fn key(&self, key: &str) -> RedisResult<String> {
+        let strategy = match self.strategy {
+            Strategy::FixedWindow => "fixed",
+            Strategy::SlidingWindow => "sliding",
+        };
+        let mut parts = vec!["ratelimit", strategy];
+        parts.extend(key.split(':'));
+        Ok(self.keys.raw(&parts)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::testing;
+
+    const WINDOW: Duration = Duration::from_millis(300);
+
+    #[test]
+    fn a_fixed_window_opens_again_once_it_ends() {
+        let (mut store, test) = match testing::redis("ratelimit_fixed") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let mut limiter = RateLimiter::fixed_window(store.connection(), &test.keys);
+        let remaining: Vec<u64> = (0..3).map(|_| limiter.check("client:1", 3, WINDOW).unwrap().remaining).collect();
+        assert_eq!(remaining, [2, 1, 0]);
+        let denied = limiter.check("client:1", 3, WINDOW).unwrap();
+        assert!(!denied.allowed);
+        assert!(denied.reset_after <= WINDOW, "resets after {:?}", denied.reset_after);
+        assert!(limiter.check("client:2", 3, WINDOW).unwrap().allowed);
+
+        thread::sleep(denied.reset_after + Duration::from_millis(50));
+        let reopened = limiter.check("client:1", 3, WINDOW).unwrap();
+        assert_eq!((reopened.allowed, reopened.remaining), (true, 2));
+    }
+
+    #[test]
+    fn a_sliding_window_lets_in_one_request_per_one_leaving() {
+        let (mut store, test) = match testing::redis("ratelimit_sliding") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let mut limiter = RateLimiter::sliding_window(store.connection(), &test.keys);
+        assert!(limiter.check("client:1", 2, WINDOW).unwrap().allowed);
+        thread::sleep(WINDOW / 2);
+        assert!(limiter.check("client:1", 2, WINDOW).unwrap().allowed);
+        let denied = limiter.check("client:1", 2, WINDOW).unwrap();
+        assert!(!denied.allowed);
+        assert!(denied.reset_after <= WINDOW / 2 + Duration::from_millis(20), "resets after {:?}", denied.reset_after);
+
+        // Only the first request has left the window; the second is still in it.
+        thread::sleep(denied.reset_after + Duration::from_millis(30));
+        let reopened = limiter.check("client:1", 2, WINDOW).unwrap();
+        assert_eq!((reopened.allowed, reopened.remaining), (true, 0));
+        assert!(!limiter.check("client:1", 2, WINDOW).unwrap().allowed);
+    }
+
+    #[test]
+    fn reset_opens_a_fresh_window() {
+        let (mut store, test) = match testing::redis("ratelimit_reset") {
+            Some(redis) => redis,
+            None => return,
+        };
+        for strategy in &[Strategy::FixedWindow, Strategy::SlidingWindow] {
+            let mut limiter = RateLimiter::new(store.connection(), &test.keys, *strategy);
+            assert!(limiter.check("client", 1, Duration::from_secs(60)).unwrap().allowed);
+            assert!(!limiter.check("client", 1, Duration::from_secs(60)).unwrap().allowed);
+            limiter.reset("client").unwrap();
+            assert!(limiter.check("client", 1, Duration::from_secs(60)).unwrap().allowed, "{:?}", limiter.strategy());
+        }
+    }
+}
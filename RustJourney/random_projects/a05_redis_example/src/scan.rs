@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+use std::ops::DerefMut;
+use std::vec;
+
+#[cfg(feature = "cluster")]
+use redis::{from_redis_value, ConnectionAddr, ConnectionInfo, ErrorKind, RedisError, Value};
+use redis::{Cmd, RedisResult};
+
+use crate::store::StoreConnection;
+
+/// The keys matching a glob pattern, fetched one SCAN batch at a time as
+/// the iterator is advanced, so neither the server nor the caller has to
+/// walk the whole keyspace at once. SCAN may report a key more than once;
+/// each is yielded only the first time, at the cost of remembering them.
+///
+/// `C` is whatever holds the connection: a plain reference, or a
+/// connection checked out of a pool that goes back when the scan is dropped.
+pub struct KeyScan<C> {
+    conn: C,
+    pattern: String,
+    count: Option<usize>,
+    /// Where the next SCAN starts, or `None` once the server has reported
+    /// the end or an error has stopped the scan.
+    cursor: Option<u64>,
+    batch: vec::IntoIter<String>,
+    seen: HashSet<String>,
+    #[cfg(feature = "cluster")]
+    masters: Masters,
+}
+
+/// A cluster connection sends SCAN to any one node, which only knows the
+/// keys of its own slots, so in cluster mode each master is scanned in
+/// turn, over a connection of the scan's own.
+#[cfg(feature = "cluster")]
+struct Masters {
+    /// How the cluster was reached, whose password and settings the
+    /// connection to each master reuses.
+    seed: ConnectionInfo,
+    /// The masters not yet scanned, once CLUSTER SLOTS has named them.
+    left: Option<vec::IntoIter<ConnectionAddr>>,
+    /// The master being scanned.
+    current: Option<redis::Connection>,
+}
+
+impl<C: DerefMut<Target = StoreConnection>> KeyScan<C> {
+    /// A scan for `pattern` that sends `count`, when given, as the COUNT
+    /// hint: roughly how many keys the server looks at per batch. With the
+    /// `cluster` feature, `seed` is the info of the node the cluster was
+    /// reached through.
+    pub fn new(
+        conn: C,
+        #[cfg(feature = "cluster")] seed: ConnectionInfo,
+        pattern: &str,
+        count: Option<usize>,
+    ) -> KeyScan<C> {
+        KeyScan {
+            conn,
+            pattern: pattern.to_string(),
+            count,
+            cursor: Some(0),
+            batch: Vec::new().into_iter(),
+            seen: HashSet::new(),
+            #[cfg(feature = "cluster")]
+            masters: Masters { seed, left: None, current: None },
+        }
+    }
+
+    #[cfg(not(feature = "cluster"))]
+    fn send(&mut self, command: &Cmd) -> RedisResult<(u64, Vec<String>)> {
+        command.query(&mut *self.conn)
+    }
+
+    /// The server has nothing past this batch.
+    #[cfg(not(feature = "cluster"))]
+    fn finished_node(&mut self) -> Option<u64> {
+        None
+    }
+
+    #[cfg(feature = "cluster")]
+    fn send(&mut self, command: &Cmd) -> RedisResult<(u64, Vec<String>)> {
+        if self.masters.left.is_none() {
+            self.masters.left = Some(masters(&mut self.conn)?.into_iter());
+        }
+        if self.masters.current.is_none() {
+            let addr = self.masters.left.as_mut().and_then(Iterator::next).ok_or_else(|| {
+                RedisError::from((ErrorKind::ResponseError, "CLUSTER SLOTS named no masters"))
+            })?;
+            let info = ConnectionInfo { addr, redis: self.masters.seed.redis.clone() };
+            self.masters.current = Some(redis::Client::open(info)?.get_connection()?);
+        }
+        command.query(self.masters.current.as_mut().expect("connected above"))
+    }
+
+    /// Moves on to the next master, starting its scan from 0, or ends the
+    /// scan after the last.
+    #[cfg(feature = "cluster")]
+    fn finished_node(&mut self) -> Option<u64> {
+        self.masters.current = None;
+        match &self.masters.left {
+            Some(left) if left.len() > 0 => Some(0),
+            _ => None,
+        }
+    }
+}
+
+impl<C: DerefMut<Target = StoreConnection>> Iterator for KeyScan<C> {
+    type Item = RedisResult<String>;
+
+    /// The next matching key, or the error a SCAN failed with, after which
+    /// the scan ends.
+    fn next(&mut self) -> Option<RedisResult<String>> {
+        loop {
+            for key in &mut self.batch {
+                if self.seen.insert(key.clone()) {
+                    return Some(Ok(key));
+                }
+            }
+            let cursor = self.cursor?;
+            let mut command = redis::cmd("SCAN");
+            command.arg(cursor).arg("MATCH").arg(&self.pattern);
+            if let Some(count) = self.count {
+                command.arg("COUNT").arg(count);
+            }
+            match self.send(&command) {
+                Ok((next, keys)) => {
+                    self.cursor = if next == 0 { self.finished_node() } else { Some(next) };
+                    self.batch = keys.into_iter();
+                },
+                Err(err) => {
+                    self.cursor = None;
+                    return Some(Err(err));
+                },
+            }
+        }
+    }
+}
+
+/// Every master CLUSTER SLOTS names, each once, in slot order. Each range of
+/// slots in the reply is `[first, last, [host, port, ...], replicas...]`.
+#[cfg(feature = "cluster")]
+fn masters(conn: &mut StoreConnection) -> RedisResult<Vec<ConnectionAddr>> {
+    let unexpected = || RedisError::from((ErrorKind::TypeError, "unexpected CLUSTER SLOTS reply"));
+    let ranges: Vec<Vec<Value>> = redis::cmd("CLUSTER").arg("SLOTS").query(conn)?;
+    let mut masters = Vec::new();
+    for range in ranges {
+        let master = match range.get(2) {
+            Some(Value::Bulk(master)) if master.len() >= 2 => master,
+            _ => return Err(unexpected()),
+        };
+        let addr = ConnectionAddr::Tcp(from_redis_value(&master[0])?, from_redis_value(&master[1])?);
+        if !masters.contains(&addr) {
+            masters.push(addr);
+        }
+    }
+    Ok(masters)
+}
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+/// How a run of timed operations went: throughput over the run as a whole,
+/// and the latency percentiles of its operations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub ops: usize,
+    pub elapsed: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl Summary {
+    /// Summarizes `latencies`, one per operation in any order, from a run
+    /// that took `elapsed` from start to end.
+    pub fn new(mut latencies: Vec<Duration>, elapsed: Duration) -> Summary {
+        latencies.sort_unstable();
+        Summary {
+            ops: latencies.len(),
+            elapsed,
+            p50: percentile(&latencies, 50.0),
+            p95: percentile(&latencies, 95.0),
+            p99: percentile(&latencies, 99.0),
+        }
+    }
+
+    /// Operations per second over the whole run, or 0 for a run too short
+    /// to time.
+    pub fn ops_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.ops as f64 / secs } else { 0.0 }
+    }
+}
+
+/// The `p`th percentile of `sorted`, ascending, by nearest rank: the
+/// smallest sample that at least `p` percent of the samples do not exceed.
+/// Always one of the samples, never an interpolation; zero when there are
+/// none.
+pub fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|&ms| Duration::from_millis(ms)).collect()
+    }
+
+    #[test]
+    fn percentiles_are_nearest_rank_samples() {
+        // 1ms to 100ms: the pth percentile is exactly p milliseconds.
+        let sorted = millis(&(1..=100).collect::<Vec<_>>());
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&sorted, 95.0), Duration::from_millis(95));
+        assert_eq!(percentile(&sorted, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&sorted, 100.0), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn percentiles_never_interpolate() {
+        let sorted = millis(&[10, 20, 30, 40]);
+        // 50% of four samples is the second; 51% already needs the third.
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_millis(20));
+        assert_eq!(percentile(&sorted, 51.0), Duration::from_millis(30));
+        assert_eq!(percentile(&sorted, 99.0), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn percentiles_of_few_samples() {
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+        let one = millis(&[7]);
+        assert_eq!(percentile(&one, 0.0), Duration::from_millis(7));
+        assert_eq!(percentile(&one, 99.0), Duration::from_millis(7));
+    }
+
+    #[test]
+    fn summary_sorts_its_samples() {
+        let latencies = millis(&[90, 10, 50, 30, 70, 20, 100, 40, 80, 60]);
+        let summary = Summary::new(latencies, Duration::from_secs(2));
+        assert_eq!(summary.ops, 10);
+        assert_eq!(summary.p50, Duration::from_millis(50));
+        assert_eq!(summary.p95, Duration::from_millis(100));
+        assert_eq!(summary.p99, Duration::from_millis(100));
+        assert_eq!(summary.ops_per_sec(), 5.0);
+    }
+
+    #[test]
+    fn a_run_too_short_to_time_has_no_throughput() {
+        let summary = Summary::new(millis(&[1, 2, 3]), Duration::ZERO);
+        assert_eq!(summary.ops_per_sec(), 0.0);
+        assert_eq!(Summary::new(Vec::new(), Duration::from_secs(1)).p99, Duration::ZERO);
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[cfg(feature = "cluster")]
+use redis::cluster::{ClusterClient, ClusterConnection, ClusterPipeline};
+#[cfg(not(feature = "cluster"))]
+use redis::Client;
+#[cfg(feature = "cluster")]
+use redis::{ConnectionInfo, IntoConnectionInfo};
+use redis::{from_redis_value, Commands, ConnectionLike, ErrorKind, FromRedisValue, Pipeline, RedisError, RedisResult};
+
+use crate::scan::KeyScan;
+
+/// What the store and everything built on it send commands over: one
+/// server's connection, or with the `cluster` feature, one that routes
+/// each command to the cluster node serving its key.
+#[cfg(not(feature = "cluster"))]
+pub type StoreConnection = redis::Connection;
+#[cfg(feature = "cluster")]
+pub type StoreConnection = ClusterConnection;
+
+/// A client for the cluster reached through `nodes`, the comma-separated
+/// node URLs `redis_url::nodes_from_env` returns. Its connections learn
+/// which master serves which slots from CLUSTER SLOTS and send each
+/// command to the node serving its key, following MOVED and ASK replies.
+/// Commands that take several keys, such as MGET or a DEL of many keys,
+/// must keep to one slot or be sent one key at a time.
+#[cfg(feature = "cluster")]
+pub fn cluster_client(nodes: &str) -> RedisResult<ClusterClient> {
+    ClusterClient::new(nodes.split(',').collect::<Vec<_>>())
+}
+
+/// The first of `nodes`, whose password a connection to any other node of
+/// the cluster reuses.
+#[cfg(feature = "cluster")]
+pub fn cluster_seed(nodes: &str) -> RedisResult<ConnectionInfo> {
+    nodes.split(',').next().unwrap_or(nodes).into_connection_info()
+}
+
+/// A pipeline for commands on keys of any slots. A plain pipeline goes
+/// whole to one node, so with the `cluster` feature this is one that is
+/// split by node, costing a round trip per node, with its replies put back
+/// in order.
+#[cfg(not(feature = "cluster"))]
+pub fn pipe() -> redis::Pipeline {
+    redis::pipe()
+}
+
+#[cfg(feature = "cluster")]
+pub fn pipe() -> ClusterPipeline {
+    redis::cluster::cluster_pipe()
+}
+
+/// Runs `commands`, a pipeline marked `atomic`, as one MULTI/EXEC
+/// transaction on `conn`, and returns what EXEC replied: Nil when a WATCH
+/// aborted it, otherwise every command's reply, `ignore` or not.
+///
+/// The transaction is written out whole rather than through
+/// `Pipeline::query`, which a cluster connection refuses; a cluster
+/// connection sends it to the node serving the first command's key, so
+/// every key in it must share that key's slot.
+pub fn transaction<T: FromRedisValue>(commands: &Pipeline, conn: &mut StoreConnection) -> RedisResult<T> {
+    let count = commands.cmd_iter().count();
+    if count == 0 {
+        return from_redis_value(&redis::Value::Bulk(Vec::new()));
+    }
+    // MULTI and each QUEUED come back before EXEC's reply.
+    let exec = conn.req_packed_commands(&commands.get_packed_pipeline(), count + 1, 1)?.pop();
+    from_redis_value(&exec.ok_or_else(|| RedisError::from((ErrorKind::ResponseError, "no reply to EXEC")))?)
+}
+
+/// How long a key has left, as Redis' TTL reports it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TtlStatus {
+    Missing,
+    /// The key exists and never expires.
+    Persistent,
+    Expiring(Duration),
+}
+
+/// A key-value store on one Redis connection, with values kept as raw
+/// bytes so whatever was set comes back unchanged.
+pub struct RedisStore {
+    conn: StoreConnection,
+    #[cfg(feature = "cluster")]
+    seed: ConnectionInfo,
+}
+
+impl RedisStore {
+    #[cfg(not(feature = "cluster"))]
+    pub fn connect(url: &str) -> RedisResult<RedisStore> {
+        let conn = Client::open(url)?.get_connection()?;
+        Ok(RedisStore { conn })
+    }
+
+    /// Connects to the cluster through `nodes`, the comma-separated node
+    /// URLs `redis_url::nodes_from_env` returns.
+    #[cfg(feature = "cluster")]
+    pub fn connect(nodes: &str) -> RedisResult<RedisStore> {
+        Ok(RedisStore { conn: cluster_client(nodes)?.get_connection()?, seed: cluster_seed(nodes)? })
+    }
+
+    /// The connection itself, for typed keys and other callers that issue
+    /// their own commands.
+    pub fn connection(&mut self) -> &mut StoreConnection {
+        &mut self.conn
+    }
+
+    /// Whether the server answers PING with PONG.
+    pub fn ping(&mut self) -> RedisResult<bool> {
+        let reply: String = redis::cmd("PING").query(&mut self.conn)?;
+        Ok(reply == "PONG")
+    }
+
+    /// The fields of the INFO reply, such as `redis_version`. Section
+    /// headers (`# Server`) and blank lines are skipped.
+    pub fn server_info(&mut self) -> RedisResult<HashMap<String, String>> {
+        let reply: String = redis::cmd("INFO").query(&mut self.conn)?;
+        Ok(reply
+            .split("\r\n")
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect())
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8]) -> RedisResult<()> {
+        self.conn.set(key, value)
+    }
+
+    /// Sets `key` to expire `ttl_secs` seconds from now, in the same command
+    /// as the write so the key is never left without its expiry.
+    pub fn set_with_ttl(&mut self, key: &str, value: &[u8], ttl_secs: u64) -> RedisResult<()> {
+        redis::cmd("SET").arg(key).arg(value).arg("EX").arg(ttl_secs).query(&mut self.conn)
+    }
+
+    pub fn ttl(&mut self, key: &str) -> RedisResult<TtlStatus> {
+        let secs: i64 = self.conn.ttl(key)?;
+        Ok(match secs {
+            -2 => TtlStatus::Missing,
+            secs if secs < 0 => TtlStatus::Persistent,
+            secs => TtlStatus::Expiring(Duration::from_secs(secs as u64)),
+        })
+    }
+
+    /// Makes an existing key expire `ttl_secs` seconds from now, returning
+    /// false when there is no such key.
+    pub fn expire(&mut self, key: &str, ttl_secs: u64) -> RedisResult<bool> {
+        self.conn.expire(key, ttl_secs as usize)
+    }
+
+    /// The value at `key`, or `None` when it does not exist.
+    pub fn get(&mut self, key: &str) -> RedisResult<Option<Vec<u8>>> {
+        self.conn.get(key)
+    }
+
+    /// Sets every pair with one MSET, so all of them are written in a single
+    /// round trip and at once.
+    #[cfg(not(feature = "cluster"))]
+    pub fn mset(&mut self, pairs: &[(&str, &str)]) -> RedisResult<()> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        redis::cmd("MSET").arg(pairs).query(&mut self.conn)
+    }
+
+    /// Sets every pair with a pipeline of SETs instead: the keys of one MSET
+    /// must share a slot, and these may belong to different nodes. The
+    /// pipeline still costs one round trip per node, but the pairs are no
+    /// longer all written at once.
+    #[cfg(feature = "cluster")]
+    pub fn mset(&mut self, pairs: &[(&str, &str)]) -> RedisResult<()> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = pipe();
+        for (key, value) in pairs {
+            pipe.set(*key, *value).ignore();
+        }
+        pipe.query(&mut self.conn)
+    }
+
+    /// The values of `keys` with one MGET, in the same order, with `None`
+    /// for each key that does not exist.
+    #[cfg(not(feature = "cluster"))]
+    pub fn mget(&mut self, keys: &[&str]) -> RedisResult<Vec<Option<String>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Spelled out because `get` sends a lone key as GET, whose reply
+        // is a value rather than a list.
+        redis::cmd("MGET").arg(keys).query(&mut self.conn)
+    }
+
+    /// The values of `keys` with a pipeline of GETs, as `mset` writes them
+    /// in cluster mode.
+    #[cfg(feature = "cluster")]
+    pub fn mget(&mut self, keys: &[&str]) -> RedisResult<Vec<Option<String>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut pipe = pipe();
+        for key in keys {
+            pipe.get(*key);
+        }
+        pipe.query(&mut self.conn)
+    }
+
+    /// Increments `key` `times` times, returning every value it passes
+    /// through, with all the INCRs queued in one pipeline. The commands go
+    /// out in a single write and the replies come back in a single read,
+    /// where a loop of `self.conn.incr(key, 1)` calls would wait out a full
+    /// round trip per increment: for 1000 increments, one network exchange
+    /// instead of 1000.
+    ///
+    /// A plain pipeline only batches the traffic; the server may run other
+    /// clients' commands between the INCRs, so the values can skip numbers.
+    /// With `atomic` the batch is wrapped in MULTI/EXEC and runs as one
+    /// transaction, so nothing lands in between and the values are
+    /// consecutive.
+    pub fn pipelined_increments(&mut self, key: &str, times: usize, atomic: bool) -> RedisResult<Vec<i64>> {
+        if times == 0 {
+            return Ok(Vec::new());
+        }
+        if atomic {
+            let mut commands = redis::pipe();
+            for _ in 0..times {
+                commands.incr(key, 1);
+            }
+            return transaction(commands.atomic(), &mut self.conn);
+        }
+        let mut pipe = pipe();
+        for _ in 0..times {
+            pipe.incr(key, 1);
+        }
+        pipe.query(&mut self.conn)
+    }
+
+    /// Sets `field` of the hash at `key`, returning whether the field is new.
+    pub fn hset(&mut self, key: &str, field: &str, value: &str) -> RedisResult<bool> {
+        self.conn.hset(key, field, value)
+    }
+
+    /// `field` of the hash at `key`, or `None` when either does not exist.
+    pub fn hget(&mut self, key: &str, field: &str) -> RedisResult<Option<String>> {
+        self.conn.hget(key, field)
+    }
+
+    /// Every field of the hash at `key`; empty when there is no such key.
+    pub fn hgetall(&mut self, key: &str) -> RedisResult<HashMap<String, String>> {
+        self.conn.hgetall(key)
+    }
+
+    /// Removes `fields` from the hash at `key`, returning how many existed.
+    pub fn hdel(&mut self, key: &str, fields: &[&str]) -> RedisResult<u64> {
+        self.conn.hdel(key, fields)
+    }
+
+    /// Sends `message` to `channel`, returning how many subscribers got it.
+    pub fn publish(&mut self, channel: &str, message: &str) -> RedisResult<u64> {
+        self.conn.publish(channel, message)
+    }
+
+    /// Deletes `key`, returning how many keys were removed: 0 or 1.
+    pub fn del(&mut self, key: &str) -> RedisResult<u64> {
+        self.conn.del(key)
+    }
+
+    /// The keys matching the glob `pattern`, found with SCAN so the server
+    /// is never blocked walking the whole keyspace at once, as KEYS would.
+    #[cfg(not(feature = "cluster"))]
+    pub fn scan(&mut self, pattern: &str, count: Option<usize>) -> KeyScan<&mut StoreConnection> {
+        KeyScan::new(&mut self.conn, pattern, count)
+    }
+
+    /// In cluster mode the scan walks every master in turn.
+    #[cfg(feature = "cluster")]
+    pub fn scan(&mut self, pattern: &str, count: Option<usize>) -> KeyScan<&mut StoreConnection> {
+        KeyScan::new(&mut self.conn, self.seed.clone(), pattern, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn a_key_set_with_a_ttl_is_gone_after_it() {
+        let (mut store, test) = match testing::redis("ttl") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = &test.keys.raw(&["session"]).unwrap();
+        store.set_with_ttl(key, b"token", 2).unwrap();
+        assert_eq!(store.get(key).unwrap(), Some(b"token".to_vec()));
+        match store.ttl(key).unwrap() {
+            TtlStatus::Expiring(left) => assert!(left <= Duration::from_secs(2), "{:?} left", left),
+            status => panic!("a key set with a TTL reports {:?}", status),
+        }
+        thread::sleep(Duration::from_millis(2500));
+        assert_eq!(store.get(key).unwrap(), None);
+        assert_eq!(store.ttl(key).unwrap(), TtlStatus::Missing);
+    }
+
+    #[test]
+    fn expire_only_applies_to_existing_keys() {
+        let (mut store, test) = match testing::redis("expire") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = &test.keys.raw(&["kept"]).unwrap();
+        assert!(!store.expire(key, 60).unwrap());
+        store.set(key, b"value").unwrap();
+        assert_eq!(store.ttl(key).unwrap(), TtlStatus::Persistent);
+        assert!(store.expire(key, 60).unwrap());
+        assert!(matches!(store.ttl(key).unwrap(), TtlStatus::Expiring(left) if left > Duration::from_secs(50)));
+    }
+
+    #[test]
+    fn pipelined_increments_return_every_value_like_a_loop_of_incrs() {
+        let (mut store, test) = match testing::redis("pipelined_increments") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let (looped, piped) = (&test.keys.raw(&["looped"]).unwrap(), &test.keys.raw(&["piped"]).unwrap());
+        let by_loop: Vec<i64> = (0..200).map(|_| store.connection().incr(looped, 1).unwrap()).collect();
+        let by_pipeline = store.pipelined_increments(piped, 200, false).unwrap();
+
+        assert_eq!(by_pipeline, by_loop);
+        assert_eq!(by_pipeline, (1..=200).collect::<Vec<i64>>());
+        assert_eq!(store.pipelined_increments(piped, 3, true).unwrap(), vec![201, 202, 203]);
+    }
+
+    #[test]
+    fn no_increments_leave_the_key_alone() {
+        let (mut store, test) = match testing::redis("no_increments") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = &test.keys.raw(&["untouched"]).unwrap();
+        assert_eq!(store.pipelined_increments(key, 0, true).unwrap(), Vec::<i64>::new());
+        assert_eq!(store.get(key).unwrap(), None);
+    }
+
+    #[test]
+    fn hash_fields_can_be_set_read_and_partly_deleted() {
+        let (mut store, test) = match testing::redis("hash") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = &test.keys.raw(&["user", "42"]).unwrap();
+        for (field, value) in &[("name", "Ada"), ("email", "ada@example.com"), ("role", "admin")] {
+            assert!(store.hset(key, field, value).unwrap());
+        }
+        assert!(!store.hset(key, "role", "owner").unwrap());
+        assert_eq!(store.hget(key, "role").unwrap().as_deref(), Some("owner"));
+
+        assert_eq!(store.hdel(key, &["email", "role", "phone"]).unwrap(), 2);
+        let left = store.hgetall(key).unwrap();
+        assert_eq!(left, [("name".to_string(), "Ada".to_string())].iter().cloned().collect());
+        assert_eq!(store.hget(key, "email").unwrap(), None);
+    }
+
+    #[test]
+    fn a_missing_hash_has_no_fields() {
+        let (mut store, test) = match testing::redis("hash_missing") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = &test.keys.raw(&["user", "none"]).unwrap();
+        assert_eq!(store.hgetall(key).unwrap(), HashMap::new());
+        assert_eq!(store.hget(key, "name").unwrap(), None);
+        assert_eq!(store.hdel(key, &["name"]).unwrap(), 0);
+    }
+}
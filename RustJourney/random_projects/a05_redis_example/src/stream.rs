@@ -0,0 +1,271 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use log::warn;
+use redis::{ErrorKind, FromRedisValue, RedisResult, Value};
+
+use crate::pubsub::stopping;
+use crate::store::StoreConnection;
+
+/// How long a consumer blocks on a stream with nothing new for it before
+/// checking whether it has been told to stop.
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(1);
+/// Entries read or claimed per round trip.
+const BATCH: usize = 10;
+/// Where an XAUTOCLAIM sweep starts, and the cursor it ends on.
+const SWEEP_START: &str = "0-0";
+
+/// One entry of a stream, with its fields in the order they were added.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamEntry {
+    pub id: String,
+    pub fields: Vec<(String, String)>,
+}
+
+/// Who `consume` reads a stream as.
+pub struct ConsumerOptions {
+    pub group: String,
+    pub consumer: String,
+    /// Takes over entries another consumer of the group read and left
+    /// unacknowledged for at least this long, as one that crashed midway
+    /// leaves them.
+    pub claim_idle: Option<Duration>,
+}
+
+/// What a consumer got through before it stopped.
+#[derive(Debug, Default)]
+pub struct ConsumeSummary {
+    pub done: usize,
+    /// Entries left pending because processing them failed.
+    pub failed: usize,
+    /// Entries taken over from idle consumers, whichever way they went.
+    pub claimed: usize,
+}
+
+/// Appends an entry of `fields` to `stream`, creating the stream if need
+/// be, and returns the id Redis gave the entry.
+pub fn add(conn: &mut StoreConnection, stream: &str, fields: &[(&str, &str)]) -> RedisResult<String> {
+    let mut xadd = redis::cmd("XADD");
+    xadd.arg(stream).arg("*");
+    for (field, value) in fields {
+        xadd.arg(*field).arg(*value);
+    }
+    xadd.query(conn)
+}
+
+/// Creates `group` on `stream`, and the stream with it when there is none,
+/// to read the stream from its first entry. Returns `false` when the group
+/// was already there, leaving it as it was.
+pub fn create_group(conn: &mut StoreConnection, stream: &str, group: &str) -> RedisResult<bool> {
+    let created = redis::cmd("XGROUP").arg("CREATE").arg(stream).arg(group).arg("0").arg("MKSTREAM").query::<()>(conn);
+    match created {
+        Ok(()) => Ok(true),
+        Err(ref err) if err.code() == Some("BUSYGROUP") => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads `stream` as `options.consumer` of `options.group`, creating the
+/// group when it is missing, and hands each entry to `process` until `stop`
+/// is set, which cuts short only the wait for entries. Entries `process`
+/// handles are acknowledged with XACK; one it fails stays pending, for a
+/// restart or a `claim_idle` sweep to retry.
+///
+/// The consumer first works through its own pending entries, those it read
+/// before it last stopped or crashed, then reads new ones. With
+/// `claim_idle` it also sweeps the group for entries idle that long, when
+/// it starts and whenever a read finds nothing new.
+pub fn consume<F>(conn: &mut StoreConnection, stream: &str, options: &ConsumerOptions, stop: &AtomicBool, mut process: F) -> RedisResult<ConsumeSummary>
+where
+    F: FnMut(&StreamEntry) -> Result<(), String>,
+{
+    create_group(conn, stream, &options.group)?;
+    let mut summary = ConsumeSummary::default();
+    // The last of its own pending entries read, until there are no more;
+    // `>` then reads entries no consumer of the group has had.
+    let mut pending = Some("0".to_string());
+    // Where the claim sweep under way has got to.
+    let mut sweep = options.claim_idle.map(|_| SWEEP_START.to_string());
+    while !stop.load(Ordering::SeqCst) {
+        let entries = match (options.claim_idle, sweep.take()) {
+            (Some(min_idle), Some(cursor)) => {
+                let (next, claimed) = auto_claim(conn, stream, options, min_idle, &cursor)?;
+                if next != SWEEP_START {
+                    sweep = Some(next);
+                }
+                summary.claimed += claimed.len();
+                claimed
+            },
+            _ => {
+                let entries = match read_group(conn, stream, options, pending.as_deref().unwrap_or(">")) {
+                    Ok(entries) => entries,
+                    Err(_) if stopping(stop) => break,
+                    Err(err) => return Err(err),
+                };
+                if pending.is_some() {
+                    pending = entries.last().map(|entry| entry.id.clone());
+                } else if entries.is_empty() && options.claim_idle.is_some() {
+                    sweep = Some(SWEEP_START.to_string());
+                }
+                entries
+            },
+        };
+        for entry in &entries {
+            // Deleted from the stream while pending: there is nothing left
+            // to process, only the pending entry to clear.
+            if entry.fields.is_empty() {
+                ack(conn, stream, &options.group, &entry.id)?;
+                continue;
+            }
+            match process(entry) {
+                Ok(()) => {
+                    ack(conn, stream, &options.group, &entry.id)?;
+                    summary.done += 1;
+                },
+                Err(error) => {
+                    warn!("Leaving {} pending: {}", entry.id, error);
+                    summary.failed += 1;
+                },
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Up to `BATCH` entries after `from`: of this consumer's pending ones for
+/// an id, or new ones for `>`, blocking up to `BLOCK_TIMEOUT` for them.
+fn read_group(conn: &mut StoreConnection, stream: &str, options: &ConsumerOptions, from: &str) -> RedisResult<Vec<StreamEntry>> {
+    let mut xreadgroup = redis::cmd("XREADGROUP");
+    xreadgroup.arg("GROUP").arg(&options.group).arg(&options.consumer).arg("COUNT").arg(BATCH);
+    if from == ">" {
+        xreadgroup.arg("BLOCK").arg(BLOCK_TIMEOUT.as_millis() as u64);
+    }
+    xreadgroup.arg("STREAMS").arg(stream).arg(from);
+    // One `[stream, entries]` pair per stream read, or nil when the block
+    // ran out with nothing.
+    let streams: Option<Vec<Value>> = xreadgroup.query(conn)?;
+    let mut entries = Vec::new();
+    for read in streams.unwrap_or_default() {
+        match Vec::<Value>::from_redis_value(&read)?.get(1) {
+            Some(read) => entries.extend(parse_entries(read)?),
+            None => return Err((ErrorKind::TypeError, "XREADGROUP answered a stream without its entries").into()),
+        }
+    }
+    Ok(entries)
+}
+
+/// Takes over up to `BATCH` of the group's entries idle for `min_idle`,
+/// from `cursor` on, returning them and where the sweep goes on from.
+fn auto_claim(conn: &mut StoreConnection, stream: &str, options: &ConsumerOptions, min_idle: Duration, cursor: &str) -> RedisResult<(String, Vec<StreamEntry>)> {
+    let reply: Vec<Value> = redis::cmd("XAUTOCLAIM")
+        .arg(stream)
+        .arg(&options.group)
+        .arg(&options.consumer)
+        .arg(min_idle.as_millis() as u64)
+        .arg(cursor)
+        .arg("COUNT")
+        .arg(BATCH)
+        .query(conn)?;
+    // Redis 7 adds the ids of claimed entries it found deleted, and so
+    // dropped instead, as a third element.
+    match (reply.first(), reply.get(1)) {
+        (Some(next), Some(entries)) => {
+            Ok((String::from_redis_value(next)?, parse_entries(entries)?))
+        },
+        _ => Err((ErrorKind::TypeError, "XAUTOCLAIM answered without a cursor and entries").into()),
+    }
+}
+
+fn ack(conn: &mut StoreConnection, stream: &str, group: &str, id: &str) -> RedisResult<()> {
+    redis::cmd("XACK").arg(stream).arg(group).arg(id).query(conn)
+}
+
+/// Entries as Redis sends them, each an `[id, fields]` pair with the
+/// fields and their values in one flat list, or nil for an entry deleted
+/// since it was read. Tuples would not do: a `Vec` of them is read from
+/// one flat list, as HGETALL answers.
+fn parse_entries(value: &Value) -> RedisResult<Vec<StreamEntry>> {
+    let mut entries = Vec::new();
+    for entry in Vec::<Value>::from_redis_value(value)? {
+        let parts = Vec::<Value>::from_redis_value(&entry)?;
+        let (id, flat) = match (parts.first(), parts.get(1)) {
+            (Some(id), Some(flat)) => (String::from_redis_value(id)?, Vec::<String>::from_redis_value(flat)?),
+            _ => return Err((ErrorKind::TypeError, "a stream entry came without its id and fields").into()),
+        };
+        let mut values = flat.into_iter();
+        let mut fields = Vec::new();
+        while let (Some(field), Some(value)) = (values.next(), values.next()) {
+            fields.push((field, value));
+        }
+        entries.push(StreamEntry { id, fields });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::store::RedisStore;
+    use crate::testing;
+
+    #[test]
+    fn a_group_hands_each_entry_to_one_consumer() {
+        let (mut store, test) = match testing::redis("stream") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let target = testing::target().expect("testing::redis found it");
+        let stream = test.keys.raw(&["orders"]).unwrap();
+        assert!(create_group(store.connection(), &stream, "packers").unwrap());
+        assert!(!create_group(store.connection(), &stream, "packers").unwrap());
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let consumers: Vec<_> = ["alice", "bob"]
+            .iter()
+            .map(|consumer| {
+                let (target, stream, stop, handled) = (target.clone(), stream.clone(), stop.clone(), handled.clone());
+                let options = ConsumerOptions { group: "packers".to_string(), consumer: consumer.to_string(), claim_idle: None };
+                thread::spawn(move || {
+                    let mut store = RedisStore::connect(&target).unwrap();
+                    consume(store.connection(), &stream, &options, &stop, |entry| {
+                        let mut handled = handled.lock().unwrap();
+                        handled.push((options.consumer.clone(), entry.clone()));
+                        if handled.len() == 5 {
+                            stop.store(true, Ordering::SeqCst);
+                        }
+                        Ok(())
+                    })
+                })
+            })
+            .collect();
+
+        let mut added = Vec::new();
+        for order in 1..=5 {
+            added.push(add(store.connection(), &stream, &[("order", &order.to_string())]).unwrap());
+            thread::sleep(Duration::from_millis(50));
+        }
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !stop.load(Ordering::SeqCst) {
+            assert!(Instant::now() < deadline, "handled only {:?}", handled.lock().unwrap());
+            thread::sleep(Duration::from_millis(20));
+        }
+        let done: usize = consumers.into_iter().map(|consumer| consumer.join().unwrap().unwrap().done).sum();
+
+        let handled = handled.lock().unwrap();
+        let mut ids: Vec<&str> = handled.iter().map(|(_, entry)| entry.id.as_str()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, added.iter().map(String::as_str).collect::<Vec<_>>(), "each entry once");
+        assert_eq!(done, 5);
+        let (_, entry) = handled.iter().find(|(_, entry)| entry.id == added[2]).unwrap();
+        assert_eq!(entry.fields, [("order".to_string(), "3".to_string())]);
+        for consumer in &["alice", "bob"] {
+            let options = ConsumerOptions { group: "packers".to_string(), consumer: consumer.to_string(), claim_idle: None };
+            assert_eq!(read_group(store.connection(), &stream, &options, "0").unwrap(), [], "{} left entries pending", consumer);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! What the tests that talk to Redis share. Each runs only when the
+//! variable it names points at a server it may write to, and passes
+//! without a word otherwise: most need `TEST_REDIS_URL`, or with the
+//! `cluster` feature `TEST_REDIS_NODES`.
+
+use std::env;
+use std::process;
+use std::time::Duration;
+
+use crate::cache::{Cache, PoolOptions};
+use crate::key::KeyBuilder;
+use crate::store::RedisStore;
+
+#[cfg(not(feature = "cluster"))]
+const TARGET_VAR: &str = "TEST_REDIS_URL";
+#[cfg(feature = "cluster")]
+const TARGET_VAR: &str = "TEST_REDIS_NODES";
+
+/// Where most tests connect, or `None` when that is not configured.
+pub fn target() -> Option<String> {
+    configured(TARGET_VAR)
+}
+
+/// The value of `var`, or `None`, saying the test is skipped, when it is
+/// unset or empty.
+pub fn configured(var: &str) -> Option<String> {
+    let value = env::var(var).ok().filter(|value| !value.is_empty());
+    if value.is_none() {
+        eprintln!("{} is not set, skipping a test that needs Redis", var);
+    }
+    value
+}
+
+/// Keys under a namespace of their own for one test, `a05_test:<test>-<pid>`,
+/// so tests running at once never see each other's keys. Whatever the test
+/// left under it is deleted on drop.
+pub struct TestKeys {
+    pub keys: KeyBuilder,
+    store: RedisStore,
+}
+
+impl TestKeys {
+    pub fn new(target: &str, test: &str) -> TestKeys {
+        let environment = format!("{}-{}", test, process::id());
+        let keys = KeyBuilder::new("a05_test", &environment).expect("test names make valid segments");
+        let store = RedisStore::connect(target).expect("the test Redis is reachable");
+        let mut test_keys = TestKeys { keys, store };
+        test_keys.clean();
+        test_keys
+    }
+
+    fn clean(&mut self) {
+        let left: Vec<String> = self.store.scan(&self.keys.pattern("*"), Some(1000)).filter_map(Result::ok).collect();
+        for key in left {
+            let _ = self.store.del(&key);
+        }
+    }
+}
+
+impl Drop for TestKeys {
+    fn drop(&mut self) {
+        self.clean();
+    }
+}
+
+/// A connection to the test Redis, and a namespace for `test`'s keys; `None`
+/// when no test Redis is configured.
+pub fn redis(test: &str) -> Option<(RedisStore, TestKeys)> {
+    let target = target()?;
+    let store = RedisStore::connect(&target).expect("the test Redis is reachable");
+    Some((store, TestKeys::new(&target, test)))
+}
+
+/// A pooled cache on the test Redis, and a namespace for `test`'s keys;
+/// `None` when no test Redis is configured.
+pub fn cache(test: &str) -> Option<(Cache, TestKeys)> {
+    let target = target()?;
+    let options = PoolOptions { size: 4, timeout: Duration::from_secs(5) };
+    let cache = Cache::connect(&target, options).expect("the test Redis is reachable");
+    Some((cache, TestKeys::new(&target, test)))
+}
@@ -0,0 +1,160 @@
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::cache::{Cache, CacheError};
+
+/// How `TypedCache` turns values into bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Json,
+    Bincode,
+}
+
+impl Encoding {
+    pub const NAMES: [&'static str; 2] = ["json", "bincode"];
+
+    /// The byte after the schema version that records which encoding wrote
+    /// a value.
+    fn tag(self) -> u8 {
+        match self {
+            Encoding::Json => b'j',
+            Encoding::Bincode => b'b',
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Encoding, String> {
+        match value {
+            "json" => Ok(Encoding::Json),
+            "bincode" => Ok(Encoding::Bincode),
+            _ => Err(format!("unknown encoding {:?}, expected json or bincode", value)),
+        }
+    }
+}
+
+/// Serde values in the pooled cache, under keys built with `KeyBuilder`.
+/// Each value starts with two bytes, the schema version and the encoding,
+/// and a value written under another version or encoding fails with
+/// `Corrupt` rather than being decoded as something it is not. Bump
+/// `version` whenever the cached types change shape.
+#[derive(Clone)]
+pub struct TypedCache {
+    cache: Cache,
+    version: u8,
+    encoding: Encoding,
+}
+
+impl TypedCache {
+    pub fn new(cache: Cache, version: u8, encoding: Encoding) -> TypedCache {
+        TypedCache { cache, version, encoding }
+    }
+
+    /// Stores `value` under `key`, expiring after `ttl_secs` seconds if given.
+    pub fn put<T: Serialize>(&self, key: &str, value: &T, ttl_secs: Option<u64>) -> Result<(), CacheError> {
+        let mut bytes = vec![self.version, self.encoding.tag()];
+        let encoded = match self.encoding {
+            Encoding::Json => serde_json::to_writer(&mut bytes, value).map_err(|err| err.to_string()),
+            Encoding::Bincode => bincode::serialize_into(&mut bytes, value).map_err(|err| err.to_string()),
+        };
+        encoded.map_err(|reason| CacheError::Unencodable { key: key.to_string(), reason })?;
+        match ttl_secs {
+            Some(ttl_secs) => self.cache.set_with_ttl(key, &bytes, ttl_secs),
+            None => self.cache.set(key, &bytes),
+        }
+    }
+
+    /// The value under `key`, or `None` when there is none.
+    pub fn fetch<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, CacheError> {
+        let bytes = match self.cache.get(key)? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        let corrupt = |reason: String| CacheError::Corrupt { key: key.to_string(), reason };
+        match bytes.as_slice() {
+            [version, tag, body @ ..] if *version == self.version && *tag == self.encoding.tag() => {
+                let decoded = match self.encoding {
+                    Encoding::Json => serde_json::from_slice(body).map_err(|err| err.to_string()),
+                    Encoding::Bincode => bincode::deserialize(body).map_err(|err| err.to_string()),
+                };
+                decoded.map(Some).map_err(corrupt)
+            },
+            [version, tag, ..] => Err(corrupt(format!(
+                "written as schema version {} with encoding {:?}, expected version {} with {:?}",
+                version,
+                char::from(*tag),
+                self.version,
+                char::from(self.encoding.tag()),
+            ))),
+            _ => Err(corrupt("too short to hold a version and encoding".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_derive::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::testing;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sale {
+        id: i32,
+        product: String,
+        amount: f64,
+        tags: Vec<String>,
+        note: Option<String>,
+    }
+
+    fn sale() -> Sale {
+        Sale { id: 7, product: "Lamp".to_string(), amount: 19.5, tags: vec!["home".to_string()], note: None }
+    }
+
+    fn round_trip(encoding: Encoding) {
+        let (cache, test) = match testing::cache(&format!("typed_{:?}", encoding).to_lowercase()) {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = &test.keys.raw(&["sale", "7"]).unwrap();
+        let sales = TypedCache::new(cache, 1, encoding);
+        assert_eq!(sales.fetch::<Sale>(key).unwrap(), None);
+        sales.put(key, &sale(), Some(60)).unwrap();
+        assert_eq!(sales.fetch::<Sale>(key).unwrap(), Some(sale()));
+    }
+
+    #[test]
+    fn json_values_round_trip() {
+        round_trip(Encoding::Json);
+    }
+
+    #[test]
+    fn bincode_values_round_trip() {
+        round_trip(Encoding::Bincode);
+    }
+
+    #[test]
+    fn values_written_another_way_are_corrupt() {
+        let (cache, test) = match testing::cache("typed_corrupt") {
+            Some(redis) => redis,
+            None => return,
+        };
+        let key = &test.keys.raw(&["sale", "7"]).unwrap();
+        TypedCache::new(cache.clone(), 1, Encoding::Json).put(key, &sale(), None).unwrap();
+        for reader in &[TypedCache::new(cache.clone(), 2, Encoding::Json), TypedCache::new(cache.clone(), 1, Encoding::Bincode)] {
+            match reader.fetch::<Sale>(key) {
+                Err(CacheError::Corrupt { key: corrupt, .. }) => assert_eq!(&corrupt, key),
+                other => panic!("a mismatched value fetched as {:?}", other),
+            }
+        }
+        cache.set(key, b"\x01").unwrap();
+        assert!(matches!(
+            TypedCache::new(cache, 1, Encoding::Json).fetch::<Sale>(key),
+            Err(CacheError::Corrupt { .. })
+        ));
+    }
+}
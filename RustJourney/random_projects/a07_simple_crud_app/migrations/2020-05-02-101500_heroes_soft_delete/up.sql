@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE heroes ADD COLUMN deleted_at TIMESTAMPTZ
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+CREATE UNIQUE INDEX heroes_name_key ON heroes (name)
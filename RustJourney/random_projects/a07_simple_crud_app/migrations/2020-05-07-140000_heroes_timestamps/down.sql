@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE heroes
+    DROP COLUMN created_at,
+    DROP COLUMN updated_at
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE heroes
+    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
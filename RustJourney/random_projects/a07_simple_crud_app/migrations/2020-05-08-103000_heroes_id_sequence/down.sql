@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE heroes ALTER COLUMN id DROP DEFAULT;
+DROP SEQUENCE heroes_id_seq
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+CREATE SEQUENCE heroes_id_seq OWNED BY heroes.id;
+SELECT setval('heroes_id_seq', COALESCE(MAX(id), 0) + 1, false) FROM heroes;
+ALTER TABLE heroes ALTER COLUMN id SET DEFAULT nextval('heroes_id_seq')
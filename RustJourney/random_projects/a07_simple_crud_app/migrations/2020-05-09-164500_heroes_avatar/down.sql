@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE heroes
+    DROP COLUMN avatar_filename,
+    DROP COLUMN avatar_content_type
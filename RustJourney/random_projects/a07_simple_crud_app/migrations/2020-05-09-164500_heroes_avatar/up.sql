@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE heroes
+    ADD COLUMN avatar_filename VARCHAR,
+    ADD COLUMN avatar_content_type VARCHAR
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE heroes
+    ADD COLUMN role TEXT CHECK (role IN ('Tank', 'Support', 'Damage'))
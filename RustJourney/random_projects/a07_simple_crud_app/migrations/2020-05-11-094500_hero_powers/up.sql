@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE hero_powers (
+    id SERIAL PRIMARY KEY,
+    hero_id INT4 NOT NULL REFERENCES heroes (id) ON DELETE CASCADE,
+    name VARCHAR NOT NULL,
+    UNIQUE (hero_id, name)
+)
@@ -0,0 +1,68 @@
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::{get, post, routes, State};
+use rocket_contrib::json::{Json, JsonValue};
+
+use crate::auth::ApiKey;
+use crate::avatar::AvatarStore;
+use crate::cache::HeroCache;
+use crate::error::ApiError;
+use crate::hero::Hero;
+use crate::store::HeroStore;
+use crate::transaction::RequestTransaction;
+
+const DEFAULT_SEED_COUNT: usize = 6;
+const MAX_SEED_COUNT: usize = 1000;
+const MAX_SLEEP_MS: u64 = 10_000;
+
+/// Mounts the `/admin` demo routes when `dev_tools = true` is set in the
+/// Rocket config. Otherwise they are never mounted, so they answer 404
+/// like any unknown path.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_attach("Dev tools", |rocket| {
+        if rocket.config().get_bool("dev_tools").unwrap_or(false) {
+            warn!("Dev tools are on: POST /admin/reset wipes the heroes table");
+            Ok(rocket.mount("/admin", routes![reset, sleep]))
+        } else {
+            Ok(rocket)
+        }
+    })
+}
+
+/// Empties the hero store and refills it with `count` seed heroes, six
+/// by default, returning the heroes created.
+#[post("/reset?<count>")]
+pub fn reset(
+    count: Option<usize>,
+    _key: ApiKey,
+    avatars: State<AvatarStore>,
+    cache: State<HeroCache>,
+    store: State<Box<dyn HeroStore>>,
+    _tx: RequestTransaction,
+) -> Result<Json<Vec<Hero>>, ApiError> {
+    let count = count.unwrap_or(DEFAULT_SEED_COUNT);
+    if count > MAX_SEED_COUNT {
+        return Err(ApiError::BadRequest(format!("count must be at most {}", MAX_SEED_COUNT)));
+    }
+    let (removed, seeded) = store.reset(count)?;
+    cache.invalidate_heroes(removed.iter().chain(&seeded).map(|hero| hero.id));
+    for filename in removed.into_iter().filter_map(|hero| hero.avatar_filename) {
+        avatars.remove_once_committed(filename);
+    }
+    Ok(Json(seeded))
+}
+
+/// Answers after `ms` milliseconds, at most ten seconds: a request that is
+/// still in flight when the test of the shutdown drain sends its signal.
+#[get("/sleep?<ms>")]
+pub fn sleep(ms: u64, _key: ApiKey) -> Result<Json<JsonValue>, Status> {
+    if ms > MAX_SLEEP_MS {
+        return Err(Status::BadRequest);
+    }
+    thread::sleep(Duration::from_millis(ms));
+    Ok(Json(json!({ "slept_ms": ms })))
+}
@@ -0,0 +1,49 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::{Outcome, Request, State};
+
+/// The key mutating routes expect in `X-API-Key`. With none configured,
+/// every such request is refused.
+pub struct ApiKeySecret(pub Option<String>);
+
+/// Request guard for routes that change data: succeeds only when the
+/// request's `X-API-Key` header matches the configured key, else fails
+/// with 401.
+pub struct ApiKey;
+
+impl<'a, 'r> FromRequest<'a, 'r> for ApiKey {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ApiKey, ()> {
+        let secret = request.guard::<State<ApiKeySecret>>()?;
+        match (&secret.0, request.headers().get_one("X-API-Key")) {
+            (Some(expected), Some(given)) if constant_time_eq(expected.as_bytes(), given.as_bytes()) => {
+                Outcome::Success(ApiKey)
+            },
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// Compares without stopping at the first differing byte, so the time taken
+/// does not reveal how much of a guessed key was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_identical_keys_are_equal() {
+        assert!(constant_time_eq(b"sekrit", b"sekrit"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"sekrit", b"sekrii"));
+        assert!(!constant_time_eq(b"sekrit", b"sekrit!"));
+        assert!(!constant_time_eq(b"sekrit", b""));
+    }
+}
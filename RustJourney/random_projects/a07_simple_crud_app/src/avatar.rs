@@ -0,0 +1,151 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::PathBuf;
+
+use log::{error, warn};
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Status};
+use rocket::response::content::Content;
+use rocket::{get, put, Data, State};
+use rocket_contrib::json::Json;
+
+use crate::auth::ApiKey;
+use crate::cache::HeroCache;
+use crate::db;
+use crate::error::ApiError;
+use crate::rate_limit::RateLimited;
+use crate::hero::Hero;
+use crate::store::HeroStore;
+use crate::transaction::RequestTransaction;
+
+const DEFAULT_AVATAR_DIR: &str = "./avatars";
+const AVATAR_LIMIT: u64 = 2 * 1024 * 1024;
+
+/// Directory hero avatars are written to, one file per hero named by id.
+#[derive(Clone)]
+pub struct AvatarStore {
+    dir: PathBuf,
+}
+
+impl AvatarStore {
+    /// Reads `avatar_dir` from the Rocket config and manages a store there.
+    pub fn fairing() -> AdHoc {
+        AdHoc::on_attach("Avatar store", |rocket| {
+            let dir = rocket.config()
+                .get_str("avatar_dir")
+                .unwrap_or(DEFAULT_AVATAR_DIR)
+                .to_string();
+            Ok(rocket.manage(AvatarStore { dir: PathBuf::from(dir) }))
+        })
+    }
+
+    fn save(&self, filename: &str, content: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.dir.join(filename), content)
+    }
+
+    fn open(&self, filename: &str) -> io::Result<File> {
+        File::open(self.dir.join(filename))
+    }
+
+    /// Removes a stored avatar, treating one that is already gone as removed.
+    pub fn remove(&self, filename: &str) -> io::Result<()> {
+        match fs::remove_file(self.dir.join(filename)) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Removes the avatar `filename` once the request's transaction commits,
+    /// logging a failure: until then the hero still points at it, and after
+    /// a rollback it does again.
+    pub fn remove_once_committed(&self, filename: String) {
+        self.remove_after_request(filename, true);
+    }
+
+    /// Removes `filename` if the request's transaction rolls back instead,
+    /// for a file saved for a write that is then undone.
+    pub fn remove_if_rolled_back(&self, filename: String) {
+        self.remove_after_request(filename, false);
+    }
+
+    fn remove_after_request(&self, filename: String, when_committed: bool) {
+        let avatars = self.clone();
+        db::after_request_transaction(move |committed| {
+            if committed != when_committed {
+                return;
+            }
+            if let Err(err) = avatars.remove(&filename) {
+                warn!("Could not remove avatar {}: {}", filename, err);
+            }
+        });
+    }
+}
+
+/// Stores a PNG or JPEG of at most 2 MB as the hero's avatar.
+#[put("/<id>/avatar", data = "<data>")]
+pub fn upload(
+    id: i32,
+    content_type: Option<&ContentType>,
+    data: Data,
+    _key: ApiKey,
+    _limit: RateLimited,
+    store: State<AvatarStore>,
+    cache: State<HeroCache>,
+    heroes: State<Box<dyn HeroStore>>,
+    _tx: RequestTransaction,
+) -> Result<Json<Hero>, Status> {
+    let hero = heroes.find(id)?.ok_or(Status::NotFound)?;
+    let (extension, content_type) = match content_type {
+        Some(content_type) if content_type.is_png() => ("png", ContentType::PNG),
+        Some(content_type) if content_type.is_jpeg() => ("jpg", ContentType::JPEG),
+        _ => return Err(Status::UnsupportedMediaType),
+    };
+    let mut content = Vec::new();
+    data.open()
+        .take(AVATAR_LIMIT + 1)
+        .read_to_end(&mut content)
+        .map_err(|_| Status::BadRequest)?;
+    if content.len() as u64 > AVATAR_LIMIT {
+        return Err(Status::PayloadTooLarge);
+    }
+
+    let filename = format!("{}.{}", id, extension);
+    store.save(&filename, &content).map_err(|err| {
+        error!("Could not store avatar {}: {}", filename, err);
+        Status::InternalServerError
+    })?;
+    // A file of the same name was overwritten in place, which no rollback
+    // brings back; any other is only dropped once the hero stops pointing
+    // at it, and the new one if the hero never does.
+    if hero.avatar_filename.as_ref() != Some(&filename) {
+        store.remove_if_rolled_back(filename.clone());
+    }
+    if let Some(previous) = hero.avatar_filename.filter(|previous| *previous != filename) {
+        store.remove_once_committed(previous);
+    }
+    let updated = heroes.set_avatar(id, &filename, &content_type.to_string())?
+        .ok_or(Status::NotFound)?;
+    cache.invalidate_hero(id);
+    Ok(Json(updated))
+}
+
+#[get("/<id>/avatar")]
+pub fn download(
+    id: i32,
+    store: State<AvatarStore>,
+    heroes: State<Box<dyn HeroStore>>,
+) -> Result<Option<Content<File>>, ApiError> {
+    let hero = match heroes.find(id)? {
+        Some(hero) => hero,
+        None => return Ok(None),
+    };
+    let filename = match hero.avatar_filename {
+        Some(filename) => filename,
+        None => return Ok(None),
+    };
+    let content_type = hero.avatar_content_type
+        .and_then(|content_type| ContentType::parse_flexible(&content_type))
+        .unwrap_or(ContentType::Binary);
+    Ok(store.open(&filename).ok().map(|file| Content(content_type, file)))
+}
@@ -0,0 +1,101 @@
+use std::io::Read;
+use std::ops::Deref;
+
+use rocket::data::{self, Data, FromDataSimple};
+use rocket::http::Status;
+use rocket::{Outcome, Request};
+use serde::de::DeserializeOwned;
+
+/// Rocket's own default for `limits.json`, used when the config sets none.
+const DEFAULT_LIMIT: u64 = 1024 * 1024;
+
+/// Why a body guard failed, stashed for the 400/413/422 catchers to report.
+/// Without a `detail` nothing failed.
+#[derive(Default)]
+pub struct BodyError {
+    pub detail: Option<String>,
+    /// Where in a JSON body parsing stopped, when serde knows.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// The field that failed, such as `role` or `heroes[2].age`.
+    pub path: Option<String>,
+}
+
+/// Data guard parsing a JSON request body of at most `limits.json` bytes.
+/// Larger bodies fail with 413, and parse failures with 422, whether the
+/// body is no JSON at all, say cut off, or JSON of the wrong shape, leaving
+/// the serde message, position and field path in a `BodyError`.
+pub struct JsonBody<T>(pub T);
+
+impl<T: DeserializeOwned> FromDataSimple for JsonBody<T> {
+    type Error = ();
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<JsonBody<T>, ()> {
+        let body = match read_limited(request, data) {
+            Ok(body) => body,
+            Err(failure) => return failure,
+        };
+        match parse_json(&body) {
+            Ok(value) => Outcome::Success(JsonBody(value)),
+            Err((err, path)) => {
+                let position = Some(err.line()).filter(|line| *line > 0).map(|line| (line, err.column()));
+                let error = BodyError {
+                    detail: Some(err.to_string()),
+                    line: position.map(|(line, _)| line),
+                    column: position.map(|(_, column)| column),
+                    path,
+                };
+                request.local_cache(|| error);
+                Outcome::Failure((Status::UnprocessableEntity, ()))
+            },
+        }
+    }
+}
+
+/// Like `serde_json::from_slice`, but also says which field failed when
+/// the error came from one.
+fn parse_json<T: DeserializeOwned>(body: &[u8]) -> Result<T, (serde_json::Error, Option<String>)> {
+    let mut deserializer = serde_json::Deserializer::from_slice(body);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+        let path = field_path(&err);
+        (err.into_inner(), path)
+    })?;
+    deserializer.end().map_err(|err| (err, None))?;
+    Ok(value)
+}
+
+/// The path to the field an error came from, unless serde lost track of it
+/// or the error was about the body as a whole.
+pub fn field_path<E>(err: &serde_path_to_error::Error<E>) -> Option<String> {
+    let path = err.path().to_string();
+    if path == "." || path.contains('?') { None } else { Some(path) }
+}
+
+/// Reads the whole body, failing with 413 past `limits.json` bytes. The
+/// same limit applies whatever the body's encoding.
+pub fn read_limited<T>(request: &Request, data: Data) -> Result<Vec<u8>, data::Outcome<T, ()>> {
+    let limit = request.limits().get("json").unwrap_or(DEFAULT_LIMIT);
+    let mut body = Vec::new();
+    if let Err(err) = data.open().take(limit + 1).read_to_end(&mut body) {
+        return Err(fail(request, Status::BadRequest, err.to_string(), None));
+    }
+    if body.len() as u64 > limit {
+        return Err(fail(request, Status::PayloadTooLarge, format!("body exceeds {} bytes", limit), None));
+    }
+    Ok(body)
+}
+
+/// Fails a data guard with `status`, leaving `detail` and the failing
+/// field's `path` for the catchers.
+pub fn fail<T>(request: &Request, status: Status, detail: String, path: Option<String>) -> data::Outcome<T, ()> {
+    request.local_cache(|| BodyError { detail: Some(detail), path, ..BodyError::default() });
+    Outcome::Failure((status, ()))
+}
+
+impl<T> Deref for JsonBody<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
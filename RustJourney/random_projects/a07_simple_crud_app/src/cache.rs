@@ -0,0 +1,182 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use redis::{Commands, Connection, PipelineCommands, RedisResult};
+use rocket::fairing::AdHoc;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::db;
+use crate::hero::Hero;
+use crate::redis_pool::RedisPool;
+
+const DEFAULT_TTL_SECS: i64 = 30;
+/// While Redis stays down, its failures are logged at most this often.
+const WARN_EVERY: Duration = Duration::from_secs(60);
+const HERO_PREFIX: &str = "hero:";
+const LIST_PREFIX: &str = "heroes:list:";
+/// Set of every list key currently cached, so writes can find them all.
+const LIST_KEYS: &str = "heroes:list:keys";
+
+/// A cached list page and the collection total sent alongside it.
+#[derive(Serialize, Deserialize)]
+struct CachedList<H> {
+    heroes: H,
+    total: i64,
+}
+
+/// Optional Redis cache in front of single heroes and the hero list. With
+/// no Redis URL, or `cache_enabled = false`, every lookup misses. Redis
+/// errors, whether refused connections or timeouts, are misses too, so
+/// callers always fall back to the database and Redis is purely an
+/// optimization. After a failure the cache stays out of the way for a
+/// few seconds; see `RedisPool`.
+///
+/// An entry read just before a write can still be stored just after it; the
+/// TTL bounds how long such an entry is served.
+#[derive(Clone)]
+pub struct HeroCache {
+    redis: Option<RedisPool>,
+    ttl_secs: usize,
+    last_warning: Arc<Mutex<Option<Instant>>>,
+}
+
+impl HeroCache {
+    /// Manages a cache on `redis_url`, with entries kept for `hero_cache_ttl`
+    /// seconds from the Rocket config. `cache_enabled = false` turns it off
+    /// even when a URL is set.
+    pub fn fairing(redis_url: Option<&str>) -> AdHoc {
+        let redis = redis_url.and_then(|url| RedisPool::open(url, "Hero cache disabled"));
+        AdHoc::on_attach("Hero cache", move |rocket| {
+            let enabled = rocket.config().get_bool("cache_enabled").unwrap_or(true);
+            let ttl_secs = rocket.config()
+                .get_int("hero_cache_ttl")
+                .unwrap_or(DEFAULT_TTL_SECS)
+                .max(1) as usize;
+            Ok(rocket.manage(HeroCache {
+                redis: redis.filter(|_| enabled),
+                ttl_secs,
+                last_warning: Arc::new(Mutex::new(None)),
+            }))
+        })
+    }
+
+    /// Hero `id` from the cache, or from `load` on a miss, caching what it
+    /// finds so the next read skips the store. Errors from `load` pass
+    /// through uncached.
+    pub fn hero_or_load<F, E>(&self, id: i32, load: F) -> Result<Option<Hero>, E>
+    where
+        F: FnOnce() -> Result<Option<Hero>, E>,
+    {
+        let key = hero_key(id);
+        if let Some(hero) = self.get_json(&key) {
+            return Ok(Some(hero));
+        }
+        let hero = load()?;
+        if let Some(hero) = &hero {
+            self.set_json(&key, hero);
+        }
+        Ok(hero)
+    }
+
+    /// The cached heroes and total for these query parameters, if any.
+    pub fn list<P: Hash>(&self, params: &P) -> Option<(Value, i64)> {
+        let cached: CachedList<Value> = self.get_json(&list_key(params))?;
+        Some((cached.heroes, cached.total))
+    }
+
+    /// Caches a list page under these query parameters.
+    pub fn store_list<P: Hash>(&self, params: &P, heroes: &Value, total: i64) {
+        let key = list_key(params);
+        let body = match serde_json::to_string(&CachedList { heroes, total }) {
+            Ok(body) => body,
+            Err(_) => return,
+        };
+        self.with_connection(|connection| {
+            redis::pipe()
+                .atomic()
+                .set_ex(&key, &body, self.ttl_secs).ignore()
+                .sadd(LIST_KEYS, &key).ignore()
+                .query::<()>(connection)
+        });
+    }
+
+    /// Drops every cached list page, for writes that add a hero.
+    pub fn invalidate_lists(&self) {
+        self.invalidate(Vec::new());
+    }
+
+    /// Drops the cached copy of hero `id` along with every list page, for
+    /// writes that change or remove it.
+    pub fn invalidate_hero(&self, id: i32) {
+        self.invalidate(vec![hero_key(id)]);
+    }
+
+    /// Like `invalidate_hero`, for writes that touch many heroes at once.
+    pub fn invalidate_heroes<I: IntoIterator<Item = i32>>(&self, ids: I) {
+        self.invalidate(ids.into_iter().map(hero_key).collect());
+    }
+
+    /// Drops `keys` and the list pages once the request's transaction has
+    /// ended. Dropped any sooner, a read landing before the COMMIT would
+    /// cache the rows from before the write again.
+    fn invalidate(&self, mut keys: Vec<String>) {
+        let cache = self.clone();
+        db::after_request_transaction(move |_| {
+            cache.with_connection(|connection| {
+                keys.extend(connection.smembers::<_, Vec<String>>(LIST_KEYS)?);
+                keys.push(LIST_KEYS.to_string());
+                connection.del::<_, ()>(keys)
+            });
+        });
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let body: Option<String> = self.with_connection(|connection| connection.get(key))?;
+        serde_json::from_str(&body?).ok()
+    }
+
+    fn set_json<T: Serialize>(&self, key: &str, value: &T) {
+        if let Ok(body) = serde_json::to_string(value) {
+            self.with_connection(|connection| connection.set_ex::<_, _, ()>(key, body, self.ttl_secs));
+        }
+    }
+
+    /// Runs `command` on a pooled connection, or gives `None` if the cache
+    /// is off or Redis fails.
+    fn with_connection<T, F>(&self, command: F) -> Option<T>
+    where
+        F: FnOnce(&Connection) -> RedisResult<T>,
+    {
+        match self.redis.as_ref()?.run(command) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.warn_unavailable(&err);
+                None
+            },
+        }
+    }
+
+    fn warn_unavailable(&self, err: &redis::RedisError) {
+        let now = Instant::now();
+        let mut last_warning = self.last_warning.lock().unwrap();
+        if last_warning.map_or(true, |at| now.duration_since(at) >= WARN_EVERY) {
+            warn!("Hero cache unavailable, reading from the database: {}", err);
+            *last_warning = Some(now);
+        }
+    }
+}
+
+fn hero_key(id: i32) -> String {
+    format!("{}{}", HERO_PREFIX, id)
+}
+
+fn list_key<P: Hash>(params: &P) -> String {
+    let mut hasher = DefaultHasher::new();
+    params.hash(&mut hasher);
+    format!("{}{:016x}", LIST_PREFIX, hasher.finish())
+}
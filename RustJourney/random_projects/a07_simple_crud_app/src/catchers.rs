@@ -0,0 +1,67 @@
+use rocket::http::Status;
+use rocket::{catch, Request};
+use rocket_contrib::json::{Json, JsonValue};
+
+use crate::body::BodyError;
+use crate::error::error_body;
+
+/// Answers a failed body guard with the parse detail it left behind, and
+/// any other failure of the same status with the usual body.
+fn body_error(request: &Request, status: Status, message: &str) -> Json<JsonValue> {
+    let error = request.local_cache(BodyError::default);
+    let detail = match &error.detail {
+        Some(detail) => detail,
+        None => return Json(error_body(request, status, message)),
+    };
+    let message = if status == Status::PayloadTooLarge { "request body too large" } else { "invalid JSON body" };
+    let mut body = error_body(request, status, message);
+    body["error"]["detail"] = detail.as_str().into();
+    if let (Some(line), Some(column)) = (error.line, error.column) {
+        body["error"]["line"] = line.into();
+        body["error"]["column"] = column.into();
+    }
+    if let Some(path) = &error.path {
+        body["error"]["path"] = path.as_str().into();
+    }
+    Json(body)
+}
+
+#[catch(400)]
+pub fn bad_request(request: &Request) -> Json<JsonValue> {
+    body_error(request, Status::BadRequest, "bad request")
+}
+
+#[catch(401)]
+pub fn unauthorized(request: &Request) -> Json<JsonValue> {
+    Json(error_body(request, Status::Unauthorized, "a valid X-API-Key header is required"))
+}
+
+#[catch(404)]
+pub fn not_found(request: &Request) -> Json<JsonValue> {
+    Json(error_body(request, Status::NotFound, "no such resource"))
+}
+
+#[catch(406)]
+pub fn not_acceptable(request: &Request) -> Json<JsonValue> {
+    Json(error_body(request, Status::NotAcceptable, "Accept allows none of application/json, application/msgpack or application/xml"))
+}
+
+#[catch(413)]
+pub fn payload_too_large(request: &Request) -> Json<JsonValue> {
+    body_error(request, Status::PayloadTooLarge, "request body too large")
+}
+
+#[catch(422)]
+pub fn unprocessable_entity(request: &Request) -> Json<JsonValue> {
+    body_error(request, Status::UnprocessableEntity, "request body has the wrong shape")
+}
+
+#[catch(500)]
+pub fn internal_error(request: &Request) -> Json<JsonValue> {
+    Json(error_body(request, Status::InternalServerError, "internal server error"))
+}
+
+#[catch(503)]
+pub fn service_unavailable(request: &Request) -> Json<JsonValue> {
+    Json(error_body(request, Status::ServiceUnavailable, "no database connection is free, try again shortly"))
+}
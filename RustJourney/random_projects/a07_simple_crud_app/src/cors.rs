@@ -0,0 +1,93 @@
+use std::io::Cursor;
+
+use rocket::config::Config;
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::{Request, Response};
+
+const ALLOWED_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS";
+const ALLOWED_HEADERS: &str = "Content-Type, Idempotency-Key, If-Match, If-None-Match, X-API-Key";
+
+/// Origins a browser may call the API from.
+enum AllowedOrigins {
+    Any,
+    List(Vec<String>),
+}
+
+impl AllowedOrigins {
+    /// Reads `cors_allowed_origins`, either `"*"` or a list of origins.
+    /// Without it no origin is allowed.
+    fn from_config(config: &Config) -> AllowedOrigins {
+        if let Ok("*") = config.get_str("cors_allowed_origins") {
+            return AllowedOrigins::Any;
+        }
+        let origins = config.get_slice("cors_allowed_origins")
+            .map(|origins| {
+                origins.iter()
+                    .filter_map(|origin| origin.as_str())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        AllowedOrigins::List(origins)
+    }
+
+    /// The `Access-Control-Allow-Origin` value for `origin`, if it is allowed.
+    fn allow(&self, origin: &str) -> Option<String> {
+        match self {
+            AllowedOrigins::Any => Some("*".to_string()),
+            AllowedOrigins::List(origins) if origins.iter().any(|allowed| allowed == origin) => {
+                Some(origin.to_string())
+            },
+            AllowedOrigins::List(_) => None,
+        }
+    }
+}
+
+/// Adds CORS headers to responses for allowed origins and answers `OPTIONS`
+/// preflights, which would otherwise hit the 404 catcher. A preflight from
+/// any other origin still gets its 204, just without the headers, and other
+/// requests from it are left alone: the browser is the one that refuses
+/// them.
+pub struct Cors {
+    origins: AllowedOrigins,
+}
+
+impl Cors {
+    pub fn fairing() -> AdHoc {
+        AdHoc::on_attach("CORS config", |rocket| {
+            let origins = AllowedOrigins::from_config(rocket.config());
+            Ok(rocket.attach(Cors { origins }))
+        })
+    }
+}
+
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info { name: "CORS", kind: Kind::Response }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let origin = match request.headers().get_one("Origin") {
+            Some(origin) => origin,
+            None => return,
+        };
+        let preflight = request.method() == Method::Options
+            && request.headers().contains("Access-Control-Request-Method");
+        if preflight && response.status() == Status::NotFound {
+            response.set_status(Status::NoContent);
+            response.set_sized_body(Cursor::new(""));
+            response.remove_header("Content-Type");
+        }
+        if let AllowedOrigins::List(_) = self.origins {
+            response.adjoin_header(Header::new("Vary", "Origin"));
+        }
+        let allowed = match self.origins.allow(origin) {
+            Some(allowed) => allowed,
+            None => return,
+        };
+        response.set_header(Header::new("Access-Control-Allow-Origin", allowed));
+        response.set_header(Header::new("Access-Control-Allow-Methods", ALLOWED_METHODS));
+        response.set_header(Header::new("Access-Control-Allow-Headers", ALLOWED_HEADERS));
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use log::error;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, status, Responder};
+use rocket_contrib::json::{Json, JsonValue};
+
+use crate::hero::Hero;
+use crate::request_id::RequestId;
+
+/// Errors a hero handler can answer with, rendered as JSON bodies.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    DeletedNeedsKey,
+    NotFound,
+    NotDeleted,
+    NameTaken(String),
+    VersionRequired,
+    StaleVersion(Hero),
+    KeyInProgress,
+    Unavailable,
+    ShuttingDown,
+    Database(DieselError),
+}
+
+impl ApiError {
+    /// Maps a diesel error raised while writing the hero called `name`,
+    /// turning unique violations on `heroes.name` into `NameTaken`.
+    pub fn from_write(err: DieselError, name: &str) -> ApiError {
+        match err {
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                ApiError::NameTaken(name.to_string())
+            },
+            err => ApiError::Database(err),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::BadRequest(_) => Status::BadRequest,
+            ApiError::DeletedNeedsKey => Status::Forbidden,
+            ApiError::NotFound => Status::NotFound,
+            ApiError::NotDeleted | ApiError::NameTaken(_) | ApiError::KeyInProgress => Status::Conflict,
+            ApiError::VersionRequired => Status::PreconditionRequired,
+            ApiError::StaleVersion(_) => Status::PreconditionFailed,
+            ApiError::Unavailable | ApiError::ShuttingDown => Status::ServiceUnavailable,
+            ApiError::Database(_) => Status::InternalServerError,
+        }
+    }
+}
+
+/// What went wrong, for logs: a database error shows itself, where its
+/// response only says the server failed.
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message) => f.write_str(message),
+            ApiError::DeletedNeedsKey => f.write_str("include_deleted requires a valid X-API-Key header"),
+            ApiError::NotFound => f.write_str("hero not found"),
+            ApiError::NotDeleted => f.write_str("hero is not deleted"),
+            ApiError::NameTaken(_) => f.write_str("name already exists"),
+            ApiError::VersionRequired => f.write_str("version required in body or If-Match header"),
+            ApiError::StaleVersion(_) => f.write_str("version mismatch"),
+            ApiError::KeyInProgress => f.write_str("a request with this Idempotency-Key is still in progress"),
+            ApiError::Unavailable => f.write_str("no database connection is free, try again shortly"),
+            ApiError::ShuttingDown => f.write_str("the server is shutting down, try again shortly"),
+            ApiError::Database(err) => write!(f, "database error: {}", err),
+        }
+    }
+}
+
+/// For routes that answer with a bare status, leaving the body to the
+/// catcher: the status the error would answer with, logging a database
+/// error as `respond_to` does.
+impl From<ApiError> for Status {
+    fn from(err: ApiError) -> Status {
+        if let ApiError::Database(err) = &err {
+            error!("Database error: {}", err);
+        }
+        err.status()
+    }
+}
+
+impl From<DieselError> for ApiError {
+    fn from(err: DieselError) -> ApiError {
+        ApiError::Database(err)
+    }
+}
+
+/// The `{"error": {"code": ..., "message": ..., "request_id": ...}}` body
+/// every error answers with, whether a handler, a guard or a catcher sent
+/// it. Errors with more to say add fields inside `error`.
+pub fn error_body(request: &Request, status: Status, message: &str) -> JsonValue {
+    json!({
+        "error": { "code": status.code, "message": message, "request_id": RequestId::of(request) }
+    })
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let status = self.status();
+        let mut body = match &self {
+            ApiError::Database(err) => {
+                error!("[{}] Database error: {}", RequestId::of(request), err);
+                error_body(request, status, "internal server error")
+            },
+            _ => error_body(request, status, &self.to_string()),
+        };
+        match self {
+            ApiError::StaleVersion(current) => body["error"]["current"] = json!(current).into(),
+            ApiError::NameTaken(name) => body["error"]["name"] = name.into(),
+            _ => {},
+        }
+        status::Custom(status, Json(body)).respond_to(request)
+    }
+}
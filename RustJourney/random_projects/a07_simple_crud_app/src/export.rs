@@ -0,0 +1,213 @@
+use std::fmt::Display;
+use std::io::{self, Read};
+
+use log::error;
+
+use rocket::http::ContentType;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+
+use crate::hero::Hero;
+
+const HEADER: &str = "id,name,identity,hometown,age,deleted_at,version,created_at,updated_at,role\n";
+
+/// Renders heroes into bytes one batch at a time as the body is read, so
+/// only one batch is ever held in memory. A batch that fails to load ends
+/// the body there, with the error logged: the status went out with the
+/// first bytes, so there is no changing it by then.
+struct Rendered<I> {
+    batches: I,
+    render: fn(Hero, &mut Vec<u8>),
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<I, E> Rendered<I>
+where
+    I: Iterator<Item = Result<Vec<Hero>, E>>,
+    E: Display,
+{
+    fn new(batches: I, preamble: &[u8], render: fn(Hero, &mut Vec<u8>)) -> Rendered<I> {
+        Rendered { batches, render, buffer: preamble.to_vec(), position: 0 }
+    }
+
+    fn fill(&mut self) -> io::Result<bool> {
+        let batch = match self.batches.next() {
+            Some(Ok(batch)) => batch,
+            Some(Err(err)) => {
+                error!("Export stopped, could not load heroes: {}", err);
+                return Err(io::Error::new(io::ErrorKind::Other, err.to_string()));
+            },
+            None => return Ok(false),
+        };
+        self.buffer.clear();
+        self.position = 0;
+        for hero in batch {
+            (self.render)(hero, &mut self.buffer);
+        }
+        Ok(true)
+    }
+}
+
+impl<I, E> Read for Rendered<I>
+where
+    I: Iterator<Item = Result<Vec<Hero>, E>>,
+    E: Display,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let pending = &self.buffer[self.position..];
+        let count = pending.len().min(buf.len());
+        buf[..count].copy_from_slice(&pending[..count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Streams heroes as CSV, rendering one batch at a time as the body is read.
+pub struct CsvExport<I>(Rendered<I>);
+
+impl<I: Iterator<Item = Result<Vec<Hero>, E>>, E: Display> CsvExport<I> {
+    pub fn new(batches: I) -> CsvExport<I> {
+        CsvExport(Rendered::new(batches, HEADER.as_bytes(), csv_record))
+    }
+}
+
+impl<'r, I: Iterator<Item = Result<Vec<Hero>, E>> + 'r, E: Display> Responder<'r> for CsvExport<I> {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        Response::build()
+            .header(ContentType::CSV)
+            .raw_header("Content-Disposition", "attachment; filename=\"heroes.csv\"")
+            .streamed_body(self.0)
+            .ok()
+    }
+}
+
+fn csv_record(hero: Hero, buffer: &mut Vec<u8>) {
+    let record = [
+        hero.id.to_string(),
+        hero.name,
+        hero.identity,
+        hero.hometown,
+        hero.age.to_string(),
+        hero.deleted_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+        hero.version.to_string(),
+        hero.created_at.to_rfc3339(),
+        hero.updated_at.to_rfc3339(),
+        hero.role.map(|role| role.to_string()).unwrap_or_default(),
+    ];
+    let record: Vec<String> = record.iter().map(|field| quote(field)).collect();
+    buffer.extend_from_slice(record.join(",").as_bytes());
+    buffer.push(b'\n');
+}
+
+/// Streams heroes as newline-delimited JSON, one hero per line in the
+/// shape the JSON routes answer with, rendering a batch at a time.
+pub struct NdjsonExport<I>(Rendered<I>);
+
+impl<I: Iterator<Item = Result<Vec<Hero>, E>>, E: Display> NdjsonExport<I> {
+    pub fn new(batches: I) -> NdjsonExport<I> {
+        NdjsonExport(Rendered::new(batches, b"", json_line))
+    }
+}
+
+impl<'r, I: Iterator<Item = Result<Vec<Hero>, E>> + 'r, E: Display> Responder<'r> for NdjsonExport<I> {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        Response::build()
+            .header(ContentType::new("application", "x-ndjson"))
+            .streamed_body(self.0)
+            .ok()
+    }
+}
+
+fn json_line(hero: Hero, buffer: &mut Vec<u8>) {
+    serde_json::to_writer(&mut *buffer, &hero).expect("a hero always serializes");
+    buffer.push(b'\n');
+}
+
+/// Quotes a field when it holds a comma, quote or line break, doubling any
+/// quotes inside it.
+fn quote(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn hero(id: i32, name: &str) -> Hero {
+        let now = Utc::now();
+        Hero {
+            id,
+            name: name.to_string(),
+            identity: "Someone".to_string(),
+            hometown: "Gotham".to_string(),
+            age: 30,
+            deleted_at: None,
+            version: 1,
+            created_at: now,
+            updated_at: now,
+            avatar_filename: None,
+            avatar_content_type: None,
+            role: None,
+        }
+    }
+
+    /// Reads `body` through a buffer of `chunk` bytes, as a slow client would.
+    fn read_in_chunks<R: Read>(mut body: R, chunk: usize) -> io::Result<String> {
+        let mut read = Vec::new();
+        let mut buf = vec![0; chunk];
+        loop {
+            match body.read(&mut buf)? {
+                0 => return Ok(String::from_utf8(read).unwrap()),
+                count => read.extend_from_slice(&buf[..count]),
+            }
+        }
+    }
+
+    #[test]
+    fn only_fields_that_need_it_are_quoted() {
+        assert_eq!(quote("Bruce"), "Bruce");
+        assert_eq!(quote(""), "");
+        assert_eq!(quote("Wayne, Bruce"), "\"Wayne, Bruce\"");
+        assert_eq!(quote("The \"Bat\""), "\"The \"\"Bat\"\"\"");
+        assert_eq!(quote("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn every_batch_is_rendered_after_the_header() {
+        let batches = vec![
+            Ok::<_, String>(vec![hero(1, "Bruce"), hero(2, "Wayne, Bruce")]),
+            Ok(Vec::new()),
+            Ok(vec![hero(3, "Clark")]),
+        ];
+        let csv = read_in_chunks(CsvExport::new(batches.into_iter()).0, 7).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], HEADER.trim_end());
+        assert!(lines[1].starts_with("1,Bruce,Someone,Gotham,30,,1,"), "{}", lines[1]);
+        assert!(lines[2].starts_with("2,\"Wayne, Bruce\",Someone,"), "{}", lines[2]);
+        assert!(lines[3].starts_with("3,Clark,"), "{}", lines[3]);
+    }
+
+    #[test]
+    fn a_batch_that_fails_ends_the_body_with_an_error() {
+        let batches = vec![Ok(vec![hero(1, "Bruce")]), Err("connection lost".to_string())];
+        let mut body = NdjsonExport::new(batches.into_iter()).0;
+        let mut buf = [0; 4096];
+        let first = body.read(&mut buf).unwrap();
+        let line: serde_json::Value = serde_json::from_slice(&buf[..first - 1]).unwrap();
+        assert_eq!(line["name"], "Bruce");
+        assert_eq!(body.read(&mut buf).unwrap_err().to_string(), "connection lost");
+    }
+}
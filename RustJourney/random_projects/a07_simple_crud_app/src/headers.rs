@@ -0,0 +1,103 @@
+use rocket::http::Status;
+use rocket::{Outcome, Request};
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, Responder, Response};
+
+/// The hero version a client sent in an `If-Match` header, if any.
+/// Accepts both bare (`3`) and entity-tag (`"3"`, `W/"3"`) forms.
+pub struct IfMatch(pub Option<i32>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<IfMatch, ()> {
+        let version = request.headers().get_one("If-Match").and_then(|value| {
+            value.trim()
+                .trim_start_matches("W/")
+                .trim_matches('"')
+                .parse()
+                .ok()
+        });
+        Outcome::Success(IfMatch(version))
+    }
+}
+
+/// Wraps a responder, adding an `X-Total-Count` header with the size of the
+/// whole collection the response is a page of.
+pub struct TotalCount<R>(pub R, pub i64);
+
+impl<'r, R: Responder<'r>> Responder<'r> for TotalCount<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        Response::build_from(self.0.respond_to(request)?)
+            .raw_header("X-Total-Count", self.1.to_string())
+            .ok()
+    }
+}
+
+/// Wraps a responder, adding an `X-Next-Cursor` header with the id to pass
+/// as `after` for the next page, when there may be one.
+pub struct NextCursor<R>(pub R, pub Option<i64>);
+
+impl<'r, R: Responder<'r>> Responder<'r> for NextCursor<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = Response::build_from(self.0.respond_to(request)?);
+        if let Some(cursor) = self.1 {
+            response.raw_header("X-Next-Cursor", cursor.to_string());
+        }
+        response.ok()
+    }
+}
+
+/// The entity tags a client sent in `If-None-Match`, if any.
+pub struct IfNoneMatch(pub Option<String>);
+
+impl IfNoneMatch {
+    /// Whether the client already holds `etag`: the header is `*` or lists
+    /// it. The comparison is weak, so `W/"x"` and `"x"` are the same tag.
+    pub fn matches(&self, etag: &str) -> bool {
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        self.0.as_ref().map_or(false, |header| {
+            header.trim() == "*" || header.split(',').any(|tag| opaque(tag) == opaque(etag))
+        })
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfNoneMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<IfNoneMatch, ()> {
+        Outcome::Success(IfNoneMatch(request.headers().get_one("If-None-Match").map(str::to_string)))
+    }
+}
+
+/// A response carrying an `ETag`: the full response when the client's copy
+/// is out of date, else an empty 304 Not Modified.
+pub enum Conditional<R> {
+    Modified(R, String),
+    NotModified(String),
+}
+
+impl<R> Conditional<R> {
+    /// `body` tagged with `etag`, or a 304 if `if_none_match` names it.
+    pub fn new(body: R, etag: String, if_none_match: &IfNoneMatch) -> Conditional<R> {
+        if if_none_match.matches(&etag) {
+            Conditional::NotModified(etag)
+        } else {
+            Conditional::Modified(body, etag)
+        }
+    }
+}
+
+impl<'r, R: Responder<'r>> Responder<'r> for Conditional<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            Conditional::Modified(body, etag) => Response::build_from(body.respond_to(request)?)
+                .raw_header("ETag", etag)
+                .ok(),
+            Conditional::NotModified(etag) => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag)
+                .ok(),
+        }
+    }
+}
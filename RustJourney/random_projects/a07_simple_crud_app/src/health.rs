@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use health::{HealthReport, Overall};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::{get, State};
+use rocket_contrib::json::{Json, JsonValue};
+
+use crate::auth::ApiKey;
+use crate::db::{self, Pool};
+use crate::redis_pool::RedisPool;
+use crate::store::HeroStore;
+
+/// How long `/ready` waits for a pooled connection before calling the
+/// database unreachable.
+const READY_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long `/health/detailed` waits on Postgres. Redis is held to the
+/// timeouts of its `RedisPool`.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Manages the `HealthReport` behind `/health/detailed`: Postgres, required,
+/// when the hero store keeps heroes there, and Redis, optional since the
+/// cache, rate limiter and idempotency keys all do without it, when
+/// `redis_url` is set. Attach it after the store.
+pub fn fairing(redis_url: Option<&str>) -> AdHoc {
+    let redis_url = redis_url.map(str::to_string);
+    AdHoc::on_attach("Health report", move |rocket| {
+        let mut report = HealthReport::new();
+        if let Some(pool) = rocket.state::<Box<dyn HeroStore>>().and_then(|store| store.pool()).cloned() {
+            report = report.required("postgres", move || db::check(&pool, PROBE_TIMEOUT));
+        }
+        if let Some(url) = redis_url {
+            let redis = RedisPool::open(&url, "Health report: Redis unchecked");
+            report = report.optional("redis", move || match &redis {
+                Some(redis) => ping_redis(redis),
+                None => Err("bad Redis URL".to_string()),
+            });
+        }
+        Ok(rocket.manage(report))
+    })
+}
+
+fn ping_redis(redis: &RedisPool) -> Result<(), String> {
+    redis.run(|connection| redis::cmd("PING").query::<String>(connection))
+        .map(drop)
+        .map_err(|err| err.to_string())
+}
+
+/// Liveness: answers as long as the process is serving requests.
+#[get("/health")]
+pub fn health() -> Json<JsonValue> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness: healthy only when the pool can hand out a working connection.
+#[get("/ready")]
+pub fn ready(pool: State<Pool>) -> status::Custom<Json<JsonValue>> {
+    let reachable = db::ping(&pool, READY_TIMEOUT);
+    let state = pool.state();
+    let stats = json!({
+        "in_use": state.connections - state.idle_connections,
+        "idle": state.idle_connections,
+    });
+    if reachable {
+        status::Custom(Status::Ok, Json(json!({ "status": "ok", "db": "reachable", "pool": stats })))
+    } else {
+        status::Custom(
+            Status::ServiceUnavailable,
+            Json(json!({ "status": "degraded", "db": "unreachable", "pool": stats })),
+        )
+    }
+}
+
+/// Every backend, up or down, with how long its probe took. Answers 503
+/// only when a required one is down; a missing optional one leaves the
+/// app `degraded` but serving.
+#[get("/health/detailed")]
+pub fn detailed(report: State<HealthReport>) -> status::Custom<Json<JsonValue>> {
+    let checked = report.check();
+    let status = if checked.overall() == Overall::Unhealthy { Status::ServiceUnavailable } else { Status::Ok };
+    status::Custom(status, Json(checked.to_json().into()))
+}
+
+/// Pool occupancy, for telling whether requests are waiting on connections.
+#[get("/debug/pool")]
+pub fn pool_state(_key: ApiKey, pool: State<Pool>) -> Json<JsonValue> {
+    Json(pool_stats(&pool))
+}
+
+#[get("/admin/pool")]
+pub fn admin_pool(_key: ApiKey, pool: State<Pool>) -> Json<JsonValue> {
+    Json(pool_stats(&pool))
+}
+
+// `state()` holds the pool lock only while copying two counters, and the
+// timeout count is a plain atomic load.
+fn pool_stats(pool: &Pool) -> JsonValue {
+    let state = pool.state();
+    json!({
+        "max_size": pool.max_size(),
+        "connections": state.connections,
+        "idle_connections": state.idle_connections,
+        "checkout_timeouts": pool.checkout_timeouts(),
+    })
+}
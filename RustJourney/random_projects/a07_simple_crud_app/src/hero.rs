@@ -1,39 +1,647 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
 use diesel;
 use diesel::prelude::*;
-use diesel::pg::PgConnection;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::{Pg, PgConnection};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::{Serialize, Serializer};
 
-use schemas::heroes;
+use crate::db;
+use crate::schemas::heroes;
 
-#[table_name = "heroes"]
-#[derive(Serialize, Deserialize, Queryable, Insertable)]
+#[derive(Clone, Debug, Serialize, Deserialize, Queryable)]
 pub struct Hero {
-    pub id: Option<i32>,
+    pub id: i32,
+    pub name: String,
+    pub identity: String,
+    pub hometown: String,
+    pub age: i32,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub avatar_filename: Option<String>,
+    pub avatar_content_type: Option<String>,
+    pub role: Option<HeroRole>
+}
+
+/// The client-writable columns of a hero, used for inserts and updates.
+/// An update without `role` clears it, like any other field left out of a
+/// replacement.
+#[table_name = "heroes"]
+#[changeset_options(treat_none_as_null = "true")]
+#[derive(Debug, Insertable, AsChangeset)]
+pub struct NewHero {
     pub name: String,
     pub identity: String,
     pub hometown: String,
-    pub age: i32
+    pub age: i32,
+    pub role: Option<HeroRole>
+}
+
+/// What a hero does in a team, stored as text that a CHECK constraint keeps
+/// to these names. Bodies carry it as the bare name in every format, where
+/// a derived impl would have MessagePack write a variant index instead.
+#[derive(Clone, Copy, Debug, PartialEq, Hash, AsExpression, FromSqlRow)]
+#[sql_type = "Text"]
+pub enum HeroRole {
+    Tank,
+    Support,
+    Damage
+}
+
+impl HeroRole {
+    pub const ALL: [HeroRole; 3] = [HeroRole::Tank, HeroRole::Support, HeroRole::Damage];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HeroRole::Tank => "Tank",
+            HeroRole::Support => "Support",
+            HeroRole::Damage => "Damage",
+        }
+    }
+}
+
+impl fmt::Display for HeroRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HeroRole {
+    type Err = String;
+
+    /// Parses a role name, naming the allowed ones when it is not one of them.
+    fn from_str(value: &str) -> Result<HeroRole, String> {
+        HeroRole::ALL.iter()
+            .cloned()
+            .find(|role| role.as_str() == value)
+            .ok_or_else(|| {
+                let allowed: Vec<&str> = HeroRole::ALL.iter().map(|role| role.as_str()).collect();
+                format!("unknown role `{}`, expected one of {}", value, allowed.join(", "))
+            })
+    }
+}
+
+impl Serialize for HeroRole {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for HeroRole {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<HeroRole, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(D::Error::custom)
+    }
+}
+
+impl ToSql<Text, Pg> for HeroRole {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<Text, Pg>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Pg> for HeroRole {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<HeroRole> {
+        let value = <String as FromSql<Text, Pg>>::from_sql(bytes)?;
+        value.parse().map_err(Into::into)
+    }
+}
+
+/// Body of a `POST /hero`: the new fields, with the server-maintained
+/// timestamps refused.
+#[derive(Deserialize)]
+#[serde(from = "HeroBody")]
+pub struct HeroCreate {
+    pub hero: NewHero
+}
+
+/// Body of a `PUT /hero/<id>`: the new fields plus, unless an `If-Match`
+/// header carries it, the version the client last saw.
+#[derive(Deserialize)]
+#[serde(from = "HeroBody")]
+pub struct HeroUpdate {
+    pub hero: NewHero,
+    pub version: Option<i32>
+}
+
+/// What a client may send for a hero. The fields are spelled out rather
+/// than flattened in from `NewHero`, so a bad value reports the path of the
+/// field it was in. `created_at`/`updated_at` are caught so they fail to
+/// parse instead of being silently dropped.
+#[derive(Deserialize)]
+struct HeroBody {
+    name: String,
+    identity: String,
+    hometown: String,
+    age: i32,
+    #[serde(default)]
+    role: Option<HeroRole>,
+    #[serde(default)]
+    version: Option<i32>,
+    #[serde(default, rename = "created_at")]
+    _created_at: Option<ReadOnly>,
+    #[serde(default, rename = "updated_at")]
+    _updated_at: Option<ReadOnly>
+}
+
+impl HeroBody {
+    fn into_new_hero(self) -> NewHero {
+        NewHero {
+            name: self.name,
+            identity: self.identity,
+            hometown: self.hometown,
+            age: self.age,
+            role: self.role,
+        }
+    }
+}
+
+impl From<HeroBody> for HeroCreate {
+    fn from(body: HeroBody) -> HeroCreate {
+        HeroCreate { hero: body.into_new_hero() }
+    }
+}
+
+impl From<HeroBody> for HeroUpdate {
+    fn from(body: HeroBody) -> HeroUpdate {
+        let version = body.version;
+        HeroUpdate { hero: body.into_new_hero(), version }
+    }
+}
+
+struct ReadOnly;
+
+impl<'de> Deserialize<'de> for ReadOnly {
+    fn deserialize<D: Deserializer<'de>>(_: D) -> Result<ReadOnly, D::Error> {
+        Err(D::Error::custom("field is set by the server and cannot be written"))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Hash)]
+pub enum SortColumn {
+    Id,
+    Name,
+    Age,
+    CreatedAt,
+    UpdatedAt
+}
+
+/// One column of the hero list ordering, written as a column name with a
+/// leading `-` for descending, e.g. `-updated_at`.
+#[derive(Clone, Copy, Debug, Hash)]
+pub struct SortKey {
+    pub column: SortColumn,
+    pub descending: bool
+}
+
+/// Parses a `?sort=` value such as `-updated_at,name` into keys applied in
+/// order. The error names the field that is unknown or repeated.
+pub fn parse_sort(value: &str) -> Result<Vec<SortKey>, String> {
+    let mut keys: Vec<SortKey> = Vec::new();
+    for field in value.split(',').map(str::trim) {
+        let (descending, name) = if field.starts_with('-') {
+            (true, &field[1..])
+        } else {
+            (false, field)
+        };
+        let column = match name {
+            "id" => SortColumn::Id,
+            "name" => SortColumn::Name,
+            "age" => SortColumn::Age,
+            "created_at" => SortColumn::CreatedAt,
+            "updated_at" => SortColumn::UpdatedAt,
+            _ => return Err(format!("unknown sort field `{}`", name)),
+        };
+        if keys.iter().any(|key| key.column == column) {
+            return Err(format!("duplicate sort field `{}`", name));
+        }
+        keys.push(SortKey { column, descending });
+    }
+    Ok(keys)
+}
+
+/// An exact match on one column, as a `filter[<column>]=<value>` query
+/// parameter asks for.
+#[derive(Clone, Debug, PartialEq, Hash)]
+pub enum ColumnFilter {
+    Name(String),
+    Identity(String),
+    Hometown(String),
+    Age(i32),
+    Role(HeroRole)
 }
 
+impl ColumnFilter {
+    pub const COLUMNS: [&'static str; 5] = ["name", "identity", "hometown", "age", "role"];
+
+    /// The filter for `column` equal to `value`. The error names the
+    /// filterable columns when `column` is not one, or says why `value`
+    /// does not fit it.
+    pub fn parse(column: &str, value: &str) -> Result<ColumnFilter, String> {
+        match column {
+            "name" => Ok(ColumnFilter::Name(value.to_string())),
+            "identity" => Ok(ColumnFilter::Identity(value.to_string())),
+            "hometown" => Ok(ColumnFilter::Hometown(value.to_string())),
+            "age" => value.parse()
+                .map(ColumnFilter::Age)
+                .map_err(|_| format!("filter on age needs a whole number, not `{}`", value)),
+            "role" => value.parse().map(ColumnFilter::Role),
+            _ => Err(format!(
+                "cannot filter on unknown column `{}`, expected one of {}",
+                column,
+                ColumnFilter::COLUMNS.join(", ")
+            )),
+        }
+    }
+
+    fn matches(&self, hero: &Hero) -> bool {
+        match self {
+            ColumnFilter::Name(name) => hero.name == *name,
+            ColumnFilter::Identity(identity) => hero.identity == *identity,
+            ColumnFilter::Hometown(hometown) => hero.hometown == *hometown,
+            ColumnFilter::Age(age) => hero.age == *age,
+            ColumnFilter::Role(role) => hero.role == Some(*role),
+        }
+    }
+
+    fn apply<'a>(&self, query: heroes::BoxedQuery<'a, Pg>) -> heroes::BoxedQuery<'a, Pg> {
+        match self {
+            ColumnFilter::Name(name) => query.filter(heroes::name.eq(name.clone())),
+            ColumnFilter::Identity(identity) => query.filter(heroes::identity.eq(identity.clone())),
+            ColumnFilter::Hometown(hometown) => query.filter(heroes::hometown.eq(hometown.clone())),
+            ColumnFilter::Age(age) => query.filter(heroes::age.eq(*age)),
+            ColumnFilter::Role(role) => query.filter(heroes::role.eq(*role)),
+        }
+    }
+}
+
+/// Which heroes the list and count cover: live ones unless
+/// `include_deleted`, only those with `role` when given, only those
+/// whose name or identity contains `query`, ignoring case, when given, and
+/// only those matching every one of `columns`.
+#[derive(Clone, Debug, Default, Hash)]
+pub struct HeroFilter {
+    pub include_deleted: bool,
+    pub role: Option<HeroRole>,
+    pub query: Option<String>,
+    pub columns: Vec<ColumnFilter>
+}
+
+impl HeroFilter {
+    /// Whether `hero` passes the filter, for stores that filter in memory.
+    /// Matches what `Hero::read` and `Hero::count` select in the database.
+    pub fn matches(&self, hero: &Hero) -> bool {
+        (self.include_deleted || hero.deleted_at.is_none())
+            && self.role.map_or(true, |role| hero.role == Some(role))
+            && self.query.as_ref().map_or(true, |query| {
+                let query = query.to_lowercase();
+                hero.name.to_lowercase().contains(&query) || hero.identity.to_lowercase().contains(&query)
+            })
+            && self.columns.iter().all(|column| column.matches(hero))
+    }
+
+    fn apply<'a>(&self, mut query: heroes::BoxedQuery<'a, Pg>) -> heroes::BoxedQuery<'a, Pg> {
+        if !self.include_deleted {
+            query = query.filter(heroes::deleted_at.is_null());
+        }
+        if let Some(role) = self.role {
+            query = query.filter(heroes::role.eq(role));
+        }
+        if let Some(text) = &self.query {
+            let pattern = format!("%{}%", escape_like(text));
+            query = query.filter(heroes::name.ilike(pattern.clone()).or(heroes::identity.ilike(pattern)));
+        }
+        for column in &self.columns {
+            query = column.apply(query);
+        }
+        query
+    }
+}
+
+/// Escapes the `LIKE` wildcards in `text` so it matches literally.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// A window onto the hero list; without a limit the list runs to the end.
+/// With `after` the window starts past that id, in id order, instead of
+/// `offset` heroes in: a keyset page, which costs the same however deep it
+/// is and neither skips nor repeats heroes others insert meanwhile.
+#[derive(Clone, Copy, Debug, Default, Hash)]
+pub struct Page {
+    pub offset: i64,
+    pub limit: Option<i64>,
+    pub after: Option<i32>
+}
+/// How many times `Hero::update` re-runs after a serialization failure.
+pub const UPDATE_RETRIES: u32 = 3;
+
+/// Up to 10ms per attempt so far, jittered from the clock so that two
+/// clashing writers are unlikely to retry in lockstep.
+fn retry_backoff(attempt: u32) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.subsec_nanos())
+        .unwrap_or(0);
+    Duration::from_millis(u64::from(nanos % 10 + 1) * u64::from(attempt))
+}
+
+/// Iterator returned by `Hero::read_batched`, yielding one batch per query.
+/// A query that fails ends the walk on its error.
+pub struct HeroBatches<C> {
+    connection: C,
+    filter: HeroFilter,
+    batch_size: i64,
+    after: Option<i32>,
+    done: bool
+}
+
+impl<C: Deref<Target = PgConnection>> Iterator for HeroBatches<C> {
+    type Item = QueryResult<Vec<Hero>>;
+
+    fn next(&mut self) -> Option<QueryResult<Vec<Hero>>> {
+        if self.done {
+            return None;
+        }
+        let mut query = self.filter.apply(heroes::table.into_boxed())
+            .order(heroes::id.asc())
+            .limit(self.batch_size);
+        if let Some(after) = self.after {
+            query = query.filter(heroes::id.gt(after));
+        }
+        let batch = match query.load::<Hero>(&*self.connection) {
+            Ok(batch) => batch,
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err));
+            },
+        };
+        self.done = (batch.len() as i64) < self.batch_size;
+        self.after = batch.last().map(|hero| hero.id);
+        if batch.is_empty() { None } else { Some(Ok(batch)) }
+    }
+}
+
+/// Heroes `Hero::seed` inserts, in id order; past the end it starts over
+/// with a number after each name.
+const SEED: [(&str, &str, &str, i32, HeroRole); 6] = [
+    ("Reinhardt", "Reinhardt Wilhelm", "Stuttgart", 61, HeroRole::Tank),
+    ("Mercy", "Angela Ziegler", "Zurich", 37, HeroRole::Support),
+    ("Tracer", "Lena Oxton", "London", 26, HeroRole::Damage),
+    ("Winston", "Winston", "Horizon Lunar Colony", 29, HeroRole::Tank),
+    ("Lucio", "Lucio Correia dos Santos", "Rio de Janeiro", 26, HeroRole::Support),
+    ("Soldier: 76", "Jack Morrison", "Bloomington", 55, HeroRole::Damage),
+];
 
 impl Hero {
-    pub fn create(hero: Hero, connection: &PgConnection) -> Hero {
+    /// A weak entity tag for the hero as served: a hash of its JSON, so any
+    /// change to a field, the version included, changes the tag.
+    pub fn etag(&self) -> String {
+        let json = serde_json::to_vec(self).expect("a hero always serializes");
+        let mut hasher = DefaultHasher::new();
+        json.hash(&mut hasher);
+        format!("W/\"{:016x}\"", hasher.finish())
+    }
+
+    pub fn create(hero: &NewHero, connection: &PgConnection) -> QueryResult<Hero> {
+        let now = Utc::now();
         diesel::insert_into(heroes::table)
-            .values(&hero)
+            .values((hero, heroes::created_at.eq(now), heroes::updated_at.eq(now)))
+            .get_result(connection)
+    }
+
+    /// Lists one page of the heroes `filter` selects, ordered by each of
+    /// `sort` in turn, then by id.
+    pub fn read(filter: &HeroFilter, sort: &[SortKey], page: Page, connection: &PgConnection) -> Vec<Hero> {
+        let mut query = filter.apply(heroes::table.into_boxed());
+        for key in sort {
+            query = match (key.column, key.descending) {
+                (SortColumn::Id, false) => query.then_order_by(heroes::id.asc()),
+                (SortColumn::Id, true) => query.then_order_by(heroes::id.desc()),
+                (SortColumn::Name, false) => query.then_order_by(heroes::name.asc()),
+                (SortColumn::Name, true) => query.then_order_by(heroes::name.desc()),
+                (SortColumn::Age, false) => query.then_order_by(heroes::age.asc()),
+                (SortColumn::Age, true) => query.then_order_by(heroes::age.desc()),
+                (SortColumn::CreatedAt, false) => query.then_order_by(heroes::created_at.asc()),
+                (SortColumn::CreatedAt, true) => query.then_order_by(heroes::created_at.desc()),
+                (SortColumn::UpdatedAt, false) => query.then_order_by(heroes::updated_at.asc()),
+                (SortColumn::UpdatedAt, true) => query.then_order_by(heroes::updated_at.desc()),
+            };
+        }
+        query = query.then_order_by(heroes::id.asc()).offset(page.offset);
+        if let Some(limit) = page.limit {
+            query = query.limit(limit);
+        }
+        query.load::<Hero>(connection).unwrap()
+    }
+
+    /// Lists the heroes `filter` selects with ids above `last_id`, in id
+    /// order: `WHERE id > $1 ORDER BY id LIMIT $2`, served from the primary
+    /// key index however far into the table the page is.
+    pub fn read_after(filter: &HeroFilter, last_id: i32, limit: Option<i64>, connection: &PgConnection) -> Vec<Hero> {
+        let mut query = filter.apply(heroes::table.into_boxed())
+            .filter(heroes::id.gt(last_id))
+            .order(heroes::id.asc());
+        if let Some(limit) = limit {
+            query = query.limit(limit);
+        }
+        query.load::<Hero>(connection).unwrap()
+    }
+
+    /// Walks every hero `filter` selects in id order, fetching `batch_size`
+    /// rows per query so the whole table is never held in memory at once.
+    pub fn read_batched<C>(connection: C, filter: HeroFilter, batch_size: i64) -> HeroBatches<C>
+    where
+        C: Deref<Target = PgConnection>,
+    {
+        HeroBatches { connection, filter, batch_size, after: None, done: false }
+    }
+
+    /// Counts the heroes `read` would list across all pages, in the database.
+    pub fn count(filter: &HeroFilter, connection: &PgConnection) -> i64 {
+        filter.apply(heroes::table.into_boxed()).count().get_result(connection).unwrap()
+    }
+
+    pub fn find(id: i32, connection: &PgConnection) -> Option<Hero> {
+        heroes::table
+            .find(id)
+            .filter(heroes::deleted_at.is_null())
+            .first(connection)
+            .optional()
+            .unwrap()
+    }
+
+    /// Deletes every hero, soft-deleted or not, and restarts ids from 1.
+    /// Returns the heroes removed, so the caller can drop their avatars.
+    pub fn truncate(connection: &PgConnection) -> QueryResult<Vec<Hero>> {
+        let removed = diesel::delete(heroes::table).get_results(connection)?;
+        diesel::sql_query("SELECT setval(pg_get_serial_sequence('heroes', 'id'), 1, false)")
+            .execute(connection)?;
+        Ok(removed)
+    }
+
+    /// Inserts `count` heroes from `SEED`, the same ones on every run, and
+    /// returns them in id order.
+    pub fn seed(connection: &PgConnection, count: usize) -> QueryResult<Vec<Hero>> {
+        Hero::seed_heroes(count).iter().map(|hero| Hero::create(hero, connection)).collect()
+    }
+
+    /// The first `count` heroes `Hero::seed` inserts, for stores that seed
+    /// themselves.
+    pub fn seed_heroes(count: usize) -> Vec<NewHero> {
+        (0..count)
+            .map(|index| {
+                let (name, identity, hometown, age, role) = SEED[index % SEED.len()];
+                let name = match index / SEED.len() {
+                    0 => name.to_string(),
+                    round => format!("{} {}", name, round + 1),
+                };
+                NewHero {
+                    name,
+                    identity: identity.to_string(),
+                    hometown: hometown.to_string(),
+                    age,
+                    role: Some(role),
+                }
+            })
+            .collect()
+    }
+
+    /// Loads a hero, soft-deleted or not, and locks its row until the
+    /// surrounding transaction ends, so no other write can change it between
+    /// this read and the caller's own.
+    pub fn find_for_update(id: i32, connection: &PgConnection) -> QueryResult<Option<Hero>> {
+        heroes::table.find(id).for_update().first(connection).optional()
+    }
+
+    /// Updates the hero only if it is still at `version`, bumping the version
+    /// and `updated_at` on success. Returns `None` when the hero is missing or
+    /// has moved on.
+    ///
+    /// The update runs in a SERIALIZABLE transaction, where Postgres may abort
+    /// one of two overlapping writers with a serialization failure even though
+    /// retrying it would succeed; those are retried up to `UPDATE_RETRIES`
+    /// times after a short random pause. Any other error is returned at once.
+    ///
+    /// Inside a transaction already open, the isolation level is that
+    /// transaction's and cannot be raised, nor could a retry see past its
+    /// snapshot, so the update runs once in a savepoint. The version filter
+    /// alone then keeps a stale write out. The update route therefore runs
+    /// outside any request transaction.
+    pub fn update(id: i32, version: i32, hero: &NewHero, connection: &PgConnection) -> QueryResult<Option<Hero>> {
+        if db::in_transaction(connection) {
+            return connection.transaction(|| Hero::update_once(id, version, hero, connection));
+        }
+        let mut attempt = 0;
+        loop {
+            let result = connection.build_transaction()
+                .serializable()
+                .run(|| Hero::update_once(id, version, hero, connection));
+            match result {
+                Err(DieselError::DatabaseError(DatabaseErrorKind::SerializationFailure, _))
+                    if attempt < UPDATE_RETRIES => {
+                    attempt += 1;
+                    thread::sleep(retry_backoff(attempt));
+                },
+                result => return result,
+            }
+        }
+    }
+
+    fn update_once(id: i32, version: i32, hero: &NewHero, connection: &PgConnection) -> QueryResult<Option<Hero>> {
+        diesel::update(
+            heroes::table
+                .find(id)
+                .filter(heroes::deleted_at.is_null())
+                .filter(heroes::version.eq(version))
+        )
+            .set((
+                hero,
+                heroes::version.eq(heroes::version + 1),
+                heroes::updated_at.eq(Utc::now()),
+            ))
+            .get_result(connection)
+            .optional()
+    }
+
+    /// Records the stored avatar of a live hero. Returns `None` when the hero
+    /// does not exist or has been deleted.
+    pub fn set_avatar(id: i32, filename: &str, content_type: &str, connection: &PgConnection) -> QueryResult<Option<Hero>> {
+        diesel::update(heroes::table.find(id).filter(heroes::deleted_at.is_null()))
+            .set((
+                heroes::avatar_filename.eq(filename),
+                heroes::avatar_content_type.eq(content_type),
+            ))
+            .get_result(connection)
+            .optional()
+    }
+
+    /// Soft-deletes a hero by stamping `deleted_at` and forgetting its avatar,
+    /// whose file the caller removes. Returns false when the hero does not
+    /// exist or was already deleted.
+    pub fn delete(id: i32, connection: &PgConnection) -> QueryResult<bool> {
+        diesel::update(heroes::table.find(id).filter(heroes::deleted_at.is_null()))
+            .set((
+                heroes::deleted_at.eq(Utc::now()),
+                heroes::avatar_filename.eq(None::<String>),
+                heroes::avatar_content_type.eq(None::<String>),
+            ))
             .execute(connection)
-            .expect("Error creating new hero");
+            .map(|count| count > 0)
+    }
 
-        heroes::table.order(heroes::id.desc()).first(connection).unwrap()
+    /// Clears `deleted_at` on a soft-deleted hero and returns it.
+    pub fn restore(id: i32, connection: &PgConnection) -> QueryResult<Option<Hero>> {
+        diesel::update(heroes::table.find(id).filter(heroes::deleted_at.is_not_null()))
+            .set(heroes::deleted_at.eq(None::<DateTime<Utc>>))
+            .get_result(connection)
+            .optional()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn read(connection: &PgConnection) -> Vec<Hero> {
-        heroes::table.order(heroes::id.asc()).load::<Hero>(connection).unwrap()
+    fn keys(value: &str) -> Result<Vec<(SortColumn, bool)>, String> {
+        parse_sort(value).map(|keys| keys.iter().map(|key| (key.column, key.descending)).collect())
     }
 
-    pub fn update(id: i32, hero: Hero, connection: &PgConnection) -> bool {
-        diesel::update(heroes::table.find(id)).set(&hero).execute(connection).is_ok()
+    #[test]
+    fn sort_fields_apply_in_the_order_given() {
+        assert_eq!(keys("name"), Ok(vec![(SortColumn::Name, false)]));
+        assert_eq!(keys("-updated_at,name"), Ok(vec![(SortColumn::UpdatedAt, true), (SortColumn::Name, false)]));
+        assert_eq!(
+            keys("age, -created_at ,id"),
+            Ok(vec![(SortColumn::Age, false), (SortColumn::CreatedAt, true), (SortColumn::Id, false)]),
+        );
     }
 
-    pub fn delete(id: i32, connection: &PgConnection) -> bool {
-        diesel::delete(heroes::table.find(id)).execute(connection).is_ok()
+    #[test]
+    fn an_unknown_sort_field_is_named() {
+        assert_eq!(keys("name,-power"), Err("unknown sort field `power`".to_string()));
+        assert_eq!(keys("hometown"), Err("unknown sort field `hometown`".to_string()));
+        assert_eq!(keys("name,"), Err("unknown sort field ``".to_string()));
+        assert_eq!(keys("--name"), Err("unknown sort field `-name`".to_string()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn a_sort_field_may_appear_only_once() {
+        assert_eq!(keys("name,-name"), Err("duplicate sort field `name`".to_string()));
+        assert_eq!(keys("age,id,age"), Err("duplicate sort field `age`".to_string()));
+    }
+}
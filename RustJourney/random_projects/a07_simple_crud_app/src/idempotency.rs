@@ -0,0 +1,145 @@
+use log::warn;
+use redis::{Commands, Connection, RedisResult};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::{Outcome, Request};
+
+use crate::db;
+use crate::error::ApiError;
+use crate::hero::Hero;
+use crate::redis_pool::RedisPool;
+
+const HEADER: &str = "Idempotency-Key";
+const MAX_KEY_LEN: usize = 255;
+const DEFAULT_TTL_SECS: i64 = 24 * 60 * 60;
+/// How long a claimed key blocks retries before its request is assumed to
+/// have died without finishing.
+const PENDING_TTL_SECS: usize = 60;
+const PENDING: &str = "pending";
+const PREFIX: &str = "idempotency:hero:";
+
+/// The client's `Idempotency-Key` header, if it sent one. Keys longer than
+/// 255 bytes are refused with 400.
+pub struct IdempotencyKey(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IdempotencyKey {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<IdempotencyKey, ()> {
+        match request.headers().get_one(HEADER) {
+            None => Outcome::Success(IdempotencyKey(None)),
+            Some(key) if key.is_empty() || key.len() > MAX_KEY_LEN => Outcome::Failure((Status::BadRequest, ())),
+            Some(key) => Outcome::Success(IdempotencyKey(Some(key.to_string()))),
+        }
+    }
+}
+
+/// What an earlier request with the same key left behind.
+enum Claim {
+    Claimed,
+    Finished(Hero),
+    InProgress,
+}
+
+/// Remembers in Redis the hero each `Idempotency-Key` created, for
+/// `idempotency_ttl` seconds from the Rocket config, so a retried create
+/// answers with the original hero instead of inserting another. Without
+/// Redis, or while it is unreachable, keys are not honoured and every
+/// create inserts.
+pub struct Idempotency {
+    redis: Option<RedisPool>,
+    ttl_secs: usize,
+}
+
+impl Idempotency {
+    pub fn fairing(redis_url: Option<&str>) -> AdHoc {
+        let redis = redis_url.and_then(|url| RedisPool::open(url, "Idempotency keys disabled"));
+        AdHoc::on_attach("Idempotency keys", move |rocket| {
+            let ttl_secs = rocket.config()
+                .get_int("idempotency_ttl")
+                .unwrap_or(DEFAULT_TTL_SECS)
+                .max(1) as usize;
+            Ok(rocket.manage(Idempotency { redis, ttl_secs }))
+        })
+    }
+
+    /// Runs `create` once per `key`. A repeat of a finished key returns the
+    /// hero it created, and a repeat while the first is still running fails
+    /// with `KeyInProgress`. A failed `create` frees the key for a retry.
+    ///
+    /// Inside a request transaction the hero is only recorded once that
+    /// commits, and a rollback frees the key instead: a retry must not be
+    /// answered with a hero that was never stored.
+    pub fn once<F>(&self, key: Option<&str>, create: F) -> Result<Hero, ApiError>
+    where
+        F: FnOnce() -> Result<Hero, ApiError>,
+    {
+        let key = match key {
+            Some(key) => format!("{}{}", PREFIX, key),
+            None => return create(),
+        };
+        match self.with_connection(|connection| claim(connection, &key)) {
+            Some(Claim::Finished(hero)) => return Ok(hero),
+            Some(Claim::InProgress) => return Err(ApiError::KeyInProgress),
+            Some(Claim::Claimed) => {},
+            None => return create(),
+        }
+        let created = create();
+        let body = created.as_ref().ok().and_then(|hero| serde_json::to_string(hero).ok());
+        let redis = self.redis.clone();
+        let ttl_secs = self.ttl_secs;
+        db::after_request_transaction(move |committed| match body {
+            Some(body) if committed => {
+                with_connection(redis.as_ref(), |connection| connection.set_ex::<_, _, ()>(&key, body, ttl_secs));
+            },
+            _ => {
+                with_connection(redis.as_ref(), |connection| connection.del::<_, ()>(&key));
+            },
+        });
+        created
+    }
+
+    fn with_connection<T, F>(&self, command: F) -> Option<T>
+    where
+        F: FnOnce(&Connection) -> RedisResult<T>,
+    {
+        with_connection(self.redis.as_ref(), command)
+    }
+}
+
+/// Runs `command` on a pooled connection, or gives `None` if there is no
+/// Redis or it fails.
+fn with_connection<T, F>(redis: Option<&RedisPool>, command: F) -> Option<T>
+where
+    F: FnOnce(&Connection) -> RedisResult<T>,
+{
+    redis?.run(command).map_err(|err| warn!("Idempotency keys unavailable, creating anyway: {}", err)).ok()
+}
+
+/// Marks `key` as in progress unless an earlier request already holds it.
+/// A key that expires between the two commands is claimed on the retry.
+fn claim(connection: &Connection, key: &str) -> RedisResult<Claim> {
+    for _ in 0..2 {
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(PENDING)
+            .arg("NX")
+            .arg("EX")
+            .arg(PENDING_TTL_SECS)
+            .query(connection)?;
+        if claimed.is_some() {
+            return Ok(Claim::Claimed);
+        }
+        match connection.get::<_, Option<String>>(key)? {
+            Some(ref value) if value == PENDING => return Ok(Claim::InProgress),
+            Some(value) => match serde_json::from_str(&value) {
+                Ok(hero) => return Ok(Claim::Finished(hero)),
+                // Unreadable, so as good as absent.
+                Err(_) => connection.del::<_, ()>(key)?,
+            },
+            None => {},
+        }
+    }
+    Ok(Claim::InProgress)
+}
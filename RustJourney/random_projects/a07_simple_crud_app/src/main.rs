@@ -7,15 +7,63 @@ extern crate r2d2;
 extern crate r2d2_diesel;
 extern crate rocket;
 
+use config::Settings;
 use rocket_contrib::json::{Json, JsonValue};
+mod admin;
+mod auth;
+mod avatar;
+mod body;
+mod cache;
+mod catchers;
+mod cors;
 mod hero;
 mod db;
+mod error;
+mod export;
+mod health;
+mod headers;
+mod idempotency;
+mod metrics;
+mod negotiate;
+mod openapi;
+mod power;
+mod rate_limit;
+mod redis_pool;
+mod request_id;
 mod schemas;
-use hero::Hero;
-use schemas::heroes;
+mod shutdown;
+mod store;
+mod transaction;
+mod xml;
+#[cfg(test)]
+mod tests;
+use auth::{ApiKey, ApiKeySecret};
+use avatar::AvatarStore;
+use cache::HeroCache;
+use cors::Cors;
+use hero::{parse_sort, ColumnFilter, Hero, HeroCreate, HeroFilter, HeroRole, HeroUpdate, Page, SortColumn};
+use error::ApiError;
+use export::{CsvExport, NdjsonExport};
+use headers::{Conditional, IfMatch, IfNoneMatch, NextCursor, TotalCount};
+use idempotency::{Idempotency, IdempotencyKey};
+use metrics::Metrics;
+use negotiate::Negotiated;
+use power::{HeroFullCreate, HeroWithPowers};
+use rate_limit::{RateLimited, RateLimiter};
+use request_id::RequestId;
+use shutdown::Shutdown;
+use store::{Batches, HeroStore};
+use transaction::RequestTransaction;
 
-use rocket::{get, routes, post, put, delete};
+use rocket::response::status;
+use rocket::fairing::AdHoc;
+use rocket::request::{FromQuery, Query};
+use rocket::{Request, Response, Route, State};
+use rocket::{catchers, get, routes, post, put, delete};
 
+const EXPORT_BATCH_SIZE: i64 = 500;
+const LEGACY_HERO: &str = "/hero";
+const LEGACY_HEROES: &str = "/heroes";
 
 #[get("/<name>/<age>")]
 fn hello(name: String, age: u8) -> String {
@@ -23,38 +71,327 @@ fn hello(name: String, age: u8) -> String {
 }
 
 #[post("/", data = "<hero>")]
-fn create(hero: Json<Hero>, connection: db::Connection) -> Json<Hero> {
-    let insert = Hero { id: None, ..hero.into_inner() };
-    Json(Hero::create(insert, &connection))
+fn create(
+    hero: Negotiated<HeroCreate>,
+    route: &Route,
+    _key: ApiKey,
+    _limit: RateLimited,
+    idempotency_key: IdempotencyKey,
+    idempotency: State<Idempotency>,
+    cache: State<HeroCache>,
+    store: State<Box<dyn HeroStore>>,
+    _tx: RequestTransaction,
+) -> Result<status::Created<Negotiated<Hero>>, ApiError> {
+    let created = idempotency.once(idempotency_key.0.as_deref(), || store.create(&hero.hero))?;
+    cache.invalidate_lists();
+    Ok(status::Created(format!("{}/{}", route.base(), created.id), Some(Negotiated(created))))
 }
 
-#[get("/")]
-fn read(connection: db::Connection) -> Json<JsonValue> {
-    Json(json!(Hero::read(&connection)))
+/// Creates a hero and its powers in one transaction; see
+/// `HeroStore::create_with_powers`.
+#[post("/full", data = "<hero>")]
+fn create_full(
+    hero: Negotiated<HeroFullCreate>,
+    route: &Route,
+    _key: ApiKey,
+    _limit: RateLimited,
+    cache: State<HeroCache>,
+    store: State<Box<dyn HeroStore>>,
+    _tx: RequestTransaction,
+) -> Result<status::Created<Negotiated<HeroWithPowers>>, ApiError> {
+    let created = store.create_with_powers(&hero.hero.hero, &hero.power_names())?;
+    cache.invalidate_lists();
+    Ok(status::Created(format!("{}/{}", route.base(), created.hero.id), Some(Negotiated(created))))
+}
+
+#[get("/<id>")]
+fn find(
+    id: i32,
+    if_none_match: IfNoneMatch,
+    cache: State<HeroCache>,
+    store: State<Box<dyn HeroStore>>,
+) -> Result<Conditional<Negotiated<Hero>>, ApiError> {
+    let hero = cache.hero_or_load(id, || store.find(id))?.ok_or(ApiError::NotFound)?;
+    let etag = hero.etag();
+    Ok(Conditional::new(Negotiated(hero), etag, &if_none_match))
+}
+
+#[get("/?<include_deleted>&<role>&<q>&<sort>&<offset>&<limit>&<after>&<columns..>")]
+fn read(
+    include_deleted: Option<bool>,
+    key: Option<ApiKey>,
+    role: Option<String>,
+    q: Option<String>,
+    sort: Option<String>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+    after: Option<i32>,
+    columns: ColumnFilters,
+    cache: State<HeroCache>,
+    store: State<Box<dyn HeroStore>>,
+) -> Result<TotalCount<NextCursor<Negotiated<JsonValue>>>, ApiError> {
+    let filter = hero_filter(allow_deleted(include_deleted, key)?, role, q, columns)?;
+    let sort = match sort {
+        Some(sort) => parse_sort(&sort).map_err(ApiError::BadRequest)?,
+        None => Vec::new(),
+    };
+    let page = Page { offset: offset.unwrap_or(0), limit, after };
+    if page.offset < 0 || page.limit.map_or(false, |limit| limit < 0) {
+        return Err(ApiError::BadRequest("offset and limit must not be negative".to_string()));
+    }
+    let in_id_order = sort.iter().all(|key| key.column == SortColumn::Id && !key.descending);
+    if after.is_some() && (offset.is_some() || !in_id_order) {
+        return Err(ApiError::BadRequest("after pages in id order, so it takes neither offset nor sort".to_string()));
+    }
+    let params = (&filter, &sort, page);
+    let (heroes, total) = match cache.list(&params) {
+        Some((heroes, total)) => (JsonValue(heroes), total),
+        None => {
+            let heroes = json!(store.list(&filter, &sort, page)?);
+            let total = store.count(&filter)?;
+            cache.store_list(&params, &heroes, total);
+            (heroes, total)
+        },
+    };
+    let next = if in_id_order { next_cursor(&heroes, page.limit) } else { None };
+    Ok(TotalCount(NextCursor(Negotiated(heroes), next), total))
+}
+
+/// The id to list `after` for the page past `heroes`, a page in id order:
+/// its last hero's, when it filled `limit` and more heroes may follow.
+/// A page short of the limit, or one without any, was the last.
+fn next_cursor(heroes: &JsonValue, limit: Option<i64>) -> Option<i64> {
+    let heroes = heroes.as_array()?;
+    if limit? == 0 || heroes.len() as i64 != limit? {
+        return None;
+    }
+    heroes.last()?["id"].as_i64()
+}
+
+/// Whether to take in soft-deleted heroes: only when asked to, and only for
+/// a client with the API key, since deleted heroes are not for everyone.
+fn allow_deleted(asked: Option<bool>, key: Option<ApiKey>) -> Result<bool, ApiError> {
+    match (asked.unwrap_or(false), key) {
+        (true, None) => Err(ApiError::DeletedNeedsKey),
+        (include_deleted, _) => Ok(include_deleted),
+    }
+}
+
+/// The filter shared by the list, count and export routes; an empty `q` is
+/// no filter at all.
+fn hero_filter(
+    include_deleted: bool,
+    role: Option<String>,
+    q: Option<String>,
+    columns: ColumnFilters,
+) -> Result<HeroFilter, ApiError> {
+    let role = match role {
+        Some(role) => Some(role.parse::<HeroRole>().map_err(ApiError::BadRequest)?),
+        None => None,
+    };
+    let columns = columns.0.iter()
+        .map(|(column, value)| ColumnFilter::parse(column, value))
+        .collect::<Result<_, _>>()
+        .map_err(ApiError::BadRequest)?;
+    Ok(HeroFilter {
+        include_deleted,
+        role,
+        query: q.filter(|q| !q.is_empty()),
+        columns,
+    })
+}
+
+/// The `filter[<column>]=<value>` pairs of a query as column and value,
+/// checked only once turned into a `ColumnFilter`. Query keys of any other
+/// shape are ignored.
+struct ColumnFilters(Vec<(String, String)>);
+
+impl<'q> FromQuery<'q> for ColumnFilters {
+    type Error = ();
+
+    fn from_query(query: Query<'q>) -> Result<ColumnFilters, ()> {
+        let pairs = query
+            .map(|item| item.key_value_decoded())
+            .filter(|(key, _)| key.starts_with("filter[") && key.ends_with(']'))
+            .map(|(key, value)| (key["filter[".len()..key.len() - 1].to_string(), value))
+            .collect();
+        Ok(ColumnFilters(pairs))
+    }
+}
+
+/// The heroes the list would return with the same filter parameters, all
+/// of them, as CSV.
+#[get("/export.csv?<include_deleted>&<role>&<q>&<columns..>")]
+fn export(
+    include_deleted: Option<bool>,
+    key: Option<ApiKey>,
+    role: Option<String>,
+    q: Option<String>,
+    columns: ColumnFilters,
+    store: State<Box<dyn HeroStore>>,
+) -> Result<CsvExport<Batches>, ApiError> {
+    let filter = hero_filter(allow_deleted(include_deleted, key)?, role, q, columns)?;
+    Ok(CsvExport::new(store.batches(filter, EXPORT_BATCH_SIZE)?))
+}
+
+/// The heroes the list would return with the same filter parameters as one
+/// JSON object per line, read from the store a batch at a time as the
+/// client takes them.
+#[get("/stream?<include_deleted>&<role>&<q>&<columns..>")]
+fn stream(
+    include_deleted: Option<bool>,
+    key: Option<ApiKey>,
+    role: Option<String>,
+    q: Option<String>,
+    columns: ColumnFilters,
+    store: State<Box<dyn HeroStore>>,
+) -> Result<NdjsonExport<Batches>, ApiError> {
+    let filter = hero_filter(allow_deleted(include_deleted, key)?, role, q, columns)?;
+    Ok(NdjsonExport::new(store.batches(filter, EXPORT_BATCH_SIZE)?))
+}
+
+/// How many heroes the list would total with the same filter parameters.
+#[get("/count?<include_deleted>&<role>&<q>&<columns..>")]
+fn count(
+    include_deleted: Option<bool>,
+    key: Option<ApiKey>,
+    role: Option<String>,
+    q: Option<String>,
+    columns: ColumnFilters,
+    store: State<Box<dyn HeroStore>>,
+) -> Result<Json<JsonValue>, ApiError> {
+    let filter = hero_filter(allow_deleted(include_deleted, key)?, role, q, columns)?;
+    Ok(Json(json!({ "count": store.count(&filter)? })))
 }
 
 #[put("/<id>", data = "<hero>")]
-fn update(id: i32, hero: Json<Hero>, connection: db::Connection) -> Json<JsonValue> {
-    let update = Hero { id: Some(id), ..hero.into_inner() };
-    Json(json!({
-        "success": Hero::update(id, update, &connection)
-    }))
+fn update(
+    id: i32,
+    hero: Negotiated<HeroUpdate>,
+    if_match: IfMatch,
+    _key: ApiKey,
+    _limit: RateLimited,
+    cache: State<HeroCache>,
+    store: State<Box<dyn HeroStore>>,
+) -> Result<Negotiated<Hero>, ApiError> {
+    // No RequestTransaction: the update is one statement in a SERIALIZABLE
+    // transaction of its own, whose retries only work when it commits here
+    // rather than at the end of the request.
+    let version = if_match.0.or(hero.version).ok_or(ApiError::VersionRequired)?;
+    match store.update(id, version, &hero.hero)? {
+        Some(updated) => {
+            cache.invalidate_hero(id);
+            Ok(Negotiated(updated))
+        },
+        None => match store.find(id)? {
+            Some(current) => Err(ApiError::StaleVersion(current)),
+            None => Err(ApiError::NotFound),
+        },
+    }
 }
 
 #[delete("/<id>")]
-fn delete(id: i32, connection: db::Connection) -> Json<JsonValue> {
-    Json(json!({
-        "success": Hero::delete(id, &connection)
-    }))
+fn delete(
+    id: i32,
+    _key: ApiKey,
+    _limit: RateLimited,
+    avatars: State<AvatarStore>,
+    cache: State<HeroCache>,
+    store: State<Box<dyn HeroStore>>,
+    _tx: RequestTransaction,
+) -> Result<Json<JsonValue>, ApiError> {
+    // The avatar file goes only once the hero no longer points at it.
+    let deleted = store.delete(id)?;
+    cache.invalidate_hero(id);
+    if let Some(filename) = deleted.avatar_filename {
+        avatars.remove_once_committed(filename);
+    }
+    Ok(Json(json!({ "success": true })))
 }
 
+#[post("/<id>/restore")]
+fn restore(
+    id: i32,
+    _key: ApiKey,
+    _limit: RateLimited,
+    cache: State<HeroCache>,
+    store: State<Box<dyn HeroStore>>,
+    _tx: RequestTransaction,
+) -> Result<Negotiated<Hero>, ApiError> {
+    let restored = store.restore(id)?;
+    cache.invalidate_hero(id);
+    Ok(Negotiated(restored))
+}
 
-fn main(){
-    rocket::ignite()
-        .manage(db::connect())
+
+/// Builds the application against the database named in `settings`, so a
+/// caller can point an instance at a different database than `main` does.
+fn rocket(settings: &Settings) -> rocket::Rocket {
+    app(rocket::ignite(), settings)
+}
+
+/// The application built on `rocket`, whose config the fairings read their
+/// extras from; tests start from a `rocket::custom` one of their own.
+fn app(rocket: rocket::Rocket, settings: &Settings) -> rocket::Rocket {
+    rocket
+        .attach(db::fairing(settings.database_url()))
+        .attach(store::fairing())
+        .manage(ApiKeySecret(settings.api_key().map(str::to_string)))
+        .attach(RequestTransaction::fairing())
+        .attach(Shutdown::fairing())
+        .attach(RequestId::fairing())
+        .attach(Metrics::fairing())
+        .attach(Cors::fairing())
+        .attach(AvatarStore::fairing())
+        .attach(HeroCache::fairing(settings.configured_redis_url()))
+        .attach(RateLimiter::fairing(settings.configured_redis_url()))
+        .attach(Idempotency::fairing(settings.configured_redis_url()))
+        .attach(admin::fairing())
+        .attach(health::fairing(settings.configured_redis_url()))
+        .register(catchers![
+            catchers::bad_request,
+            catchers::unauthorized,
+            catchers::not_found,
+            catchers::not_acceptable,
+            catchers::payload_too_large,
+            catchers::unprocessable_entity,
+            catchers::internal_error,
+            catchers::service_unavailable,
+            rate_limit::too_many_requests,
+        ])
+        .mount("/", routes![health::health, health::detailed, health::ready, health::pool_state, health::admin_pool, metrics::metrics])
+        .mount("/", routes![openapi::spec, openapi::docs])
         .mount("/hello", routes![hello])
-        .mount("/hero", routes![create, update, delete])
-        .mount("/heroes", routes![read])
-        .launch()
-    ;
+        .attach(AdHoc::on_response("Deprecation header", mark_deprecated))
+        .mount("/api/v1/heroes", routes_v1())
+        .mount(LEGACY_HERO, routes![create, create_full, find, update, delete, restore, avatar::upload, avatar::download])
+        .mount(LEGACY_HEROES, routes![read, count, export, stream])
+}
+
+/// Every hero route, for mounting as one collection at `/api/v1/heroes`.
+fn routes_v1() -> Vec<Route> {
+    routes![
+        read, count, export, stream,
+        create, create_full, find, update, delete, restore,
+        avatar::upload, avatar::download,
+    ]
+}
+
+/// Flags responses served from the pre-`/api/v1` mounts, which stay only so
+/// existing clients keep working.
+fn mark_deprecated(request: &Request, response: &mut Response) {
+    let legacy = request.route()
+        .map_or(false, |route| route.base() == LEGACY_HERO || route.base() == LEGACY_HEROES);
+    if legacy {
+        response.set_raw_header("Deprecation", "true");
+    }
+}
+
+fn main(){
+    logging::init();
+    // Rocket's own messages, its launch banner and a few lines per request,
+    // go through the JSON logger too: keep terminal colours out of them.
+    std::env::set_var("ROCKET_CLI_COLORS", "off");
+    let settings = Settings::load().expect("Can't load settings");
+    rocket(&settings).launch();
 }
\ No newline at end of file
@@ -0,0 +1,121 @@
+use std::time::Instant;
+
+use log::error;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Status};
+use rocket::response::content::Content;
+use rocket::{get, Data, Request, Response, Rocket, State};
+
+use crate::db::Pool;
+
+const METRICS_PATH: &str = "/metrics";
+/// Route label for requests that matched no route.
+const UNMATCHED: &str = "unmatched";
+
+/// Prometheus series for the service, kept in managed state and served at
+/// `/metrics` in the text exposition format.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    durations: HistogramVec,
+    pool_connections: IntGaugeVec,
+    pool_max_size: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Metrics> {
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests answered, by route, method and status."),
+            &["route", "method", "status"],
+        )?;
+        let durations = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time spent answering a request."),
+            &["route", "method"],
+        )?;
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections, by whether they are idle or in use."),
+            &["state"],
+        )?;
+        let pool_max_size = IntGauge::new("db_pool_max_size", "Most connections the database pool will open.")?;
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(durations.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(pool_max_size.clone()))?;
+        Ok(Metrics { registry, requests, durations, pool_connections, pool_max_size })
+    }
+
+    /// Manages the registry and times every request except scrapes of
+    /// `/metrics` itself, labelling each by its route template rather than
+    /// its path so ids do not each get their own series.
+    pub fn fairing() -> MetricsFairing {
+        MetricsFairing
+    }
+
+    fn observe(&self, request: &Request, response: &Response) {
+        let route = request.route().map_or(UNMATCHED, |route| route.uri.path());
+        if route == METRICS_PATH {
+            return;
+        }
+        let method = request.method().as_str();
+        let started = request.local_cache(|| RequestStart(None)).0;
+        if let Some(started) = started {
+            self.durations.with_label_values(&[route, method]).observe(started.elapsed().as_secs_f64());
+        }
+        let status = response.status().code.to_string();
+        self.requests.with_label_values(&[route, method, &status]).inc();
+    }
+}
+
+/// When the current request arrived, set by the fairing.
+struct RequestStart(Option<Instant>);
+
+pub struct MetricsFairing;
+
+impl Fairing for MetricsFairing {
+    fn info(&self) -> Info {
+        Info { name: "Prometheus metrics", kind: Kind::Attach | Kind::Request | Kind::Response }
+    }
+
+    fn on_attach(&self, rocket: Rocket) -> Result<Rocket, Rocket> {
+        match Metrics::new() {
+            Ok(metrics) => Ok(rocket.manage(metrics)),
+            Err(err) => {
+                error!("Could not register metrics: {}", err);
+                Err(rocket)
+            },
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.local_cache(|| RequestStart(Some(Instant::now())));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if let Some(metrics) = request.guard::<State<Metrics>>().succeeded() {
+            metrics.observe(request, response);
+        }
+    }
+}
+
+/// Every series in the Prometheus text format, with the pool gauges read
+/// fresh for this scrape.
+#[get("/metrics")]
+pub fn metrics(metrics: State<Metrics>, pool: State<Pool>) -> Result<Content<Vec<u8>>, Status> {
+    let state = pool.state();
+    metrics.pool_connections.with_label_values(&["idle"]).set(i64::from(state.idle_connections));
+    metrics.pool_connections
+        .with_label_values(&["in_use"])
+        .set(i64::from(state.connections - state.idle_connections));
+    metrics.pool_max_size.set(i64::from(pool.max_size()));
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder.encode(&metrics.registry.gather(), &mut body).map_err(|err| {
+        error!("Could not encode metrics: {}", err);
+        Status::InternalServerError
+    })?;
+    let content_type = encoder.format_type().parse().unwrap_or(ContentType::Plain);
+    Ok(Content(content_type, body))
+}
@@ -0,0 +1,151 @@
+use std::cmp::Ordering;
+use std::io::Cursor;
+use std::ops::Deref;
+
+use log::error;
+use rocket::data::{self, Data, FromDataSimple};
+use rocket::http::{ContentType, MediaType, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::{Outcome, Request};
+use rocket_contrib::json::{Json, JsonValue};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::body::{fail, field_path, read_limited, JsonBody};
+use crate::hero::Hero;
+use crate::power::HeroWithPowers;
+use crate::xml;
+
+/// A value read or written as JSON, MessagePack or XML, whichever the
+/// client asked for. Bodies are MessagePack when their `Content-Type` is
+/// `application/msgpack` and JSON otherwise, exactly as `JsonBody` would
+/// read them; XML is only written. Responses are in the format `Accept`
+/// ranks highest, JSON when it is missing or leaves the choice open, and
+/// 406 when it names nothing this can write.
+pub struct Negotiated<T>(pub T);
+
+/// The root element of a value written as XML.
+pub trait XmlRoot {
+    const ELEMENT: &'static str;
+}
+
+impl XmlRoot for Hero {
+    const ELEMENT: &'static str = "hero";
+}
+
+impl XmlRoot for HeroWithPowers {
+    const ELEMENT: &'static str = "hero";
+}
+
+/// Only lists of heroes are answered as bare JSON values.
+impl XmlRoot for JsonValue {
+    const ELEMENT: &'static str = "heroes";
+}
+
+/// The formats a response can be written in.
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    MessagePack,
+    Xml,
+}
+
+impl Format {
+    /// The format `media_type` asks for, if any; a wildcard is JSON.
+    fn of(media_type: &MediaType) -> Option<Format> {
+        let (top, sub) = (media_type.top(), media_type.sub());
+        if top == "*" || (top == "application" && (sub == "*" || sub == "json")) {
+            Some(Format::Json)
+        } else if is_msgpack(media_type) {
+            Some(Format::MessagePack)
+        } else if (top == "application" || top == "text") && sub == "xml" {
+            Some(Format::Xml)
+        } else {
+            None
+        }
+    }
+
+    /// The first format `request`'s `Accept` asks for, taking types by
+    /// weight and then in the order listed, and skipping those weighted 0.
+    fn accepted(request: &Request) -> Option<Format> {
+        let accept = match request.accept() {
+            Some(accept) => accept,
+            None => return Some(Format::Json),
+        };
+        let mut ranked: Vec<_> = accept.iter().filter(|media_type| media_type.weight_or(1.0) > 0.0).collect();
+        ranked.sort_by(|a, b| b.weight_or(1.0).partial_cmp(&a.weight_or(1.0)).unwrap_or(Ordering::Equal));
+        ranked.iter().find_map(|media_type| Format::of(media_type.media_type()))
+    }
+}
+
+fn is_msgpack(media_type: &MediaType) -> bool {
+    media_type.top() == "application"
+        && (media_type.sub() == "msgpack" || media_type.sub() == "x-msgpack")
+}
+
+impl<T: DeserializeOwned> FromDataSimple for Negotiated<T> {
+    type Error = ();
+
+    /// MessagePack bodies fail like JSON ones: 413 past the limit and 422
+    /// when the value has the wrong shape or is not MessagePack at all.
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Negotiated<T>, ()> {
+        let msgpack = request.content_type().map_or(false, |content_type| is_msgpack(content_type.media_type()));
+        if !msgpack {
+            return JsonBody::from_data(request, data).map(|body| Negotiated(body.0));
+        }
+        let body = match read_limited(request, data) {
+            Ok(body) => body,
+            Err(failure) => return failure,
+        };
+        let mut deserializer = rmp_serde::Deserializer::from_read_ref(&body);
+        match serde_path_to_error::deserialize(&mut deserializer) {
+            Ok(value) => Outcome::Success(Negotiated(value)),
+            Err(err) => {
+                let path = field_path(&err);
+                fail(request, Status::UnprocessableEntity, err.into_inner().to_string(), path)
+            },
+        }
+    }
+}
+
+impl<'r, T: Serialize + XmlRoot> Responder<'r> for Negotiated<T> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let response = match Format::accepted(request).ok_or(Status::NotAcceptable)? {
+            Format::Json => Json(self.0).respond_to(request)?,
+            Format::MessagePack => {
+                let body = rmp_serde::to_vec_named(&self.0).map_err(|err| {
+                    error!("Could not encode MessagePack response: {}", err);
+                    Status::InternalServerError
+                })?;
+                Response::build()
+                    .header(ContentType::new("application", "msgpack"))
+                    .sized_body(Cursor::new(body))
+                    .finalize()
+            },
+            Format::Xml => {
+                let body = serde_json::to_value(&self.0)
+                    .map_err(|err| err.to_string())
+                    .and_then(|value| xml::to_xml(T::ELEMENT, &value).map_err(|err| err.to_string()))
+                    .map_err(|err| {
+                        error!("Could not encode XML response: {}", err);
+                        Status::InternalServerError
+                    })?;
+                Response::build()
+                    .header(ContentType::new("application", "xml"))
+                    .sized_body(Cursor::new(body))
+                    .finalize()
+            },
+        };
+        Response::build_from(response)
+            .raw_header("Vary", "Accept")
+            .ok()
+    }
+}
+
+impl<T> Deref for Negotiated<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
@@ -0,0 +1,499 @@
+use rocket::get;
+use rocket::response::content::Html;
+use rocket_contrib::json::{Json, JsonValue};
+use serde_json::{Map, Value};
+
+use crate::hero::HeroRole;
+
+/// Where the versioned hero routes are mounted; every path below is
+/// relative to it.
+const V1: &str = "/api/v1/heroes";
+
+/// Swagger UI, loaded from a CDN and pointed at `/openapi.json`.
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Hero API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@3/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@3/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+/// The OpenAPI 3 description of the hero routes. The document is written
+/// by hand, so a change to a hero route or body belongs here too.
+#[get("/openapi.json")]
+pub fn spec() -> Json<JsonValue> {
+    Json(JsonValue(document()))
+}
+
+#[get("/docs")]
+pub fn docs() -> Html<&'static str> {
+    Html(DOCS_PAGE)
+}
+
+fn document() -> Value {
+    let mut paths = Map::new();
+    for (suffix, mut item) in hero_paths() {
+        // Every hero route goes through the store, which on Postgres waits
+        // for a pooled connection and gives up with a 503.
+        for operation in item.as_object_mut().unwrap().values_mut() {
+            operation["responses"]["503"] = error("No database connection came free in time; try again shortly.").0;
+        }
+        paths.insert(format!("{}{}", V1, suffix), item.clone());
+        for (legacy, item) in legacy_paths(&suffix, item) {
+            let entry = paths.entry(legacy).or_insert_with(|| json!({}).0);
+            entry.as_object_mut().unwrap().extend(item.as_object().unwrap().clone());
+        }
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Hero API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Create, list, update and soft-delete heroes. Bodies are JSON, or MessagePack \
+                            when sent as and accepted as application/msgpack. Heroes can also be \
+                            accepted as application/xml."
+        },
+        "paths": paths,
+        "components": { "schemas": schemas(), "securitySchemes": security_schemes() }
+    }).0
+}
+
+/// The `/hero` and `/heroes` copies of a versioned path, marked deprecated.
+/// The collection itself is split: listing stayed on `/heroes`, creating on
+/// `/hero`.
+fn legacy_paths(suffix: &str, item: Value) -> Vec<(String, Value)> {
+    let mut item = item;
+    for operation in item.as_object_mut().unwrap().values_mut() {
+        operation["deprecated"] = json!(true).0;
+    }
+    match suffix {
+        "" => {
+            let operations = item.as_object().unwrap();
+            vec![
+                ("/heroes".to_string(), json!({ "get": operations["get"] }).0),
+                ("/hero".to_string(), json!({ "post": operations["post"] }).0),
+            ]
+        },
+        "/count" | "/export.csv" | "/stream" => vec![(format!("/heroes{}", suffix), item)],
+        _ => vec![(format!("/hero{}", suffix), item)],
+    }
+}
+
+fn hero_paths() -> Vec<(String, Value)> {
+    vec![
+        ("".to_string(), json!({ "get": list(), "post": create() }).0),
+        ("/full".to_string(), json!({ "post": create_full() }).0),
+        ("/count".to_string(), json!({ "get": count() }).0),
+        ("/export.csv".to_string(), json!({ "get": export() }).0),
+        ("/stream".to_string(), json!({ "get": stream() }).0),
+        ("/{id}".to_string(), json!({ "get": find(), "put": update(), "delete": delete() }).0),
+        ("/{id}/restore".to_string(), json!({ "post": restore() }).0),
+        ("/{id}/avatar".to_string(), json!({ "get": avatar_download(), "put": avatar_upload() }).0),
+    ]
+}
+
+fn list() -> JsonValue {
+    let mut parameters = filter_parameters();
+    parameters.extend(vec![
+        query("sort", "Comma-separated columns (id, name, age, created_at, updated_at), each with a leading - \
+                       for descending.", json!({ "type": "string", "example": "-updated_at,name" })),
+        query("offset", "Heroes to skip.", json!({ "type": "integer", "minimum": 0, "default": 0 })),
+        query("limit", "Most heroes to return; all of them when left out.", json!({ "type": "integer", "minimum": 0 })),
+        query("after", "Lists the heroes with ids above this one, in id order: the X-Next-Cursor of the page \
+                        before. Takes neither offset nor sort.", json!({ "type": "integer" })),
+    ]);
+    json!({
+        "summary": "List heroes",
+        "parameters": parameters,
+        "responses": {
+            "200": {
+                "description": "One page of heroes.",
+                "headers": {
+                    "X-Total-Count": {
+                        "description": "How many heroes the filter matches across every page.",
+                        "schema": { "type": "integer" }
+                    },
+                    "X-Next-Cursor": {
+                        "description": "The after for the next page, on a full page in id order.",
+                        "schema": { "type": "integer" }
+                    }
+                },
+                "content": response_body(json!({ "type": "array", "items": schema_ref("Hero") }))
+            },
+            "400": error("A bad role, filter or sort field, a negative offset or limit, or after with offset or sort."),
+            "403": error("include_deleted without a valid X-API-Key."),
+            "406": error("Accept allows none of JSON, MessagePack or XML.")
+        }
+    })
+}
+
+fn count() -> JsonValue {
+    json!({
+        "summary": "Count the heroes a list would return",
+        "parameters": filter_parameters(),
+        "responses": {
+            "200": {
+                "description": "The count.",
+                "content": json_body(json!({
+                    "type": "object",
+                    "properties": { "count": { "type": "integer" } }
+                }))
+            },
+            "400": error("An unknown role or filter column."),
+            "403": error("include_deleted without a valid X-API-Key.")
+        }
+    })
+}
+
+fn export() -> JsonValue {
+    json!({
+        "summary": "Export the heroes a list would return as CSV",
+        "parameters": filter_parameters(),
+        "responses": {
+            "200": {
+                "description": "One row per hero, streamed.",
+                "content": { "text/csv": { "schema": { "type": "string" } } }
+            },
+            "400": error("An unknown role or filter column."),
+            "403": error("include_deleted without a valid X-API-Key.")
+        }
+    })
+}
+
+fn stream() -> JsonValue {
+    json!({
+        "summary": "Stream the heroes a list would return as newline-delimited JSON",
+        "parameters": filter_parameters(),
+        "responses": {
+            "200": {
+                "description": "One hero per line, in id order, streamed.",
+                "content": { "application/x-ndjson": { "schema": schema_ref("Hero") } }
+            },
+            "400": error("An unknown role or filter column."),
+            "403": error("include_deleted without a valid X-API-Key.")
+        }
+    })
+}
+
+fn create() -> JsonValue {
+    json!({
+        "summary": "Create a hero",
+        "security": [{ "apiKey": [] }],
+        "parameters": [{
+            "name": "Idempotency-Key",
+            "in": "header",
+            "description": "Repeating a create with the same key returns the hero the first one made.",
+            "schema": { "type": "string" }
+        }],
+        "requestBody": { "required": true, "content": body(schema_ref("HeroInput")) },
+        "responses": {
+            "201": {
+                "description": "The created hero.",
+                "headers": { "Location": { "description": "URL of the new hero.", "schema": { "type": "string" } } },
+                "content": response_body(schema_ref("Hero"))
+            },
+            "401": error("Missing or wrong X-API-Key."),
+            "409": error("The name is taken, or a request with this Idempotency-Key is still running."),
+            "422": error("The body is not a valid hero."),
+            "429": { "description": "Rate limit reached; see Retry-After." }
+        }
+    })
+}
+
+fn create_full() -> JsonValue {
+    json!({
+        "summary": "Create a hero with its powers",
+        "description": "The hero and every power are written in one transaction: if any insert fails, none are kept.",
+        "security": [{ "apiKey": [] }],
+        "requestBody": { "required": true, "content": body(schema_ref("HeroFullInput")) },
+        "responses": {
+            "201": {
+                "description": "The created hero, powers nested.",
+                "headers": { "Location": { "description": "URL of the new hero.", "schema": { "type": "string" } } },
+                "content": response_body(schema_ref("HeroWithPowers"))
+            },
+            "400": error("A power is listed more than once."),
+            "401": error("Missing or wrong X-API-Key."),
+            "409": error("The name is taken."),
+            "422": error("The body is not a valid hero."),
+            "429": { "description": "Rate limit reached; see Retry-After." }
+        }
+    })
+}
+
+fn find() -> JsonValue {
+    json!({
+        "summary": "Get a hero",
+        "parameters": [id(), {
+            "name": "If-None-Match",
+            "in": "header",
+            "description": "ETags the client already holds; a match answers 304 instead of the hero.",
+            "schema": { "type": "string" }
+        }],
+        "responses": {
+            "200": {
+                "description": "The hero.",
+                "headers": { "ETag": etag_header() },
+                "content": response_body(schema_ref("Hero"))
+            },
+            "304": { "description": "The hero still matches If-None-Match.", "headers": { "ETag": etag_header() } },
+            "404": error("No hero has this id."),
+            "406": error("Accept allows none of JSON, MessagePack or XML.")
+        }
+    })
+}
+
+fn update() -> JsonValue {
+    json!({
+        "summary": "Replace a hero",
+        "description": "Needs the version last read, in If-Match or the body's `version`. Fields left out, \
+                        such as `role`, are cleared.",
+        "security": [{ "apiKey": [] }],
+        "parameters": [id(), {
+            "name": "If-Match",
+            "in": "header",
+            "description": "The version last read, bare (3) or as an entity tag (\"3\").",
+            "schema": { "type": "string" }
+        }],
+        "requestBody": { "required": true, "content": body(schema_ref("HeroInput")) },
+        "responses": {
+            "200": { "description": "The updated hero.", "content": response_body(schema_ref("Hero")) },
+            "401": error("Missing or wrong X-API-Key."),
+            "404": error("No hero has this id."),
+            "409": error("The name is taken."),
+            "412": error("The version is stale; `error.current` holds the hero as it is now."),
+            "422": error("The body is not a valid hero."),
+            "428": error("No version was given."),
+            "429": { "description": "Rate limit reached; see Retry-After." }
+        }
+    })
+}
+
+fn delete() -> JsonValue {
+    json!({
+        "summary": "Soft-delete a hero",
+        "security": [{ "apiKey": [] }],
+        "parameters": [id()],
+        "responses": {
+            "200": {
+                "description": "The hero is deleted.",
+                "content": json_body(json!({
+                    "type": "object",
+                    "properties": { "success": { "type": "boolean" } }
+                }))
+            },
+            "401": error("Missing or wrong X-API-Key."),
+            "404": error("No live hero has this id."),
+            "429": { "description": "Rate limit reached; see Retry-After." }
+        }
+    })
+}
+
+fn restore() -> JsonValue {
+    json!({
+        "summary": "Restore a soft-deleted hero",
+        "security": [{ "apiKey": [] }],
+        "parameters": [id()],
+        "responses": {
+            "200": { "description": "The restored hero.", "content": response_body(schema_ref("Hero")) },
+            "401": error("Missing or wrong X-API-Key."),
+            "404": error("No hero has this id."),
+            "409": error("The hero is not deleted."),
+            "429": { "description": "Rate limit reached; see Retry-After." }
+        }
+    })
+}
+
+fn avatar_download() -> JsonValue {
+    json!({
+        "summary": "Get a hero's avatar",
+        "parameters": [id()],
+        "responses": {
+            "200": { "description": "The image, in the type it was uploaded as.", "content": images() },
+            "404": error("No hero has this id, or it has no avatar.")
+        }
+    })
+}
+
+fn avatar_upload() -> JsonValue {
+    json!({
+        "summary": "Set a hero's avatar",
+        "security": [{ "apiKey": [] }],
+        "parameters": [id()],
+        "requestBody": { "required": true, "description": "A PNG or JPEG of at most 2 MB.", "content": images() },
+        "responses": {
+            "200": { "description": "The hero with its new avatar.", "content": json_body(schema_ref("Hero")) },
+            "401": error("Missing or wrong X-API-Key."),
+            "404": error("No hero has this id."),
+            "413": error("The image is over 2 MB."),
+            "415": { "description": "The image is neither PNG nor JPEG." },
+            "429": { "description": "Rate limit reached; see Retry-After." }
+        }
+    })
+}
+
+/// The query parameters the list, count and export routes share.
+fn filter_parameters() -> Vec<JsonValue> {
+    vec![
+        query("include_deleted", "Include soft-deleted heroes; needs X-API-Key.", json!({ "type": "boolean", "default": false })),
+        query("role", "Only heroes with this role.", schema_ref("HeroRole")),
+        query("q", "Only heroes whose name or identity contains this, ignoring case.", json!({ "type": "string" })),
+        json!({
+            "name": "filter",
+            "in": "query",
+            "description": "Exact matches, all of which must hold, written filter[<column>]=<value>. An \
+                            unknown column is a 400.",
+            "style": "deepObject",
+            "explode": true,
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "identity": { "type": "string" },
+                    "hometown": { "type": "string" },
+                    "age": { "type": "integer" },
+                    "role": schema_ref("HeroRole")
+                },
+                "additionalProperties": false
+            }
+        }),
+    ]
+}
+
+fn query(name: &str, description: &str, schema: JsonValue) -> JsonValue {
+    json!({ "name": name, "in": "query", "description": description, "schema": schema })
+}
+
+fn id() -> JsonValue {
+    json!({ "name": "id", "in": "path", "required": true, "schema": { "type": "integer" } })
+}
+
+fn etag_header() -> JsonValue {
+    json!({ "description": "Weak tag of the hero as served.", "schema": { "type": "string" } })
+}
+
+fn schema_ref(name: &str) -> JsonValue {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn json_body(schema: JsonValue) -> JsonValue {
+    json!({ "application/json": { "schema": schema } })
+}
+
+/// A body negotiated between JSON and MessagePack.
+fn body(schema: JsonValue) -> JsonValue {
+    json!({
+        "application/json": { "schema": schema },
+        "application/msgpack": { "schema": schema }
+    })
+}
+
+/// A response negotiated between JSON, MessagePack and XML.
+fn response_body(schema: JsonValue) -> JsonValue {
+    let mut content = body(schema.clone());
+    content["application/xml"] = json!({ "schema": schema }).0;
+    content
+}
+
+fn images() -> JsonValue {
+    let image = json!({ "schema": { "type": "string", "format": "binary" } });
+    json!({ "image/png": image, "image/jpeg": image })
+}
+
+fn error(description: &str) -> JsonValue {
+    json!({ "description": description, "content": json_body(schema_ref("Error")) })
+}
+
+fn schemas() -> JsonValue {
+    let roles: Vec<&str> = HeroRole::ALL.iter().map(|role| role.as_str()).collect();
+    json!({
+        "HeroRole": { "type": "string", "enum": roles },
+        "Hero": {
+            "type": "object",
+            "required": ["id", "name", "identity", "hometown", "age", "version", "created_at", "updated_at"],
+            "properties": {
+                "id": { "type": "integer" },
+                "name": { "type": "string" },
+                "identity": { "type": "string" },
+                "hometown": { "type": "string" },
+                "age": { "type": "integer" },
+                "role": { "allOf": [schema_ref("HeroRole")], "nullable": true },
+                "version": { "type": "integer", "description": "Goes up on every change; send it back to update." },
+                "deleted_at": { "type": "string", "format": "date-time", "nullable": true },
+                "created_at": { "type": "string", "format": "date-time" },
+                "updated_at": { "type": "string", "format": "date-time" },
+                "avatar_filename": { "type": "string", "nullable": true },
+                "avatar_content_type": { "type": "string", "nullable": true }
+            }
+        },
+        "HeroInput": {
+            "type": "object",
+            "description": "The writable fields. created_at and updated_at are refused.",
+            "required": ["name", "identity", "hometown", "age"],
+            "properties": {
+                "name": { "type": "string" },
+                "identity": { "type": "string" },
+                "hometown": { "type": "string" },
+                "age": { "type": "integer" },
+                "role": schema_ref("HeroRole"),
+                "version": { "type": "integer", "description": "On update, the version last read." }
+            }
+        },
+        "Power": {
+            "type": "object",
+            "required": ["id", "hero_id", "name"],
+            "properties": {
+                "id": { "type": "integer" },
+                "hero_id": { "type": "integer" },
+                "name": { "type": "string" }
+            }
+        },
+        "HeroFullInput": {
+            "allOf": [schema_ref("HeroInput"), {
+                "type": "object",
+                "properties": {
+                    "powers": {
+                        "type": "array",
+                        "items": { "type": "object", "required": ["name"], "properties": { "name": { "type": "string" } } }
+                    }
+                }
+            }]
+        },
+        "HeroWithPowers": {
+            "allOf": [schema_ref("Hero"), {
+                "type": "object",
+                "required": ["powers"],
+                "properties": { "powers": { "type": "array", "items": schema_ref("Power") } }
+            }]
+        },
+        "Error": {
+            "type": "object",
+            "required": ["error"],
+            "properties": {
+                "error": {
+                    "type": "object",
+                    "description": "Some errors add fields: `current` on a stale version, `name` on a taken \
+                                    name, and `detail`, `line`, `column` and `path` on a body that did not parse.",
+                    "required": ["code", "message", "request_id"],
+                    "properties": {
+                        "code": { "type": "integer" },
+                        "message": { "type": "string" },
+                        "request_id": { "type": "string" }
+                    },
+                    "additionalProperties": true
+                }
+            }
+        }
+    })
+}
+
+fn security_schemes() -> JsonValue {
+    json!({ "apiKey": { "type": "apiKey", "in": "header", "name": "X-API-Key" } })
+}
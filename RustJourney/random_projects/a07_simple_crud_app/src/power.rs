@@ -0,0 +1,83 @@
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+use crate::error::ApiError;
+use crate::hero::{Hero, HeroCreate};
+use crate::schemas::hero_powers;
+
+#[derive(Clone, Debug, Serialize, Queryable)]
+pub struct Power {
+    pub id: i32,
+    pub hero_id: i32,
+    pub name: String
+}
+
+#[table_name = "hero_powers"]
+#[derive(Insertable)]
+struct NewPower<'a> {
+    hero_id: i32,
+    name: &'a str
+}
+
+/// One power in the body of a `POST /hero/full`.
+#[derive(Deserialize)]
+pub struct PowerBody {
+    pub name: String
+}
+
+/// Body of a `POST /hero/full`: a hero as `POST /hero` takes it, plus its
+/// powers.
+#[derive(Deserialize)]
+pub struct HeroFullCreate {
+    #[serde(flatten)]
+    pub hero: HeroCreate,
+    #[serde(default)]
+    pub powers: Vec<PowerBody>
+}
+
+impl HeroFullCreate {
+    pub fn power_names(&self) -> Vec<String> {
+        self.powers.iter().map(|power| power.name.clone()).collect()
+    }
+}
+
+/// A hero with its powers nested, as `POST /hero/full` answers.
+#[derive(Clone, Debug, Serialize)]
+pub struct HeroWithPowers {
+    #[serde(flatten)]
+    pub hero: Hero,
+    pub powers: Vec<Power>
+}
+
+/// Why a hero cannot have `names` as its powers: a name given twice.
+pub fn duplicate(names: &[String]) -> Option<ApiError> {
+    names.iter()
+        .enumerate()
+        .find(|(index, name)| names[..*index].contains(name))
+        .map(|(_, name)| repeated(name))
+}
+
+fn repeated(name: &str) -> ApiError {
+    ApiError::BadRequest(format!("power `{}` is listed more than once", name))
+}
+
+impl Power {
+    /// Inserts `names` as the powers of hero `hero_id`, one row each in
+    /// order. Run it in the transaction that created the hero: a name given
+    /// twice fails the second insert, and the hero must go with it.
+    pub fn create_all(hero_id: i32, names: &[String], connection: &PgConnection) -> Result<Vec<Power>, ApiError> {
+        names.iter()
+            .map(|name| {
+                diesel::insert_into(hero_powers::table)
+                    .values(&NewPower { hero_id, name })
+                    .get_result(connection)
+                    .map_err(|err| match err {
+                        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => repeated(name),
+                        err => ApiError::Database(err),
+                    })
+            })
+            .collect()
+    }
+}
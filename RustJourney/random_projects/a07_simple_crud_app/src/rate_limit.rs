@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{error, warn};
+use redis::PipelineCommands;
+use rocket::config::Config;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, status, Responder, Response};
+use rocket::{catch, Outcome, Request, State};
+use rocket_contrib::json::Json;
+
+use crate::auth::ApiKey;
+use crate::error::error_body;
+use crate::redis_pool::RedisPool;
+
+const DEFAULT_CAPACITY: f64 = 10.0;
+const DEFAULT_REFILL_PER_SEC: f64 = 1.0;
+const PRUNE_EVERY: Duration = Duration::from_secs(60);
+const DEFAULT_WINDOW_REQUESTS: i64 = 100;
+const DEFAULT_WINDOW_SECS: i64 = 60;
+
+/// Source of the current time, swappable so the limiter can be driven by hand.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    by_client: HashMap<String, Bucket>,
+    pruned: Instant,
+}
+
+/// Fixed-window counter per client IP in Redis, so every instance of the
+/// app shares one limit: at most `requests` per `window_secs`.
+pub struct RedisWindow {
+    redis: RedisPool,
+    requests: i64,
+    window_secs: u64,
+}
+
+impl RedisWindow {
+    /// Counts a request from `ip` and, past the limit, says how long until
+    /// the window rolls over. Errors if Redis cannot be reached.
+    fn check(&self, ip: Option<IpAddr>) -> redis::RedisResult<Result<(), Duration>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let window = now / self.window_secs;
+        let ip = ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+        let key = format!("rate:{}:{}", ip, window);
+        let (count,): (i64,) = self.redis.run(|connection| {
+            redis::pipe()
+                .atomic()
+                .incr(&key, 1)
+                .expire(&key, self.window_secs as usize).ignore()
+                .query(connection)
+        })?;
+        if count <= self.requests {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(Duration::from_secs((window + 1) * self.window_secs - now)))
+        }
+    }
+}
+
+/// Token bucket per client: each request takes a token, and tokens trickle
+/// back at `refill_per_sec` up to `capacity`. With a `RedisWindow` that
+/// limit applies instead, and the buckets only stand in while Redis is
+/// unreachable.
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    clock: Box<dyn Clock>,
+    buckets: Mutex<Buckets>,
+    redis: Option<RedisWindow>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: f64, refill_per_sec: f64, clock: Box<dyn Clock>) -> RateLimiter {
+        let now = clock.now();
+        RateLimiter {
+            capacity,
+            refill_per_sec,
+            clock,
+            buckets: Mutex::new(Buckets { by_client: HashMap::new(), pruned: now }),
+            redis: None,
+        }
+    }
+
+    /// Reads `rate_limit_capacity` and `rate_limit_refill_per_sec` from the
+    /// Rocket config and manages a limiter built from them. Given a Redis
+    /// URL, `rate_limit_window_requests` and `rate_limit_window_secs` set a
+    /// shared fixed window that is used in preference. A capacity below 1
+    /// could never let a request through, and a refill of 0 or less never
+    /// gives a token back, so either fails the launch.
+    pub fn fairing(redis_url: Option<&str>) -> AdHoc {
+        let redis = redis_url.and_then(|url| RedisPool::open(url, "Redis rate limit disabled"));
+        AdHoc::on_attach("Rate limiter", move |rocket| {
+            let config = rocket.config();
+            let capacity = config_number(config, "rate_limit_capacity")
+                .unwrap_or(DEFAULT_CAPACITY);
+            let refill_per_sec = config_number(config, "rate_limit_refill_per_sec")
+                .unwrap_or(DEFAULT_REFILL_PER_SEC);
+            if capacity.is_nan() || capacity < 1.0 {
+                error!("rate_limit_capacity must be at least 1, got {}", capacity);
+                return Err(rocket);
+            }
+            if refill_per_sec.is_nan() || refill_per_sec <= 0.0 {
+                error!("rate_limit_refill_per_sec must be above 0, got {}", refill_per_sec);
+                return Err(rocket);
+            }
+            let mut limiter = RateLimiter::new(capacity, refill_per_sec, Box::new(SystemClock));
+            limiter.redis = redis.map(|redis| RedisWindow {
+                redis,
+                requests: config.get_int("rate_limit_window_requests").unwrap_or(DEFAULT_WINDOW_REQUESTS),
+                window_secs: config.get_int("rate_limit_window_secs").unwrap_or(DEFAULT_WINDOW_SECS).max(1) as u64,
+            });
+            Ok(rocket.manage(limiter))
+        })
+    }
+
+    /// Charges a request from `ip` to the Redis window, or to the bucket for
+    /// `client` when there is no window or Redis fails.
+    fn check_request(&self, client: &str, ip: Option<IpAddr>) -> Result<(), Duration> {
+        if let Some(redis) = &self.redis {
+            match redis.check(ip) {
+                Ok(result) => return result,
+                Err(err) => warn!("Redis rate limit unavailable, limiting locally: {}", err),
+            }
+        }
+        self.check(client)
+    }
+
+    /// Takes a token for `client`, or says how long until one is available.
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        let now = self.clock.now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.duration_since(buckets.pruned) >= PRUNE_EVERY {
+            self.prune(&mut buckets, now);
+        }
+        let capacity = self.capacity;
+        let bucket = buckets.by_client
+            .entry(client.to_string())
+            .or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - bucket.tokens;
+            Err(Duration::from_secs_f64(missing / self.refill_per_sec))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity)
+    }
+
+    /// Drops buckets that have refilled completely, since a fresh bucket
+    /// would behave the same.
+    fn prune(&self, buckets: &mut Buckets, now: Instant) {
+        let capacity = self.capacity;
+        let by_client = &mut buckets.by_client;
+        let full: Vec<String> = by_client.iter()
+            .filter(|(_, bucket)| self.refilled(bucket, now) >= capacity)
+            .map(|(client, _)| client.clone())
+            .collect();
+        for client in full {
+            by_client.remove(&client);
+        }
+        buckets.pruned = now;
+    }
+}
+
+/// Reads a number from the extras, whether it was written as `2` or `2.0`.
+fn config_number(config: &Config, name: &str) -> Option<f64> {
+    config.get_float(name)
+        .or_else(|_| config.get_int(name).map(|value| value as f64))
+        .ok()
+}
+
+/// Seconds a limited client should wait, stashed for the 429 catcher.
+struct RetryAfter(u64);
+
+/// Request guard that counts the request against the client's limit. The
+/// Redis window goes by IP address. The local buckets give requests with
+/// the valid `X-API-Key` a bucket of their own and tell the rest apart by
+/// IP address, so a client cannot dodge its limit by making up keys.
+pub struct RateLimited;
+
+impl<'a, 'r> FromRequest<'a, 'r> for RateLimited {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<RateLimited, ()> {
+        let limiter = request.guard::<State<RateLimiter>>()?;
+        let ip = request.client_ip();
+        let client = if request.guard::<ApiKey>().is_success() {
+            "key".to_string()
+        } else {
+            ip.map(|ip| format!("ip:{}", ip)).unwrap_or_else(|| "unknown".to_string())
+        };
+        match limiter.check_request(&client, ip) {
+            Ok(()) => Outcome::Success(RateLimited),
+            Err(wait) => {
+                let seconds = wait.as_secs() + if wait.subsec_nanos() > 0 { 1 } else { 0 };
+                request.local_cache(|| RetryAfter(seconds));
+                Outcome::Failure((Status::TooManyRequests, ()))
+            },
+        }
+    }
+}
+
+pub struct TooManyRequests(u64);
+
+impl<'r> Responder<'r> for TooManyRequests {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let body = Json(error_body(request, Status::TooManyRequests, "too many requests"));
+        Response::build_from(status::Custom(Status::TooManyRequests, body).respond_to(request)?)
+            .raw_header("Retry-After", self.0.to_string())
+            .ok()
+    }
+}
+
+#[catch(429)]
+pub fn too_many_requests(request: &Request) -> TooManyRequests {
+    TooManyRequests(request.local_cache(|| RetryAfter(1)).0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// A clock that only moves when told to.
+    #[derive(Clone)]
+    struct ManualClock(Arc<Mutex<Instant>>);
+
+    impl ManualClock {
+        fn new() -> ManualClock {
+            ManualClock(Arc::new(Mutex::new(Instant::now())))
+        }
+
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn limiter(capacity: f64, refill_per_sec: f64) -> (RateLimiter, ManualClock) {
+        let clock = ManualClock::new();
+        (RateLimiter::new(capacity, refill_per_sec, Box::new(clock.clone())), clock)
+    }
+
+    #[test]
+    fn a_client_is_allowed_then_blocked_then_recovers() {
+        let (limiter, clock) = limiter(2.0, 1.0);
+        assert_eq!(limiter.check("a"), Ok(()));
+        assert_eq!(limiter.check("a"), Ok(()));
+        assert_eq!(limiter.check("a"), Err(Duration::from_secs(1)));
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(limiter.check("a"), Err(Duration::from_millis(500)));
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(limiter.check("a"), Ok(()));
+        assert!(limiter.check("a").is_err());
+    }
+
+    #[test]
+    fn each_client_has_a_bucket_of_its_own() {
+        let (limiter, _clock) = limiter(1.0, 1.0);
+        assert_eq!(limiter.check("a"), Ok(()));
+        assert!(limiter.check("a").is_err());
+        assert_eq!(limiter.check("b"), Ok(()));
+    }
+
+    #[test]
+    fn a_bucket_refills_only_up_to_its_capacity() {
+        let (limiter, clock) = limiter(3.0, 10.0);
+        clock.advance(Duration::from_secs(3600));
+        for _ in 0..3 {
+            assert_eq!(limiter.check("a"), Ok(()));
+        }
+        assert_eq!(limiter.check("a"), Err(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn full_buckets_are_pruned() {
+        let (limiter, clock) = limiter(2.0, 1.0);
+        limiter.check("idle").unwrap();
+        limiter.check("busy").unwrap();
+        clock.advance(PRUNE_EVERY - Duration::from_secs(1));
+        limiter.check("busy").unwrap();
+        limiter.check("busy").unwrap();
+        clock.advance(Duration::from_secs(1));
+        limiter.check("busy").unwrap();
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_client.keys().collect::<Vec<_>>(), vec!["busy"]);
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use redis::{Client, Connection, ErrorKind, RedisError, RedisResult};
+
+/// How long a caller waits for a connection, including opening one, before
+/// treating Redis as down. A host that drops packets costs this, not the
+/// operating system's connect timeout.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+/// How long a single command may take on an open connection.
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(250);
+/// After a failure, how long every call fails at once without trying Redis.
+const BACKOFF: Duration = Duration::from_secs(5);
+const MAX_SIZE: u32 = 8;
+
+/// A pooled connection, and whether a command on it failed on the way to
+/// or from Redis. Such a connection may still have a late reply coming, so
+/// it is dropped rather than handed to someone else.
+pub struct Pooled {
+    connection: Connection,
+    broken: bool,
+}
+
+/// Opens Redis connections for r2d2, each with read and write timeouts, and
+/// checks them with a PING before handing them out again.
+pub struct Manager(Client);
+
+impl r2d2::ManageConnection for Manager {
+    type Connection = Pooled;
+    type Error = RedisError;
+
+    fn connect(&self) -> RedisResult<Pooled> {
+        let connection = self.0.get_connection()?;
+        connection.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+        connection.set_write_timeout(Some(COMMAND_TIMEOUT))?;
+        Ok(Pooled { connection, broken: false })
+    }
+
+    fn is_valid(&self, pooled: &mut Pooled) -> RedisResult<()> {
+        redis::cmd("PING").query(&pooled.connection)
+    }
+
+    fn has_broken(&self, pooled: &mut Pooled) -> bool {
+        pooled.broken
+    }
+}
+
+/// A small pool of Redis connections that backs off after a failure: for
+/// `BACKOFF` afterwards every call fails without touching the network, so
+/// a Redis that is down costs a request at most one `CONNECT_TIMEOUT`
+/// every few seconds rather than one per command. Clones share the pool
+/// and the backoff.
+#[derive(Clone)]
+pub struct RedisPool {
+    pool: r2d2::Pool<Manager>,
+    down_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RedisPool {
+    /// A pool on `url`, or `None`, after a warning saying what goes without
+    /// it, when the URL is unusable. No connection is opened yet.
+    pub fn open(url: &str, disabled: &str) -> Option<RedisPool> {
+        match Client::open(url) {
+            Ok(client) => Some(RedisPool::new(client)),
+            Err(err) => {
+                warn!("{}, bad Redis URL: {}", disabled, err);
+                None
+            },
+        }
+    }
+
+    pub fn new(client: Client) -> RedisPool {
+        let pool = r2d2::Pool::builder()
+            .max_size(MAX_SIZE)
+            .min_idle(Some(0))
+            .connection_timeout(CONNECT_TIMEOUT)
+            // Callers warn about failures themselves, at their own pace.
+            .error_handler(Box::new(r2d2::NopErrorHandler))
+            .build_unchecked(Manager(client));
+        RedisPool { pool, down_until: Arc::new(Mutex::new(None)) }
+    }
+
+    /// Runs `command` on a pooled connection. Fails at once while backing
+    /// off, and starts backing off when the checkout or the command fails.
+    pub fn run<T, F>(&self, command: F) -> RedisResult<T>
+    where
+        F: FnOnce(&Connection) -> RedisResult<T>,
+    {
+        if let Some(until) = *self.down_until.lock().unwrap() {
+            if Instant::now() < until {
+                return Err(RedisError::from((ErrorKind::IoError, "backing off after a Redis failure")));
+            }
+        }
+        let result = match self.pool.get() {
+            Ok(mut pooled) => {
+                let result = command(&pooled.connection);
+                pooled.broken = result.as_ref().err().map_or(false, RedisError::is_io_error);
+                result
+            },
+            Err(err) => Err(RedisError::from((ErrorKind::IoError, "no Redis connection", err.to_string()))),
+        };
+        let mut down_until = self.down_until.lock().unwrap();
+        match &result {
+            // A reply Redis sent, such as a wrong type, says nothing about
+            // whether it is reachable.
+            Err(err) if err.is_io_error() => *down_until = Some(Instant::now() + BACKOFF),
+            _ => *down_until = None,
+        }
+        result
+    }
+}
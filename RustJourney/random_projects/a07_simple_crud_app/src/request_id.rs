@@ -0,0 +1,80 @@
+use std::time::Instant;
+
+use log::Level;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{self, FromRequest};
+use rocket::{Data, Outcome, Request, Response};
+use serde_json::json;
+use uuid::Uuid;
+
+const HEADER: &str = "X-Request-Id";
+/// Longer incoming ids are replaced rather than echoed into logs and headers.
+const MAX_LEN: usize = 128;
+
+/// Correlation id of the current request: the client's `X-Request-Id`, or a
+/// fresh UUID when it sent none.
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// The id of `request`, assigned on first use and cached for the rest of
+    /// the request.
+    pub fn of<'r>(request: &'r Request) -> &'r str {
+        &request.local_cache(|| {
+            let id = request.headers()
+                .get_one(HEADER)
+                .filter(|id| !id.is_empty() && id.len() <= MAX_LEN)
+                .map(str::to_string)
+                .unwrap_or_else(|| Uuid::new_v4().to_string());
+            RequestId(id)
+        }).0
+    }
+
+    /// Assigns every request its id up front and echoes it back on the
+    /// response, logging one event per request under that id: at info, or
+    /// at error for a 5xx.
+    pub fn fairing() -> RequestIdFairing {
+        RequestIdFairing
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for RequestId {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<RequestId, ()> {
+        Outcome::Success(RequestId(RequestId::of(request).to_string()))
+    }
+}
+
+pub struct RequestIdFairing;
+
+/// When the fairing first saw the request, for the time it took.
+struct Started(Instant);
+
+impl Fairing for RequestIdFairing {
+    fn info(&self) -> Info {
+        Info { name: "Request id", kind: Kind::Request | Kind::Response }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.local_cache(|| Started(Instant::now()));
+        RequestId::of(request);
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let id = RequestId::of(request);
+        let started = request.local_cache(|| Started(Instant::now()));
+        let status = response.status();
+        let level = if status.code >= 500 { Level::Error } else { Level::Info };
+        let fields = json!({
+            "request_id": id,
+            "method": request.method().as_str(),
+            "uri": request.uri().to_string(),
+            "status": status.code,
+            "duration_ms": started.0.elapsed().as_micros() as f64 / 1000.0,
+        });
+        let message = format!("[{}] {} {} -> {}", id, request.method(), request.uri(), status);
+        logging::event(level, module_path!(), &message, fields);
+        response.set_header(Header::new(HEADER, id.to_string()));
+    }
+}
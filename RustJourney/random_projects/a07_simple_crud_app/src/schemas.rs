@@ -5,5 +5,27 @@ table! {
         identity -> Varchar,
         hometown -> Varchar,
         age -> Int4,
+        deleted_at -> Nullable<Timestamptz>,
+        version -> Int4,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        avatar_filename -> Nullable<Varchar>,
+        avatar_content_type -> Nullable<Varchar>,
+        role -> Nullable<Text>,
     }
-}
\ No newline at end of file
+}
+
+table! {
+    hero_powers (id) {
+        id -> Int4,
+        hero_id -> Int4,
+        name -> Varchar,
+    }
+}
+
+joinable!(hero_powers -> heroes (hero_id));
+
+allow_tables_to_appear_in_same_query!(
+    hero_powers,
+    heroes,
+);
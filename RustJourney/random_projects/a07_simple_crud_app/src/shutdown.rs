@@ -0,0 +1,176 @@
+use std::io::{self, Read};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Method;
+use rocket::http::uri::Origin;
+use rocket::{get, routes, Data, Request, Response, Rocket, State};
+
+use crate::db::Pool;
+use crate::error::ApiError;
+
+const DEFAULT_GRACE_SECS: i64 = 30;
+/// How often the watcher looks for a signal, then for requests finishing.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Where requests arriving during a shutdown are sent to be refused.
+const SHUTTING_DOWN_PATH: &str = "/shutting-down";
+
+/// Set by the signal handler, which may do little more than store to an
+/// atomic; the watcher thread does the rest.
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    SIGNALLED.store(true, Ordering::SeqCst);
+}
+
+/// Whether a shutdown has begun, and how many requests are still being
+/// answered.
+#[derive(Default)]
+pub struct Drain {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+impl Drain {
+    fn finish(&self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Shuts the app down cleanly on SIGINT or SIGTERM. Rocket cannot stop
+/// listening, so from the signal on every new request is answered 503 with
+/// `Connection: close`, without reaching its handler. Once the requests
+/// already in flight have been answered, or `shutdown_grace_secs` (30 by
+/// default) have passed, the database pool's connections are closed and
+/// the process exits.
+pub struct Shutdown {
+    drain: Arc<Drain>,
+}
+
+impl Shutdown {
+    pub fn fairing() -> Shutdown {
+        Shutdown { drain: Arc::new(Drain::default()) }
+    }
+}
+
+/// Marks a request counted in `Drain::in_flight`, so its response hands the
+/// count on to its body.
+struct Counted(bool);
+
+/// A response body that counts its request answered when dropped, which
+/// Rocket does once the last byte is written, so a streamed body is waited
+/// for as well.
+struct Tracked<R> {
+    body: R,
+    drain: Arc<Drain>,
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+impl<R> Drop for Tracked<R> {
+    fn drop(&mut self) {
+        self.drain.finish();
+    }
+}
+
+impl Fairing for Shutdown {
+    fn info(&self) -> Info {
+        Info { name: "Graceful shutdown", kind: Kind::Attach | Kind::Launch | Kind::Request | Kind::Response }
+    }
+
+    fn on_attach(&self, rocket: Rocket) -> Result<Rocket, Rocket> {
+        Ok(rocket.manage(self.drain.clone()).mount("/", routes![shutting_down]))
+    }
+
+    fn on_launch(&self, rocket: &Rocket) {
+        let grace = rocket.config().get_int("shutdown_grace_secs").unwrap_or(DEFAULT_GRACE_SECS).max(0);
+        let grace = Duration::from_secs(grace as u64);
+        let pool = rocket.state::<Pool>().cloned();
+        let drain = self.drain.clone();
+        unsafe {
+            libc::signal(libc::SIGINT, on_signal as libc::sighandler_t);
+            libc::signal(libc::SIGTERM, on_signal as libc::sighandler_t);
+        }
+        thread::spawn(move || watch(&drain, pool, grace));
+    }
+
+    // Counting before looking at the flag means the watcher, which sets the
+    // flag before reading the count, cannot miss a request that got in.
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        self.drain.in_flight.fetch_add(1, Ordering::SeqCst);
+        if self.drain.draining.load(Ordering::SeqCst) {
+            self.drain.finish();
+            info!("Refusing {} {} while shutting down", request.method(), request.uri());
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(SHUTTING_DOWN_PATH).expect("a valid path"));
+        } else {
+            request.local_cache(|| Counted(true));
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if self.drain.draining.load(Ordering::SeqCst) {
+            response.set_raw_header("Connection", "close");
+        }
+        if !request.local_cache(|| Counted(false)).0 {
+            return;
+        }
+        match response.take_body() {
+            Some(body) => {
+                let drain = self.drain.clone();
+                response.set_raw_body(body.map(|body| Tracked { body, drain }));
+            },
+            None => self.drain.finish(),
+        }
+    }
+}
+
+/// What a request arriving during a shutdown is rewritten to. Any other
+/// time it is not found, like an unknown path.
+#[get("/shutting-down")]
+fn shutting_down(drain: State<Arc<Drain>>) -> Option<ApiError> {
+    if drain.draining.load(Ordering::SeqCst) {
+        Some(ApiError::ShuttingDown)
+    } else {
+        None
+    }
+}
+
+/// Waits for a signal, then drains and exits.
+fn watch(drain: &Drain, pool: Option<Pool>, grace: Duration) {
+    while !SIGNALLED.load(Ordering::SeqCst) {
+        thread::sleep(POLL_INTERVAL);
+    }
+    drain.draining.store(true, Ordering::SeqCst);
+    let waiting = drain.in_flight.load(Ordering::SeqCst);
+    info!("Shutting down: refusing new requests, waiting up to {}s for {} in flight", grace.as_secs(), waiting);
+    let deadline = Instant::now() + grace;
+    while drain.in_flight.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+    // Nothing joins the count once draining, so it only falls from here.
+    let unfinished = drain.in_flight.load(Ordering::SeqCst);
+    info!("Drained {} requests", waiting.saturating_sub(unfinished));
+    if unfinished > 0 {
+        warn!("Gave up on {} requests still in flight after {}s", unfinished, grace.as_secs());
+    }
+    if let Some(pool) = pool {
+        let state = pool.state();
+        let closed = pool.close();
+        info!("Closed {} database connections returned to the pool", closed);
+        let in_use = state.connections - state.idle_connections;
+        if in_use > 0 {
+            warn!("{} database connections were still checked out", in_use);
+        }
+    }
+    process::exit(0);
+}
@@ -0,0 +1,365 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::Utc;
+use log::error;
+use rocket::fairing::AdHoc;
+
+use crate::db::{self, Pool};
+use crate::error::ApiError;
+use crate::hero::{Hero, HeroFilter, NewHero, Page, SortColumn, SortKey};
+use crate::power::{self, HeroWithPowers, Power};
+
+/// Heroes a batch at a time, for bodies streamed as the client reads them.
+pub type Batches = Box<dyn Iterator<Item = Result<Vec<Hero>, ApiError>>>;
+
+/// Where the hero routes keep their heroes, managed as a
+/// `Box<dyn HeroStore>` so handlers work the same against either backend.
+/// Every method reports failures as the `ApiError` the handler answers with.
+pub trait HeroStore: Send + Sync {
+    /// Inserts a hero at version 1, failing with `NameTaken` when another
+    /// hero, deleted or not, already has its name.
+    fn create(&self, hero: &NewHero) -> Result<Hero, ApiError>;
+
+    /// Creates a hero as `create` does together with its powers, all or
+    /// nothing: a power named twice fails the whole creation with
+    /// `BadRequest` and leaves no hero behind.
+    fn create_with_powers(&self, hero: &NewHero, powers: &[String]) -> Result<HeroWithPowers, ApiError>;
+
+    /// The live hero `id`, if there is one.
+    fn find(&self, id: i32) -> Result<Option<Hero>, ApiError>;
+
+    /// One page of the heroes `filter` selects, ordered by each of `sort` in
+    /// turn, then by id; see `Hero::read`. A page with `after` is in id
+    /// order alone; see `Hero::read_after`.
+    fn list(&self, filter: &HeroFilter, sort: &[SortKey], page: Page) -> Result<Vec<Hero>, ApiError>;
+
+    /// How many heroes `list` would return across all pages.
+    fn count(&self, filter: &HeroFilter) -> Result<i64, ApiError>;
+
+    /// Every hero `filter` selects, in id order, `batch_size` at a time; see
+    /// `Hero::read_batched`.
+    fn batches(&self, filter: HeroFilter, batch_size: i64) -> Result<Batches, ApiError>;
+
+    /// Replaces the live hero `id` if it is still at `version`. Returns
+    /// `None` when it is missing or has moved on.
+    fn update(&self, id: i32, version: i32, hero: &NewHero) -> Result<Option<Hero>, ApiError>;
+
+    /// Soft-deletes the live hero `id` and forgets its avatar, returning the
+    /// hero as it was so the caller can remove the avatar file.
+    fn delete(&self, id: i32) -> Result<Hero, ApiError>;
+
+    /// Brings back the soft-deleted hero `id`, failing with `NotDeleted` when
+    /// it is live.
+    fn restore(&self, id: i32) -> Result<Hero, ApiError>;
+
+    /// Records the stored avatar of the live hero `id`. Returns `None` when
+    /// there is no such hero.
+    fn set_avatar(&self, id: i32, filename: &str, content_type: &str) -> Result<Option<Hero>, ApiError>;
+
+    /// Removes every hero, deleted or not, with their powers, and adds
+    /// `count` seed heroes with ids from 1; see `Hero::seed`. Returns the
+    /// heroes removed, so the caller can drop their avatars, and those added.
+    fn reset(&self, count: usize) -> Result<(Vec<Hero>, Vec<Hero>), ApiError>;
+
+    /// The pool a `RequestTransaction` opens its transaction on, for stores
+    /// kept in the database; `None` for those with nothing to roll back.
+    fn pool(&self) -> Option<&Pool>;
+}
+
+/// Manages the store named by `hero_store` in the Rocket config: `postgres`
+/// (the default) on the managed pool, or `memory` for a store that lives and
+/// dies with the process. Attach after `db::fairing`. The database check at
+/// launch still runs with `memory`, so set `lazy_db` to run without one.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_attach("Hero store", |rocket| {
+        let store: Box<dyn HeroStore> = match rocket.config().get_str("hero_store").unwrap_or("postgres") {
+            "postgres" => match rocket.state::<Pool>() {
+                Some(pool) => Box::new(PgStore { pool: pool.clone() }),
+                None => {
+                    error!("hero_store = \"postgres\" needs the database pool attached first");
+                    return Err(rocket);
+                },
+            },
+            "memory" => Box::new(InMemoryStore::default()),
+            other => {
+                error!("Unknown hero_store {:?}, expected \"postgres\" or \"memory\"", other);
+                return Err(rocket);
+            },
+        };
+        Ok(rocket.manage(store))
+    })
+}
+
+/// The Diesel-backed store, checking a connection out of the pool per call,
+/// or using the request transaction's when one is open.
+pub struct PgStore {
+    pool: Pool,
+}
+
+impl PgStore {
+    fn connection(&self) -> Result<db::Checkout, ApiError> {
+        self.pool.checkout().map_err(|_| ApiError::Unavailable)
+    }
+}
+
+impl HeroStore for PgStore {
+    fn create(&self, hero: &NewHero) -> Result<Hero, ApiError> {
+        Hero::create(hero, &*self.connection()?).map_err(|err| ApiError::from_write(err, &hero.name))
+    }
+
+    fn create_with_powers(&self, hero: &NewHero, powers: &[String]) -> Result<HeroWithPowers, ApiError> {
+        let connection = self.connection()?;
+        db::with_transaction(&connection, |tx| {
+            let created = Hero::create(hero, tx).map_err(|err| ApiError::from_write(err, &hero.name))?;
+            let powers = Power::create_all(created.id, powers, tx)?;
+            Ok(HeroWithPowers { hero: created, powers })
+        })
+    }
+
+    fn find(&self, id: i32) -> Result<Option<Hero>, ApiError> {
+        Ok(Hero::find(id, &*self.connection()?))
+    }
+
+    fn list(&self, filter: &HeroFilter, sort: &[SortKey], page: Page) -> Result<Vec<Hero>, ApiError> {
+        let connection = self.connection()?;
+        Ok(match page.after {
+            Some(last_id) => Hero::read_after(filter, last_id, page.limit, &*connection),
+            None => Hero::read(filter, sort, page, &*connection),
+        })
+    }
+
+    fn count(&self, filter: &HeroFilter) -> Result<i64, ApiError> {
+        Ok(Hero::count(filter, &*self.connection()?))
+    }
+
+    /// Holds one connection until the last batch is read.
+    fn batches(&self, filter: HeroFilter, batch_size: i64) -> Result<Batches, ApiError> {
+        let batches = Hero::read_batched(self.connection()?, filter, batch_size);
+        Ok(Box::new(batches.map(|batch| batch.map_err(ApiError::Database))))
+    }
+
+    fn update(&self, id: i32, version: i32, hero: &NewHero) -> Result<Option<Hero>, ApiError> {
+        Hero::update(id, version, hero, &*self.connection()?).map_err(|err| ApiError::from_write(err, &hero.name))
+    }
+
+    fn delete(&self, id: i32) -> Result<Hero, ApiError> {
+        let connection = self.connection()?;
+        db::with_transaction(&connection, |tx| {
+            let hero = match Hero::find_for_update(id, tx)? {
+                Some(ref hero) if hero.deleted_at.is_some() => return Err(ApiError::NotFound),
+                Some(hero) => hero,
+                None => return Err(ApiError::NotFound),
+            };
+            if !Hero::delete(id, tx)? {
+                return Err(ApiError::NotFound);
+            }
+            Ok(hero)
+        })
+    }
+
+    fn restore(&self, id: i32) -> Result<Hero, ApiError> {
+        let connection = self.connection()?;
+        db::with_transaction(&connection, |tx| {
+            match Hero::find_for_update(id, tx)? {
+                None => Err(ApiError::NotFound),
+                Some(ref hero) if hero.deleted_at.is_none() => Err(ApiError::NotDeleted),
+                Some(_) => Hero::restore(id, tx)?.ok_or(ApiError::NotDeleted),
+            }
+        })
+    }
+
+    fn set_avatar(&self, id: i32, filename: &str, content_type: &str) -> Result<Option<Hero>, ApiError> {
+        Ok(Hero::set_avatar(id, filename, content_type, &*self.connection()?)?)
+    }
+
+    fn reset(&self, count: usize) -> Result<(Vec<Hero>, Vec<Hero>), ApiError> {
+        let connection = self.connection()?;
+        db::with_transaction(&connection, |tx| Ok((Hero::truncate(tx)?, Hero::seed(tx, count)?)))
+    }
+
+    fn pool(&self) -> Option<&Pool> {
+        Some(&self.pool)
+    }
+}
+
+/// A store in a map guarded by one lock, keeping the database's rules:
+/// unique names across deleted heroes too, versions bumped on update and
+/// soft deletes that forget the avatar.
+#[derive(Default)]
+pub struct InMemoryStore {
+    heroes: RwLock<HashMap<i32, Hero>>,
+    powers: RwLock<Vec<Power>>,
+}
+
+impl InMemoryStore {
+    fn name_taken(heroes: &HashMap<i32, Hero>, name: &str, except: Option<i32>) -> bool {
+        heroes.values().any(|hero| hero.name == name && Some(hero.id) != except)
+    }
+
+    /// `hero` as stored under `id` at version 1, just now.
+    fn stored(id: i32, hero: &NewHero) -> Hero {
+        let now = Utc::now();
+        Hero {
+            id,
+            name: hero.name.clone(),
+            identity: hero.identity.clone(),
+            hometown: hero.hometown.clone(),
+            age: hero.age,
+            deleted_at: None,
+            version: 1,
+            created_at: now,
+            updated_at: now,
+            avatar_filename: None,
+            avatar_content_type: None,
+            role: hero.role,
+        }
+    }
+}
+
+fn compare(a: &Hero, b: &Hero, sort: &[SortKey]) -> Ordering {
+    sort.iter()
+        .map(|key| {
+            let ordering = match key.column {
+                SortColumn::Id => a.id.cmp(&b.id),
+                SortColumn::Name => a.name.cmp(&b.name),
+                SortColumn::Age => a.age.cmp(&b.age),
+                SortColumn::CreatedAt => a.created_at.cmp(&b.created_at),
+                SortColumn::UpdatedAt => a.updated_at.cmp(&b.updated_at),
+            };
+            if key.descending { ordering.reverse() } else { ordering }
+        })
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or_else(|| a.id.cmp(&b.id))
+}
+
+impl HeroStore for InMemoryStore {
+    fn create(&self, hero: &NewHero) -> Result<Hero, ApiError> {
+        let mut heroes = self.heroes.write().unwrap();
+        if InMemoryStore::name_taken(&heroes, &hero.name, None) {
+            return Err(ApiError::NameTaken(hero.name.clone()));
+        }
+        let created = InMemoryStore::stored(heroes.keys().max().map_or(1, |id| id + 1), hero);
+        heroes.insert(created.id, created.clone());
+        Ok(created)
+    }
+
+    /// Checks the powers before creating anything, so there is nothing to
+    /// undo when one is named twice.
+    fn create_with_powers(&self, hero: &NewHero, powers: &[String]) -> Result<HeroWithPowers, ApiError> {
+        if let Some(err) = power::duplicate(powers) {
+            return Err(err);
+        }
+        let created = self.create(hero)?;
+        let mut stored = self.powers.write().unwrap();
+        let first_id = stored.len() as i32 + 1;
+        let powers: Vec<Power> = powers.iter()
+            .zip(first_id..)
+            .map(|(name, id)| Power { id, hero_id: created.id, name: name.clone() })
+            .collect();
+        stored.extend(powers.iter().cloned());
+        Ok(HeroWithPowers { hero: created, powers })
+    }
+
+    fn find(&self, id: i32) -> Result<Option<Hero>, ApiError> {
+        let heroes = self.heroes.read().unwrap();
+        Ok(heroes.get(&id).filter(|hero| hero.deleted_at.is_none()).cloned())
+    }
+
+    fn list(&self, filter: &HeroFilter, sort: &[SortKey], page: Page) -> Result<Vec<Hero>, ApiError> {
+        let heroes = self.heroes.read().unwrap();
+        let mut listed: Vec<&Hero> = heroes.values()
+            .filter(|hero| filter.matches(hero) && page.after.map_or(true, |last_id| hero.id > last_id))
+            .collect();
+        listed.sort_by(|a, b| compare(a, b, sort));
+        let limit = page.limit.map_or(usize::max_value(), |limit| limit as usize);
+        Ok(listed.into_iter().skip(page.offset as usize).take(limit).cloned().collect())
+    }
+
+    fn count(&self, filter: &HeroFilter) -> Result<i64, ApiError> {
+        let heroes = self.heroes.read().unwrap();
+        Ok(heroes.values().filter(|hero| filter.matches(hero)).count() as i64)
+    }
+
+    /// Copies out every selected hero at once, as the store already holds
+    /// them all in memory anyway.
+    fn batches(&self, filter: HeroFilter, batch_size: i64) -> Result<Batches, ApiError> {
+        let heroes = self.heroes.read().unwrap();
+        let mut selected: Vec<Hero> = heroes.values().filter(|hero| filter.matches(hero)).cloned().collect();
+        selected.sort_by_key(|hero| hero.id);
+        let batches: Vec<Vec<Hero>> = selected.chunks(batch_size.max(1) as usize).map(<[Hero]>::to_vec).collect();
+        Ok(Box::new(batches.into_iter().map(Ok)))
+    }
+
+    fn update(&self, id: i32, version: i32, hero: &NewHero) -> Result<Option<Hero>, ApiError> {
+        let mut heroes = self.heroes.write().unwrap();
+        match heroes.get(&id) {
+            Some(current) if current.deleted_at.is_none() && current.version == version => {},
+            _ => return Ok(None),
+        }
+        if InMemoryStore::name_taken(&heroes, &hero.name, Some(id)) {
+            return Err(ApiError::NameTaken(hero.name.clone()));
+        }
+        let current = heroes.get_mut(&id).expect("checked above");
+        current.name = hero.name.clone();
+        current.identity = hero.identity.clone();
+        current.hometown = hero.hometown.clone();
+        current.age = hero.age;
+        current.role = hero.role;
+        current.version += 1;
+        current.updated_at = Utc::now();
+        Ok(Some(current.clone()))
+    }
+
+    fn delete(&self, id: i32) -> Result<Hero, ApiError> {
+        let mut heroes = self.heroes.write().unwrap();
+        let current = match heroes.get_mut(&id) {
+            Some(current) if current.deleted_at.is_none() => current,
+            _ => return Err(ApiError::NotFound),
+        };
+        let deleted = current.clone();
+        current.deleted_at = Some(Utc::now());
+        current.avatar_filename = None;
+        current.avatar_content_type = None;
+        Ok(deleted)
+    }
+
+    fn restore(&self, id: i32) -> Result<Hero, ApiError> {
+        let mut heroes = self.heroes.write().unwrap();
+        match heroes.get_mut(&id) {
+            None => Err(ApiError::NotFound),
+            Some(current) if current.deleted_at.is_none() => Err(ApiError::NotDeleted),
+            Some(current) => {
+                current.deleted_at = None;
+                Ok(current.clone())
+            },
+        }
+    }
+
+    fn set_avatar(&self, id: i32, filename: &str, content_type: &str) -> Result<Option<Hero>, ApiError> {
+        let mut heroes = self.heroes.write().unwrap();
+        Ok(heroes.get_mut(&id).filter(|hero| hero.deleted_at.is_none()).map(|hero| {
+            hero.avatar_filename = Some(filename.to_string());
+            hero.avatar_content_type = Some(content_type.to_string());
+            hero.clone()
+        }))
+    }
+
+    fn reset(&self, count: usize) -> Result<(Vec<Hero>, Vec<Hero>), ApiError> {
+        let mut heroes = self.heroes.write().unwrap();
+        let mut removed: Vec<Hero> = heroes.drain().map(|(_, hero)| hero).collect();
+        removed.sort_by_key(|hero| hero.id);
+        self.powers.write().unwrap().clear();
+        let seeded: Vec<Hero> = Hero::seed_heroes(count).iter()
+            .zip(1..)
+            .map(|(hero, id)| InMemoryStore::stored(id, hero))
+            .collect();
+        heroes.extend(seeded.iter().map(|hero| (hero.id, hero.clone())));
+        Ok((removed, seeded))
+    }
+
+    fn pool(&self) -> Option<&Pool> {
+        None
+    }
+}
@@ -0,0 +1,76 @@
+//! The dev-only `POST /admin/reset`, present only when `dev_tools` is on.
+
+use rocket::config::Value;
+use rocket::http::Status;
+use rocket::local::Client;
+use serde_json::Value as JsonValue;
+
+use super::{api_key, create, each_store, hero, json_body, memory_client};
+
+fn dev_tools() -> [(&'static str, Value); 1] {
+    [("dev_tools", Value::from(true))]
+}
+
+fn names(heroes: &JsonValue) -> Vec<&str> {
+    heroes.as_array().unwrap().iter().map(|hero| hero["name"].as_str().unwrap()).collect()
+}
+
+fn reset(client: &Client, path: &str) -> JsonValue {
+    let mut response = client.post(path.to_string()).header(api_key()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    json_body(&mut response)
+}
+
+#[test]
+fn a_reset_replaces_every_hero_with_the_seed_set() {
+    each_store(&dev_tools(), |client| {
+        create(client, &hero("Bruce"));
+        create(client, &hero("Clark"));
+
+        let seeded = reset(client, "/admin/reset");
+        assert_eq!(names(&seeded), vec!["Reinhardt", "Mercy", "Tracer", "Winston", "Lucio", "Soldier: 76"]);
+        assert_eq!(seeded[0]["id"], 1);
+        assert_eq!(seeded[0]["role"], "Tank");
+
+        let mut response = client.get("/api/v1/heroes").dispatch();
+        assert_eq!(json_body(&mut response), seeded);
+        let again = reset(client, "/admin/reset");
+        assert_eq!(names(&again), names(&seeded));
+        assert_eq!(again[5]["id"], 6);
+    });
+}
+
+#[test]
+fn a_reset_seeds_as_many_heroes_as_asked() {
+    each_store(&dev_tools(), |client| {
+        assert_eq!(names(&reset(client, "/admin/reset?count=2")), vec!["Reinhardt", "Mercy"]);
+        let seeded = reset(client, "/admin/reset?count=8");
+        assert_eq!(&names(&seeded)[6..], &["Reinhardt 2", "Mercy 2"]);
+
+        let response = client.post("/admin/reset?count=1001").header(api_key()).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    });
+}
+
+#[test]
+fn a_reset_needs_the_api_key() {
+    let client = memory_client(&dev_tools());
+    create(&client, &hero("Bruce"));
+    assert_eq!(client.post("/admin/reset").dispatch().status(), Status::Unauthorized);
+    let mut response = client.get("/api/v1/heroes").dispatch();
+    assert_eq!(names(&json_body(&mut response)), vec!["Bruce"]);
+}
+
+#[test]
+fn without_dev_tools_there_is_no_reset_route() {
+    for extras in &[vec![], vec![("dev_tools", Value::from(false))]] {
+        let client = memory_client(extras);
+        create(&client, &hero("Bruce"));
+        let mut response = client.post("/admin/reset").header(api_key()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(json_body(&mut response)["error"]["message"], "no such resource");
+
+        let mut response = client.get("/api/v1/heroes").dispatch();
+        assert_eq!(json_body(&mut response).as_array().map(Vec::len), Some(1));
+    }
+}
@@ -0,0 +1,79 @@
+//! Every route that changes heroes wants the API key; reading them does not.
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::{Client, LocalResponse};
+use serde_json::json;
+
+use super::{api_key, create, each_store, hero, json_body, API_KEY};
+
+/// Sends each mutating route a request with `key`, if any, as `X-API-Key`.
+fn mutations<'c>(client: &'c Client, key: Option<&str>) -> Vec<(&'static str, LocalResponse<'c>)> {
+    let mut body = hero("Clark");
+    body["version"] = json!(1);
+    let requests = vec![
+        ("create", client.post("/api/v1/heroes").header(ContentType::JSON).body(hero("Diana").to_string())),
+        ("update", client.put("/api/v1/heroes/1").header(ContentType::JSON).body(body.to_string())),
+        ("avatar", client.put("/api/v1/heroes/1/avatar").header(ContentType::PNG).body("png")),
+        ("delete", client.delete("/api/v1/heroes/1")),
+        ("restore", client.post("/api/v1/heroes/1/restore")),
+    ];
+    requests.into_iter()
+        .map(|(route, request)| match key {
+            Some(key) => (route, request.header(Header::new("X-API-Key", key.to_string())).dispatch()),
+            None => (route, request.dispatch()),
+        })
+        .collect()
+}
+
+#[test]
+fn a_missing_key_is_refused() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        for (route, response) in mutations(client, None) {
+            assert_eq!(response.status(), Status::Unauthorized, "{} without a key", route);
+        }
+    });
+}
+
+#[test]
+fn a_wrong_key_is_refused() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        for key in &["wrong-key", "test-ke", "test-key ", ""] {
+            for (route, response) in mutations(client, Some(key)) {
+                assert_eq!(response.status(), Status::Unauthorized, "{} with key {:?}", route, key);
+            }
+        }
+        let mut response = client.get("/api/v1/heroes/1").dispatch();
+        assert_eq!(json_body(&mut response)["name"], "Bruce");
+    });
+}
+
+#[test]
+fn the_right_key_is_let_through() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let statuses: Vec<_> = mutations(client, Some(API_KEY))
+            .into_iter()
+            .map(|(route, response)| (route, response.status()))
+            .collect();
+        assert_eq!(statuses, vec![
+            ("create", Status::Created),
+            ("update", Status::Ok),
+            ("avatar", Status::Ok),
+            ("delete", Status::Ok),
+            ("restore", Status::Ok),
+        ]);
+    });
+}
+
+#[test]
+fn reads_need_no_key() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        for path in &["/api/v1/heroes", "/api/v1/heroes/1", "/api/v1/heroes/count", "/heroes", "/hero/1"] {
+            assert_eq!(client.get(*path).dispatch().status(), Status::Ok, "GET {}", path);
+        }
+        assert_eq!(client.get("/api/v1/heroes").header(api_key()).dispatch().status(), Status::Ok);
+    });
+}
@@ -0,0 +1,100 @@
+//! Avatars go to a directory of the test's own and come back byte for byte,
+//! and go again with their hero.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use rocket::config::Value;
+use rocket::http::{ContentType, Status};
+use rocket::local::{Client, LocalResponse};
+
+use super::{api_key, create, each_store, hero, json_body};
+
+const PIXEL: &[u8] = include_bytes!("fixtures/pixel.png");
+
+/// An avatar directory for one test, removed with whatever is left in it.
+pub struct AvatarDir(pub PathBuf);
+
+impl AvatarDir {
+    pub fn new(test: &str) -> AvatarDir {
+        let dir = env::temp_dir().join(format!("a07-avatars-{}-{}", test, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        AvatarDir(dir)
+    }
+
+    pub fn extras(&self) -> [(&'static str, Value); 1] {
+        [("avatar_dir", Value::from(self.0.to_str().unwrap()))]
+    }
+}
+
+impl Drop for AvatarDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn upload<'c>(client: &'c Client, id: i32, content_type: ContentType, body: &[u8]) -> LocalResponse<'c> {
+    client.put(format!("/api/v1/heroes/{}/avatar", id))
+        .header(content_type)
+        .header(api_key())
+        .body(body)
+        .dispatch()
+}
+
+#[test]
+fn an_uploaded_png_comes_back_as_it_went() {
+    let dir = AvatarDir::new("round_trip");
+    each_store(&dir.extras(), |client| {
+        create(client, &hero("Bruce"));
+        let mut response = upload(client, 1, ContentType::PNG, PIXEL);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(json_body(&mut response)["avatar_content_type"], "image/png");
+        assert_eq!(fs::read(dir.0.join("1.png")).unwrap(), PIXEL);
+
+        let mut response = client.get("/api/v1/heroes/1/avatar").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert_eq!(response.body_bytes().unwrap(), PIXEL);
+
+    });
+}
+
+#[test]
+fn a_replaced_avatar_of_another_type_leaves_no_file_behind() {
+    let dir = AvatarDir::new("replace");
+    each_store(&dir.extras(), |client| {
+        create(client, &hero("Bruce"));
+        assert_eq!(upload(client, 1, ContentType::PNG, PIXEL).status(), Status::Ok);
+        assert_eq!(upload(client, 1, ContentType::JPEG, b"\xff\xd8\xff\xd9").status(), Status::Ok);
+        assert!(!dir.0.join("1.png").exists());
+        let response = client.get("/api/v1/heroes/1/avatar").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::JPEG));
+    });
+}
+
+#[test]
+fn deleting_a_hero_removes_its_avatar() {
+    let dir = AvatarDir::new("delete");
+    each_store(&dir.extras(), |client| {
+        create(client, &hero("Bruce"));
+        upload(client, 1, ContentType::PNG, PIXEL);
+        assert_eq!(client.delete("/api/v1/heroes/1").header(api_key()).dispatch().status(), Status::Ok);
+        assert!(!dir.0.join("1.png").exists());
+        assert_eq!(client.get("/api/v1/heroes/1/avatar").dispatch().status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn uploads_are_refused_for_missing_heroes_other_types_and_past_the_limit() {
+    let dir = AvatarDir::new("refused");
+    each_store(&dir.extras(), |client| {
+        assert_eq!(upload(client, 1, ContentType::PNG, PIXEL).status(), Status::NotFound);
+        create(client, &hero("Bruce"));
+        assert_eq!(upload(client, 1, ContentType::GIF, PIXEL).status(), Status::UnsupportedMediaType);
+        let too_large = vec![0; 2 * 1024 * 1024 + 1];
+        assert_eq!(upload(client, 1, ContentType::PNG, &too_large).status(), Status::PayloadTooLarge);
+        assert_eq!(client.get("/api/v1/heroes/1/avatar").dispatch().status(), Status::NotFound);
+    });
+}
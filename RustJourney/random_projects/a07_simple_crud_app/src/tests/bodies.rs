@@ -0,0 +1,92 @@
+//! Bodies that cannot be read as the hero they should be, answered with
+//! what serde had to say about them.
+
+use rocket::config::Limits;
+use rocket::http::{ContentType, Status};
+use serde_json::{json, Value as JsonValue};
+
+use super::{api_key, create, each_store, hero, json_body, memory_client_with_limits, send};
+
+#[test]
+fn a_field_of_the_wrong_type_is_named() {
+    each_store(&[], |client| {
+        let mut body = hero("Bruce");
+        body["name"] = json!(5);
+        let mut response = send(client, "POST", "/api/v1/heroes", &body);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let error = json_body(&mut response)["error"].clone();
+        assert_eq!(error["code"], 422);
+        assert_eq!(error["message"], "invalid JSON body");
+        assert_eq!(error["path"], "name");
+        assert_eq!(error["line"], 1);
+        assert!(error["column"].as_u64().unwrap() > 0);
+        assert!(error["detail"].as_str().unwrap().starts_with("invalid type: integer `5`, expected a string"), "{}", error);
+
+        let created = create(client, &hero("Bruce"));
+        let mut body = hero("Bruce");
+        body["age"] = json!("thirty");
+        body["version"] = created["version"].clone();
+        let mut response = send(client, "PUT", "/api/v1/heroes/1", &body);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert_eq!(json_body(&mut response)["error"]["path"], "age");
+    });
+}
+
+#[test]
+fn a_nested_field_is_named_by_its_path() {
+    each_store(&[], |client| {
+        let mut body = hero("Bruce");
+        body["powers"] = json!([{ "name": "Detective" }, { "name": false }]);
+        let mut response = send(client, "POST", "/api/v1/heroes/full", &body);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert_eq!(json_body(&mut response)["error"]["path"], "powers[1].name");
+    });
+}
+
+#[test]
+fn a_missing_field_is_reported() {
+    each_store(&[], |client| {
+        let mut body = hero("Bruce");
+        body.as_object_mut().unwrap().remove("age");
+        let mut response = send(client, "POST", "/api/v1/heroes", &body);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let error = json_body(&mut response)["error"].clone();
+        assert!(error["detail"].as_str().unwrap().starts_with("missing field `age`"), "{}", error);
+        assert_eq!(client.get("/api/v1/heroes/1").dispatch().status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn truncated_json_is_unprocessable_saying_where_it_stopped() {
+    each_store(&[], |client| {
+        let mut response = client.post("/api/v1/heroes")
+            .header(ContentType::JSON)
+            .header(api_key())
+            .body("{\n  \"name\": \"Bruce\",\n  \"age\": 3")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let error = json_body(&mut response)["error"].clone();
+        assert_eq!(error["code"], 422);
+        assert_eq!(error["message"], "invalid JSON body");
+        assert_eq!(error["line"], 3);
+        assert!(error["detail"].as_str().unwrap().starts_with("EOF while parsing"), "{}", error);
+    });
+}
+
+#[test]
+fn a_body_past_the_configured_limit_is_too_large() {
+    let client = memory_client_with_limits(Limits::new().limit("json", 128));
+    let mut body = hero("Bruce");
+    assert_eq!(send(&client, "POST", "/api/v1/heroes", &body).status(), Status::Created);
+
+    body["name"] = JsonValue::from("B".repeat(128));
+    let mut response = send(&client, "POST", "/api/v1/heroes", &body);
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    let error = json_body(&mut response)["error"].clone();
+    assert_eq!(error["code"], 413);
+    assert_eq!(error["message"], "request body too large");
+    assert_eq!(error["detail"], "body exceeds 128 bytes");
+
+    let mut response = client.get("/api/v1/heroes").dispatch();
+    assert_eq!(json_body(&mut response).as_array().unwrap().len(), 1);
+}
@@ -0,0 +1,115 @@
+//! With Redis configured, reads are served from the cache until a write
+//! invalidates what it changed, and without a Redis that answers they go
+//! to the store as if there were no cache.
+
+use std::time::{Duration, Instant};
+
+use redis::Commands;
+use rocket::http::Status;
+use rocket::local::Client;
+use serde_json::{json, Value as JsonValue};
+
+use super::{api_key, create, hero, json_body, memory_client_on_redis, on_redis, send};
+use crate::hero::NewHero;
+use crate::store::HeroStore;
+
+fn list(client: &Client) -> JsonValue {
+    json_body(&mut client.get("/api/v1/heroes").dispatch())
+}
+
+/// Adds a hero straight to the store, behind the cache's back.
+fn create_uncached(client: &Client, name: &str) {
+    let store = client.rocket().state::<Box<dyn HeroStore>>().unwrap();
+    let hero = NewHero {
+        name: name.to_string(),
+        identity: "Someone".to_string(),
+        hometown: "Themyscira".to_string(),
+        age: 30,
+        role: None,
+    };
+    store.create(&hero).unwrap();
+}
+
+fn count(list: &JsonValue) -> usize {
+    list.as_array().unwrap().len()
+}
+
+#[test]
+fn a_list_is_stored_on_a_miss_and_served_on_a_hit() {
+    on_redis(&[], |client, redis| {
+        create(client, &hero("Bruce"));
+        let missed = list(client);
+        assert_eq!(count(&missed), 1);
+        let cached: Vec<String> = redis.smembers("heroes:list:keys").unwrap();
+        assert_eq!(cached.len(), 1);
+        assert!(redis.exists::<_, bool>(&cached[0]).unwrap());
+
+        create_uncached(client, "Diana");
+        assert_eq!(list(client), missed);
+    });
+}
+
+#[test]
+fn a_post_invalidates_the_cached_lists() {
+    on_redis(&[], |client, redis| {
+        create(client, &hero("Bruce"));
+        list(client);
+        create_uncached(client, "Diana");
+        let mut filtered = client.get("/api/v1/heroes?q=Bruce").dispatch();
+        assert_eq!(count(&json_body(&mut filtered)), 1);
+        assert_eq!(redis.smembers::<_, Vec<String>>("heroes:list:keys").unwrap().len(), 2);
+
+        create(client, &hero("Clark"));
+        assert!(redis.smembers::<_, Vec<String>>("heroes:list:keys").unwrap().is_empty());
+        let names: Vec<_> = list(client).as_array().unwrap().iter().map(|hero| hero["name"].clone()).collect();
+        assert_eq!(names, vec!["Bruce", "Diana", "Clark"]);
+    });
+}
+
+#[test]
+fn a_cached_hero_is_dropped_when_it_changes() {
+    on_redis(&[], |client, redis| {
+        create(client, &hero("Bruce"));
+        json_body(&mut client.get("/api/v1/heroes/1").dispatch());
+        let cached: String = redis.get("hero:1").unwrap();
+        assert_eq!(serde_json::from_str::<JsonValue>(&cached).unwrap()["name"], "Bruce");
+
+        let mut body = hero("Bruce");
+        body["hometown"] = json!("Bludhaven");
+        body["version"] = json!(1);
+        send(client, "PUT", "/api/v1/heroes/1", &body);
+        assert!(!redis.exists::<_, bool>("hero:1").unwrap());
+        assert_eq!(json_body(&mut client.get("/api/v1/heroes/1").dispatch())["hometown"], "Bludhaven");
+
+        client.delete("/api/v1/heroes/1").header(api_key()).dispatch();
+        assert!(!redis.exists::<_, bool>("hero:1").unwrap());
+        assert_eq!(client.get("/api/v1/heroes/1").dispatch().status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn cache_enabled_false_leaves_redis_alone() {
+    on_redis(&[("cache_enabled", false.into())], |client, redis| {
+        create(client, &hero("Bruce"));
+        list(client);
+        json_body(&mut client.get("/api/v1/heroes/1").dispatch());
+        assert!(redis.keys::<_, Vec<String>>("hero*").unwrap().is_empty());
+    });
+}
+
+#[test]
+fn reads_fall_through_to_the_store_while_redis_is_down() {
+    // Nothing listens on port 1, so every Redis call is refused.
+    let client = memory_client_on_redis("redis://127.0.0.1:1", &[]);
+    let started = Instant::now();
+    create(&client, &hero("Bruce"));
+    for _ in 0..3 {
+        assert_eq!(count(&list(&client)), 1);
+        let mut response = client.get("/api/v1/heroes/1").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(json_body(&mut response)["name"], "Bruce");
+    }
+    create(&client, &hero("Clark"));
+    assert_eq!(count(&list(&client)), 2);
+    assert!(started.elapsed() < Duration::from_secs(5), "took {:?} without Redis", started.elapsed());
+}
@@ -0,0 +1,65 @@
+//! Whatever fails, and wherever, the client gets the JSON error body rather
+//! than Rocket's HTML page.
+
+use rocket::http::{ContentType, Status};
+use rocket::local::{Client, LocalResponse};
+use rocket::{get, routes};
+use serde_json::json;
+
+use super::{api_key, json_body, memory_client, memory_rocket};
+
+/// Stands in for a handler that breaks. Rocket 0.4 never turns a panic in a
+/// handler into a response, so this fails the one way that reaches the 500
+/// catcher: by returning the status.
+#[get("/broken")]
+fn broken() -> Result<(), Status> {
+    Err(Status::InternalServerError)
+}
+
+fn assert_json_error(response: &mut LocalResponse, code: u16) -> serde_json::Value {
+    assert_eq!(response.status().code, code);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    let body = json_body(response);
+    assert_eq!(body["error"]["code"], code);
+    assert!(body["error"]["request_id"].is_string(), "error {}", body);
+    body
+}
+
+#[test]
+fn an_unknown_route_is_a_json_404() {
+    let client = memory_client(&[]);
+    let body = assert_json_error(&mut client.get("/no/such/route").dispatch(), 404);
+    assert_eq!(body["error"]["message"], "no such resource");
+}
+
+#[test]
+fn a_body_of_the_wrong_shape_is_a_json_422_saying_why() {
+    let client = memory_client(&[]);
+    let mut response = client.post("/hero")
+        .header(ContentType::JSON)
+        .header(api_key())
+        .body(json!({ "name": "Bruce" }).to_string())
+        .dispatch();
+    let body = assert_json_error(&mut response, 422);
+    assert_eq!(body["error"]["message"], "invalid JSON body");
+    assert!(body["error"]["detail"].as_str().unwrap().contains("missing field"), "error {}", body);
+}
+
+#[test]
+fn a_body_that_is_not_json_is_a_json_422_with_its_position() {
+    let client = memory_client(&[]);
+    let mut response = client.post("/hero")
+        .header(ContentType::JSON)
+        .header(api_key())
+        .body("{\n  \"name\": ")
+        .dispatch();
+    let body = assert_json_error(&mut response, 422);
+    assert_eq!(body["error"]["line"], 2);
+}
+
+#[test]
+fn a_failing_handler_is_a_json_500() {
+    let client = Client::new(memory_rocket(&[]).mount("/test", routes![broken])).unwrap();
+    let body = assert_json_error(&mut client.get("/test/broken").dispatch(), 500);
+    assert_eq!(body["error"]["message"], "internal server error");
+}
@@ -0,0 +1,81 @@
+//! Browsers on an allowed origin get the CORS headers, on preflights and
+//! real requests alike; any other origin gets none.
+
+use rocket::config::Value;
+use rocket::http::{Header, Status};
+use rocket::local::{Client, LocalResponse};
+
+use super::memory_client;
+
+const ALLOWED: &str = "https://heroes.example";
+const OTHER: &str = "https://elsewhere.example";
+
+/// Whether the response says it varies with the `Origin` of the request.
+fn varies_by_origin(response: &LocalResponse) -> bool {
+    response.headers().get("Vary").any(|vary| vary.split(',').any(|name| name.trim() == "Origin"))
+}
+
+fn listing(origins: &[&str]) -> Client {
+    let origins = origins.iter().map(|origin| Value::from(*origin)).collect();
+    memory_client(&[("cors_allowed_origins", Value::Array(origins))])
+}
+
+fn preflight<'c>(client: &'c Client, origin: &str) -> LocalResponse<'c> {
+    client.options("/api/v1/heroes")
+        .header(Header::new("Origin", origin.to_string()))
+        .header(Header::new("Access-Control-Request-Method", "POST"))
+        .header(Header::new("Access-Control-Request-Headers", "Content-Type, X-API-Key"))
+        .dispatch()
+}
+
+#[test]
+fn a_preflight_from_an_allowed_origin_is_answered() {
+    let client = listing(&[ALLOWED]);
+    let mut response = preflight(&client, ALLOWED);
+    assert_eq!(response.status(), Status::NoContent);
+    let headers = response.headers();
+    assert_eq!(headers.get_one("Access-Control-Allow-Origin"), Some(ALLOWED));
+    assert!(headers.get_one("Access-Control-Allow-Methods").unwrap().contains("POST"));
+    assert!(headers.get_one("Access-Control-Allow-Headers").unwrap().contains("X-API-Key"));
+    assert!(varies_by_origin(&response));
+    assert_eq!(response.body_string().unwrap_or_default(), "");
+}
+
+#[test]
+fn a_preflight_from_another_origin_gets_no_cors_headers() {
+    let client = listing(&[ALLOWED]);
+    let response = preflight(&client, OTHER);
+    assert_eq!(response.status(), Status::NoContent);
+    assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), None);
+    assert_eq!(response.headers().get_one("Access-Control-Allow-Methods"), None);
+}
+
+#[test]
+fn a_get_carries_the_headers_only_for_an_allowed_origin() {
+    let client = listing(&[OTHER, ALLOWED]);
+    let response = client.get("/api/v1/heroes").header(Header::new("Origin", ALLOWED)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), Some(ALLOWED));
+
+    let response = client.get("/api/v1/heroes").header(Header::new("Origin", "https://evil.example")).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), None);
+
+    assert!(varies_by_origin(&response));
+}
+
+#[test]
+fn a_wildcard_allows_every_origin() {
+    let client = memory_client(&[("cors_allowed_origins", Value::from("*"))]);
+    let response = client.get("/api/v1/heroes").header(Header::new("Origin", OTHER)).dispatch();
+    assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), Some("*"));
+    assert!(!varies_by_origin(&response));
+    assert_eq!(preflight(&client, OTHER).headers().get_one("Access-Control-Allow-Origin"), Some("*"));
+}
+
+#[test]
+fn without_a_configured_list_no_origin_is_allowed() {
+    let client = memory_client(&[]);
+    let response = client.get("/api/v1/heroes").header(Header::new("Origin", ALLOWED)).dispatch();
+    assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), None);
+}
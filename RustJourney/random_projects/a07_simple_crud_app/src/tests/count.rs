@@ -0,0 +1,60 @@
+//! `GET /api/v1/heroes/count` totals what the list would, filters and all.
+
+use rocket::config::Value;
+use rocket::http::Status;
+use rocket::local::Client;
+
+use super::{api_key, each_store, json_body};
+
+/// The count route's `count` and the list's `X-Total-Count` for `query`,
+/// both asked with the API key.
+fn count_and_total(client: &Client, query: &str) -> (i64, i64) {
+    let mut response = client.get(format!("/api/v1/heroes/count?{}", query)).header(api_key()).dispatch();
+    assert_eq!(response.status(), Status::Ok, "count?{}", query);
+    let count = json_body(&mut response)["count"].as_i64().unwrap();
+
+    let response = client.get(format!("/api/v1/heroes?limit=2&{}", query)).header(api_key()).dispatch();
+    assert_eq!(response.status(), Status::Ok, "list?{}", query);
+    let total = response.headers().get_one("X-Total-Count").unwrap().parse().unwrap();
+    (count, total)
+}
+
+#[test]
+fn the_count_agrees_with_the_list_total_for_every_filter() {
+    each_store(&[("dev_tools", Value::from(true))], |client| {
+        let response = client.post("/admin/reset?count=12").header(api_key()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        for id in &[1, 3, 4, 8] {
+            let response = client.delete(format!("/api/v1/heroes/{}", id)).header(api_key()).dispatch();
+            assert_eq!(response.status(), Status::Ok);
+        }
+
+        let expected = [
+            ("", 8),
+            ("include_deleted=true", 12),
+            ("role=Tank", 2),
+            ("role=Tank&include_deleted=true", 4),
+            ("q=IN", 2),
+            ("q=in&role=Support", 0),
+            ("q=in&role=Tank&include_deleted=true", 4),
+            ("filter%5Bhometown%5D=London", 1),
+        ];
+        for (query, count) in &expected {
+            let (counted, total) = count_and_total(client, query);
+            assert_eq!(counted, total, "count and total differ for ?{}", query);
+            assert_eq!(counted, *count, "?{}", query);
+        }
+    });
+}
+
+#[test]
+fn counting_deleted_heroes_needs_the_key() {
+    each_store(&[], |client| {
+        let mut response = client.get("/api/v1/heroes/count").dispatch();
+        assert_eq!(json_body(&mut response)["count"], 0);
+        let response = client.get("/api/v1/heroes/count?include_deleted=true").dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client.get("/api/v1/heroes/count?role=Healer").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    });
+}
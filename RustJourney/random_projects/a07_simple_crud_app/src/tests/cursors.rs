@@ -0,0 +1,103 @@
+//! Keyset pages: `?after=<id>&limit=<n>` lists the heroes past `id` in id
+//! order, and `X-Next-Cursor` says where the next page starts.
+
+use rocket::http::Status;
+use rocket::local::Client;
+
+use super::{api_key, create, each_store, hero, json_body};
+
+/// The ids on the page at `path` and its next cursor, if any.
+fn page(client: &Client, path: &str) -> (Vec<i64>, Option<String>) {
+    let mut response = client.get(path.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok, "{}", path);
+    let next = response.headers().get_one("X-Next-Cursor").map(str::to_string);
+    let ids = json_body(&mut response).as_array().unwrap().iter().map(|hero| hero["id"].as_i64().unwrap()).collect();
+    (ids, next)
+}
+
+/// Every id from the first page of `limit` on, following the cursors, and
+/// how many pages that took.
+fn walk(client: &Client, limit: usize, query: &str) -> (Vec<i64>, usize) {
+    let (mut ids, mut next) = page(client, &format!("/api/v1/heroes?limit={}{}", limit, query));
+    let mut pages = 1;
+    while let Some(after) = next {
+        let (more, cursor) = page(client, &format!("/api/v1/heroes?after={}&limit={}{}", after, limit, query));
+        assert!(more.len() <= limit);
+        ids.extend(more);
+        next = cursor;
+        pages += 1;
+    }
+    (ids, pages)
+}
+
+fn create_many(client: &Client, count: usize) {
+    for index in 0..count {
+        create(client, &hero(&format!("Hero {}", index)));
+    }
+}
+
+#[test]
+fn walking_the_cursors_visits_every_hero_once() {
+    each_store(&[], |client| {
+        create_many(client, 11);
+        for id in &[2, 6, 7] {
+            client.delete(format!("/api/v1/heroes/{}", id)).header(api_key()).dispatch();
+        }
+        let (ids, pages) = walk(client, 3, "");
+        assert_eq!(ids, vec![1, 3, 4, 5, 8, 9, 10, 11]);
+        assert_eq!(pages, 3);
+    });
+}
+
+#[test]
+fn a_full_last_page_is_followed_by_an_empty_one() {
+    each_store(&[], |client| {
+        create_many(client, 6);
+        let (ids, pages) = walk(client, 3, "");
+        assert_eq!(ids, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(pages, 3);
+        assert_eq!(page(client, "/api/v1/heroes?after=6&limit=3"), (vec![], None));
+    });
+}
+
+#[test]
+fn a_hero_inserted_mid_walk_is_reached_without_repeats() {
+    each_store(&[], |client| {
+        create_many(client, 4);
+        let (mut ids, next) = page(client, "/api/v1/heroes?limit=2");
+        assert_eq!(next.as_deref(), Some("2"));
+        create(client, &hero("Late"));
+
+        let (more, next) = page(client, "/api/v1/heroes?after=2&limit=2");
+        ids.extend(more);
+        let (more, next) = page(client, &format!("/api/v1/heroes?after={}&limit=2", next.unwrap()));
+        ids.extend(more);
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+        assert_eq!(next, None);
+    });
+}
+
+#[test]
+fn no_after_starts_from_the_first_hero_and_filters_still_apply() {
+    each_store(&[], |client| {
+        create_many(client, 5);
+        assert_eq!(page(client, "/api/v1/heroes?limit=2").0, vec![1, 2]);
+        let (ids, _) = walk(client, 1, "&q=Hero%203");
+        assert_eq!(ids, vec![4]);
+    });
+}
+
+#[test]
+fn after_refuses_an_offset_or_another_order() {
+    each_store(&[], |client| {
+        for query in &["after=1&offset=2", "after=1&sort=-name", "after=1&sort=-id"] {
+            let mut response = client.get(format!("/api/v1/heroes?{}", query)).dispatch();
+            assert_eq!(response.status(), Status::BadRequest, "?{}", query);
+            assert_eq!(
+                json_body(&mut response)["error"]["message"],
+                "after pages in id order, so it takes neither offset nor sort",
+            );
+        }
+        assert_eq!(client.get("/api/v1/heroes?after=1&sort=id").dispatch().status(), Status::Ok);
+    });
+}
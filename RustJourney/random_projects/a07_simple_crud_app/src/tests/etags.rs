@@ -0,0 +1,75 @@
+//! Hero reads carry a weak `ETag`, and a client holding the current one is
+//! answered 304 Not Modified.
+
+use rocket::http::{Header, Status};
+use rocket::local::Client;
+use serde_json::json;
+
+use super::{create, each_store, hero, send};
+
+fn etag(client: &Client, path: &str) -> String {
+    let response = client.get(path.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    response.headers().get_one("ETag").expect("the read is tagged").to_string()
+}
+
+fn if_none_match(tags: &str) -> Header<'static> {
+    Header::new("If-None-Match", tags.to_string())
+}
+
+#[test]
+fn a_refetch_with_the_etag_is_not_modified() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let tag = etag(client, "/api/v1/heroes/1");
+        assert!(tag.starts_with("W/\"") && tag.ends_with('"'), "{}", tag);
+        assert_eq!(etag(client, "/api/v1/heroes/1"), tag);
+
+        let mut response = client.get("/api/v1/heroes/1").header(if_none_match(&tag)).dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+        assert_eq!(response.headers().get_one("ETag"), Some(tag.as_str()));
+        assert!(response.body_string().map_or(true, |body| body.is_empty()));
+    });
+}
+
+#[test]
+fn the_match_is_weak_and_may_be_one_of_a_list() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let tag = etag(client, "/api/v1/heroes/1");
+        let strong = tag.trim_start_matches("W/").to_string();
+        for header in &[strong.clone(), format!("\"other\", {}", tag), "*".to_string()] {
+            let response = client.get("/api/v1/heroes/1").header(if_none_match(header)).dispatch();
+            assert_eq!(response.status(), Status::NotModified, "If-None-Match: {}", header);
+        }
+        let response = client.get("/api/v1/heroes/1").header(if_none_match("W/\"other\"")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    });
+}
+
+#[test]
+fn an_update_changes_the_etag() {
+    each_store(&[], |client| {
+        let created = create(client, &hero("Bruce"));
+        let tag = etag(client, "/api/v1/heroes/1");
+        let other = create(client, &hero("Clark"));
+        assert_ne!(etag(client, &format!("/api/v1/heroes/{}", other["id"])), tag);
+
+        let mut body = hero("Bruce");
+        body["hometown"] = json!("Metropolis");
+        body["version"] = created["version"].clone();
+        assert_eq!(send(client, "PUT", "/api/v1/heroes/1", &body).status(), Status::Ok);
+
+        let response = client.get("/api/v1/heroes/1").header(if_none_match(&tag)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_ne!(response.headers().get_one("ETag"), Some(tag.as_str()));
+    });
+}
+
+#[test]
+fn a_missing_hero_is_not_found_whatever_the_etag() {
+    each_store(&[], |client| {
+        let response = client.get("/api/v1/heroes/1").header(if_none_match("*")).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    });
+}
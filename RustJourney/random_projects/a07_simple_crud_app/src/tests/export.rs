@@ -0,0 +1,96 @@
+//! The CSV export holds every hero the list would, fields quoted so that
+//! it parses back to what was stored.
+
+use rocket::http::{ContentType, Status};
+use rocket::local::Client;
+use serde_json::{json, Value as JsonValue};
+
+use super::{api_key, create, each_store, hero};
+
+/// Splits CSV into records of fields, undoing the quoting.
+fn parse_csv(csv: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') => quoted = true,
+            (false, ',') => record.push(field.split_off(0)),
+            (false, '\n') => {
+                record.push(field.split_off(0));
+                records.push(record.split_off(0));
+            },
+            (false, c) => field.push(c),
+        }
+    }
+    assert!(field.is_empty() && record.is_empty(), "the CSV ends mid-record");
+    records
+}
+
+fn export(client: &Client, query: &str, keyed: bool) -> Vec<Vec<String>> {
+    let mut request = client.get(format!("/heroes/export.csv{}", query));
+    if keyed {
+        request = request.header(api_key());
+    }
+    let mut response = request.dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::CSV));
+    assert_eq!(response.headers().get_one("Content-Disposition"), Some("attachment; filename=\"heroes.csv\""));
+    parse_csv(&response.body_string().unwrap())
+}
+
+/// The fields of a hero's JSON the export writes before the timestamps,
+/// which JSON writes with `Z` and the export with `+00:00`.
+fn as_record(hero: &JsonValue) -> Vec<String> {
+    let text = |value: &JsonValue| match value {
+        JsonValue::Null => String::new(),
+        JsonValue::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    ["id", "name", "identity", "hometown", "age", "deleted_at", "version"]
+        .iter()
+        .map(|field| text(&hero[*field]))
+        .collect()
+}
+
+#[test]
+fn the_export_parses_back_to_the_seeded_heroes() {
+    each_store(&[], |client| {
+        let mut tricky = hero("Wayne, Bruce");
+        tricky["identity"] = json!("The \"Bat\"");
+        let seeded = vec![create(client, &tricky), create(client, &hero("Clark")), create(client, &hero("Diana"))];
+
+        let records = export(client, "", false);
+        assert_eq!(records[0].join(","), "id,name,identity,hometown,age,deleted_at,version,created_at,updated_at,role");
+        assert_eq!(records.len(), 4);
+        for (record, hero) in records[1..].iter().zip(&seeded) {
+            assert_eq!(record[..7], as_record(hero)[..]);
+        }
+        assert_eq!(records[1][1], "Wayne, Bruce");
+        assert_eq!(records[1][2], "The \"Bat\"");
+    });
+}
+
+#[test]
+fn the_export_takes_the_list_filters() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        create(client, &hero("Clark"));
+        client.delete("/api/v1/heroes/1").header(api_key()).dispatch();
+
+        let names = |records: Vec<Vec<String>>| records[1..].iter().map(|record| record[1].clone()).collect::<Vec<_>>();
+        assert_eq!(names(export(client, "", false)), vec!["Clark"]);
+        assert_eq!(names(export(client, "?include_deleted=true", true)), vec!["Bruce", "Clark"]);
+        assert_eq!(names(export(client, "?q=Clark", false)), vec!["Clark"]);
+        let response = client.get("/heroes/export.csv?include_deleted=true").dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+    });
+}
@@ -0,0 +1,75 @@
+//! `filter[<column>]=<value>` narrows the list to heroes matching every
+//! filter given.
+
+use rocket::http::Status;
+use rocket::local::Client;
+use serde_json::json;
+
+use super::{create, each_store, hero, json_body};
+
+fn names(client: &Client, query: &str) -> Vec<String> {
+    let mut response = client.get(format!("/api/v1/heroes?{}", query)).dispatch();
+    assert_eq!(response.status(), Status::Ok, "?{}", query);
+    json_body(&mut response).as_array().unwrap()
+        .iter()
+        .map(|hero| hero["name"].as_str().unwrap().to_string())
+        .collect()
+}
+
+fn seed(client: &Client) {
+    for (name, hometown, age) in &[("Bruce", "Gotham", 30), ("Dick", "Gotham", 21), ("Clark", "Metropolis", 30)] {
+        let mut body = hero(name);
+        body["hometown"] = json!(hometown);
+        body["age"] = json!(age);
+        create(client, &body);
+    }
+}
+
+#[test]
+fn two_filters_must_both_match() {
+    each_store(&[], |client| {
+        seed(client);
+        assert_eq!(names(client, "filter%5Bhometown%5D=Gotham"), vec!["Bruce", "Dick"]);
+        assert_eq!(names(client, "filter%5Bage%5D=30"), vec!["Bruce", "Clark"]);
+        assert_eq!(names(client, "filter%5Bhometown%5D=Gotham&filter%5Bage%5D=30"), vec!["Bruce"]);
+        assert!(names(client, "filter%5Bhometown%5D=Metropolis&filter%5Bage%5D=21").is_empty());
+
+        let mut response = client.get("/api/v1/heroes/count?filter%5Bhometown%5D=Gotham&filter%5Bage%5D=30").dispatch();
+        assert_eq!(json_body(&mut response)["count"], 1);
+    });
+}
+
+#[test]
+fn a_filter_matches_exactly() {
+    each_store(&[], |client| {
+        seed(client);
+        assert_eq!(names(client, "filter%5Bname%5D=Bruce"), vec!["Bruce"]);
+        assert!(names(client, "filter%5Bname%5D=bruce").is_empty());
+        assert!(names(client, "filter%5Bname%5D=Bru").is_empty());
+        assert_eq!(names(client, "filter%5Bidentity%5D=Clark%20Doe"), vec!["Clark"]);
+    });
+}
+
+#[test]
+fn unknown_query_keys_are_ignored() {
+    each_store(&[], |client| {
+        seed(client);
+        assert_eq!(names(client, "alignment=good&power=flight"), vec!["Bruce", "Dick", "Clark"]);
+    });
+}
+
+#[test]
+fn a_filter_on_an_unknown_column_or_with_a_bad_value_is_refused() {
+    each_store(&[], |client| {
+        let mut response = client.get("/api/v1/heroes?filter%5Bpower%5D=flight").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            json_body(&mut response)["error"]["message"],
+            "cannot filter on unknown column `power`, expected one of name, identity, hometown, age, role",
+        );
+
+        let mut response = client.get("/api/v1/heroes?filter%5Bage%5D=old").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(json_body(&mut response)["error"]["message"], "filter on age needs a whole number, not `old`");
+    });
+}
@@ -0,0 +1,45 @@
+//! Liveness answers whatever the database is doing; readiness only when the
+//! pool hands out a working connection, and quickly either way.
+
+use std::time::{Duration, Instant};
+
+use rocket::http::Status;
+use serde_json::json;
+
+use super::{json_body, memory_client, on_postgres};
+
+#[test]
+fn liveness_holds_without_a_database() {
+    let client = memory_client(&[]);
+    let mut response = client.get("/health").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json_body(&mut response), json!({ "status": "ok" }));
+}
+
+#[test]
+fn an_unreachable_database_is_not_ready_and_says_so_quickly() {
+    // The memory store's app has a pool all the same, on a port with no
+    // database behind it.
+    let client = memory_client(&[("db_pool_timeout_secs", 30.into())]);
+    let started = Instant::now();
+    let mut response = client.get("/ready").dispatch();
+    assert!(started.elapsed() < Duration::from_secs(5), "/ready took {:?}", started.elapsed());
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    let body = json_body(&mut response);
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["db"], "unreachable");
+    assert_eq!(body["pool"], json!({ "in_use": 0, "idle": 0 }));
+}
+
+#[test]
+fn a_reachable_database_is_ready() {
+    on_postgres(&[], |client| {
+        let mut response = client.get("/ready").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = json_body(&mut response);
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["db"], "reachable");
+        assert_eq!(body["pool"]["in_use"], 0);
+        assert!(body["pool"]["idle"].as_u64().unwrap() >= 1, "pool {}", body["pool"]);
+    });
+}
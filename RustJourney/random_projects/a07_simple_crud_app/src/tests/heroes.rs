@@ -0,0 +1,162 @@
+//! Creating, reading, replacing and deleting heroes, and how each of those
+//! fails.
+
+use rocket::http::{ContentType, Status};
+use serde_json::json;
+
+use super::{api_key, create, each_store, hero, json_body, send};
+
+#[test]
+fn a_created_hero_is_answered_with_its_location() {
+    each_store(&[], |client| {
+        let mut response = send(client, "POST", "/api/v1/heroes", &hero("Bruce"));
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(response.headers().get_one("Location"), Some("/api/v1/heroes/1"));
+        let created = json_body(&mut response);
+        assert_eq!(created["id"], 1);
+        assert_eq!(created["name"], "Bruce");
+        assert_eq!(created["hometown"], "Gotham");
+        assert_eq!(created["age"], 30);
+        assert_eq!(created["version"], 1);
+    });
+}
+
+#[test]
+fn the_location_of_a_created_hero_reads_it_back() {
+    each_store(&[], |client| {
+        for (base, name) in &[("/api/v1/heroes", "Bruce"), ("/hero", "Clark")] {
+            let mut response = send(client, "POST", base, &hero(name));
+            assert_eq!(response.status(), Status::Created);
+            let location = response.headers().get_one("Location").unwrap().to_string();
+            let created = json_body(&mut response);
+            assert_eq!(location, format!("{}/{}", base, created["id"]));
+
+            let mut response = client.get(location).dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(json_body(&mut response), created);
+        }
+    });
+}
+
+#[test]
+fn a_created_hero_can_be_read_alone_and_in_the_list() {
+    each_store(&[], |client| {
+        let bruce = create(client, &hero("Bruce"));
+        let clark = create(client, &hero("Clark"));
+
+        let mut response = client.get(format!("/api/v1/heroes/{}", clark["id"])).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(json_body(&mut response), clark);
+
+        let mut response = client.get("/api/v1/heroes").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("X-Total-Count"), Some("2"));
+        assert_eq!(json_body(&mut response), json!([bruce, clark]));
+    });
+}
+
+#[test]
+fn an_update_replaces_the_hero_and_bumps_its_version() {
+    each_store(&[], |client| {
+        let created = create(client, &hero("Bruce"));
+        let mut body = hero("Bruce");
+        body["hometown"] = json!("Metropolis");
+        body["version"] = created["version"].clone();
+
+        let mut response = send(client, "PUT", "/api/v1/heroes/1", &body);
+        assert_eq!(response.status(), Status::Ok);
+        let updated = json_body(&mut response);
+        assert_eq!(updated["hometown"], "Metropolis");
+        assert_eq!(updated["version"], 2);
+
+        let mut response = client.get("/api/v1/heroes/1").dispatch();
+        assert_eq!(json_body(&mut response)["hometown"], "Metropolis");
+    });
+}
+
+#[test]
+fn a_deleted_hero_is_gone() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let mut response = client.delete("/api/v1/heroes/1").header(api_key()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(json_body(&mut response), json!({ "success": true }));
+
+        assert_eq!(client.get("/api/v1/heroes/1").dispatch().status(), Status::NotFound);
+        let mut response = client.get("/api/v1/heroes").dispatch();
+        assert_eq!(json_body(&mut response), json!([]));
+        let response = client.delete("/api/v1/heroes/1").header(api_key()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn a_missing_hero_is_not_found_by_any_route() {
+    each_store(&[], |client| {
+        let mut response = client.get("/api/v1/heroes/41").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let error = json_body(&mut response);
+        assert_eq!(error["error"]["code"], 404);
+        assert_eq!(error["error"]["message"], "hero not found");
+
+        let mut body = hero("Bruce");
+        body["version"] = json!(1);
+        assert_eq!(send(client, "PUT", "/api/v1/heroes/41", &body).status(), Status::NotFound);
+        let response = client.delete("/api/v1/heroes/41").header(api_key()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn a_body_of_the_wrong_shape_is_unprocessable() {
+    each_store(&[], |client| {
+        let mut body = hero("Bruce");
+        body["age"] = json!("thirty");
+        let mut response = send(client, "POST", "/api/v1/heroes", &body);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let error = json_body(&mut response);
+        assert_eq!(error["error"]["code"], 422);
+        assert_eq!(error["error"]["path"], "age");
+
+        let response = client.post("/api/v1/heroes")
+            .header(ContentType::JSON)
+            .header(api_key())
+            .body(r#"{"name": "Bruce""#)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert_eq!(client.get("/api/v1/heroes/1").dispatch().status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn creating_a_hero_under_a_taken_name_is_a_conflict() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let mut response = send(client, "POST", "/api/v1/heroes", &hero("Bruce"));
+        assert_eq!(response.status(), Status::Conflict);
+        let error = json_body(&mut response);
+        assert_eq!(error["error"]["code"], 409);
+        assert_eq!(error["error"]["message"], "name already exists");
+        assert_eq!(error["error"]["name"], "Bruce");
+
+        let mut response = client.get("/api/v1/heroes").dispatch();
+        assert_eq!(json_body(&mut response).as_array().unwrap().len(), 1);
+    });
+}
+
+#[test]
+fn renaming_a_hero_to_a_taken_name_is_a_conflict() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let clark = create(client, &hero("Clark"));
+        let mut renamed = hero("Bruce");
+        renamed["version"] = clark["version"].clone();
+        let path = format!("/api/v1/heroes/{}", clark["id"]);
+
+        let mut response = send(client, "PUT", &path, &renamed);
+        assert_eq!(response.status(), Status::Conflict);
+        assert_eq!(json_body(&mut response)["error"]["name"], "Bruce");
+        let mut response = client.get(path).dispatch();
+        assert_eq!(json_body(&mut response), clark);
+    });
+}
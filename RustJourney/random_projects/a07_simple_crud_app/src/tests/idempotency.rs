@@ -0,0 +1,120 @@
+//! A create retried under the same `Idempotency-Key` answers with the hero
+//! the first one made instead of inserting another.
+
+use std::env;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use redis::Commands;
+use rocket::config::{Limits, Value};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::{Client, LocalResponse};
+use serde_json::{json, Value as JsonValue};
+
+use super::{api_key, app, create, hero, json_body, memory_client, on_postgres, on_redis};
+use crate::schemas::heroes;
+
+fn create_with_key<'c>(client: &'c Client, key: &str, body: &JsonValue) -> LocalResponse<'c> {
+    client.post("/api/v1/heroes")
+        .header(ContentType::JSON)
+        .header(api_key())
+        .header(Header::new("Idempotency-Key", key.to_string()))
+        .body(body.to_string())
+        .dispatch()
+}
+
+fn hero_count(client: &Client) -> usize {
+    let mut response = client.get("/api/v1/heroes").dispatch();
+    json_body(&mut response).as_array().unwrap().len()
+}
+
+#[test]
+fn a_repeated_key_answers_with_the_first_hero() {
+    on_redis(&[("idempotency_ttl", Value::from(60))], |client, redis| {
+        let mut first = create_with_key(client, "retry-1", &hero("Bruce"));
+        assert_eq!(first.status(), Status::Created);
+        let created = json_body(&mut first);
+
+        let mut second = create_with_key(client, "retry-1", &hero("Bruce"));
+        assert_eq!(second.status(), Status::Created);
+        assert_eq!(second.headers().get_one("Location"), Some("/api/v1/heroes/1"));
+        assert_eq!(json_body(&mut second), created);
+        assert_eq!(hero_count(client), 1);
+
+        let ttl: i64 = redis.ttl("idempotency:hero:retry-1").unwrap();
+        assert!(ttl > 0 && ttl <= 60, "ttl {}", ttl);
+    });
+}
+
+#[test]
+fn a_repeated_key_inserts_one_row() {
+    on_postgres(&[], |_| {
+        on_redis(&[], |_, _| {
+            let database_url = env::var("TEST_DATABASE_URL").unwrap();
+            let redis_url = env::var("TEST_REDIS_URL").unwrap();
+            let client = Client::new(app(&database_url, Some(&redis_url), &[], &[], Limits::default()))
+                .expect("the app launches");
+            for _ in 0..2 {
+                assert_eq!(create_with_key(&client, "retry-1", &hero("Bruce")).status(), Status::Created);
+            }
+            let conn = PgConnection::establish(&database_url).unwrap();
+            assert_eq!(heroes::table.count().get_result::<i64>(&conn).unwrap(), 1);
+        });
+    });
+}
+
+#[test]
+fn a_repeated_key_ignores_a_changed_body() {
+    on_redis(&[], |client, _| {
+        let created = json_body(&mut create_with_key(client, "retry-1", &hero("Bruce")));
+        let mut again = create_with_key(client, "retry-1", &hero("Clark"));
+        assert_eq!(json_body(&mut again), created);
+        assert_eq!(hero_count(client), 1);
+
+        let mut other = create_with_key(client, "retry-2", &hero("Clark"));
+        assert_eq!(json_body(&mut other)["name"], "Clark");
+        assert_eq!(hero_count(client), 2);
+    });
+}
+
+#[test]
+fn a_failed_create_frees_its_key() {
+    on_redis(&[], |client, redis| {
+        create(client, &hero("Bruce"));
+        assert_eq!(create_with_key(client, "retry-1", &hero("Bruce")).status(), Status::Conflict);
+        assert_eq!(redis.exists::<_, bool>("idempotency:hero:retry-1").unwrap(), false);
+
+        let mut retried = create_with_key(client, "retry-1", &hero("Clark"));
+        assert_eq!(retried.status(), Status::Created);
+        assert_eq!(json_body(&mut retried)["name"], "Clark");
+    });
+}
+
+#[test]
+fn a_key_whose_first_request_is_still_running_is_a_conflict() {
+    on_redis(&[], |client, redis| {
+        redis.set_ex::<_, _, ()>("idempotency:hero:retry-1", "pending", 60).unwrap();
+        let mut response = create_with_key(client, "retry-1", &hero("Bruce"));
+        assert_eq!(response.status(), Status::Conflict);
+        assert_eq!(json_body(&mut response)["error"]["message"], "a request with this Idempotency-Key is still in progress");
+        assert_eq!(hero_count(client), 0);
+    });
+}
+
+#[test]
+fn an_empty_or_overlong_key_is_refused() {
+    let client = memory_client(&[]);
+    for key in &["".to_string(), "k".repeat(256)] {
+        assert_eq!(create_with_key(&client, key, &hero("Bruce")).status(), Status::BadRequest);
+    }
+    assert_eq!(hero_count(&client), 0);
+}
+
+#[test]
+fn without_redis_every_create_inserts() {
+    let client = memory_client(&[]);
+    create_with_key(&client, "retry-1", &hero("Bruce"));
+    let mut response = create_with_key(&client, "retry-1", &hero("Clark"));
+    assert_eq!(response.status(), Status::Created);
+    assert_eq!(json_body(&mut response)["id"], json!(2));
+}
@@ -0,0 +1,69 @@
+//! The pre-`/api/v1` paths answer as the new ones do, flagged deprecated.
+
+use rocket::http::Status;
+use rocket::local::{Client, LocalResponse};
+
+use super::{api_key, create, each_store, hero, json_body, send};
+
+fn deprecated(response: &LocalResponse) -> bool {
+    response.headers().get_one("Deprecation") == Some("true")
+}
+
+fn get<'c>(client: &'c Client, path: &str) -> LocalResponse<'c> {
+    client.get(path.to_string()).dispatch()
+}
+
+#[test]
+fn old_and_new_paths_read_the_same() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        create(client, &hero("Clark"));
+        let pairs = [
+            ("/api/v1/heroes", "/heroes"),
+            ("/api/v1/heroes?sort=-name&limit=1", "/heroes?sort=-name&limit=1"),
+            ("/api/v1/heroes/count", "/heroes/count"),
+            ("/api/v1/heroes/export.csv", "/heroes/export.csv"),
+            ("/api/v1/heroes/stream", "/heroes/stream"),
+            ("/api/v1/heroes/2", "/hero/2"),
+        ];
+        for (new, old) in &pairs {
+            let mut new_response = get(client, new);
+            let mut old_response = get(client, old);
+            assert_eq!(new_response.status(), Status::Ok, "GET {}", new);
+            assert_eq!(old_response.status(), Status::Ok, "GET {}", old);
+            assert!(!deprecated(&new_response), "{} is flagged deprecated", new);
+            assert!(deprecated(&old_response), "{} is not flagged deprecated", old);
+            assert_eq!(new_response.body_string(), old_response.body_string(), "{} and {} differ", new, old);
+        }
+    });
+}
+
+#[test]
+fn old_paths_still_change_heroes() {
+    each_store(&[], |client| {
+        let response = send(client, "POST", "/hero", &hero("Bruce"));
+        assert_eq!(response.status(), Status::Created);
+        assert!(deprecated(&response));
+
+        let mut body = hero("Bruce");
+        body["age"] = 31.into();
+        body["version"] = 1.into();
+        let mut response = send(client, "PUT", "/hero/1", &body);
+        assert!(deprecated(&response));
+        assert_eq!(json_body(&mut response)["age"], 31);
+
+        let response = client.delete("/hero/1").header(api_key()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(deprecated(&response));
+        assert_eq!(get(client, "/api/v1/heroes/1").status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn other_routes_are_not_flagged() {
+    each_store(&[], |client| {
+        for path in &["/health", "/api/v1/heroes/41", "/no/such/route"] {
+            assert!(!deprecated(&get(client, path)), "{} is flagged deprecated", path);
+        }
+    });
+}
@@ -0,0 +1,71 @@
+//! The Prometheus scrape at `/metrics`.
+
+use rocket::http::{ContentType, Status};
+use rocket::local::Client;
+
+use super::{create, hero, memory_client, on_postgres};
+
+fn scrape(client: &Client) -> String {
+    let mut response = client.get("/metrics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::new("text", "plain")));
+    response.body_string().unwrap()
+}
+
+/// The value of `series`, labels and all, in `scraped`.
+fn value(scraped: &str, series: &str) -> Option<f64> {
+    scraped.lines()
+        .find(|line| line.starts_with(series) && line[series.len()..].starts_with(' '))
+        .map(|line| line[series.len() + 1..].parse().unwrap())
+}
+
+#[test]
+fn requests_are_counted_by_route_template_method_and_status() {
+    let client = memory_client(&[]);
+    create(&client, &hero("Bruce"));
+    client.get("/api/v1/heroes/1").dispatch();
+    client.get("/api/v1/heroes/1").dispatch();
+    client.get("/api/v1/heroes/2").dispatch();
+    client.get("/nowhere").dispatch();
+
+    let scraped = scrape(&client);
+    let count = |labels: &str| value(&scraped, &format!("http_requests_total{{{}}}", labels));
+    assert_eq!(count(r#"method="POST",route="/api/v1/heroes",status="201""#), Some(1.0));
+    assert_eq!(count(r#"method="GET",route="/api/v1/heroes/<id>",status="200""#), Some(2.0));
+    assert_eq!(count(r#"method="GET",route="/api/v1/heroes/<id>",status="404""#), Some(1.0));
+    assert_eq!(count(r#"method="GET",route="unmatched",status="404""#), Some(1.0));
+}
+
+#[test]
+fn request_durations_are_recorded() {
+    let client = memory_client(&[]);
+    create(&client, &hero("Bruce"));
+    client.get("/api/v1/heroes/1").dispatch();
+
+    let scraped = scrape(&client);
+    let series = r#"{method="GET",route="/api/v1/heroes/<id>"}"#;
+    assert_eq!(value(&scraped, &format!("http_request_duration_seconds_count{}", series)), Some(1.0));
+    assert!(value(&scraped, &format!("http_request_duration_seconds_sum{}", series)).unwrap() > 0.0);
+    assert!(scraped.contains(r#"http_request_duration_seconds_bucket{method="GET",route="/api/v1/heroes/<id>",le="+Inf"} 1"#));
+}
+
+#[test]
+fn scrapes_are_not_counted() {
+    let client = memory_client(&[]);
+    scrape(&client);
+    let scraped = scrape(&client);
+    assert!(!scraped.contains(r#"route="/metrics""#), "{}", scraped);
+}
+
+#[test]
+fn the_pool_gauges_show_its_connections() {
+    on_postgres(&[], |client| {
+        create(client, &hero("Bruce"));
+        let scraped = scrape(client);
+        assert_eq!(value(&scraped, "db_pool_max_size"), Some(2.0));
+        let idle = value(&scraped, r#"db_pool_connections{state="idle"}"#).unwrap();
+        let in_use = value(&scraped, r#"db_pool_connections{state="in_use"}"#).unwrap();
+        assert!(idle > 0.0, "{}", scraped);
+        assert!(idle + in_use <= 2.0, "{}", scraped);
+    });
+}
@@ -0,0 +1,279 @@
+//! Route tests, run in process through Rocket's local client once per hero
+//! store: on the in-memory one always, and on Postgres as well when
+//! `TEST_DATABASE_URL` names a database of their own, which the tests empty
+//! as they go. There the migrations are applied on first use, and the tests
+//! take turns at the database, each starting from empty tables.
+
+mod admin;
+mod auth;
+mod avatars;
+mod bodies;
+mod cache;
+mod catchers;
+mod cors;
+mod count;
+mod cursors;
+mod etags;
+mod export;
+mod filters;
+mod health;
+mod heroes;
+mod idempotency;
+mod legacy;
+mod metrics;
+mod msgpack;
+mod openapi;
+mod pool;
+mod powers;
+mod rate_limit;
+mod request_id;
+mod roles;
+mod soft_delete;
+mod sorting;
+mod timestamps;
+mod transactions;
+mod versions;
+mod xml;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+use config::Settings;
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::Bool;
+use redis::Commands;
+use rocket::config::{Config, Environment, Limits, LoggingLevel, Value};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::{Client, LocalResponse};
+use rocket::Rocket;
+use serde_json::{json, Value as JsonValue};
+
+pub const API_KEY: &str = "test-key";
+
+static MIGRATED: Once = Once::new();
+/// Held by whichever test is using the database.
+static DATABASE_TAKEN: AtomicBool = AtomicBool::new(false);
+/// Held by whichever test is using Redis.
+static REDIS_TAKEN: AtomicBool = AtomicBool::new(false);
+/// What the app keeps in Redis, deleted before each test there.
+const REDIS_PATTERNS: [&str; 4] = ["hero:*", "heroes:list:*", "idempotency:*", "rate:*"];
+
+/// A turn at the database or Redis, handed back on drop, a failing test's
+/// included.
+struct Turn(&'static AtomicBool);
+
+impl Turn {
+    fn take(taken: &'static AtomicBool) -> Turn {
+        while taken.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        Turn(taken)
+    }
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// What the memory store is given as its database, never contacted since
+/// `lazy_db` is set.
+pub const UNUSED_DATABASE_URL: &str = "postgres://nobody@127.0.0.1:1/none";
+
+/// The extras that put the app on the memory store.
+fn memory_store() -> [(&'static str, Value); 2] {
+    [("hero_store", Value::from("memory")), ("lazy_db", Value::from(true))]
+}
+
+/// Names the store a test was running against when it failed.
+struct Running(&'static str);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            eprintln!("failed against the {} store", self.0);
+        }
+    }
+}
+
+/// Runs `test` against the app on each configured store, with `extras`
+/// added to the Rocket config of every instance.
+pub fn each_store<F: Fn(&Client)>(extras: &[(&str, Value)], test: F) {
+    {
+        let _running = Running("memory");
+        test(&memory_client(extras));
+    }
+    on_postgres(extras, test);
+}
+
+/// Runs `test` against the app on the Postgres store alone, if there is a
+/// test database, for what only a real database shows.
+pub fn on_postgres<F: Fn(&Client)>(extras: &[(&str, Value)], test: F) {
+    let database_url = match env::var("TEST_DATABASE_URL").ok().filter(|url| !url.is_empty()) {
+        Some(url) => url,
+        None => {
+            eprintln!("TEST_DATABASE_URL is not set, skipping the Postgres store");
+            return;
+        },
+    };
+    let _turn = Turn::take(&DATABASE_TAKEN);
+    let _running = Running("postgres");
+    prepare(&database_url);
+    test(&Client::new(app(&database_url, None, &[], extras, Limits::default())).expect("the app launches"));
+}
+
+/// Runs `test` against the app on the memory store with Redis at
+/// `TEST_REDIS_URL`, if that is set, handing it a connection of its own to
+/// look at what the app keeps there.
+pub fn on_redis<F: Fn(&Client, &redis::Connection)>(extras: &[(&str, Value)], test: F) {
+    let redis_url = match env::var("TEST_REDIS_URL").ok().filter(|url| !url.is_empty()) {
+        Some(url) => url,
+        None => {
+            eprintln!("TEST_REDIS_URL is not set, skipping a test that needs Redis");
+            return;
+        },
+    };
+    let _turn = Turn::take(&REDIS_TAKEN);
+    let redis = redis::Client::open(redis_url.as_str())
+        .and_then(|client| client.get_connection())
+        .expect("the test Redis is reachable");
+    for pattern in &REDIS_PATTERNS {
+        let keys: Vec<String> = redis.keys(*pattern).unwrap();
+        if !keys.is_empty() {
+            redis.del::<_, ()>(keys).unwrap();
+        }
+    }
+    test(&memory_client_on_redis(&redis_url, extras), &redis);
+}
+
+/// The app on the memory store alone, for tests whose routes behave the
+/// same whichever store is behind them.
+pub fn memory_client(extras: &[(&str, Value)]) -> Client {
+    Client::new(memory_rocket(extras)).expect("the app launches")
+}
+
+/// What `memory_client` runs, for tests that mount routes of their own.
+pub fn memory_rocket(extras: &[(&str, Value)]) -> Rocket {
+    app(UNUSED_DATABASE_URL, None, &memory_store(), extras, Limits::default())
+}
+
+/// The app on the memory store with `limits` in place of Rocket's own.
+pub fn memory_client_with_limits(limits: Limits) -> Client {
+    Client::new(app(UNUSED_DATABASE_URL, None, &memory_store(), &[], limits)).expect("the app launches")
+}
+
+/// The app on the memory store with Redis at `redis_url`, which need not
+/// answer.
+pub fn memory_client_on_redis(redis_url: &str, extras: &[(&str, Value)]) -> Client {
+    Client::new(app(UNUSED_DATABASE_URL, Some(redis_url), &memory_store(), extras, Limits::default()))
+        .expect("the app launches")
+}
+
+/// The app on `database_url` and `redis_url`, if any, with the store's
+/// extras and then the test's, the body `limits`, and the API key set. The
+/// rate limit is high enough that only the tests of it run into it.
+fn app(
+    database_url: &str,
+    redis_url: Option<&str>,
+    store: &[(&str, Value)],
+    extras: &[(&str, Value)],
+    limits: Limits,
+) -> Rocket {
+    let mut config = Config::build(Environment::Development)
+        .log_level(LoggingLevel::Off)
+        .limits(limits)
+        .extra("db_pool_max_size", 2)
+        .extra("rate_limit_capacity", 1000);
+    for (name, value) in store.iter().chain(extras) {
+        config = config.extra(name, value.clone());
+    }
+    let rocket = rocket::custom(config.finalize().expect("the test config is valid"));
+    let settings = Settings::new(database_url, redis_url, Some(API_KEY));
+    crate::app(rocket, &settings)
+}
+
+/// Applies whatever migrations the database lacks, the first time round,
+/// then empties the tables.
+fn prepare(database_url: &str) {
+    let conn = PgConnection::establish(database_url).expect("the test database is reachable");
+    MIGRATED.call_once(|| migrate(&conn));
+    conn.batch_execute("TRUNCATE heroes, hero_powers RESTART IDENTITY CASCADE")
+        .expect("the test tables can be emptied");
+}
+
+/// Runs each `migrations/*/up.sql` not yet recorded, in order, noting it in
+/// the table Diesel's CLI keeps, so either can carry on from the other.
+fn migrate(conn: &PgConnection) {
+    conn.batch_execute(
+        "CREATE TABLE IF NOT EXISTS __diesel_schema_migrations (
+            version VARCHAR(50) PRIMARY KEY NOT NULL,
+            run_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+    ).unwrap();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+    let mut migrations: Vec<_> = fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    migrations.sort();
+    for migration in migrations {
+        let name = migration.file_name().unwrap().to_string_lossy().into_owned();
+        let version: String = name.split('_').next().unwrap().chars().filter(char::is_ascii_digit).collect();
+        let applied = diesel::select(diesel::dsl::sql::<Bool>(&format!(
+            "EXISTS (SELECT 1 FROM __diesel_schema_migrations WHERE version = '{}')",
+            version
+        ))).get_result::<bool>(conn).unwrap();
+        if applied {
+            continue;
+        }
+        let up = fs::read_to_string(migration.join("up.sql")).unwrap();
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            conn.batch_execute(&up)?;
+            conn.batch_execute(&format!("INSERT INTO __diesel_schema_migrations (version) VALUES ('{}')", version))
+        }).unwrap_or_else(|err| panic!("migration {} failed: {}", name, err));
+    }
+}
+
+/// A hero body with every required field, for `name`.
+pub fn hero(name: &str) -> JsonValue {
+    json!({ "name": name, "identity": format!("{} Doe", name), "hometown": "Gotham", "age": 30 })
+}
+
+/// The header that lets a request change data.
+pub fn api_key() -> Header<'static> {
+    Header::new("X-API-Key", API_KEY)
+}
+
+/// Sends `body` as JSON with the API key.
+pub fn send<'c>(client: &'c Client, method: &str, path: &str, body: &JsonValue) -> LocalResponse<'c> {
+    let request = match method {
+        "POST" => client.post(path.to_string()),
+        "PUT" => client.put(path.to_string()),
+        other => panic!("no body is sent with {}", other),
+    };
+    request
+        .header(ContentType::JSON)
+        .header(api_key())
+        .body(body.to_string())
+        .dispatch()
+}
+
+/// Creates a hero through the API, returning it as the app does.
+pub fn create(client: &Client, body: &JsonValue) -> JsonValue {
+    let mut response = send(client, "POST", "/api/v1/heroes", body);
+    assert_eq!(response.status(), Status::Created, "creating {}", body);
+    json_body(&mut response)
+}
+
+pub fn json_body(response: &mut LocalResponse) -> JsonValue {
+    let body = response.body_string().expect("the response has a body");
+    serde_json::from_str(&body).unwrap_or_else(|err| panic!("{} in {:?}", err, body))
+}
@@ -0,0 +1,106 @@
+//! Heroes sent and answered as MessagePack, beside the JSON everyone else
+//! keeps getting.
+
+use rocket::http::{Accept, ContentType, MediaType, Status};
+use rocket::local::{Client, LocalResponse};
+use serde_json::{json, Value as JsonValue};
+
+use super::{api_key, create, each_store, hero, json_body};
+
+fn msgpack() -> ContentType {
+    ContentType::new("application", "msgpack")
+}
+
+fn accept_msgpack() -> Accept {
+    Accept::from(MediaType::new("application", "msgpack"))
+}
+
+/// Sends `body` encoded as MessagePack, asking for MessagePack back.
+fn send_msgpack<'c>(client: &'c Client, method: &str, path: &str, body: &JsonValue) -> LocalResponse<'c> {
+    let request = match method {
+        "POST" => client.post(path.to_string()),
+        "PUT" => client.put(path.to_string()),
+        other => panic!("no body is sent with {}", other),
+    };
+    request
+        .header(msgpack())
+        .header(accept_msgpack())
+        .header(api_key())
+        .body(rmp_serde::to_vec_named(body).unwrap())
+        .dispatch()
+}
+
+fn msgpack_body(response: &mut LocalResponse) -> JsonValue {
+    assert_eq!(response.content_type(), Some(msgpack()));
+    let body = response.body_bytes().expect("the response has a body");
+    rmp_serde::from_slice(&body).unwrap_or_else(|err| panic!("{} in {:?}", err, body))
+}
+
+#[test]
+fn a_hero_round_trips_through_msgpack() {
+    each_store(&[], |client| {
+        let mut response = send_msgpack(client, "POST", "/api/v1/heroes", &hero("Bruce"));
+        assert_eq!(response.status(), Status::Created);
+        let created = msgpack_body(&mut response);
+        assert_eq!(created["name"], "Bruce");
+        assert_eq!(created["age"], 30);
+
+        let mut response = client.get("/api/v1/heroes/1").header(accept_msgpack()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(msgpack_body(&mut response), created);
+
+        let mut body = hero("Bruce");
+        body["hometown"] = json!("Metropolis");
+        body["version"] = created["version"].clone();
+        let mut response = send_msgpack(client, "PUT", "/api/v1/heroes/1", &body);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(msgpack_body(&mut response)["hometown"], "Metropolis");
+
+        let mut response = client.get("/api/v1/heroes").header(accept_msgpack()).dispatch();
+        let listed = msgpack_body(&mut response);
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+        assert_eq!(listed[0]["hometown"], "Metropolis");
+    });
+}
+
+#[test]
+fn a_json_client_is_unaffected() {
+    each_store(&[], |client| {
+        let created = create(client, &hero("Bruce"));
+
+        let mut response = client.get("/api/v1/heroes/1").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(json_body(&mut response), created);
+
+        let mut response = client.get("/api/v1/heroes/1").header(Accept::Any).dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(json_body(&mut response), created);
+    });
+}
+
+#[test]
+fn a_hero_sent_as_msgpack_can_be_read_as_json() {
+    each_store(&[], |client| {
+        let response = client.post("/api/v1/heroes")
+            .header(msgpack())
+            .header(api_key())
+            .body(rmp_serde::to_vec_named(&hero("Bruce")).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+
+        let mut response = client.get("/api/v1/heroes/1").dispatch();
+        assert_eq!(json_body(&mut response)["name"], "Bruce");
+    });
+}
+
+#[test]
+fn a_msgpack_body_of_the_wrong_shape_is_unprocessable() {
+    each_store(&[], |client| {
+        let mut body = hero("Bruce");
+        body["age"] = json!("thirty");
+        let mut response = send_msgpack(client, "POST", "/api/v1/heroes", &body);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert_eq!(json_body(&mut response)["error"]["path"], "age");
+    });
+}
@@ -0,0 +1,54 @@
+//! The hand-written spec at `/openapi.json` keeps up with the hero routes.
+
+use rocket::http::Status;
+
+use super::{json_body, memory_client};
+
+/// The statuses each hero route answers with, from its guards and handler,
+/// by method and path under `/api/v1/heroes` as the spec writes it. Every
+/// route can also answer 503 when no pooled connection comes free.
+const ANSWERS: &[(&str, &str, &[u16])] = &[
+    ("get", "", &[200, 400, 403, 406]),
+    ("post", "", &[201, 401, 409, 422, 429]),
+    ("post", "/full", &[201, 400, 401, 409, 422, 429]),
+    ("get", "/count", &[200, 400, 403]),
+    ("get", "/export.csv", &[200, 400, 403]),
+    ("get", "/stream", &[200, 400, 403]),
+    ("get", "/{id}", &[200, 304, 404, 406]),
+    ("put", "/{id}", &[200, 401, 404, 409, 412, 422, 428, 429]),
+    ("delete", "/{id}", &[200, 401, 404, 429]),
+    ("post", "/{id}/restore", &[200, 401, 404, 409, 429]),
+    ("get", "/{id}/avatar", &[200, 404]),
+    ("put", "/{id}/avatar", &[200, 401, 404, 413, 415, 429]),
+];
+
+/// A route's path as the spec writes it: `<id>` as `{id}`, and the
+/// collection itself as the bare mount point.
+fn spec_path(route_path: &str) -> String {
+    let path = route_path.replace('<', "{").replace('>', "}");
+    if path == "/" { String::new() } else { path }
+}
+
+#[test]
+fn every_v1_route_is_in_the_spec_with_the_statuses_it_answers() {
+    let client = memory_client(&[]);
+    let mut response = client.get("/openapi.json").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let spec = json_body(&mut response);
+
+    for route in crate::routes_v1() {
+        let method = route.method.as_str().to_lowercase();
+        let path = spec_path(route.uri.path());
+        let answers = ANSWERS.iter()
+            .find(|(m, p, _)| *m == method && *p == path)
+            .unwrap_or_else(|| panic!("no expected statuses for {} {}", method, path));
+        let operation = &spec["paths"][format!("/api/v1/heroes{}", path)][&method];
+        assert!(operation.is_object(), "{} {} is not in the spec", method, path);
+        for status in answers.2.iter().chain(&[503]) {
+            assert!(
+                operation["responses"][status.to_string()].is_object(),
+                "{} {} does not list {}", method, path, status
+            );
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! The database pool: checked at launch unless `lazy_db` is set, answering
+//! 503 when no connection frees up in time, and shown by `/admin/pool` with
+//! how often a checkout gave up waiting.
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rocket::config::Value;
+use rocket::error::LaunchErrorKind;
+use rocket::http::Status;
+use rocket::local::Client;
+use serde_json::Value as JsonValue;
+
+use super::{api_key, app, json_body, memory_client, on_postgres, UNUSED_DATABASE_URL};
+use crate::db::Pool;
+
+fn pool_stats(client: &Client) -> JsonValue {
+    let mut response = client.get("/admin/pool").header(api_key()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    json_body(&mut response)
+}
+
+#[test]
+fn the_pool_stats_want_the_key_and_have_their_shape() {
+    let client = memory_client(&[("db_pool_max_size", 3.into())]);
+    assert_eq!(client.get("/admin/pool").dispatch().status(), Status::Unauthorized);
+    let stats = pool_stats(&client);
+    let mut fields: Vec<_> = stats.as_object().unwrap().keys().cloned().collect();
+    fields.sort();
+    assert_eq!(fields, vec!["checkout_timeouts", "connections", "idle_connections", "max_size"]);
+    assert_eq!(stats["max_size"], 3);
+    assert_eq!(stats["checkout_timeouts"], 0);
+}
+
+#[test]
+fn a_checkout_that_finds_the_pool_taken_is_counted() {
+    let extras = [("db_pool_max_size", 1.into()), ("db_pool_timeout_secs", 1.into())];
+    on_postgres(&extras, |client| {
+        let pool = client.rocket().state::<Pool>().unwrap().clone();
+        let held = pool.get().expect("the pool's one connection");
+
+        let contender = pool.clone();
+        let second = thread::spawn(move || contender.get().is_ok()).join().unwrap();
+        assert!(!second, "a second checkout got a connection from a pool of one");
+        assert_eq!(client.get("/api/v1/heroes/1").dispatch().status(), Status::ServiceUnavailable);
+        let stats = pool_stats(client);
+        assert_eq!(stats["checkout_timeouts"], 2);
+        assert_eq!(stats["connections"], 1);
+        assert_eq!(stats["idle_connections"], 0);
+
+        drop(held);
+        assert_eq!(client.get("/api/v1/heroes/1").dispatch().status(), Status::NotFound);
+        assert_eq!(pool_stats(client)["idle_connections"], 1);
+    });
+}
+
+#[test]
+fn an_unreachable_database_stops_the_launch() {
+    let extras = [("db_pool_timeout_secs", Value::from(1))];
+    let started = Instant::now();
+    let err = match Client::new(app(UNUSED_DATABASE_URL, None, &[], &extras, Default::default())) {
+        Ok(_) => panic!("the app launched without its database"),
+        Err(err) => err,
+    };
+    match err.kind() {
+        LaunchErrorKind::FailedFairings(failed) => assert_eq!(failed.first(), Some(&"Database pool")),
+        other => panic!("expected the pool's fairing to fail, got {}", other),
+    }
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn lazy_db_launches_without_the_database() {
+    let extras = [("lazy_db", Value::from(true)), ("db_pool_timeout_secs", Value::from(1))];
+    let client = Client::new(app(UNUSED_DATABASE_URL, None, &[], &extras, Default::default()))
+        .expect("the app launches");
+    let mut response = client.get("/api/v1/heroes/1").dispatch();
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    assert_eq!(json_body(&mut response)["error"]["code"], 503);
+}
+
+#[test]
+fn a_request_waiting_on_a_sleeping_holder_is_unavailable() {
+    let extras = [("db_pool_max_size", 1.into()), ("db_pool_timeout_secs", 1.into())];
+    on_postgres(&extras, |client| {
+        let pool = client.rocket().state::<Pool>().unwrap().clone();
+        let (checked_out, wait) = mpsc::channel();
+        let sleeper = thread::spawn(move || {
+            let _held = pool.get().expect("the pool's one connection");
+            checked_out.send(()).unwrap();
+            thread::sleep(Duration::from_millis(1500));
+        });
+        wait.recv().unwrap();
+
+        let mut response = client.get("/api/v1/heroes/1").dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert_eq!(json_body(&mut response)["error"]["message"], "no database connection is free, try again shortly");
+
+        sleeper.join().unwrap();
+        assert_eq!(client.get("/api/v1/heroes/1").dispatch().status(), Status::NotFound);
+    });
+}
@@ -0,0 +1,69 @@
+//! `POST /api/v1/heroes/full` creates a hero and its powers together.
+
+use std::env;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use rocket::http::Status;
+use serde_json::{json, Value as JsonValue};
+
+use super::{create, each_store, hero, json_body, on_postgres, send};
+use crate::schemas::{hero_powers, heroes};
+
+fn with_powers(name: &str, powers: &[&str]) -> JsonValue {
+    let mut body = hero(name);
+    body["powers"] = powers.iter().map(|power| json!({ "name": power })).collect();
+    body
+}
+
+#[test]
+fn a_hero_is_answered_with_its_powers() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let mut response = send(client, "POST", "/api/v1/heroes/full", &with_powers("Clark", &["Flight", "X-ray vision"]));
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(response.headers().get_one("Location"), Some("/api/v1/heroes/2"));
+        let created = json_body(&mut response);
+        assert_eq!(created["id"], 2);
+        assert_eq!(created["name"], "Clark");
+        assert_eq!(created["powers"], json!([
+            { "id": 1, "hero_id": 2, "name": "Flight" },
+            { "id": 2, "hero_id": 2, "name": "X-ray vision" },
+        ]));
+
+        let mut response = client.get("/api/v1/heroes/2").dispatch();
+        assert_eq!(json_body(&mut response)["name"], "Clark");
+    });
+}
+
+#[test]
+fn powers_may_be_left_out() {
+    each_store(&[], |client| {
+        let mut response = send(client, "POST", "/api/v1/heroes/full", &hero("Bruce"));
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(json_body(&mut response)["powers"], json!([]));
+    });
+}
+
+#[test]
+fn a_taken_name_creates_no_powers() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let response = send(client, "POST", "/api/v1/heroes/full", &with_powers("Bruce", &["Flight"]));
+        assert_eq!(response.status(), Status::Conflict);
+        let mut response = send(client, "POST", "/api/v1/heroes/full", &with_powers("Clark", &["Flight"]));
+        assert_eq!(json_body(&mut response)["powers"][0]["id"], 1);
+    });
+}
+
+#[test]
+fn a_failed_power_insert_leaves_no_rows_behind() {
+    on_postgres(&[], |client| {
+        let body = with_powers("Bruce", &["Detective", "Gadgets", "Detective"]);
+        assert_eq!(send(client, "POST", "/api/v1/heroes/full", &body).status(), Status::BadRequest);
+
+        let conn = PgConnection::establish(&env::var("TEST_DATABASE_URL").unwrap()).unwrap();
+        assert_eq!(heroes::table.count().get_result::<i64>(&conn).unwrap(), 0);
+        assert_eq!(hero_powers::table.count().get_result::<i64>(&conn).unwrap(), 0);
+    });
+}
@@ -0,0 +1,88 @@
+//! A client that spends its tokens is turned away with 429 until they
+//! trickle back, or with Redis, once it is past the shared window, which
+//! the tokens stand in for while Redis is down. A limit that could never
+//! be met stops the launch.
+
+use std::thread;
+use std::time::Duration;
+
+use rocket::config::Value;
+use rocket::error::LaunchErrorKind;
+use rocket::http::Status;
+use rocket::local::Client;
+
+use super::{api_key, each_store, hero, memory_client_on_redis, memory_rocket, on_redis, send};
+
+#[test]
+fn a_client_past_its_limit_is_told_when_to_retry() {
+    let limit = [("rate_limit_capacity", Value::from(2)), ("rate_limit_refill_per_sec", Value::from(5))];
+    each_store(&limit, |client| {
+        assert_eq!(send(client, "POST", "/api/v1/heroes", &hero("Bruce")).status(), Status::Created);
+        assert_eq!(send(client, "POST", "/api/v1/heroes", &hero("Clark")).status(), Status::Created);
+        let response = send(client, "POST", "/api/v1/heroes", &hero("Diana"));
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert_eq!(response.headers().get_one("Retry-After"), Some("1"));
+
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(send(client, "POST", "/api/v1/heroes", &hero("Diana")).status(), Status::Created);
+    });
+}
+
+#[test]
+fn a_client_past_the_redis_window_is_told_when_it_rolls_over() {
+    let window = [("rate_limit_window_requests", Value::from(2)), ("rate_limit_window_secs", Value::from(3600))];
+    on_redis(&window, |client, _| {
+        assert_eq!(send(client, "POST", "/api/v1/heroes", &hero("Bruce")).status(), Status::Created);
+        assert_eq!(send(client, "POST", "/api/v1/heroes", &hero("Clark")).status(), Status::Created);
+        let response = send(client, "POST", "/api/v1/heroes", &hero("Diana"));
+        assert_eq!(response.status(), Status::TooManyRequests);
+        let retry_after: u64 = response.headers().get_one("Retry-After").unwrap().parse().unwrap();
+        assert!((1..=3600).contains(&retry_after), "Retry-After: {}", retry_after);
+    });
+}
+
+#[test]
+fn the_buckets_limit_while_redis_is_down() {
+    let limit = [
+        ("rate_limit_capacity", Value::from(2)),
+        ("rate_limit_refill_per_sec", Value::from(0.01)),
+        ("rate_limit_window_requests", Value::from(100)),
+    ];
+    let client = memory_client_on_redis("redis://127.0.0.1:1", &limit);
+    assert_eq!(send(&client, "POST", "/api/v1/heroes", &hero("Bruce")).status(), Status::Created);
+    assert_eq!(send(&client, "POST", "/api/v1/heroes", &hero("Clark")).status(), Status::Created);
+    let response = send(&client, "POST", "/api/v1/heroes", &hero("Diana"));
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert_eq!(response.headers().get_one("Retry-After"), Some("100"));
+}
+
+#[test]
+fn reads_are_not_limited() {
+    let limit = [("rate_limit_capacity", Value::from(1)), ("rate_limit_refill_per_sec", Value::from(0.01))];
+    each_store(&limit, |client| {
+        for _ in 0..5 {
+            assert_eq!(client.get("/api/v1/heroes").header(api_key()).dispatch().status(), Status::Ok);
+        }
+    });
+}
+
+#[test]
+fn a_capacity_below_one_or_a_refill_of_zero_or_less_stops_the_launch() {
+    let limits = [
+        ("rate_limit_capacity", Value::from(0.5)),
+        ("rate_limit_capacity", Value::from(-3)),
+        ("rate_limit_refill_per_sec", Value::from(0)),
+        ("rate_limit_refill_per_sec", Value::from(-1.5)),
+    ];
+    for limit in &limits {
+        let err = match Client::new(memory_rocket(&[limit.clone()])) {
+            Ok(_) => panic!("the app launched with {} = {}", limit.0, limit.1),
+            Err(err) => err,
+        };
+        match err.kind() {
+            LaunchErrorKind::FailedFairings(failed) => assert_eq!(failed.first(), Some(&"Rate limiter")),
+            other => panic!("expected the rate limiter's fairing to fail, got {}", other),
+        }
+    }
+    assert!(Client::new(memory_rocket(&[("rate_limit_capacity", Value::from(1))])).is_ok());
+}
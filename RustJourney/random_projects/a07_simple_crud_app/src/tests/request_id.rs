@@ -0,0 +1,48 @@
+//! Every response carries the request's correlation id, the client's own
+//! when it sent one, and so do error bodies.
+
+use rocket::http::{Header, Status};
+use uuid::Uuid;
+
+use super::{json_body, memory_client};
+
+#[test]
+fn a_client_id_is_echoed_back() {
+    let client = memory_client(&[]);
+    let response = client.get("/health").header(Header::new("X-Request-Id", "trace-42")).dispatch();
+    assert_eq!(response.headers().get_one("X-Request-Id"), Some("trace-42"));
+}
+
+#[test]
+fn a_request_without_one_is_given_a_uuid() {
+    let client = memory_client(&[]);
+    let first = client.get("/health").dispatch();
+    let second = client.get("/health").dispatch();
+    let first = first.headers().get_one("X-Request-Id").unwrap();
+    let second = second.headers().get_one("X-Request-Id").unwrap();
+    assert!(Uuid::parse_str(first).is_ok(), "{:?} is not a UUID", first);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn an_empty_or_overlong_id_is_replaced() {
+    let client = memory_client(&[]);
+    for id in &[String::new(), "x".repeat(129)] {
+        let response = client.get("/health").header(Header::new("X-Request-Id", id.clone())).dispatch();
+        let echoed = response.headers().get_one("X-Request-Id").unwrap();
+        assert!(Uuid::parse_str(echoed).is_ok(), "{:?} came back as {:?}", id, echoed);
+    }
+}
+
+#[test]
+fn the_404_body_names_the_request() {
+    let client = memory_client(&[]);
+    let mut response = client.get("/no/such/route").header(Header::new("X-Request-Id", "trace-404")).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(response.headers().get_one("X-Request-Id"), Some("trace-404"));
+    assert_eq!(json_body(&mut response)["error"]["request_id"], "trace-404");
+
+    let mut response = client.get("/api/v1/heroes/41").dispatch();
+    let id = response.headers().get_one("X-Request-Id").unwrap().to_string();
+    assert_eq!(json_body(&mut response)["error"]["request_id"], id.as_str());
+}
@@ -0,0 +1,86 @@
+//! A hero's role is one of a fixed few, kept as sent and filterable.
+
+use rocket::http::Status;
+use serde_json::{json, Value as JsonValue};
+
+use super::{create, each_store, hero, json_body, send};
+
+fn with_role(name: &str, role: &str) -> JsonValue {
+    let mut body = hero(name);
+    body["role"] = json!(role);
+    body
+}
+
+#[test]
+fn every_role_round_trips() {
+    each_store(&[], |client| {
+        for (name, role) in &[("Bruce", "Tank"), ("Clark", "Support"), ("Diana", "Damage")] {
+            let created = create(client, &with_role(name, role));
+            assert_eq!(created["role"], *role);
+            let mut response = client.get(format!("/api/v1/heroes/{}", created["id"])).dispatch();
+            assert_eq!(json_body(&mut response)["role"], *role);
+        }
+        let created = create(client, &hero("Arthur"));
+        assert_eq!(created["role"], JsonValue::Null);
+    });
+}
+
+#[test]
+fn an_unknown_role_is_refused_naming_the_allowed_ones() {
+    each_store(&[], |client| {
+        let mut response = send(client, "POST", "/api/v1/heroes", &with_role("Bruce", "Healer"));
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let error = json_body(&mut response);
+        assert_eq!(error["error"]["path"], "role");
+        let detail = error["error"]["detail"].as_str().unwrap();
+        assert!(detail.contains("unknown role `Healer`, expected one of Tank, Support, Damage"), "{}", detail);
+    });
+}
+
+#[test]
+fn an_update_sets_or_clears_the_role() {
+    each_store(&[], |client| {
+        create(client, &with_role("Bruce", "Tank"));
+        let mut body = with_role("Bruce", "Support");
+        body["version"] = json!(1);
+        let mut response = send(client, "PUT", "/api/v1/heroes/1", &body);
+        assert_eq!(json_body(&mut response)["role"], "Support");
+
+        let mut body = hero("Bruce");
+        body["version"] = json!(2);
+        let mut response = send(client, "PUT", "/api/v1/heroes/1", &body);
+        assert_eq!(json_body(&mut response)["role"], JsonValue::Null);
+    });
+}
+
+#[test]
+fn a_hero_created_with_powers_keeps_its_role() {
+    each_store(&[], |client| {
+        let mut body = with_role("Bruce", "Damage");
+        body["powers"] = json!([{ "name": "Detective" }]);
+        let mut response = send(client, "POST", "/api/v1/heroes/full", &body);
+        assert_eq!(response.status(), Status::Created);
+        let created = json_body(&mut response);
+        assert_eq!(created["role"], "Damage");
+        assert_eq!(created["powers"][0]["name"], "Detective");
+    });
+}
+
+#[test]
+fn the_list_filters_by_role() {
+    each_store(&[], |client| {
+        create(client, &with_role("Bruce", "Tank"));
+        create(client, &with_role("Clark", "Support"));
+        create(client, &with_role("Diana", "Tank"));
+        create(client, &hero("Arthur"));
+
+        let mut response = client.get("/api/v1/heroes?role=Tank").dispatch();
+        assert_eq!(response.headers().get_one("X-Total-Count"), Some("2"));
+        let names: Vec<_> = json_body(&mut response).as_array().unwrap().iter().map(|hero| hero["name"].clone()).collect();
+        assert_eq!(names, vec!["Bruce", "Diana"]);
+
+        let mut response = client.get("/api/v1/heroes?role=Healer").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(json_body(&mut response)["error"]["message"].as_str().unwrap().starts_with("unknown role `Healer`"));
+    });
+}
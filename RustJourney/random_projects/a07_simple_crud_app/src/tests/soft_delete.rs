@@ -0,0 +1,78 @@
+//! Deleted heroes drop out of every read but the keyed `include_deleted`
+//! list, and come back when restored.
+
+use rocket::http::Status;
+use rocket::local::Client;
+use serde_json::{json, Value as JsonValue};
+
+use super::{api_key, create, each_store, hero, json_body};
+
+fn names(client: &Client, path: &str, keyed: bool) -> JsonValue {
+    let mut request = client.get(path.to_string());
+    if keyed {
+        request = request.header(api_key());
+    }
+    let mut response = request.dispatch();
+    assert_eq!(response.status(), Status::Ok, "listing {}", path);
+    let heroes = json_body(&mut response);
+    json!(heroes.as_array().unwrap().iter().map(|hero| hero["name"].clone()).collect::<Vec<_>>())
+}
+
+#[test]
+fn a_hero_goes_through_delete_and_restore_and_back() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        create(client, &hero("Clark"));
+
+        assert_eq!(client.delete("/api/v1/heroes/1").header(api_key()).dispatch().status(), Status::Ok);
+        assert_eq!(names(client, "/api/v1/heroes", false), json!(["Clark"]));
+        assert_eq!(names(client, "/api/v1/heroes?include_deleted=true", true), json!(["Bruce", "Clark"]));
+        let mut response = client.get("/api/v1/heroes/count").dispatch();
+        assert_eq!(json_body(&mut response), json!({ "count": 1 }));
+
+        let mut response = client.post("/api/v1/heroes/1/restore").header(api_key()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let restored = json_body(&mut response);
+        assert_eq!(restored["name"], "Bruce");
+        assert_eq!(restored["deleted_at"], JsonValue::Null);
+        assert_eq!(names(client, "/api/v1/heroes", false), json!(["Bruce", "Clark"]));
+        assert_eq!(client.get("/api/v1/heroes/1").dispatch().status(), Status::Ok);
+    });
+}
+
+#[test]
+fn deleted_heroes_are_listed_with_their_deletion_time() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        client.delete("/api/v1/heroes/1").header(api_key()).dispatch();
+        let mut response = client.get("/api/v1/heroes?include_deleted=true").header(api_key()).dispatch();
+        let heroes = json_body(&mut response);
+        assert!(heroes[0]["deleted_at"].is_string(), "listed {}", heroes);
+    });
+}
+
+#[test]
+fn only_a_keyed_client_sees_deleted_heroes() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        client.delete("/api/v1/heroes/1").header(api_key()).dispatch();
+        let mut response = client.get("/api/v1/heroes?include_deleted=true").dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert_eq!(json_body(&mut response)["error"]["code"], 403);
+    });
+}
+
+#[test]
+fn deleting_twice_is_not_found_and_restoring_a_live_hero_a_conflict() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let mut response = client.post("/api/v1/heroes/1/restore").header(api_key()).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        assert_eq!(json_body(&mut response)["error"]["message"], "hero is not deleted");
+
+        assert_eq!(client.delete("/api/v1/heroes/1").header(api_key()).dispatch().status(), Status::Ok);
+        assert_eq!(client.delete("/api/v1/heroes/1").header(api_key()).dispatch().status(), Status::NotFound);
+        let response = client.post("/api/v1/heroes/2/restore").header(api_key()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    });
+}
@@ -0,0 +1,55 @@
+//! A later sort field decides between heroes the earlier ones tie on.
+
+use diesel::connection::SimpleConnection;
+use rocket::http::Status;
+use rocket::local::Client;
+use serde_json::json;
+
+use super::{create, each_store, hero, json_body, on_postgres};
+use crate::db::Pool;
+
+fn names(client: &Client, sort: &str) -> Vec<String> {
+    let mut response = client.get(format!("/api/v1/heroes?sort={}", sort)).dispatch();
+    assert_eq!(response.status(), Status::Ok, "sorting by {}", sort);
+    json_body(&mut response).as_array().unwrap().iter().map(|hero| hero["name"].as_str().unwrap().to_string()).collect()
+}
+
+#[test]
+fn heroes_of_one_age_are_ordered_by_the_next_field() {
+    each_store(&[], |client| {
+        for (name, age) in &[("Clark", 35), ("Bruce", 30), ("Diana", 30), ("Arthur", 35)] {
+            let mut body = hero(name);
+            body["age"] = json!(age);
+            create(client, &body);
+        }
+        assert_eq!(names(client, "age,name"), vec!["Bruce", "Diana", "Arthur", "Clark"]);
+        assert_eq!(names(client, "age,-name"), vec!["Diana", "Bruce", "Clark", "Arthur"]);
+        assert_eq!(names(client, "-age,id"), vec!["Clark", "Arthur", "Bruce", "Diana"]);
+    });
+}
+
+#[test]
+fn heroes_updated_together_are_ordered_by_name() {
+    on_postgres(&[], |client| {
+        for name in &["Diana", "Bruce", "Clark"] {
+            create(client, &hero(name));
+        }
+        let pool = client.rocket().state::<Pool>().unwrap();
+        pool.get().unwrap()
+            .batch_execute("UPDATE heroes SET updated_at = '2020-05-07 12:00:00+00' WHERE name <> 'Clark'")
+            .unwrap();
+        assert_eq!(names(client, "-updated_at,name"), vec!["Clark", "Bruce", "Diana"]);
+        assert_eq!(names(client, "-updated_at,-name"), vec!["Clark", "Diana", "Bruce"]);
+    });
+}
+
+#[test]
+fn an_unknown_or_repeated_field_is_a_bad_request() {
+    each_store(&[], |client| {
+        for (sort, message) in &[("name,-power", "unknown sort field `power`"), ("name,-name", "duplicate sort field `name`")] {
+            let mut response = client.get(format!("/api/v1/heroes?sort={}", sort)).dispatch();
+            assert_eq!(response.status(), Status::BadRequest);
+            assert_eq!(json_body(&mut response)["error"]["message"], *message);
+        }
+    });
+}
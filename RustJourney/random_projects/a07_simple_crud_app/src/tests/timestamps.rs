@@ -0,0 +1,58 @@
+//! The server keeps `created_at` and `updated_at` itself: an update moves
+//! only the second, and a client may set neither.
+
+use std::thread;
+use std::time::Duration;
+
+use chrono::DateTime;
+use rocket::http::Status;
+use serde_json::json;
+
+use super::{create, each_store, hero, json_body, send};
+
+#[test]
+fn an_update_moves_updated_at_but_not_created_at() {
+    each_store(&[], |client| {
+        let created = create(client, &hero("Bruce"));
+        assert_eq!(created["created_at"], created["updated_at"]);
+        thread::sleep(Duration::from_millis(10));
+
+        let mut body = hero("Bruce");
+        body["version"] = json!(1);
+        let mut response = send(client, "PUT", "/api/v1/heroes/1", &body);
+        let updated = json_body(&mut response);
+        assert_eq!(updated["created_at"], created["created_at"]);
+        let rfc3339 = |value: &serde_json::Value| DateTime::parse_from_rfc3339(value.as_str().unwrap()).unwrap();
+        assert!(rfc3339(&updated["updated_at"]) > rfc3339(&created["updated_at"]), "updated {}", updated);
+    });
+}
+
+#[test]
+fn a_client_cannot_set_the_timestamps() {
+    each_store(&[], |client| {
+        for field in &["created_at", "updated_at"] {
+            let mut body = hero("Bruce");
+            body[*field] = json!("2020-01-01T00:00:00Z");
+            let mut response = send(client, "POST", "/api/v1/heroes", &body);
+            assert_eq!(response.status(), Status::UnprocessableEntity, "setting {}", field);
+            assert_eq!(json_body(&mut response)["error"]["path"], *field);
+        }
+    });
+}
+
+#[test]
+fn the_list_sorts_by_most_recently_updated() {
+    each_store(&[], |client| {
+        for name in &["Bruce", "Clark", "Diana"] {
+            create(client, &hero(name));
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut body = hero("Bruce");
+        body["version"] = json!(1);
+        assert_eq!(send(client, "PUT", "/api/v1/heroes/1", &body).status(), Status::Ok);
+
+        let mut response = client.get("/api/v1/heroes?sort=-updated_at").dispatch();
+        let names: Vec<_> = json_body(&mut response).as_array().unwrap().iter().map(|hero| hero["name"].clone()).collect();
+        assert_eq!(names, vec!["Bruce", "Diana", "Clark"]);
+    });
+}
@@ -0,0 +1,154 @@
+//! Writes of several statements land together or not at all, and so do
+//! the writes of a request that ends in an error.
+
+use std::env;
+use std::fs;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use rocket::config::{Limits, Value};
+use rocket::http::Status;
+use rocket::local::Client;
+use rocket::{post, routes, State};
+use serde_json::json;
+
+use super::avatars::AvatarDir;
+use super::{app, create, each_store, hero, json_body, on_postgres, send};
+use crate::avatar::AvatarStore;
+use crate::db;
+use crate::error::ApiError;
+use crate::hero::{Hero, NewHero};
+use crate::schemas::heroes;
+use crate::store::HeroStore;
+use crate::transaction::RequestTransaction;
+
+fn new_hero(name: &str) -> NewHero {
+    NewHero {
+        name: name.to_string(),
+        identity: format!("{} Doe", name),
+        hometown: "Gotham".to_string(),
+        age: 30,
+        role: None,
+    }
+}
+
+/// A connection of the test's own to the database `on_postgres` prepared.
+fn connection() -> PgConnection {
+    PgConnection::establish(&env::var("TEST_DATABASE_URL").unwrap()).unwrap()
+}
+
+/// Creates a hero in the request's transaction, then answers `status`: a
+/// handler that wrote and then failed, when that is not 2xx.
+#[post("/create-then-answer?<status>")]
+fn create_then_answer(
+    status: u16,
+    store: State<Box<dyn HeroStore>>,
+    _tx: RequestTransaction,
+) -> Result<Status, ApiError> {
+    store.create(&new_hero("Bruce"))?;
+    Ok(Status::from_code(status).unwrap())
+}
+
+/// Removes the avatar `1.png` once the request's transaction commits, then
+/// answers `status`.
+#[post("/remove-avatar-then-answer?<status>")]
+fn remove_avatar_then_answer(status: u16, avatars: State<AvatarStore>, _tx: RequestTransaction) -> Status {
+    avatars.remove_once_committed("1.png".to_string());
+    Status::from_code(status).unwrap()
+}
+
+/// The app on the test database with `extras` and the test routes mounted
+/// at `/test`, for use inside `on_postgres`.
+fn client_with_test_route(extras: &[(&str, Value)]) -> Client {
+    let rocket = app(&env::var("TEST_DATABASE_URL").unwrap(), None, &[], extras, Limits::default())
+        .mount("/test", routes![create_then_answer, remove_avatar_then_answer]);
+    Client::new(rocket).expect("the app launches")
+}
+
+/// The names of every hero as `conn` sees them, in id order.
+fn names(conn: &PgConnection) -> Vec<String> {
+    heroes::table.select(heroes::name).order(heroes::id).load(conn).unwrap()
+}
+
+#[test]
+fn a_failing_power_takes_its_hero_with_it() {
+    each_store(&[], |client| {
+        let mut body = hero("Bruce");
+        body["powers"] = json!([{ "name": "Flight" }, { "name": "Flight" }]);
+        let mut response = send(client, "POST", "/api/v1/heroes/full", &body);
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(json_body(&mut response)["error"]["message"], "power `Flight` is listed more than once");
+
+        let mut response = client.get("/api/v1/heroes").dispatch();
+        assert_eq!(json_body(&mut response), json!([]));
+        create(client, &hero("Bruce"));
+    });
+}
+
+#[test]
+fn an_error_rolls_back_the_statements_before_it() {
+    on_postgres(&[], |_| {
+        let conn = connection();
+        let result: Result<(), ApiError> = db::with_transaction(&conn, |tx| {
+            Hero::create(&new_hero("Bruce"), tx)?;
+            Hero::create(&new_hero("Bruce"), tx).map_err(|err| ApiError::from_write(err, "Bruce"))?;
+            Ok(())
+        });
+        match result {
+            Err(ApiError::NameTaken(name)) => assert_eq!(name, "Bruce"),
+            other => panic!("expected the second insert to fail, got {:?}", other.map(|_| ())),
+        }
+        assert!(names(&conn).is_empty());
+
+        db::with_transaction(&conn, |tx| Ok(Hero::create(&new_hero("Clark"), tx)?)).unwrap();
+        assert_eq!(names(&connection()), vec!["Clark"]);
+    });
+}
+
+#[test]
+fn a_nested_error_undoes_only_its_own_statements() {
+    on_postgres(&[], |_| {
+        let conn = connection();
+        db::with_transaction(&conn, |tx| {
+            Hero::create(&new_hero("Bruce"), tx)?;
+            let inner: Result<(), ApiError> = db::with_transaction(tx, |tx| {
+                Hero::create(&new_hero("Clark"), tx)?;
+                Err(ApiError::NotFound)
+            });
+            assert!(inner.is_err());
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(names(&connection()), vec!["Bruce"]);
+    });
+}
+
+#[test]
+fn a_request_answered_with_an_error_rolls_back_its_insert() {
+    on_postgres(&[], |_| {
+        let client = client_with_test_route(&[]);
+        for status in &[400, 409, 500, 503] {
+            let response = client.post(format!("/test/create-then-answer?status={}", status)).dispatch();
+            assert_eq!(response.status().code, *status);
+            assert!(names(&connection()).is_empty(), "a {} kept its insert", status);
+        }
+        assert_eq!(client.post("/test/create-then-answer?status=200").dispatch().status(), Status::Ok);
+        assert_eq!(names(&connection()), vec!["Bruce"]);
+    });
+}
+
+#[test]
+fn an_avatar_is_only_removed_once_the_request_commits() {
+    let dir = AvatarDir::new("after_commit");
+    on_postgres(&[], |_| {
+        let client = client_with_test_route(&dir.extras());
+        fs::create_dir_all(&dir.0).unwrap();
+        fs::write(dir.0.join("1.png"), b"png").unwrap();
+        let response = client.post("/test/remove-avatar-then-answer?status=500").dispatch();
+        assert_eq!(response.status(), Status::InternalServerError);
+        assert!(dir.0.join("1.png").exists(), "a rolled back request removed the avatar");
+
+        assert_eq!(client.post("/test/remove-avatar-then-answer?status=200").dispatch().status(), Status::Ok);
+        assert!(!dir.0.join("1.png").exists());
+    });
+}
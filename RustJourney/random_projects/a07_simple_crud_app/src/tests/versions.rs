@@ -0,0 +1,81 @@
+//! Updates carry the version the client last saw, so one client's change
+//! is never silently overwritten by another's.
+
+use rocket::http::{ContentType, Header, Status};
+use serde_json::json;
+
+use super::{api_key, create, each_store, hero, json_body, send};
+
+#[test]
+fn an_update_needs_the_current_version() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let mut response = send(client, "PUT", "/api/v1/heroes/1", &hero("Bruce"));
+        assert_eq!(response.status(), Status::PreconditionRequired);
+        assert_eq!(json_body(&mut response)["error"]["code"], 428);
+
+        let mut stale = hero("Bruce");
+        stale["version"] = json!(7);
+        let mut response = send(client, "PUT", "/api/v1/heroes/1", &stale);
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        assert_eq!(json_body(&mut response)["error"]["current"]["version"], 1);
+    });
+}
+
+#[test]
+fn the_second_of_two_updates_from_one_version_is_stale() {
+    each_store(&[], |client| {
+        let original = create(client, &hero("Bruce"));
+        let mut first = hero("Bruce");
+        first["hometown"] = json!("Metropolis");
+        first["version"] = original["version"].clone();
+        let mut second = hero("Bruce");
+        second["age"] = json!(45);
+        second["version"] = original["version"].clone();
+
+        let mut response = send(client, "PUT", "/api/v1/heroes/1", &first);
+        assert_eq!(response.status(), Status::Ok);
+        let updated = json_body(&mut response);
+        assert_eq!(updated["version"], 2);
+
+        let mut response = send(client, "PUT", "/api/v1/heroes/1", &second);
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        let error = json_body(&mut response);
+        assert_eq!(error["error"]["current"], updated);
+        let mut response = client.get("/api/v1/heroes/1").dispatch();
+        assert_eq!(json_body(&mut response), updated);
+    });
+}
+
+#[test]
+fn the_version_may_come_in_if_match_instead() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        for (if_match, version) in &[("1", 2), ("\"2\"", 3), ("W/\"3\"", 4)] {
+            let mut response = client.put("/api/v1/heroes/1")
+                .header(ContentType::JSON)
+                .header(api_key())
+                .header(Header::new("If-Match", *if_match))
+                .body(hero("Bruce").to_string())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok, "If-Match: {}", if_match);
+            assert_eq!(json_body(&mut response)["version"], *version);
+        }
+    });
+}
+
+#[test]
+fn if_match_wins_over_the_body_version() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let mut body = hero("Bruce");
+        body["version"] = json!(1);
+        let response = client.put("/api/v1/heroes/1")
+            .header(ContentType::JSON)
+            .header(api_key())
+            .header(Header::new("If-Match", "\"5\""))
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::PreconditionFailed);
+    });
+}
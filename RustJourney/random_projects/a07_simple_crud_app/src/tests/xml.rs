@@ -0,0 +1,101 @@
+//! Hero reads answered as XML to a client that asks for it, and 406 to one
+//! asking for a type nothing here writes.
+
+use rocket::http::{Accept, ContentType, MediaType, Status};
+use rocket::local::Client;
+
+use super::{create, each_store, hero, json_body, memory_client};
+
+/// What the app writes XML as, whichever XML type was asked for. Rocket's
+/// own `ContentType::XML` is `text/xml`.
+fn xml() -> ContentType {
+    ContentType::new("application", "xml")
+}
+
+fn accept_xml() -> Accept {
+    Accept::from(MediaType::new("application", "xml"))
+}
+
+fn xml_body(client: &Client, path: &str, accept: Accept) -> String {
+    let mut response = client.get(path.to_string()).header(accept).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(xml()));
+    assert_eq!(response.headers().get_one("Vary"), Some("Accept"));
+    response.body_string().unwrap()
+}
+
+#[test]
+fn a_hero_is_written_as_xml_when_asked() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        let body = xml_body(client, "/api/v1/heroes/1", accept_xml());
+        assert!(body.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?><hero>"#), "{}", body);
+        assert!(body.contains("<id>1</id>"), "{}", body);
+        assert!(body.contains("<name>Bruce</name>"), "{}", body);
+        assert!(body.contains("<deleted_at/>"), "{}", body);
+        assert!(body.ends_with("</hero>"), "{}", body);
+
+        assert_eq!(xml_body(client, "/api/v1/heroes/1", Accept::XML), body);
+    });
+}
+
+#[test]
+fn a_list_is_written_as_heroes_of_hero() {
+    each_store(&[], |client| {
+        create(client, &hero("Bruce"));
+        create(client, &hero("Clark"));
+        let body = xml_body(client, "/api/v1/heroes", accept_xml());
+        assert!(body.contains("<heroes><hero>"), "{}", body);
+        assert_eq!(body.matches("<hero>").count(), 2);
+        assert!(body.find("<name>Bruce</name>").unwrap() < body.find("<name>Clark</name>").unwrap());
+
+        let empty = xml_body(client, "/api/v1/heroes?q=nobody", accept_xml());
+        assert!(empty.ends_with("<heroes/>"), "{}", empty);
+    });
+}
+
+#[test]
+fn json_stays_the_default() {
+    let client = memory_client(&[]);
+    create(&client, &hero("Bruce"));
+    for accept in &[None, Some(Accept::JSON), Some(Accept::Any)] {
+        let mut request = client.get("/api/v1/heroes/1");
+        if let Some(accept) = accept {
+            request = request.header(accept.clone());
+        }
+        let mut response = request.dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(json_body(&mut response)["name"], "Bruce");
+    }
+}
+
+#[test]
+fn the_accept_weights_pick_the_format() {
+    let client = memory_client(&[]);
+    create(&client, &hero("Bruce"));
+    let response = client.get("/api/v1/heroes/1").header(accept_xml()).dispatch();
+    assert_eq!(response.content_type(), Some(xml()));
+
+    let weighted: Accept = "application/json;q=0.5, application/xml".parse().unwrap();
+    let response = client.get("/api/v1/heroes/1").header(weighted).dispatch();
+    assert_eq!(response.content_type(), Some(xml()));
+
+    let refused: Accept = "application/xml;q=0, application/json".parse().unwrap();
+    let response = client.get("/api/v1/heroes/1").header(refused).dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+}
+
+#[test]
+fn an_unsupported_type_is_not_acceptable() {
+    let client = memory_client(&[]);
+    create(&client, &hero("Bruce"));
+    for path in &["/api/v1/heroes/1", "/api/v1/heroes"] {
+        let mut response = client.get(path.to_string()).header(Accept::HTML).dispatch();
+        assert_eq!(response.status(), Status::NotAcceptable);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(
+            json_body(&mut response)["error"]["message"],
+            "Accept allows none of application/json, application/msgpack or application/xml",
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use log::{error, warn};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Status, StatusClass};
+use rocket::request::{self, FromRequest};
+use rocket::response::Responder;
+use rocket::{Data, Outcome, Request, Response, State};
+
+use crate::db;
+use crate::error::ApiError;
+use crate::request_id::RequestId;
+use crate::store::HeroStore;
+
+/// Request guard that runs the rest of the request in one database
+/// transaction, which `RequestTransaction::fairing` commits when the
+/// response is 2xx and rolls back on any other status. Until then every
+/// connection the store or a `db::Connection` guard draws is this one, so a
+/// handler that writes and then fails leaves nothing behind.
+///
+/// The handlers' own transactions become savepoints inside it:
+/// `HeroStore::create_with_powers`, `delete` and `restore` still undo their
+/// own steps on `Err`, and `Hero::create`, a single INSERT, simply joins
+/// the request's. What must not happen before the COMMIT, such as dropping
+/// cached heroes or removing an avatar file, waits for it through
+/// `db::after_request_transaction`.
+///
+/// The hero update route, `PUT /<id>`, takes no guard and is exempt: a
+/// serialization failure would only surface at the request's COMMIT, after
+/// the response was built, where `Hero::update` could no longer retry it.
+/// Its one statement commits in a SERIALIZABLE transaction of its own
+/// before the handler returns, so its cache invalidation runs at once.
+///
+/// Declare the guard after the others so it only opens once they have
+/// passed. With a store outside the database there is nothing to roll back
+/// and it does nothing.
+pub struct RequestTransaction(());
+
+impl RequestTransaction {
+    pub fn fairing() -> RequestTransactionFairing {
+        RequestTransactionFairing
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for RequestTransaction {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<RequestTransaction, ()> {
+        let store = request.guard::<State<Box<dyn HeroStore>>>()?;
+        let pool = match store.pool() {
+            Some(pool) => pool,
+            None => return Outcome::Success(RequestTransaction(())),
+        };
+        // A second guard in the same request shares the first's transaction.
+        if let Some(connection) = db::take_request_connection() {
+            db::lend_request_connection(connection);
+            return Outcome::Success(RequestTransaction(()));
+        }
+        let connection = match pool.get() {
+            Ok(connection) => connection,
+            Err(_) => return Outcome::Failure((Status::ServiceUnavailable, ())),
+        };
+        if let Err(err) = db::begin_transaction(&connection) {
+            error!("[{}] Could not begin the request transaction: {}", RequestId::of(request), err);
+            return Outcome::Failure((Status::InternalServerError, ()));
+        }
+        db::lend_request_connection(connection);
+        Outcome::Success(RequestTransaction(()))
+    }
+}
+
+pub struct RequestTransactionFairing;
+
+impl Fairing for RequestTransactionFairing {
+    fn info(&self) -> Info {
+        Info { name: "Request transactions", kind: Kind::Request | Kind::Response }
+    }
+
+    // Only a request that never reached its response, such as one whose
+    // handler panicked, can leave a transaction on the thread.
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        if let Some(stale) = db::take_request_connection() {
+            warn!("[{}] Rolling back a transaction an earlier request left open", RequestId::of(request));
+            let _ = db::finish_transaction(&stale, false);
+            db::request_transaction_ended(false);
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let connection = match db::take_request_connection() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let commit = response.status().class() == StatusClass::Success;
+        match db::finish_transaction(&connection, commit) {
+            Ok(()) => db::request_transaction_ended(commit),
+            Err(err) if commit => {
+                db::request_transaction_ended(false);
+                if let Ok(failed) = ApiError::Database(err).respond_to(request) {
+                    response.merge(failed);
+                }
+            },
+            Err(err) => {
+                db::request_transaction_ended(false);
+                error!("[{}] Could not roll back the request transaction: {}", RequestId::of(request), err);
+            },
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::io::Cursor;
+
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Result, Writer};
+use serde_json::Value;
+
+/// The element each item of a list is written as, by the list's own
+/// element. Items of any other list are `<item>`s.
+const ITEM_ELEMENTS: [(&str, &str); 2] = [("heroes", "hero"), ("powers", "power")];
+
+/// `value` as an XML document with `root` as its root element. Object
+/// fields become child elements of the same name and list items repeated
+/// child elements, so `[{"id": 1, "powers": []}]` under `heroes` is
+/// `<heroes><hero><id>1</id><powers/></hero></heroes>`; `null` is an empty
+/// element.
+pub fn to_xml(root: &str, value: &Value) -> Result<Vec<u8>> {
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+    write_element(&mut writer, root, value)?;
+    Ok(writer.into_inner().into_inner())
+}
+
+fn write_element(writer: &mut Writer<Cursor<Vec<u8>>>, name: &str, value: &Value) -> Result<()> {
+    let start = BytesStart::borrowed_name(name.as_bytes());
+    let end = BytesEnd::borrowed(name.as_bytes());
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            writer.write_event(Event::Start(start))?;
+            let item = item_element(name);
+            for value in items {
+                write_element(writer, item, value)?;
+            }
+            writer.write_event(Event::End(end))?;
+        },
+        Value::Object(fields) if !fields.is_empty() => {
+            writer.write_event(Event::Start(start))?;
+            for (field, value) in fields {
+                write_element(writer, field, value)?;
+            }
+            writer.write_event(Event::End(end))?;
+        },
+        Value::Null | Value::Array(_) | Value::Object(_) => {
+            writer.write_event(Event::Empty(start))?;
+        },
+        Value::Bool(_) | Value::Number(_) | Value::String(_) => {
+            let text = match value {
+                Value::String(text) => text.clone(),
+                scalar => scalar.to_string(),
+            };
+            writer.write_event(Event::Start(start))?;
+            writer.write_event(Event::Text(BytesText::from_plain_str(&text)))?;
+            writer.write_event(Event::End(end))?;
+        },
+    }
+    Ok(())
+}
+
+fn item_element(list: &str) -> &'static str {
+    ITEM_ELEMENTS.iter()
+        .find(|(element, _)| *element == list)
+        .map_or("item", |(_, item)| item)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn xml(root: &str, value: Value) -> String {
+        String::from_utf8(to_xml(root, &value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn lists_repeat_their_item_element() {
+        assert_eq!(
+            xml("heroes", json!([{ "id": 1, "powers": [{ "name": "Flight" }] }, { "id": 2, "powers": [] }])),
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                "<heroes><hero><id>1</id><powers><power><name>Flight</name></power></powers></hero>",
+                "<hero><id>2</id><powers/></hero></heroes>",
+            ),
+        );
+        assert!(xml("tags", json!(["a"])).ends_with("<tags><item>a</item></tags>"));
+    }
+
+    #[test]
+    fn scalars_are_text_and_null_is_empty() {
+        assert!(xml("hero", json!({ "age": 30, "deleted_at": null, "name": "Bruce & <Co>" }))
+            .ends_with("<hero><age>30</age><deleted_at/><name>Bruce &amp; &lt;Co&gt;</name></hero>"));
+        assert!(xml("heroes", json!([])).ends_with("<heroes/>"));
+    }
+}
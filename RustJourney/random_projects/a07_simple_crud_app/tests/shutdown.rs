@@ -0,0 +1,115 @@
+//! Starts the app, sends SIGTERM while a slow request is in flight, and
+//! checks that the request is still answered before the process exits, or
+//! that the process exits anyway once the grace period is over.
+//! The database is never contacted, so this needs no Postgres.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const API_KEY: &str = "shutdown-test";
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn start(port: u16, grace_secs: u32) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_a07_simple_crud_app"))
+        .env("ROCKET_PORT", port.to_string())
+        .env("ROCKET_LAZY_DB", "true")
+        .env("ROCKET_DEV_TOOLS", "true")
+        .env("ROCKET_SHUTDOWN_GRACE_SECS", grace_secs.to_string())
+        .env("DATABASE_URL", "postgres://nobody@127.0.0.1:1/none")
+        .env("API_KEY", API_KEY)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("the app starts")
+}
+
+/// Sends `GET path` on a fresh connection, returning the connection to read
+/// the response from.
+fn send(port: u16, path: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(20))).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nX-API-Key: {}\r\nConnection: close\r\n\r\n", path, API_KEY)
+        .unwrap();
+    stream
+}
+
+fn response(mut stream: TcpStream) -> String {
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn terminate(app: &Child) {
+    unsafe {
+        libc::kill(app.id() as libc::pid_t, libc::SIGTERM);
+    }
+}
+
+/// Waits for the app to exit, returning whether it exited cleanly and what
+/// it logged.
+fn exit(mut app: Child) -> (bool, String) {
+    let status = app.wait().unwrap();
+    let mut log = String::new();
+    app.stderr.take().unwrap().read_to_string(&mut log).unwrap();
+    (status.success(), log)
+}
+
+fn wait_until_up(port: u16) {
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(Instant::now() < deadline, "the app did not start listening");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn sigterm_lets_a_slow_request_finish() {
+    let port = free_port();
+    let app = start(port, 10);
+    wait_until_up(port);
+
+    let slow = send(port, "/admin/sleep?ms=1500");
+    thread::sleep(Duration::from_millis(300));
+    terminate(&app);
+    // The watcher notices the signal within its poll interval.
+    thread::sleep(Duration::from_millis(300));
+    let refused = response(send(port, "/api/v1/heroes"));
+    let slow = response(slow);
+
+    let (success, log) = exit(app);
+
+    assert!(slow.starts_with("HTTP/1.1 200"), "slow request got {}", slow);
+    assert!(slow.contains(r#""slept_ms":1500"#), "slow request got {}", slow);
+    assert!(refused.starts_with("HTTP/1.1 503"), "new request got {}", refused);
+    assert!(success, "the app did not exit cleanly: {}", log);
+    assert!(log.contains("Drained 1 requests"), "log: {}", log);
+}
+
+#[test]
+fn sigterm_gives_up_on_a_request_slower_than_the_grace_period() {
+    let port = free_port();
+    let mut app = start(port, 1);
+    wait_until_up(port);
+
+    let mut slow = send(port, "/admin/sleep?ms=8000");
+    thread::sleep(Duration::from_millis(300));
+    let signalled = Instant::now();
+    terminate(&app);
+    // The connection goes away with the process, unanswered.
+    let mut answer = String::new();
+    let _ = slow.read_to_string(&mut answer);
+    let exited = app.wait().map(|_| signalled.elapsed()).unwrap();
+    let (success, log) = exit(app);
+
+    assert!(exited < Duration::from_secs(5), "the app took {:?} to exit", exited);
+    assert!(!answer.starts_with("HTTP/1.1 200"), "slow request got {}", answer);
+    assert!(success, "the app did not exit cleanly: {}", log);
+    assert!(log.contains("Drained 0 requests"), "log: {}", log);
+    assert!(log.contains("Gave up on 1 requests still in flight after 1s"), "log: {}", log);
+}